@@ -0,0 +1,255 @@
+//! Ways of doing the same thing in the VM, timed side by side: alternative implementations
+//! of one opcode, and alternative encodings of the instructions a loop dispatches on.
+//!
+//! `cargo bench --bench opcodes`, prints nanoseconds per operation for each variant and how
+//! it compares with the first one of its group, which is what the VM does today.
+
+use std::{
+    collections::HashMap,
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use testing::task_1_and_2::{asm, Value, Vm, VmConfig};
+
+/// Divisions per variant.
+const DIVISIONS: u64 = 20_000_000;
+/// Steps of the summing loop, and how many times it is run per variant.
+const STEPS: i64 = 1_000;
+const RUNS: u64 = 2_000;
+/// Instructions one run of the summing loop executes.
+const INSTRS: u64 = 4 + 10 * STEPS as u64;
+
+/// Sums 1..=n, the loop `shared_program` runs.
+const SUM: &str = "\
+LoadVal 0
+WriteVar total
+loop:
+ReadVar total
+ReadVar n
+Add
+WriteVar total
+LoadVal 1
+ReadVar n
+Subtract
+WriteVar n
+ReadVar n
+JumpIfPos loop
+ReadVar total
+ReturnValue
+";
+
+/// Times each variant doing `ops` operations and prints them under `title`.
+fn group(title: &str, ops: u64, variants: &[(&str, &dyn Fn())]) {
+    println!("{}", title);
+    let mut baseline = None;
+    for (name, variant) in variants {
+        let start = Instant::now();
+        variant();
+        let elapsed = start.elapsed();
+        let baseline = *baseline.get_or_insert(elapsed);
+        report(name, ops, elapsed, baseline);
+    }
+    println!();
+}
+
+fn report(name: &str, ops: u64, elapsed: Duration, baseline: Duration) {
+    let ns = elapsed.as_nanos() as f64 / ops as f64;
+    let relative = elapsed.as_secs_f64() / baseline.as_secs_f64();
+    println!("  {:<40} {:>8.2} ns/op {:>6.2}x", name, ns, relative);
+}
+
+/// Operands from a fixed generator, one divisor in 16 zero and some `i64::MIN / -1`.
+fn operands() -> Vec<(i64, i64)> {
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    (0..1024)
+        .map(|i| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            match i % 16 {
+                0 => (state as i64, 0),
+                1 => (i64::MIN, -1),
+                _ => (state as i64, (state >> 40) as i64 - (1 << 23)),
+            }
+        })
+        .collect()
+}
+
+fn divide(operands: &[(i64, i64)], div: impl Fn(i64, i64) -> Option<i64>) {
+    for i in 0..DIVISIONS as usize {
+        let (a, b) = black_box(operands[i % operands.len()]);
+        black_box(div(a, b));
+    }
+}
+
+/// An instruction of the summing loop, jumping to a `T`.
+enum Op<T> {
+    Push(i64),
+    Load(usize),
+    Store(usize),
+    Add,
+    Sub,
+    JumpIfPos(T),
+    Ret,
+}
+
+const N: usize = 0;
+const TOTAL: usize = 1;
+
+/// `SUM`, jumping to `target`.
+fn sum_ops<T: Clone>(target: T) -> Vec<Op<T>> {
+    use Op::*;
+
+    vec![
+        Push(0),
+        Store(TOTAL),
+        Load(TOTAL),
+        Load(N),
+        Add,
+        Store(TOTAL),
+        Push(1),
+        Load(N),
+        Sub,
+        Store(N),
+        Load(N),
+        JumpIfPos(target),
+        Load(TOTAL),
+        Ret,
+    ]
+}
+
+/// Runs `ops` with `n`, finding where jumps go with `target`.
+fn run_ops<T>(ops: &[Op<T>], target: impl Fn(&T) -> usize, n: i64) -> i64 {
+    let mut vars = [n, 0];
+    let mut stack = Vec::with_capacity(4);
+    let mut ip = 0;
+    loop {
+        match &ops[ip] {
+            Op::Push(val) => stack.push(*val),
+            Op::Load(slot) => stack.push(vars[*slot]),
+            Op::Store(slot) => vars[*slot] = stack.pop().unwrap(),
+            Op::Add => {
+                let (a, b) = (stack.pop().unwrap(), stack.pop().unwrap());
+                stack.push(a + b);
+            }
+            Op::Sub => {
+                let (a, b) = (stack.pop().unwrap(), stack.pop().unwrap());
+                stack.push(a - b);
+            }
+            Op::JumpIfPos(to) => {
+                if stack.pop().unwrap() > 0 {
+                    ip = target(to);
+                    continue;
+                }
+            }
+            Op::Ret => return stack.pop().unwrap(),
+        }
+        ip += 1;
+    }
+}
+
+/// `ops` packed into a word each, the opcode in the low byte and the operand above it.
+fn pack(ops: &[Op<usize>]) -> Vec<u32> {
+    ops.iter()
+        .map(|op| match *op {
+            Op::Push(val) => u32::try_from(val).unwrap() << 8,
+            Op::Load(slot) => 1 | (slot as u32) << 8,
+            Op::Store(slot) => 2 | (slot as u32) << 8,
+            Op::Add => 3,
+            Op::Sub => 4,
+            Op::JumpIfPos(target) => 5 | (target as u32) << 8,
+            Op::Ret => 6,
+        })
+        .collect()
+}
+
+fn run_packed(words: &[u32], n: i64) -> i64 {
+    let mut vars = [n, 0];
+    let mut stack = Vec::with_capacity(4);
+    let mut ip = 0;
+    loop {
+        let (opcode, operand) = (words[ip] & 0xff, (words[ip] >> 8) as usize);
+        match opcode {
+            0 => stack.push(operand as i64),
+            1 => stack.push(vars[operand]),
+            2 => vars[operand] = stack.pop().unwrap(),
+            3 => {
+                let (a, b) = (stack.pop().unwrap(), stack.pop().unwrap());
+                stack.push(a + b);
+            }
+            4 => {
+                let (a, b) = (stack.pop().unwrap(), stack.pop().unwrap());
+                stack.push(a - b);
+            }
+            5 => {
+                if stack.pop().unwrap() > 0 {
+                    ip = operand;
+                    continue;
+                }
+            }
+            _ => return stack.pop().unwrap(),
+        }
+        ip += 1;
+    }
+}
+
+fn main() {
+    let operands = operands();
+    group(
+        "Divide, None for a zero divisor or an overflow",
+        DIVISIONS,
+        &[
+            ("branch on zero, then checked_div", &|| {
+                divide(&operands, |a, b| match b {
+                    0 => None,
+                    _ => a.checked_div(b),
+                })
+            }),
+            ("checked_div alone", &|| divide(&operands, i64::checked_div)),
+            ("branch on zero and overflow, then /", &|| {
+                divide(&operands, |a, b| match (a, b) {
+                    (_, 0) | (i64::MIN, -1) => None,
+                    _ => Some(a / b),
+                })
+            }),
+        ],
+    );
+
+    let bytecode = asm::parse(SUM).unwrap();
+    let labelled = sum_ops("loop".to_owned());
+    let labels = HashMap::from([("loop".to_owned(), 2)]);
+    let resolved = sum_ops(2);
+    let packed = pack(&resolved);
+    let expected = STEPS * (STEPS + 1) / 2;
+    assert_eq!(run_ops(&labelled, |label| labels[label], STEPS), expected);
+    assert_eq!(run_packed(&packed, STEPS), expected);
+    group(
+        "Summing loop, per instruction executed",
+        RUNS * INSTRS,
+        &[
+            ("Vm, Instruction and Value", &|| {
+                for _ in 0..RUNS {
+                    let mut vm = Vm::new(&bytecode, VmConfig::unlimited());
+                    vm.vars_mut().insert("n".to_owned(), Value::Int(STEPS));
+                    black_box(vm.run()).unwrap();
+                }
+            }),
+            ("enum, labels looked up per jump", &|| {
+                for _ in 0..RUNS {
+                    black_box(run_ops(&labelled, |label| labels[label], black_box(STEPS)));
+                }
+            }),
+            ("enum, targets resolved", &|| {
+                for _ in 0..RUNS {
+                    black_box(run_ops(&resolved, |&to| to, black_box(STEPS)));
+                }
+            }),
+            ("u32 words, opcode and operand packed", &|| {
+                for _ in 0..RUNS {
+                    black_box(run_packed(&packed, black_box(STEPS)));
+                }
+            }),
+        ],
+    );
+}
@@ -0,0 +1,72 @@
+//! Many inputs, one program: a shared `Program` against cloning the bytecode per run.
+//!
+//! `cargo bench --bench shared_program`, prints runs per second for each way.
+
+use std::{
+    hint::black_box,
+    thread,
+    time::{Duration, Instant},
+};
+
+use testing::task_1_and_2::{asm, program::Program, Value};
+
+const INPUTS: i64 = 20_000;
+const THREADS: i64 = 4;
+
+/// Sums 1..=n, about 10 instructions per step.
+const SUM: &str = "\
+LoadVal 0
+WriteVar total
+loop:
+ReadVar total
+ReadVar n
+Add
+WriteVar total
+LoadVal 1
+ReadVar n
+Subtract
+WriteVar n
+ReadVar n
+JumpIfPos loop
+ReadVar total
+ReturnValue
+";
+
+fn report(name: &str, elapsed: Duration) {
+    let per_sec = INPUTS as f64 / elapsed.as_secs_f64();
+    println!("{:<32} {:>12.0} runs/s", name, per_sec);
+}
+
+fn main() {
+    let program = Program::new(asm::parse(SUM).unwrap());
+
+    let start = Instant::now();
+    for n in 0..INPUTS {
+        let bytecode = program.bytecode().clone();
+        let program = Program::new(bytecode);
+        black_box(program.run_with(&[("n", Value::Int(n % 50))])).ok();
+    }
+    report("clone per input, 1 thread", start.elapsed());
+
+    let start = Instant::now();
+    for n in 0..INPUTS {
+        black_box(program.run_with(&[("n", Value::Int(n % 50))])).ok();
+    }
+    report("shared, 1 thread", start.elapsed());
+
+    let start = Instant::now();
+    let workers: Vec<_> = (0..THREADS)
+        .map(|worker| {
+            let program = program.share();
+            thread::spawn(move || {
+                for n in (worker..INPUTS).step_by(THREADS as usize) {
+                    black_box(program.run_with(&[("n", Value::Int(n % 50))])).ok();
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    report(&format!("shared, {} threads", THREADS), start.elapsed());
+}
@@ -0,0 +1,11 @@
+#!/bin/sh
+# Builds, lints and tests every supported feature combination.
+set -eu
+
+for features in "--no-default-features" "" "--features async" "--features remote" "--features alloc-stats" "--features serde" "--all-features"; do
+    echo "== cargo $features"
+    # shellcheck disable=SC2086
+    cargo clippy --all-targets $features -- -D warnings
+    # shellcheck disable=SC2086
+    cargo test $features
+done
@@ -0,0 +1,5 @@
+#!/bin/sh
+# Runs the tests of the unsafe allocator module under Miri, needs a nightly toolchain with miri.
+set -eu
+
+cargo +nightly miri test --no-default-features --features alloc-stats alloc_stats
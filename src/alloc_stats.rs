@@ -0,0 +1,122 @@
+//! The one module allowed unsafe code: a `GlobalAlloc` that forwards to `System`.
+//!
+//! Its tests call the allocator directly, run them under Miri with `ci/miri.sh`.
+#![allow(unsafe_code)]
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// The system allocator, counting every allocation it hands out.
+///
+/// Installed as the global allocator when the `alloc-stats` feature is on.
+pub struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED: AtomicU64 = AtomicU64::new(0);
+static FREED: AtomicU64 = AtomicU64::new(0);
+
+fn record_alloc(size: usize) {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    ALLOCATED.fetch_add(size as u64, Ordering::Relaxed);
+}
+
+fn record_free(size: usize) {
+    FREED.fetch_add(size as u64, Ordering::Relaxed);
+}
+
+// SAFETY: every call is forwarded unchanged to `System`, counting has no effect on the memory.
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record_alloc(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        record_alloc(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        record_free(layout.size());
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record_free(layout.size());
+        record_alloc(new_size);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+/// Allocator counters, process-wide since start or as a difference between two snapshots.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AllocStats {
+    pub allocations: u64,
+    pub allocated: u64,
+    pub freed: u64,
+}
+
+impl AllocStats {
+    pub fn now() -> Self {
+        AllocStats {
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            allocated: ALLOCATED.load(Ordering::Relaxed),
+            freed: FREED.load(Ordering::Relaxed),
+        }
+    }
+
+    /// What happened since `earlier`, this includes all threads.
+    pub fn since(&self, earlier: &AllocStats) -> Self {
+        AllocStats {
+            allocations: self.allocations - earlier.allocations,
+            allocated: self.allocated - earlier.allocated,
+            freed: self.freed - earlier.freed,
+        }
+    }
+
+    /// Bytes still held, negative when more was freed than allocated.
+    pub fn growth(&self) -> i64 {
+        self.allocated as i64 - self.freed as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{GlobalAlloc, Layout};
+
+    use crate::alloc_stats::{AllocStats, CountingAllocator};
+
+    #[test]
+    fn counts_allocations_and_frees() {
+        let layout = Layout::from_size_align(4096, 8).unwrap();
+        let before = AllocStats::now();
+        // SAFETY: the layout has a non-zero size and the block is freed with the same layout.
+        unsafe {
+            let ptr = CountingAllocator.alloc(layout);
+            assert!(!ptr.is_null());
+            CountingAllocator.dealloc(ptr, layout);
+        }
+        // Other tests allocate concurrently, only lower bounds hold.
+        let used = AllocStats::now().since(&before);
+        assert!(used.allocations >= 1);
+        assert!(used.allocated >= 4096);
+        assert!(used.freed >= 4096);
+    }
+
+    #[test]
+    fn realloc_keeps_contents() {
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        // SAFETY: the block is written only within its size and freed with its current layout.
+        unsafe {
+            let ptr = CountingAllocator.alloc_zeroed(layout);
+            assert!(!ptr.is_null());
+            ptr.write(7);
+            let ptr = CountingAllocator.realloc(ptr, layout, 64);
+            assert!(!ptr.is_null());
+            assert_eq!((ptr.read(), ptr.add(15).read()), (7, 0));
+            CountingAllocator.dealloc(ptr, Layout::from_size_align(64, 8).unwrap());
+        }
+    }
+}
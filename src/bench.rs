@@ -0,0 +1,160 @@
+//! `bench-self`, throughput of the search and the VM on this machine, so that results from
+//! different hardware and file systems can be compared.
+//!
+//! The workloads are fixed, only the elapsed times differ between machines.
+
+use std::time::{Duration, Instant};
+#[cfg(feature = "search")]
+use std::{fs, io, path::PathBuf};
+
+use testing::task_1_and_2::{examples::EXAMPLES, Vm, VmConfig};
+
+/// Directories in the generated tree.
+#[cfg(feature = "search")]
+const DIRS: usize = 16;
+/// Files per directory.
+#[cfg(feature = "search")]
+const FILES: usize = 64;
+/// How long each program keeps being run.
+const VM_BUDGET: Duration = Duration::from_millis(250);
+
+/// One timed run of the whole generated tree.
+#[cfg(feature = "search")]
+#[derive(Debug, Clone, Copy)]
+pub struct SearchRun {
+    pub files: usize,
+    pub lines: usize,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+/// Runs of one example program.
+#[derive(Debug, Clone, Copy)]
+pub struct VmRun {
+    pub name: &'static str,
+    pub runs: u64,
+    pub instructions: u64,
+    pub elapsed: Duration,
+}
+
+/// `count` per second over `elapsed`, 0 when no time passed.
+pub fn rate(count: f64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        count / secs
+    } else {
+        0.0
+    }
+}
+
+/// A scratch tree of source files below the temporary directory, removed on drop.
+#[cfg(feature = "search")]
+pub struct Tree {
+    pub root: PathBuf,
+    pub bytes: u64,
+}
+
+#[cfg(feature = "search")]
+impl Tree {
+    /// `DIRS` directories of `FILES` files each, from 1 to 400 lines long.
+    pub fn create() -> io::Result<Tree> {
+        let root = std::env::temp_dir().join(format!("testing-bench-self-{}", std::process::id()));
+        Tree::create_in(root)
+    }
+
+    fn create_in(root: PathBuf) -> io::Result<Tree> {
+        let mut bytes = 0;
+        for dir in 0..DIRS {
+            let dir_path = root.join(format!("d{:02}", dir));
+            fs::create_dir_all(&dir_path)?;
+            for file in 0..FILES {
+                let lines = 1 + (dir * FILES + file) * 37 % 400;
+                let mut content = String::new();
+                for line in 0..lines {
+                    let indent = "    ".repeat(line % 4);
+                    content.push_str(&format!("{}let x{} = {};\n", indent, line, line * 7));
+                }
+                bytes += content.len() as u64;
+                fs::write(dir_path.join(format!("f{:03}.rs", file)), content)?;
+            }
+        }
+        Ok(Tree { root, bytes })
+    }
+
+    /// Counts the lines of every file in the tree.
+    pub fn search(&self) -> Result<SearchRun, anyhow::Error> {
+        use testing::task4::{default_io_threads, Filter, SearchBuilder};
+
+        let search = SearchBuilder::new(&self.root, Filter::new("rs"))
+            .io_threads(default_io_threads())
+            .build();
+        let started = Instant::now();
+        let summary = search.count()?;
+        Ok(SearchRun {
+            files: summary.files,
+            lines: summary.lines,
+            bytes: self.bytes,
+            elapsed: started.elapsed(),
+        })
+    }
+}
+
+#[cfg(feature = "search")]
+impl Drop for Tree {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+/// Runs every example over and over for `VM_BUDGET` each.
+pub fn vm() -> Vec<VmRun> {
+    EXAMPLES
+        .iter()
+        .map(|example| {
+            let bytecode = example.bytecode();
+            let (mut runs, mut instructions) = (0, 0);
+            let started = Instant::now();
+            while started.elapsed() < VM_BUDGET {
+                let mut vm = Vm::new(&bytecode, VmConfig::default());
+                // A failing example still executed its instructions.
+                let _ = vm.run();
+                runs += 1;
+                instructions += vm.executed();
+            }
+            VmRun {
+                name: example.name,
+                runs,
+                instructions,
+                elapsed: started.elapsed(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::bench::rate;
+
+    #[test]
+    fn rates_per_second() {
+        assert_eq!(rate(500.0, Duration::from_millis(250)), 2000.0);
+        assert_eq!(rate(500.0, Duration::ZERO), 0.0);
+    }
+
+    #[cfg(feature = "search")]
+    #[test]
+    fn searches_the_generated_tree() {
+        use crate::bench::{Tree, DIRS, FILES};
+
+        let root = std::env::temp_dir().join(format!("testing-bench-tree-{}", std::process::id()));
+        let tree = Tree::create_in(root.clone()).unwrap();
+        let run = tree.search().unwrap();
+        assert_eq!(run.files, DIRS * FILES);
+        assert!(run.lines >= run.files);
+        assert_eq!(tree.root, root);
+        drop(tree);
+        assert!(!root.exists());
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+
+use crate::i18n::{self, Message};
+
+/// Read from the working directory unless `TESTING_CONFIG` names another file.
+const DEFAULT_PATH: &str = "testing.conf";
+
+/// Settings from the config file, `key = value` lines with `#` comments.
+///
+/// The hooks are programs run before and after `run` and `search`, see `hook` in main.
+/// Paths are relative to the config file. A `bookmark.<name>` line holds the arguments of a
+/// search saved with `--save-as`, as words separated by spaces where `"` quotes a word with
+/// spaces or `#` in it and `\` escapes the next character.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Config {
+    pub pre_run: Option<PathBuf>,
+    pub post_run: Option<PathBuf>,
+    pub pre_search: Option<PathBuf>,
+    pub post_search: Option<PathBuf>,
+    /// Where to count subcommand runs, see [`Usage`](crate::usage::Usage).
+    pub stats_file: Option<PathBuf>,
+    pub bookmarks: BTreeMap<String, Vec<String>>,
+}
+
+const BOOKMARK: &str = "bookmark.";
+
+impl Config {
+    /// The config at `TESTING_CONFIG`, or `testing.conf` when it exists, or an empty one.
+    pub fn load() -> Result<Config, anyhow::Error> {
+        let Some(path) = located() else {
+            return Ok(Config::default());
+        };
+        let text = read(&path)?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        Config::parse(&text, base).map_err(|e| anyhow!("{}:{}", path.display(), e))
+    }
+
+    /// Stores `args` as the bookmark `name` in the config file, replacing one of the same
+    /// name and creating the file when there is none. The rest of the file stays as it was.
+    #[cfg(feature = "search")]
+    pub fn save_bookmark(name: &str, args: &[String]) -> Result<(), anyhow::Error> {
+        use std::fmt::Write as _;
+
+        let path = path();
+        let text = if path.is_file() {
+            read(&path)?
+        } else {
+            String::new()
+        };
+        let mut out = String::new();
+        for line in text.lines() {
+            if bookmark_line(line).is_some_and(|(other, _)| other == name) {
+                continue;
+            }
+            out.push_str(line);
+            out.push('\n');
+        }
+        let _ = writeln!(out, "{}{} = {}", BOOKMARK, name, join_words(args));
+        std::fs::write(&path, out).map_err(|e| {
+            anyhow!(i18n::message(
+                Message::CantWriteConfig,
+                &[&path.display(), &e]
+            ))
+        })
+    }
+
+    fn parse(text: &str, base: &Path) -> Result<Config, anyhow::Error> {
+        let mut config = Config::default();
+        for (line, source) in text.lines().enumerate() {
+            let fail = |msg, arg: &str| anyhow!("{}: {}", line + 1, i18n::message(msg, &[&arg]));
+            // Checked before comments are cut off, quoted words may hold a `#`.
+            if let Some((name, words)) = bookmark_line(source) {
+                if name.is_empty() {
+                    return Err(fail(Message::UnknownConfigKey, BOOKMARK));
+                }
+                let words = split_words(words).ok_or_else(|| fail(Message::BadBookmark, name))?;
+                config.bookmarks.insert(name.to_owned(), words);
+                continue;
+            }
+            let setting = source.split('#').next().unwrap_or_default().trim();
+            if setting.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = setting.split_once('=') else {
+                return Err(fail(Message::ConfigExpectsValue, setting));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            let slot = match key {
+                "pre-run" => &mut config.pre_run,
+                "post-run" => &mut config.post_run,
+                "pre-search" => &mut config.pre_search,
+                "post-search" => &mut config.post_search,
+                "stats-file" => &mut config.stats_file,
+                _ => return Err(fail(Message::UnknownConfigKey, key)),
+            };
+            if value.is_empty() {
+                return Err(fail(Message::ConfigExpectsValue, key));
+            }
+            *slot = Some(base.join(value));
+        }
+        Ok(config)
+    }
+}
+
+/// Where the config is read from and bookmarks are saved to.
+pub fn path() -> PathBuf {
+    env::var_os("TESTING_CONFIG").map_or_else(|| PathBuf::from(DEFAULT_PATH), PathBuf::from)
+}
+
+/// The file [`Config::load`] reads, `None` when it goes without one.
+pub fn located() -> Option<PathBuf> {
+    let path = path();
+    (env::var_os("TESTING_CONFIG").is_some() || path.is_file()).then_some(path)
+}
+
+fn read(path: &Path) -> Result<String, anyhow::Error> {
+    std::fs::read_to_string(path).map_err(|e| {
+        anyhow!(i18n::message(
+            Message::CantReadConfig,
+            &[&path.display(), &e]
+        ))
+    })
+}
+
+/// The name and the words of a `bookmark.<name> = <words>` line.
+fn bookmark_line(line: &str) -> Option<(&str, &str)> {
+    let (key, words) = line.split_once('=')?;
+    Some((key.trim().strip_prefix(BOOKMARK)?, words))
+}
+
+/// Splits `text` into words up to an unquoted `#`, `None` when a quote or escape is left open.
+fn split_words(text: &str) -> Option<Vec<String>> {
+    let mut words = vec![];
+    let mut chars = text.chars();
+    // Whether a word is under way, `""` is one too.
+    let mut word: Option<String> = None;
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => word.get_or_insert_with(String::new).push(chars.next()?),
+            '"' => {
+                word.get_or_insert_with(String::new);
+                quoted = !quoted;
+            }
+            '#' if !quoted => break,
+            c if c.is_whitespace() && !quoted => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    words.extend(word);
+    Some(words)
+}
+
+/// The inverse of [`split_words`], words are only quoted when they need it.
+#[cfg(any(feature = "search", test))]
+fn join_words(words: &[String]) -> String {
+    let mut out = String::new();
+    for word in words {
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        let plain = !word.is_empty()
+            && !word
+                .chars()
+                .any(|c| c.is_whitespace() || matches!(c, '"' | '\\' | '#'));
+        if plain {
+            out.push_str(word);
+            continue;
+        }
+        out.push('"');
+        for c in word.chars() {
+            if matches!(c, '"' | '\\') {
+                out.push('\\');
+            }
+            out.push(c);
+        }
+        out.push('"');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::config::{join_words, split_words, Config};
+
+    #[test]
+    fn reads_hooks_relative_to_the_file() {
+        let text = "\
+# fail the build when the tree grows too large
+post-search = hooks/budget.tasm
+
+pre-run=check.tasm  # before every run
+";
+        let config = Config::parse(text, Path::new("ci")).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                pre_run: Some(PathBuf::from("ci/check.tasm")),
+                post_search: Some(PathBuf::from("ci/hooks/budget.tasm")),
+                ..Config::default()
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_keys_and_missing_values() {
+        let error = |text| Config::parse(text, Path::new("")).unwrap_err().to_string();
+        assert!(error("\npre-serch = a").starts_with("2: "));
+        assert!(error("post-run").starts_with("1: "));
+        assert!(error("post-run =").starts_with("1: "));
+    }
+
+    #[test]
+    fn reads_bookmarks_and_quotes_them_back() {
+        let text = "bookmark.rust-src = src rs --skip-hidden  # the crate's own code\n\
+                    bookmark.odd = \"two words\" \"\" \"a#b\" back\\\\slash\n";
+        let config = Config::parse(text, Path::new("")).unwrap();
+        assert_eq!(config.bookmarks["rust-src"], ["src", "rs", "--skip-hidden"]);
+        let odd = &config.bookmarks["odd"];
+        assert_eq!(odd, &["two words", "", "a#b", "back\\slash"]);
+        assert_eq!(split_words(&join_words(odd)).as_ref(), Some(odd));
+        assert_eq!(
+            join_words(&config.bookmarks["rust-src"]),
+            "src rs --skip-hidden"
+        );
+
+        assert_eq!(split_words("\"open"), None);
+        assert!(Config::parse("bookmark. = a", Path::new("")).is_err());
+    }
+}
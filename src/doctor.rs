@@ -0,0 +1,199 @@
+//! `doctor`, checks that this installation works on this machine and prints what to do
+//! about what doesn't, so that a bug report can start from its output.
+
+use std::{fmt, fs, path::Path};
+
+use testing::task_1_and_2::{self, asm, Bytecode, Value};
+
+use crate::{
+    config::{self, Config},
+    i18n::{self, Message},
+};
+
+/// What the smoke test returns, 6 * 7 through a variable.
+const SMOKE_TEST: &str = "LoadVal 6\nLoadVal 7\nMultiply\nWriteVar x\nReadVar x\nReturnValue";
+const SMOKE_RESULT: Value = Value::Int(42);
+/// Components of the long path, long enough together to be over the 260 characters Windows
+/// allows without long path support.
+const LONG_PATH_DIRS: usize = 6;
+const LONG_PATH_NAME: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// Works, with a limitation some uses will run into.
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub status: Status,
+    /// What was found, and what to do about it unless it is fine.
+    pub text: String,
+}
+
+/// `ok    VM: ...`, the tags stay the same in every language so that reports can be searched.
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tag = match self.status {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        write!(f, "{:<5} {}", tag, self.text)
+    }
+}
+
+/// Every check, scratch files go below `scratch`, which is removed afterwards.
+pub fn run(scratch: &Path) -> Vec<Check> {
+    let checks = vec![
+        vm(),
+        file_system(&scratch.join("fs")),
+        long_paths(&scratch.join("long")),
+        config(),
+    ];
+    let _ = fs::remove_dir_all(scratch);
+    checks
+}
+
+/// Assembles, encodes, decodes and runs a program.
+fn vm() -> Check {
+    let result = asm::parse(SMOKE_TEST)
+        .map_err(|e| e.to_string())
+        .and_then(|bytecode| Bytecode::from_bytes(&bytecode.to_bytes()).map_err(|e| e.to_string()))
+        .and_then(|bytecode| task_1_and_2::run(bytecode).map_err(|e| e.to_string()));
+    match result {
+        Ok(val) if val == SMOKE_RESULT => ok(i18n::message(Message::DoctorVmOk, &[&val])),
+        Ok(val) => fail(i18n::message(Message::DoctorVmFailed, &[&val])),
+        Err(e) => fail(i18n::message(Message::DoctorVmFailed, &[&e])),
+    }
+}
+
+/// Writes a file below `dir` and counts its lines the way a search does.
+fn file_system(dir: &Path) -> Check {
+    let file = dir.join("a.txt");
+    let written = fs::create_dir_all(dir).and_then(|()| fs::write(&file, "1\n2\n"));
+    let counted = written.and_then(|()| count(dir, &file));
+    match counted {
+        Ok(2) => ok(i18n::message(Message::DoctorFsOk, &[&dir.display()])),
+        Ok(lines) => fail(i18n::message(
+            Message::DoctorFsFailed,
+            &[
+                &file.display(),
+                &format!("counted {} lines instead of 2", lines),
+            ],
+        )),
+        Err(e) => fail(i18n::message(
+            Message::DoctorFsFailed,
+            &[&dir.display(), &e],
+        )),
+    }
+}
+
+fn long_paths(dir: &Path) -> Check {
+    let deep = (0..LONG_PATH_DIRS).fold(dir.to_owned(), |path, i| {
+        path.join(format!("{}", i).repeat(LONG_PATH_NAME))
+    });
+    let file = deep.join("a.txt");
+    let len = file.as_os_str().len();
+    let counted = fs::create_dir_all(&deep)
+        .and_then(|()| fs::write(&file, "1\n"))
+        .and_then(|()| count(dir, &file));
+    match counted {
+        Ok(1) => ok(i18n::message(Message::DoctorLongPathOk, &[&len])),
+        Ok(lines) => warn(i18n::message(
+            Message::DoctorLongPathFailed,
+            &[&len, &format!("counted {} lines instead of 1", lines)],
+        )),
+        Err(e) => warn(i18n::message(Message::DoctorLongPathFailed, &[&len, &e])),
+    }
+}
+
+/// The lines of `file`, searched for from `root` when the search is built in.
+#[cfg(feature = "search")]
+fn count(root: &Path, file: &Path) -> std::io::Result<usize> {
+    use testing::task4::{Filter, SearchBuilder};
+
+    let files = SearchBuilder::new(root, Filter::new("txt"))
+        .fail_fast(true)
+        .build()
+        .run()
+        .map_err(std::io::Error::other)?;
+    let found = files.iter().find(|found| found.path == file);
+    Ok(found.map_or(0, |found| found.lines))
+}
+
+#[cfg(not(feature = "search"))]
+fn count(_: &Path, file: &Path) -> std::io::Result<usize> {
+    Ok(fs::read_to_string(file)?.lines().count())
+}
+
+/// Parses the config file and loads the hooks it names.
+fn config() -> Check {
+    let Some(path) = config::located() else {
+        let path = config::path();
+        return ok(i18n::message(Message::DoctorNoConfig, &[&path.display()]));
+    };
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(e) => return fail(i18n::message(Message::DoctorConfigFailed, &[&e])),
+    };
+    let hooks = [
+        ("pre-run", &config.pre_run),
+        ("post-run", &config.post_run),
+        ("pre-search", &config.pre_search),
+        ("post-search", &config.post_search),
+    ];
+    for (key, hook) in hooks {
+        if let Some(Err(e)) = hook.as_ref().map(crate::load_program) {
+            return fail(i18n::message(Message::DoctorHookFailed, &[&key, &e]));
+        }
+    }
+    let settings = hooks.iter().filter(|(_, hook)| hook.is_some()).count()
+        + usize::from(config.stats_file.is_some());
+    ok(i18n::message(
+        Message::DoctorConfigOk,
+        &[&path.display(), &settings, &config.bookmarks.len()],
+    ))
+}
+
+fn ok(text: String) -> Check {
+    Check {
+        status: Status::Ok,
+        text,
+    }
+}
+
+fn warn(text: String) -> Check {
+    Check {
+        status: Status::Warn,
+        text,
+    }
+}
+
+fn fail(text: String) -> Check {
+    Check {
+        status: Status::Fail,
+        text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::doctor::{file_system, long_paths, vm, Status};
+
+    #[test]
+    fn passes_on_a_working_install() {
+        let scratch = std::env::temp_dir().join(format!("testing-doctor-{}", std::process::id()));
+        let checks = [
+            vm(),
+            file_system(&scratch.join("fs")),
+            long_paths(&scratch.join("long")),
+        ];
+        let _ = std::fs::remove_dir_all(&scratch);
+        for check in checks {
+            assert_eq!(check.status, Status::Ok, "{}", check);
+        }
+    }
+}
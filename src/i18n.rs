@@ -0,0 +1,627 @@
+use std::{env, fmt, sync::OnceLock};
+
+/// Languages the CLI has messages in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    En,
+    Ru,
+}
+
+impl Locale {
+    /// `TESTING_LANG` wins, then the usual `LC_ALL`, `LC_MESSAGES` and `LANG`.
+    pub fn from_env() -> Self {
+        ["TESTING_LANG", "LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .map_or(Locale::En, |value| Self::parse(&value))
+    }
+
+    /// Reads names like `ru`, `ru_RU.UTF-8` or `en-GB`, anything unknown is English.
+    pub fn parse(name: &str) -> Self {
+        let lang = name.split(['_', '-', '.', '@']).next().unwrap_or_default();
+        match lang.to_ascii_lowercase().as_str() {
+            "ru" => Locale::Ru,
+            _ => Locale::En,
+        }
+    }
+}
+
+/// The locale picked from the environment on first use.
+pub fn locale() -> Locale {
+    static LOCALE: OnceLock<Locale> = OnceLock::new();
+    *LOCALE.get_or_init(Locale::from_env)
+}
+
+/// Every user-facing string of the CLI, `{}` marks where arguments go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+// Every build uses a different subset, depending on the enabled features.
+#[allow(dead_code)]
+pub enum Message {
+    Usage,
+    InvalidUsage,
+    UnknownOption,
+    /// An option and what it wants as its value.
+    OptionExpects,
+    PositiveNumber,
+    Number,
+    Path,
+    ArchivePath,
+    ManifestPath,
+    Duration,
+    DigestName,
+    DelimiterName,
+    Revision,
+    TypeNames,
+    FormatNames,
+    ColorNames,
+    ExpectedPositional,
+    Error,
+    Skipped,
+    Interrupted,
+    TimeBudgetRanOut,
+    ResultLimit,
+    ScanLimit,
+    ScanCost,
+    ConfirmScan,
+    CantReadManifest,
+    CantReadOffsets,
+    CantWriteOffsets,
+    CantOpenSocket,
+    TruncatedVerify,
+    DelimiterWithMetrics,
+    TraceWithTraceOut,
+    ProfileWithTrace,
+    GasCostWithoutGas,
+    GasLeft,
+    AddedLinesWithoutRev,
+    ExamplesUsage,
+    UnknownExample,
+    NoSearch,
+    Allocations,
+    NoAllocStats,
+    Hours,
+    SoakUsage,
+    SoakTooLong,
+    SoakSeed,
+    SoakHeld,
+    SoakPanicked,
+    CantWriteTrace,
+    RunUsage,
+    CantReadProgram,
+    AssembleUsage,
+    DisassembleUsage,
+    CantWriteProgram,
+    VerifyDeterminismUsage,
+    Deterministic,
+    OpsLimit,
+    GasCost,
+    MapUsage,
+    CantReadCsv,
+    RowFailed,
+    ColumnName,
+    Reduced,
+    CantReadConfig,
+    CantWriteConfig,
+    BadBookmark,
+    UnknownBookmark,
+    BookmarkName,
+    UnknownConfigKey,
+    ConfigExpectsValue,
+    HookVetoed,
+    CorpusStatsUsage,
+    StatsUsage,
+    BenchSelfUsage,
+    BenchSearch,
+    BenchVm,
+    TestUsage,
+    TestReturned,
+    TestFailed,
+    TestPanicked,
+    TestSkipped,
+    TestSummary,
+    NoStatsFile,
+    CantReadStats,
+    DoctorUsage,
+    DoctorVersion,
+    DoctorVmOk,
+    DoctorVmFailed,
+    DoctorFsOk,
+    DoctorFsFailed,
+    DoctorLongPathOk,
+    DoctorLongPathFailed,
+    DoctorNoConfig,
+    DoctorConfigOk,
+    DoctorConfigFailed,
+    DoctorHookFailed,
+    DoctorSummary,
+    NewUsage,
+    UnknownTemplate,
+    CantCreateProject,
+    ProjectCreated,
+    TestUnexpected,
+    CantReadExpected,
+    UnknownFileType,
+    UnknownFormat,
+    UnknownColor,
+    Warning,
+}
+
+impl Message {
+    pub fn text(self, locale: Locale) -> &'static str {
+        match locale {
+            Locale::En => self.en(),
+            Locale::Ru => self.ru(),
+        }
+    }
+
+    fn en(self) -> &'static str {
+        match self {
+            Message::Usage => {
+                "\
+USAGE: testing [OPTIONS] [search] <dir> [<ext>]
+       testing examples list|show <name>|run <name>
+       testing run <file>
+       testing assemble <file> <out>
+       testing disassemble <file>
+       testing map <file> <csv> [--column NAME]
+       testing corpus-stats <dir>
+       testing stats self
+       testing bench-self
+       testing verify-determinism <file> [--runs N]
+       testing soak [--hours H] [--seed N]
+       testing test <dir> [--fail-fast-on-error] [--total-gas N]
+       testing doctor
+       testing new list|<template> <dir>
+
+OPTIONS:
+    --io-threads N      count lines on N threads
+    --max-open-files N  keep at most N files and directories open at once, default 256
+    --explain           print why each entry is included or excluded, count nothing
+    --skip-hidden       leave out hidden files and directories, and system files on Windows
+    --invert            select the files that don't match instead
+    --empty-dirs        list directories with no matching file below them, count nothing
+    --age               median and oldest line age from git blame per file and directory
+    --type T            select files by content: rust, script, binary or text
+    --collect FILE      also write matched files into a .tar or .tar.gz archive
+    --manifest sha256   print path, digest and line count of every matched file
+    --verify FILE       compare against a manifest, list added, deleted and modified files
+    --offsets FILE      keep where the count of each file ended in FILE and count only what
+                        was appended since, for logs; rewritten or rotated files start over
+    --metrics           also print line length and indentation statistics per file
+    --long-line N       lines over N bytes count as long in --metrics, default 100
+    --delimiter D       count records ended by D instead of lines: lf, cr, crlf, nul, nel,
+                        or bytes with \\n, \\r, \\t, \\0, \\\\ and \\xNN escapes
+    --estimate          estimate line counts of files over 16 MiB from samples, ± 95% margin
+    --records           count JSON array elements, CSV rows and notebook cells instead of
+                        lines in .json, .csv and .ipynb files
+    --since-rev REV     only files changed since the git revision REV, e.g. HEAD~10
+    --added-lines       with --since-rev, count the lines added since then instead
+    --estimate-cost     count the matching files and bytes first and ask before scanning,
+                        without a terminal print the count and stop
+    --max-time T        stop after T (e.g. 500ms, 10s, 2m), shallow directories go first
+    --max-results N     stop after reporting N files
+    --max-files-scanned N
+                        stop after looking at N files, matched or not
+    --fail-fast-on-error
+                        stop at the first error, also unreadable directories, with test
+                        skip the programs after the first that fails
+    --output-socket P   also stream results as JSON lines to the Unix socket or pipe at P
+    --format F          human, json, quiet or null
+    -0                  same as --format null, NUL-separated raw paths for xargs -0
+    --color WHEN        auto, always or never
+    --alloc-stats       report heap allocations of the run, needs the alloc-stats feature
+    --hours H           run soak for H hours (e.g. 0.5), default 1
+    --seed N            start soak from seed N to repeat an earlier run
+    --trace-out FILE    with run or examples run, write a Chrome trace of the run to FILE
+    --trace             with run or examples run, print every executed instruction to stderr
+    --profile           with run or examples run, also print how often each instruction ran
+    --runs N            how often verify-determinism runs the program, default 10
+    --max-ops N         stop programs after N instructions, or never with unlimited, default 1000
+    --max-stack N       fail programs whose stack grows past N values, or unlimited, default 1024
+    --gas N             give programs N gas, each instruction costs 1 to 16 by its kind
+    --total-gas N       with test, share N gas among all programs, skip them once it is spent
+    --gas-cost OP=N     with --gas, make instruction OP cost N, may be repeated
+    --column NAME       name of the column map appends, default result
+    --reduce FILE       fold the matched files with the program in FILE, see task4::reduce
+    --filter-prog FILE  keep files the program in FILE returns nonzero for, see task4::predicate
+    --save-as NAME      also save the search's arguments in the config as bookmark NAME
+    --use NAME          search with the arguments of bookmark NAME, then the ones given
+
+A directory named like a subcommand, e.g. test, is searched with `testing search test`.
+Hooks in testing.conf, or the file TESTING_CONFIG names, run before and after run and
+search, and stats-file there turns on local counts of subcommand runs, see config.rs. Messages follow TESTING_LANG, LC_ALL or LANG, en and ru are available."
+            }
+            Message::InvalidUsage => "invalid usage: {}",
+            Message::UnknownOption => "unknown option {}",
+            Message::OptionExpects => "{} expects {}",
+            Message::PositiveNumber => "a positive number",
+            Message::Number => "a number",
+            Message::Path => "a path",
+            Message::ArchivePath => "an archive path",
+            Message::ManifestPath => "a manifest path",
+            Message::Duration => "a duration like 10s",
+            Message::DigestName => "a digest, e.g. sha256",
+            Message::DelimiterName => "a delimiter: lf, cr, crlf, nul, nel or bytes like \\x1e",
+            Message::Revision => "a git revision, e.g. HEAD~10",
+            Message::TypeNames => "rust, script, binary or text",
+            Message::FormatNames => "human, json, quiet or null",
+            Message::ColorNames => "auto, always or never",
+            Message::ExpectedPositional => "expected <dir> and either <ext> or --type",
+            Message::Error => "error:",
+            Message::Skipped => "skipped: {}",
+            Message::Interrupted => "-- partial results: interrupted after {} files, {} lines --",
+            Message::TimeBudgetRanOut => {
+                "-- partial results: time budget ran out after {} files, {} lines --"
+            }
+            Message::ResultLimit => {
+                "-- partial results: --max-results reached after {} files, {} lines --"
+            }
+            Message::ScanLimit => {
+                "-- partial results: --max-files-scanned reached after {} files, {} lines --"
+            }
+            Message::CantReadManifest => "can't read manifest {}: {}",
+            Message::CantReadOffsets => "can't read offsets {}: {}",
+            Message::CantWriteOffsets => "can't write offsets {}: {}",
+            Message::CantOpenSocket => "can't open output socket {}: {}",
+            Message::TruncatedVerify => "can't verify a manifest against a truncated scan",
+            Message::DelimiterWithMetrics => "--metrics measures \\n lines, it can't go with --delimiter",
+            Message::TraceWithTraceOut => "--trace and --trace-out can't be used together",
+            Message::ProfileWithTrace => "--profile can't go with --trace or --trace-out",
+            Message::GasCostWithoutGas => "--gas-cost changes what --gas charges, give both",
+            Message::GasLeft => "gas left: {}",
+            Message::ScanCost => "{} files, {} bytes to read",
+            Message::ConfirmScan => "scan them? [y/N] ",
+            Message::AddedLinesWithoutRev => "--added-lines counts against --since-rev, give both",
+            Message::ExamplesUsage => "expected examples list, show <name> or run <name>",
+            Message::UnknownExample => "unknown example '{}', see `testing examples list`",
+            Message::NoSearch => "this build has no file search, rebuild with the `search` feature",
+            Message::Allocations => "{} allocations, {} bytes allocated, {} bytes still held",
+            Message::NoAllocStats => "--alloc-stats needs a build with the `alloc-stats` feature",
+            Message::Hours => "a positive number of hours",
+            Message::SoakUsage => "expected soak [--hours H] [--seed N]",
+            Message::SoakTooLong => "--hours {} is longer than this system can wait",
+            Message::SoakSeed => "soak test, seed {}",
+            Message::SoakHeld => "{}, heap {} bytes over the start",
+            Message::SoakPanicked => "this program made the VM panic:\n{}",
+            Message::CantWriteTrace => "can't write trace {}: {}",
+            Message::RunUsage => "expected run <file>",
+            Message::CantReadProgram => "can't read program {}: {}",
+            Message::AssembleUsage => "expected assemble <file> <out>",
+            Message::DisassembleUsage => "expected disassemble <file>",
+            Message::CantWriteProgram => "can't write program {}: {}",
+            Message::VerifyDeterminismUsage => "expected verify-determinism <file> [--runs N]",
+            Message::Deterministic => "{} runs gave the same result and trace: {}",
+            Message::OpsLimit => "a positive number or unlimited",
+            Message::GasCost => "an instruction and its cost, e.g. Divide=4",
+            Message::MapUsage => "expected map <file> <csv> [--column NAME]",
+            Message::CantReadCsv => "can't read table {}: {}",
+            Message::RowFailed => "row {}: {}",
+            Message::ColumnName => "a column name",
+            Message::Reduced => "reduced: {}",
+            Message::CantReadConfig => "can't read config {}: {}",
+            Message::CantWriteConfig => "can't write config {}: {}",
+            Message::BadBookmark => "bookmark {} has an unclosed quote or a trailing \\",
+            Message::UnknownBookmark => "no bookmark '{}', save one with --save-as",
+            Message::BookmarkName => "a bookmark name without spaces or =",
+            Message::UnknownConfigKey => "unknown setting '{}'",
+            Message::ConfigExpectsValue => "expected {} = <value>",
+            Message::HookVetoed => "hook {} returned 0, stopping",
+            Message::CorpusStatsUsage => "expected corpus-stats <dir>",
+            Message::StatsUsage => "expected stats self",
+            Message::BenchSelfUsage => "expected bench-self without arguments",
+            Message::BenchSearch => {
+                "search: {} files, {} lines, {} bytes in {} ms, {} files/s, {} MB/s"
+            }
+            Message::BenchVm => "vm {}: {} runs, {} instructions in {} ms, {} instructions/s",
+            Message::TestUsage => "expected test <dir>",
+            Message::TestReturned => "ok {}: {}",
+            Message::TestFailed => "{}: {}",
+            Message::TestPanicked => "{}: the VM panicked: {}",
+            Message::TestSkipped => "skipped {}",
+            Message::TestSummary => "{} returned, {} failed, {} skipped, {} gas",
+            Message::NoStatsFile => "usage statistics are off, set stats-file in testing.conf",
+            Message::CantReadStats => "can't read usage statistics {}: {}",
+            Message::DoctorUsage => "expected doctor without arguments",
+            Message::DoctorVersion => "testing {} on {} {}, features: {}",
+            Message::DoctorVmOk => "VM: assembled, encoded, decoded and ran a program, it returned {}",
+            Message::DoctorVmFailed => {
+                "VM: the smoke test gave {} instead of 42, this build is broken, please report it"
+            }
+            Message::DoctorFsOk => "file system: wrote, counted and removed files in {}",
+            Message::DoctorFsFailed => {
+                "file system: {}: {}, set TMPDIR (TEMP on Windows) to a writable directory"
+            }
+            Message::DoctorLongPathOk => "long paths: counted a file at a path of {} characters",
+            Message::DoctorLongPathFailed => {
+                "long paths: a path of {} characters failed: {}, deep trees won't be searched \
+                 whole, on Windows turn on LongPathsEnabled"
+            }
+            Message::DoctorNoConfig => "config: no {}, the defaults apply",
+            Message::DoctorConfigOk => "config: {} is valid, {} settings, {} bookmarks",
+            Message::DoctorConfigFailed => {
+                "config: {}, the settings are pre-run, post-run, pre-search, post-search, \
+                 stats-file and bookmark.<name>"
+            }
+            Message::DoctorHookFailed => "config: the {} hook doesn't load: {}",
+            Message::DoctorSummary => "{} ok, {} warnings, {} failed, paste all of this into bug reports",
+            Message::NewUsage => "expected new list or new <template> <dir>",
+            Message::UnknownTemplate => "unknown template '{}', see `testing new list`",
+            Message::CantCreateProject => "can't create {}: {}",
+            Message::ProjectCreated => {
+                "created {} from the {} template, run it with `testing test {}`"
+            }
+            Message::TestUnexpected => "{}: expected {}, returned {}",
+            Message::CantReadExpected => "can't read the expected value of {}: {}",
+            Message::UnknownFileType => "unknown type '{}', expected {}",
+            Message::UnknownFormat => "unknown format '{}', expected {}",
+            Message::UnknownColor => "unknown color choice '{}', expected {}",
+            Message::Warning => "warning:",
+        }
+    }
+
+    fn ru(self) -> &'static str {
+        match self {
+            Message::Usage => {
+                "\
+ВЫЗОВ: testing [ПАРАМЕТРЫ] [search] <каталог> [<расширение>]
+       testing examples list|show <имя>|run <имя>
+       testing run <файл>
+       testing assemble <файл> <выход>
+       testing disassemble <файл>
+       testing map <файл> <csv> [--column NAME]
+       testing corpus-stats <каталог>
+       testing stats self
+       testing bench-self
+       testing verify-determinism <файл> [--runs N]
+       testing soak [--hours H] [--seed N]
+       testing test <каталог> [--fail-fast-on-error] [--total-gas N]
+       testing doctor
+       testing new list|<шаблон> <каталог>
+
+ПАРАМЕТРЫ:
+    --io-threads N      считать строки в N потоков
+    --max-open-files N  держать открытыми не больше N файлов и каталогов, по умолчанию 256
+    --explain           показать, почему каждый путь включён или исключён, ничего не считать
+    --skip-hidden       пропускать скрытые файлы и каталоги, а в Windows и системные файлы
+    --invert            выбирать, наоборот, неподходящие файлы
+    --empty-dirs        перечислить каталоги без подходящих файлов внутри, ничего не считать
+    --age               медианный и самый старый возраст строк по git blame для файлов и каталогов
+    --type T            выбирать файлы по содержимому: rust, script, binary или text
+    --collect FILE      также сложить найденные файлы в архив .tar или .tar.gz
+    --manifest sha256   вывести путь, хеш и число строк каждого найденного файла
+    --verify FILE       сверить с манифестом, перечислить добавленные, удалённые и изменённые файлы
+    --offsets FILE      хранить в FILE, где закончился подсчёт каждого файла, и считать только
+                        дописанное с тех пор, для логов; перезаписанные файлы считаются заново
+    --metrics           также вывести статистику длины строк и отступов по каждому файлу
+    --long-line N       строки длиннее N байт считаются длинными в --metrics, по умолчанию 100
+    --delimiter D       считать вместо строк записи, оканчивающиеся на D: lf, cr, crlf, nul, nel
+                        или байты с экранированием \\n, \\r, \\t, \\0, \\\\ и \\xNN
+    --estimate          оценить число строк файлов больше 16 МиБ по выборке, ± при 95%
+    --records           считать элементы массивов JSON, строки CSV и ячейки блокнотов
+                        вместо строк в файлах .json, .csv и .ipynb
+    --since-rev REV     только файлы, изменённые после ревизии git REV, например HEAD~10
+    --added-lines       с --since-rev считать только строки, добавленные с тех пор
+    --estimate-cost     сначала подсчитать подходящие файлы и байты и спросить перед чтением,
+                        без терминала вывести подсчёт и остановиться
+    --max-time T        остановиться через T (например 500ms, 10s, 2m), неглубокие каталоги первыми
+    --max-results N     остановиться после N найденных файлов
+    --max-files-scanned N
+                        остановиться после просмотра N файлов, подходящих или нет
+    --fail-fast-on-error
+                        остановиться на первой ошибке, включая нечитаемые каталоги, с test
+                        пропустить программы после первой упавшей
+    --output-socket P   также передавать результаты строками JSON в Unix-сокет или канал P
+    --format F          human, json, quiet или null
+    -0                  то же, что --format null: пути как есть, через NUL, для xargs -0
+    --color WHEN        auto, always или never
+    --alloc-stats       сообщить о выделениях памяти за запуск, нужна функция alloc-stats
+    --hours H           выполнять soak H часов (например 0.5), по умолчанию 1
+    --seed N            начать soak с значения N, чтобы повторить прошлый запуск
+    --trace-out FILE    с run или examples run записать трассировку запуска в формате Chrome в FILE
+    --trace             с run или examples run печатать каждую выполненную инструкцию в stderr
+    --profile           с run или examples run также вывести, сколько раз выполнялась каждая инструкция
+    --runs N            сколько раз verify-determinism запускает программу, по умолчанию 10
+    --max-ops N         остановить программу после N инструкций, unlimited без предела, по умолчанию 1000
+    --max-stack N       прервать программу, чей стек больше N значений, unlimited без предела, по умолчанию 1024
+    --gas N             дать программе N газа, инструкция стоит от 1 до 16 в зависимости от вида
+    --total-gas N       с test, разделить N газа на все программы, пропустить остальные, когда он кончится
+    --gas-cost OP=N     с --gas назначить инструкции OP стоимость N, можно повторять
+    --column NAME       имя столбца, который добавляет map, по умолчанию result
+    --reduce FILE       свернуть найденные файлы программой из FILE, см. task4::reduce
+    --filter-prog FILE  оставить файлы, для которых программа из FILE вернула не ноль, см. task4::predicate
+    --save-as NAME      также сохранить аргументы поиска в настройках как закладку NAME
+    --use NAME          искать с аргументами закладки NAME, затем с указанными
+
+Каталог с именем подкоманды, например test, ищется командой `testing search test`.
+Хуки из testing.conf или файла из TESTING_CONFIG выполняются до и после run и search,
+а stats-file там включает локальный подсчёт запусков подкоманд, см. config.rs. Язык сообщений берётся из TESTING_LANG, LC_ALL или LANG, доступны en и ru."
+            }
+            Message::InvalidUsage => "неверный вызов: {}",
+            Message::UnknownOption => "неизвестный параметр {}",
+            Message::OptionExpects => "{} ожидает {}",
+            Message::PositiveNumber => "положительное число",
+            Message::Number => "число",
+            Message::Path => "путь",
+            Message::ArchivePath => "путь к архиву",
+            Message::ManifestPath => "путь к манифесту",
+            Message::Duration => "длительность, например 10s",
+            Message::DigestName => "алгоритм хеширования, например sha256",
+            Message::DelimiterName => "разделитель: lf, cr, crlf, nul, nel или байты вроде \\x1e",
+            Message::Revision => "ревизия git, например HEAD~10",
+            Message::TypeNames => "rust, script, binary или text",
+            Message::FormatNames => "human, json, quiet или null",
+            Message::ColorNames => "auto, always или never",
+            Message::ExpectedPositional => "ожидается <каталог> и либо <расширение>, либо --type",
+            Message::Error => "ошибка:",
+            Message::Skipped => "пропущено: {}",
+            Message::Interrupted => "-- частичные результаты: прервано, файлов: {}, строк: {} --",
+            Message::TimeBudgetRanOut => {
+                "-- частичные результаты: время вышло, файлов: {}, строк: {} --"
+            }
+            Message::ResultLimit => {
+                "-- частичные результаты: достигнут --max-results, файлов: {}, строк: {} --"
+            }
+            Message::ScanLimit => {
+                "-- частичные результаты: достигнут --max-files-scanned, файлов: {}, строк: {} --"
+            }
+            Message::CantReadManifest => "не удалось прочитать манифест {}: {}",
+            Message::CantReadOffsets => "не удалось прочитать смещения {}: {}",
+            Message::CantWriteOffsets => "не удалось записать смещения {}: {}",
+            Message::CantOpenSocket => "не удалось открыть сокет вывода {}: {}",
+            Message::TruncatedVerify => "манифест нельзя сверить с неполным обходом",
+            Message::DelimiterWithMetrics => {
+                "--metrics измеряет строки до \\n и не сочетается с --delimiter"
+            }
+            Message::TraceWithTraceOut => "--trace и --trace-out нельзя использовать вместе",
+            Message::ProfileWithTrace => "--profile не сочетается с --trace и --trace-out",
+            Message::GasCostWithoutGas => "--gas-cost меняет цены для --gas, укажите оба",
+            Message::GasLeft => "осталось газа: {}",
+            Message::ScanCost => "файлов: {}, байт для чтения: {}",
+            Message::ConfirmScan => "сканировать? [y/N] ",
+            Message::AddedLinesWithoutRev => {
+                "--added-lines считает относительно --since-rev, укажите оба"
+            }
+            Message::ExamplesUsage => "ожидается examples list, show <имя> или run <имя>",
+            Message::UnknownExample => "нет примера '{}', см. `testing examples list`",
+            Message::NoSearch => "эта сборка без поиска файлов, пересоберите с функцией `search`",
+            Message::Allocations => "выделений: {}, выделено байт: {}, осталось занято: {}",
+            Message::NoAllocStats => {
+                "--alloc-stats работает только в сборке с функцией `alloc-stats`"
+            }
+            Message::Hours => "положительное число часов",
+            Message::SoakUsage => "ожидается soak [--hours H] [--seed N]",
+            Message::SoakTooLong => "--hours {}: система не может ждать так долго",
+            Message::SoakSeed => "нагрузочная проверка, начальное значение {}",
+            Message::SoakHeld => "{}, куча больше начальной на {} байт",
+            Message::SoakPanicked => "эта программа вызвала панику в VM:\n{}",
+            Message::CantWriteTrace => "не удалось записать трассировку {}: {}",
+            Message::RunUsage => "ожидается run <файл>",
+            Message::CantReadProgram => "не удалось прочитать программу {}: {}",
+            Message::AssembleUsage => "ожидается assemble <файл> <выход>",
+            Message::DisassembleUsage => "ожидается disassemble <файл>",
+            Message::CantWriteProgram => "не удалось записать программу {}: {}",
+            Message::VerifyDeterminismUsage => "ожидается verify-determinism <файл> [--runs N]",
+            Message::Deterministic => "запусков: {}, результат и трассировка совпали: {}",
+            Message::OpsLimit => "положительное число или unlimited",
+            Message::GasCost => "инструкция и её стоимость, например Divide=4",
+            Message::MapUsage => "ожидается map <файл> <csv> [--column NAME]",
+            Message::CantReadCsv => "не удалось прочитать таблицу {}: {}",
+            Message::RowFailed => "строка {}: {}",
+            Message::ColumnName => "имя столбца",
+            Message::Reduced => "свёртка: {}",
+            Message::CantReadConfig => "не удалось прочитать настройки {}: {}",
+            Message::CantWriteConfig => "не удалось записать настройки {}: {}",
+            Message::BadBookmark => "в закладке {} не закрыта кавычка или в конце стоит \\",
+            Message::UnknownBookmark => "нет закладки '{}', сохраните её с --save-as",
+            Message::BookmarkName => "имя закладки без пробелов и =",
+            Message::UnknownConfigKey => "неизвестная настройка '{}'",
+            Message::ConfigExpectsValue => "ожидается {} = <значение>",
+            Message::HookVetoed => "хук {} вернул 0, остановка",
+            Message::CorpusStatsUsage => "ожидается corpus-stats <каталог>",
+            Message::StatsUsage => "ожидается stats self",
+            Message::BenchSelfUsage => "ожидается bench-self без аргументов",
+            Message::BenchSearch => {
+                "поиск: файлов {}, строк {}, байт {} за {} мс, {} файлов/с, {} МБ/с"
+            }
+            Message::BenchVm => {
+                "vm {}: запусков {}, инструкций {} за {} мс, {} инструкций/с"
+            }
+            Message::TestUsage => "ожидается test <каталог>",
+            Message::TestReturned => "ok {}: {}",
+            Message::TestFailed => "{}: {}",
+            Message::TestPanicked => "{}: VM упала: {}",
+            Message::TestSkipped => "пропущено {}",
+            Message::TestSummary => "вернули значение {}, упали {}, пропущено {}, газа {}",
+            Message::NoStatsFile => "статистика запусков выключена, задайте stats-file в testing.conf",
+            Message::CantReadStats => "не удалось прочитать статистику запусков {}: {}",
+            Message::DoctorUsage => "ожидается doctor без аргументов",
+            Message::DoctorVersion => "testing {} на {} {}, возможности: {}",
+            Message::DoctorVmOk => {
+                "VM: программа собрана, закодирована, раскодирована и вернула {}"
+            }
+            Message::DoctorVmFailed => {
+                "VM: проверочная программа дала {} вместо 42, сборка неисправна, сообщите об этом"
+            }
+            Message::DoctorFsOk => "файловая система: файлы в {} записаны, посчитаны и удалены",
+            Message::DoctorFsFailed => {
+                "файловая система: {}: {}, укажите в TMPDIR (TEMP в Windows) каталог, \
+                 доступный для записи"
+            }
+            Message::DoctorLongPathOk => "длинные пути: посчитан файл по пути из {} символов",
+            Message::DoctorLongPathFailed => {
+                "длинные пути: путь из {} символов не работает: {}, глубокие деревья не будут \
+                 обойдены целиком, в Windows включите LongPathsEnabled"
+            }
+            Message::DoctorNoConfig => "настройки: {} нет, действуют значения по умолчанию",
+            Message::DoctorConfigOk => "настройки: {} в порядке, настроек {}, закладок {}",
+            Message::DoctorConfigFailed => {
+                "настройки: {}, допустимы pre-run, post-run, pre-search, post-search, \
+                 stats-file и bookmark.<имя>"
+            }
+            Message::DoctorHookFailed => "настройки: хук {} не загружается: {}",
+            Message::DoctorSummary => {
+                "ok {}, предупреждений {}, ошибок {}; приложите весь этот вывод к сообщению об ошибке"
+            }
+            Message::NewUsage => "ожидается new list или new <шаблон> <каталог>",
+            Message::UnknownTemplate => "нет шаблона '{}', см. `testing new list`",
+            Message::CantCreateProject => "не удалось создать {}: {}",
+            Message::ProjectCreated => {
+                "{} создан по шаблону {}, запустите его командой `testing test {}`"
+            }
+            Message::TestUnexpected => "{}: ожидалось {}, вернула {}",
+            Message::CantReadExpected => "не удалось прочитать ожидаемое значение {}: {}",
+            Message::UnknownFileType => "неизвестный тип '{}', ожидается {}",
+            Message::UnknownFormat => "неизвестный формат '{}', ожидается {}",
+            Message::UnknownColor => "неизвестный режим цвета '{}', ожидается {}",
+            Message::Warning => "предупреждение:",
+        }
+    }
+}
+
+/// The message in the current locale.
+pub fn text(msg: Message) -> &'static str {
+    msg.text(locale())
+}
+
+/// The message in the current locale with each `{}` replaced by the next of `args`.
+pub fn message(msg: Message, args: &[&dyn fmt::Display]) -> String {
+    fill(text(msg), args)
+}
+
+fn fill(template: &str, args: &[&dyn fmt::Display]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut args = args.iter();
+    let mut parts = template.split("{}");
+    out.push_str(parts.next().unwrap_or_default());
+    for part in parts {
+        if let Some(arg) = args.next() {
+            out.push_str(&arg.to_string());
+        }
+        out.push_str(part);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::i18n::{fill, Locale, Message};
+
+    #[test]
+    fn picks_locale_from_names() {
+        assert_eq!(Locale::parse("ru_RU.UTF-8"), Locale::Ru);
+        assert_eq!(Locale::parse("RU"), Locale::Ru);
+        assert_eq!(Locale::parse("en-GB"), Locale::En);
+        assert_eq!(Locale::parse("C"), Locale::En);
+    }
+
+    #[test]
+    fn fills_placeholders_in_order() {
+        let template = Message::OptionExpects.text(Locale::Ru);
+        assert_eq!(
+            fill(template, &[&"--io-threads", &"число"]),
+            "--io-threads ожидает число"
+        );
+        assert_eq!(fill("{} and {}", &[&1]), "1 and ");
+    }
+}
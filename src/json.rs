@@ -0,0 +1,218 @@
+use std::fmt::Write as _;
+#[cfg(feature = "search")]
+use std::{
+    fs::OpenOptions,
+    io::{self, LineWriter, Write},
+    path::Path,
+};
+
+#[cfg(feature = "search")]
+use crate::task4::{FileLines, SearchSummary};
+
+/// Streams results as JSON lines, one object per matched file and a summary at the end.
+///
+/// Every record is flushed right away so readers can render while the walk goes on.
+#[cfg(feature = "search")]
+pub struct JsonLines<W: Write> {
+    out: LineWriter<W>,
+}
+
+#[cfg(feature = "search")]
+impl JsonLines<Box<dyn Write + Send>> {
+    /// Connects to the Unix socket at `path`, anything else (a named pipe) is opened for writing.
+    pub fn connect(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        #[cfg(unix)]
+        {
+            use std::os::unix::{fs::FileTypeExt, net::UnixStream};
+
+            if std::fs::metadata(path)?.file_type().is_socket() {
+                return Ok(Self::new(Box::new(UnixStream::connect(path)?)));
+            }
+        }
+        Ok(Self::new(Box::new(
+            OpenOptions::new().write(true).open(path)?,
+        )))
+    }
+}
+
+#[cfg(feature = "search")]
+impl<W: Write> JsonLines<W> {
+    pub fn new(writer: W) -> Self {
+        JsonLines {
+            out: LineWriter::new(writer),
+        }
+    }
+
+    pub fn file(&mut self, file: &FileLines) -> io::Result<()> {
+        writeln!(self.out, "{}", file_json(file))
+    }
+
+    pub fn summary(&mut self, summary: &SearchSummary) -> io::Result<()> {
+        writeln!(self.out, "{}", summary_json(summary))
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        self.out.into_inner().map_err(|e| e.into_error())
+    }
+}
+
+#[cfg(feature = "search")]
+pub fn file_json(file: &FileLines) -> String {
+    let mut json = format!("{{\"path\":{},\"lines\":{}", path(&file.path), file.lines);
+    if let Some(digest) = &file.digest {
+        let _ = write!(json, ",\"digest\":{}", string(digest));
+    }
+    if let Some(m) = &file.metrics {
+        let _ = write!(
+            json,
+            ",\"metrics\":{{\"max_len\":{},\"avg_len\":{},\"long_lines\":{},\
+             \"max_indent\":{},\"avg_indent\":{}}}",
+            m.max_len,
+            m.avg_len(),
+            m.long_lines,
+            m.max_indent,
+            m.avg_indent()
+        );
+    }
+    if let Some(e) = &file.estimate {
+        let _ = write!(
+            json,
+            ",\"estimate\":{{\"margin\":{},\"sampled\":{}}}",
+            e.margin, e.sampled
+        );
+    }
+    json.push('}');
+    json
+}
+
+#[cfg(feature = "search")]
+pub fn summary_json(summary: &SearchSummary) -> String {
+    let limit = summary
+        .limit
+        .map_or("null".to_owned(), |limit| string(limit.name()));
+    format!(
+        "{{\"summary\":{{\"files\":{},\"lines\":{},\"interrupted\":{},\"truncated\":{},\"limit\":{}}}}}",
+        summary.files, summary.lines, summary.interrupted, summary.truncated, limit
+    )
+}
+
+/// A JSON string literal for `s`.
+pub fn string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    push_escaped(&mut quoted, s);
+    quoted.push('"');
+    quoted
+}
+
+/// A JSON string literal for `path` that keeps every byte: bytes that aren't UTF-8 become
+/// the lone surrogates `\udc80` to `\udcff`, as Python's `surrogateescape` does.
+#[cfg(feature = "search")]
+pub fn path(path: &Path) -> String {
+    let path = crate::task4::fs::simplified(path);
+    let bytes = path.as_os_str().as_encoded_bytes();
+    let mut quoted = String::with_capacity(bytes.len() + 2);
+    quoted.push('"');
+    for chunk in bytes.utf8_chunks() {
+        push_escaped(&mut quoted, chunk.valid());
+        for byte in chunk.invalid() {
+            let _ = write!(quoted, "\\u{:04x}", 0xdc00 | u16::from(*byte));
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn push_escaped(quoted: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::json::string;
+    #[cfg(feature = "search")]
+    use crate::{
+        json::JsonLines,
+        task4::{FileLines, SearchSummary},
+    };
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(string("a\"b\\c\nd\u{1}é"), r#""a\"b\\c\nd\u0001é""#);
+    }
+
+    #[cfg(all(unix, feature = "search"))]
+    #[test]
+    fn keeps_every_byte_of_paths() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::Path};
+
+        let name = Path::new(OsStr::from_bytes(b"caf\xe9 \"\xff.rs"));
+        assert_eq!(crate::json::path(name), r#""caf\udce9 \"\udcff.rs""#);
+        assert_eq!(crate::json::path(Path::new("é.rs")), r#""é.rs""#);
+    }
+
+    #[cfg(feature = "search")]
+    #[test]
+    fn writes_one_record_per_line() {
+        let mut out = JsonLines::new(vec![]);
+        out.file(&FileLines {
+            path: "dir/a.rs".into(),
+            lines: 3,
+            digest: Some("ab".to_owned()),
+            metrics: None,
+            estimate: None,
+        })
+        .unwrap();
+        out.summary(&SearchSummary {
+            files: 1,
+            lines: 3,
+            interrupted: false,
+            truncated: true,
+            limit: None,
+        })
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(out.finish().unwrap()).unwrap(),
+            "{\"path\":\"dir/a.rs\",\"lines\":3,\"digest\":\"ab\"}\n\
+             {\"summary\":{\"files\":1,\"lines\":3,\"interrupted\":false,\"truncated\":true,\"limit\":null}}\n"
+        );
+    }
+
+    #[cfg(all(unix, feature = "search"))]
+    #[test]
+    fn streams_to_a_unix_socket() {
+        use std::{io::Read, os::unix::net::UnixListener};
+
+        let dir = std::env::temp_dir().join(format!("testing-json-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("results.sock");
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+
+        let mut out = JsonLines::connect(&socket).unwrap();
+        out.summary(&SearchSummary::default()).unwrap();
+        drop(out);
+        let mut received = String::new();
+        listener
+            .accept()
+            .unwrap()
+            .0
+            .read_to_string(&mut received)
+            .unwrap();
+        assert!(received.starts_with("{\"summary\":"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
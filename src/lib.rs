@@ -0,0 +1,24 @@
+//! A line-counting file search and a small stack-machine interpreter, the `testing`
+//! binary is a command line around them.
+//!
+//! ```
+//! use testing::{run, task_1_and_2::asm, Value};
+//!
+//! let bytecode = asm::parse("LoadVal 2\nLoadVal 3\nMultiply\nReturnValue").unwrap();
+//! assert_eq!(run(bytecode), Ok(Value::Int(6)));
+//! let bytecode = asm::parse("LoadVal 2\nLoadVal 1.5\nMultiply\nReturnValue").unwrap();
+//! assert_eq!(run(bytecode), Ok(Value::Float(3.0)));
+//! ```
+#![deny(unsafe_code)]
+
+pub mod json;
+#[cfg(feature = "search")]
+pub mod task4;
+pub mod task_1_and_2;
+
+#[cfg(feature = "search")]
+pub use task4::{FileLines, FileType, Filter, Search, SearchBuilder, SearchSummary};
+pub use task_1_and_2::{
+    program::Program, run, run_with_config, Bytecode, Instruction, InterpretationError, Value,
+    ValueType, VmConfig,
+};
@@ -1,15 +1,1349 @@
-use std::env;
+// Only `alloc_stats` may use unsafe code, the library denies it as well.
+#![deny(unsafe_code)]
+
+#[cfg(feature = "search")]
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use std::{
+    env, io,
+    path::Path,
+    process,
+    time::{Duration, Instant, SystemTime},
+};
 
 use anyhow::anyhow;
 
-mod task4;
-mod task_1_and_2;
+use i18n::Message;
+use report::{ColorChoice, Format, Reporter, UsageError};
+use testing::task_1_and_2::{self, Value};
+#[cfg(feature = "search")]
+use testing::{json, task4, task_1_and_2::ValueType};
+
+#[cfg(feature = "alloc-stats")]
+mod alloc_stats;
+mod bench;
+mod config;
+mod doctor;
+mod i18n;
+mod report;
+mod scaffold;
+mod usage;
+
+/// Exit code when the search was stopped by Ctrl-C, same as shells use for SIGINT.
+#[cfg(feature = "search")]
+const EXIT_INTERRUPTED: i32 = 130;
+/// Exit code when `--verify` found differences.
+#[cfg(feature = "search")]
+const EXIT_CHANGED: i32 = 1;
+const EXIT_FAILURE: i32 = 1;
+const DEFAULT_LONG_LINE: usize = 100;
+/// Files over this many bytes are sampled with `--estimate`.
+#[cfg(feature = "search")]
+const ESTIMATE_ABOVE: u64 = 16 * 1024 * 1024;
+const DEFAULT_RUNS: usize = 10;
+const DEFAULT_COLUMN: &str = "result";
+const SOAK_BATCH: u64 = 1_000;
+/// How often `soak` prints where it stands.
+const SOAK_PROGRESS: Duration = Duration::from_secs(10);
+
+#[cfg(feature = "alloc-stats")]
+#[global_allocator]
+static GLOBAL: alloc_stats::CountingAllocator = alloc_stats::CountingAllocator;
+
+/// The parsed command line, search options are checked once a search actually runs.
+#[cfg_attr(not(feature = "search"), allow(dead_code))]
+struct Options {
+    io_threads: Option<usize>,
+    max_open_files: Option<usize>,
+    explain: bool,
+    skip_hidden: bool,
+    invert: bool,
+    empty_dirs: bool,
+    age: bool,
+    file_type: Option<String>,
+    collect: Option<String>,
+    digest: Option<String>,
+    verify: Option<String>,
+    offsets: Option<String>,
+    metrics: bool,
+    estimate: bool,
+    records: bool,
+    estimate_cost: bool,
+    delimiter: Option<String>,
+    long_line: usize,
+    max_time: Option<Duration>,
+    max_results: Option<usize>,
+    max_files_scanned: Option<usize>,
+    fail_fast: bool,
+    output_socket: Option<String>,
+    format: Format,
+    color: ColorChoice,
+    alloc_stats: bool,
+    hours: Option<f64>,
+    seed: Option<u64>,
+    trace_out: Option<String>,
+    trace: bool,
+    profile: bool,
+    gas_costs: Option<task_1_and_2::gas::GasTable>,
+    runs: Option<usize>,
+    total_gas: Option<u64>,
+    column: Option<String>,
+    reduce: Option<String>,
+    filter_prog: Option<String>,
+    since_rev: Option<String>,
+    added_lines: bool,
+    save_as: Option<String>,
+    use_bookmark: Option<String>,
+    /// The arguments without `--save-as` and `--use`, what a bookmark keeps.
+    args: Vec<String>,
+    vm: task_1_and_2::VmConfig,
+    positional: Vec<String>,
+}
+
+fn main() {
+    let code = match parse_command_line(env::args().skip(1).collect()) {
+        Ok(options) => {
+            let mut reporter = report::reporter(options.format, options.color);
+            run(options, reporter.as_mut()).unwrap_or_else(|err| {
+                // Output piped into `head` and the like, nobody is left to tell.
+                let closed = err
+                    .downcast_ref::<io::Error>()
+                    .is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe);
+                if !closed {
+                    reporter.error(&err);
+                }
+                EXIT_FAILURE
+            })
+        }
+        Err(err) => {
+            report::reporter(Format::Human, ColorChoice::Auto).error(&err);
+            EXIT_FAILURE
+        }
+    };
+    process::exit(code);
+}
+
+/// Parses `args`, the arguments saved in the bookmark `--use` names go before them.
+fn parse_command_line(args: Vec<String>) -> Result<Options, anyhow::Error> {
+    let options = parse_args(args.iter().cloned())?;
+    let Some(name) = &options.use_bookmark else {
+        return Ok(options);
+    };
+    let config = config::Config::load()?;
+    let saved = config
+        .bookmarks
+        .get(name)
+        .ok_or_else(|| anyhow!(i18n::message(Message::UnknownBookmark, &[name])))?;
+    parse_args(saved.iter().chain(&args).cloned())
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Result<Options, anyhow::Error> {
+    let all: Vec<_> = args.collect();
+    let mut args = all.iter().cloned();
+    let mut options = Options {
+        io_threads: None,
+        max_open_files: None,
+        explain: false,
+        skip_hidden: false,
+        invert: false,
+        empty_dirs: false,
+        age: false,
+        file_type: None,
+        collect: None,
+        digest: None,
+        verify: None,
+        offsets: None,
+        metrics: false,
+        estimate: false,
+        records: false,
+        estimate_cost: false,
+        delimiter: None,
+        long_line: DEFAULT_LONG_LINE,
+        max_time: None,
+        max_results: None,
+        max_files_scanned: None,
+        fail_fast: false,
+        output_socket: None,
+        format: Format::default(),
+        color: ColorChoice::default(),
+        alloc_stats: false,
+        hours: None,
+        seed: None,
+        trace_out: None,
+        trace: false,
+        profile: false,
+        gas_costs: None,
+        runs: None,
+        total_gas: None,
+        column: None,
+        reduce: None,
+        filter_prog: None,
+        since_rev: None,
+        added_lines: false,
+        save_as: None,
+        use_bookmark: None,
+        args: bookmarkable(&all),
+        vm: task_1_and_2::VmConfig::default(),
+        positional: vec![],
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--io-threads" => {
+                options.io_threads = Some(
+                    args.next()
+                        .and_then(|n| n.parse().ok())
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| expects("--io-threads", Message::PositiveNumber))?,
+                );
+            }
+            "--max-open-files" => {
+                options.max_open_files = Some(
+                    args.next()
+                        .and_then(|n| n.parse().ok())
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| expects("--max-open-files", Message::PositiveNumber))?,
+                );
+            }
+            "--explain" => options.explain = true,
+            "--skip-hidden" => options.skip_hidden = true,
+            "--invert" => options.invert = true,
+            "--empty-dirs" => options.empty_dirs = true,
+            "--age" => options.age = true,
+            "--metrics" => options.metrics = true,
+            "--estimate" => options.estimate = true,
+            "--records" => options.records = true,
+            "--estimate-cost" => options.estimate_cost = true,
+            "--alloc-stats" => options.alloc_stats = true,
+            "--hours" => {
+                options.hours = Some(
+                    args.next()
+                        .and_then(|n| n.parse().ok())
+                        .filter(|&n: &f64| n > 0.0 && n.is_finite())
+                        .ok_or_else(|| expects("--hours", Message::Hours))?,
+                );
+            }
+            "--trace" => options.trace = true,
+            "--profile" => options.profile = true,
+            "--trace-out" => {
+                options.trace_out = Some(
+                    args.next()
+                        .ok_or_else(|| expects("--trace-out", Message::Path))?,
+                );
+            }
+            "--max-ops" => options.vm.max_ops = parse_limit("--max-ops", args.next())?,
+            "--max-stack" => options.vm.max_stack = parse_limit("--max-stack", args.next())?,
+            flag @ ("--save-as" | "--use") => {
+                let name = args
+                    .next()
+                    .filter(|name| {
+                        !name.is_empty() && !name.contains(|c: char| c == '=' || c.is_whitespace())
+                    })
+                    .ok_or_else(|| expects(flag, Message::BookmarkName))?;
+                if flag == "--use" {
+                    options.use_bookmark = Some(name);
+                } else {
+                    options.save_as = Some(name);
+                }
+            }
+            "--gas" => {
+                let limit = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| expects("--gas", Message::PositiveNumber))?;
+                options.vm.gas = Some(task_1_and_2::gas::Gas::new(limit));
+            }
+            "--gas-cost" => {
+                let costs = options.gas_costs.get_or_insert_with(Default::default);
+                let set = args.next().and_then(|cost| {
+                    let (name, cost) = cost.split_once('=')?;
+                    costs.set(name, cost.parse().ok()?).then_some(())
+                });
+                set.ok_or_else(|| expects("--gas-cost", Message::GasCost))?;
+            }
+            "--runs" => {
+                options.runs = Some(
+                    args.next()
+                        .and_then(|n| n.parse().ok())
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| expects("--runs", Message::PositiveNumber))?,
+                );
+            }
+            "--total-gas" => {
+                options.total_gas = Some(
+                    args.next()
+                        .and_then(|n| n.parse().ok())
+                        .filter(|&n| n > 0)
+                        .ok_or_else(|| expects("--total-gas", Message::PositiveNumber))?,
+                );
+            }
+            "--column" => {
+                options.column = Some(
+                    args.next()
+                        .ok_or_else(|| expects("--column", Message::ColumnName))?,
+                );
+            }
+            "--reduce" => {
+                options.reduce = Some(
+                    args.next()
+                        .ok_or_else(|| expects("--reduce", Message::Path))?,
+                );
+            }
+            "--since-rev" => {
+                options.since_rev = Some(
+                    args.next()
+                        .ok_or_else(|| expects("--since-rev", Message::Revision))?,
+                );
+            }
+            "--added-lines" => options.added_lines = true,
+            "--filter-prog" => {
+                options.filter_prog = Some(
+                    args.next()
+                        .ok_or_else(|| expects("--filter-prog", Message::Path))?,
+                );
+            }
+            "--seed" => {
+                options.seed = Some(
+                    args.next()
+                        .and_then(|n| n.parse().ok())
+                        .ok_or_else(|| expects("--seed", Message::Number))?,
+                );
+            }
+            "--output-socket" => {
+                options.output_socket = Some(
+                    args.next()
+                        .ok_or_else(|| expects("--output-socket", Message::Path))?,
+                );
+            }
+            "--max-time" => {
+                options.max_time = Some(
+                    args.next()
+                        .as_deref()
+                        .and_then(parse_duration)
+                        .ok_or_else(|| expects("--max-time", Message::Duration))?,
+                );
+            }
+            "--max-results" => {
+                options.max_results = Some(
+                    args.next()
+                        .and_then(|n| n.parse().ok())
+                        .ok_or_else(|| expects("--max-results", Message::Number))?,
+                );
+            }
+            "--max-files-scanned" => {
+                options.max_files_scanned = Some(
+                    args.next()
+                        .and_then(|n| n.parse().ok())
+                        .ok_or_else(|| expects("--max-files-scanned", Message::Number))?,
+                );
+            }
+            "--fail-fast-on-error" => options.fail_fast = true,
+            "--long-line" => {
+                options.long_line = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .ok_or_else(|| expects("--long-line", Message::Number))?;
+            }
+            "--collect" => {
+                options.collect = Some(
+                    args.next()
+                        .ok_or_else(|| expects("--collect", Message::ArchivePath))?,
+                );
+            }
+            "--delimiter" => {
+                options.delimiter = Some(
+                    args.next()
+                        .ok_or_else(|| expects("--delimiter", Message::DelimiterName))?,
+                );
+            }
+            "--manifest" => {
+                let kind = args
+                    .next()
+                    .ok_or_else(|| expects("--manifest", Message::DigestName))?;
+                options.digest = Some(kind);
+            }
+            "--verify" => {
+                options.verify = Some(
+                    args.next()
+                        .ok_or_else(|| expects("--verify", Message::ManifestPath))?,
+                );
+            }
+            "--offsets" => {
+                options.offsets = Some(
+                    args.next()
+                        .ok_or_else(|| expects("--offsets", Message::Path))?,
+                );
+            }
+            "--type" => {
+                let name = args
+                    .next()
+                    .ok_or_else(|| expects("--type", Message::TypeNames))?;
+                options.file_type = Some(name);
+            }
+            "--format" => {
+                let name = args
+                    .next()
+                    .ok_or_else(|| expects("--format", Message::FormatNames))?;
+                options.format = name.parse()?;
+            }
+            "-0" => options.format = Format::Null,
+            "--color" => {
+                let when = args
+                    .next()
+                    .ok_or_else(|| expects("--color", Message::ColorNames))?;
+                options.color = when.parse()?;
+            }
+            flag if flag.starts_with("--") => {
+                return Err(usage_error(&i18n::message(
+                    Message::UnknownOption,
+                    &[&flag],
+                )))
+            }
+            _ => options.positional.push(arg),
+        }
+    }
+    if let Some(costs) = options.gas_costs {
+        let gas = options
+            .vm
+            .gas
+            .as_mut()
+            .ok_or_else(|| usage_error(i18n::text(Message::GasCostWithoutGas)))?;
+        gas.costs = costs;
+    }
+    Ok(options)
+}
+
+/// `args` without `--save-as` and `--use` and the names after them.
+fn bookmarkable(args: &[String]) -> Vec<String> {
+    let mut kept = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if matches!(arg.as_str(), "--save-as" | "--use") {
+            args.next();
+        } else {
+            kept.push(arg.clone());
+        }
+    }
+    kept
+}
+
+fn run(mut options: Options, reporter: &mut dyn Reporter) -> Result<i32, anyhow::Error> {
+    #[cfg(not(feature = "alloc-stats"))]
+    if options.alloc_stats {
+        return Err(anyhow!(i18n::text(Message::NoAllocStats)));
+    }
+    #[cfg(feature = "alloc-stats")]
+    let before = options.alloc_stats.then(alloc_stats::AllocStats::now);
+
+    let started = Instant::now();
+    let command = match options.positional.first().map(String::as_str) {
+        Some(
+            command @ ("examples" | "soak" | "run" | "assemble" | "disassemble" | "map"
+            | "verify-determinism" | "corpus-stats" | "stats" | "bench-self" | "test"
+            | "doctor" | "new"),
+        ) => command.to_owned(),
+        // Spelled out, the directory after it may be named like a subcommand.
+        Some("search") => {
+            options.positional.remove(0);
+            "search".to_owned()
+        }
+        _ => "search".to_owned(),
+    };
+    // The doctor reports a broken config instead of failing on it.
+    let config = match command.as_str() {
+        "doctor" => config::Config::default(),
+        _ => config::Config::load()?,
+    };
+    let code = match command.as_str() {
+        "examples" => examples(&options, reporter),
+        "soak" => soak(&options, reporter),
+        "run" => run_file(&options, &config, reporter),
+        "assemble" => assemble(&options),
+        "disassemble" => disassemble(&options, reporter),
+        "map" => map_csv(&options, reporter),
+        "verify-determinism" => verify_determinism(&options, reporter),
+        "corpus-stats" => corpus_stats(&options, reporter),
+        "stats" => show_usage(&options, &config, reporter),
+        "bench-self" => bench_self(&options, reporter),
+        "test" => test_programs(&options, reporter),
+        "doctor" => doctor(&options, reporter),
+        "new" => new_project(&options, reporter),
+        _ => search(options, &config, reporter),
+    };
+    if let (Some(path), false) = (&config.stats_file, command == "stats") {
+        // Losing a count isn't worth failing the command over.
+        let _ = usage::Usage::record(path, &command, started.elapsed());
+    }
+    let code = code?;
+
+    #[cfg(feature = "alloc-stats")]
+    if let Some(before) = before {
+        let used = alloc_stats::AllocStats::now().since(&before);
+        reporter.note(&i18n::message(
+            Message::Allocations,
+            &[&used.allocations, &used.allocated, &used.growth()],
+        ))?;
+    }
+    Ok(code)
+}
+
+#[cfg(not(feature = "search"))]
+fn search(
+    _options: Options,
+    _config: &config::Config,
+    _reporter: &mut dyn Reporter,
+) -> Result<i32, anyhow::Error> {
+    Err(anyhow!(i18n::text(Message::NoSearch)))
+}
+
+#[cfg(feature = "search")]
+fn search(
+    options: Options,
+    config: &config::Config,
+    reporter: &mut dyn Reporter,
+) -> Result<i32, anyhow::Error> {
+    let positional = &options.positional;
+    let file_type = options
+        .file_type
+        .as_deref()
+        .map(|name| {
+            name.parse::<task4::FileType>().map_err(|_| {
+                anyhow!(i18n::message(
+                    Message::UnknownFileType,
+                    &[&name, &i18n::text(Message::TypeNames)]
+                ))
+            })
+        })
+        .transpose()?;
+    let mut digest = options
+        .digest
+        .as_deref()
+        .map(str::parse::<task4::count::DigestKind>)
+        .transpose()?;
+    let filter = match (file_type, positional.len()) {
+        (Some(file_type), 1) => task4::Filter::by_type(file_type),
+        (None, 2) => task4::Filter::new(&positional[1]),
+        _ => return Err(usage_error(i18n::text(Message::ExpectedPositional))),
+    }
+    .invert(options.invert);
+    let expected = options
+        .verify
+        .as_ref()
+        .map(|path| -> Result<_, anyhow::Error> {
+            let text = std::fs::read_to_string(path)
+                .map_err(|e| anyhow!(i18n::message(Message::CantReadManifest, &[path, &e])))?;
+            task4::manifest::Manifest::parse(&text)
+        })
+        .transpose()?;
+    if expected.is_some() {
+        digest.get_or_insert(task4::count::DigestKind::Sha256);
+    }
+    let interrupt = Arc::new(AtomicBool::new(false));
+    let mut builder = task4::SearchBuilder::new(&positional[0], filter)
+        .file_system(task4::fs::for_root(&positional[0])?)
+        .io_threads(options.io_threads.unwrap_or_else(task4::default_io_threads))
+        .max_open_files(options.max_open_files.unwrap_or(task4::MAX_OPEN_FILES))
+        .skip_hidden(options.skip_hidden)
+        .fail_fast(options.fail_fast)
+        .interrupt(Arc::clone(&interrupt));
+    if let Some(kind) = digest {
+        builder = builder.digest(kind);
+    }
+    if options.metrics {
+        builder = builder.metrics(options.long_line);
+    }
+    if options.estimate {
+        builder = builder.estimate(ESTIMATE_ABOVE);
+    }
+    if options.records {
+        builder = builder.registry(task4::records::Registry::builtin());
+    }
+    if let Some(delimiter) = &options.delimiter {
+        if options.metrics {
+            return Err(usage_error(i18n::text(Message::DelimiterWithMetrics)));
+        }
+        builder = builder.delimiter(delimiter.parse()?);
+    }
+    match &options.since_rev {
+        Some(rev) => {
+            let changes = task4::churn::Changes::since(Path::new(&positional[0]), rev)?;
+            builder = builder.changes(changes).added_lines(options.added_lines);
+        }
+        None if options.added_lines => {
+            return Err(usage_error(i18n::text(Message::AddedLinesWithoutRev)));
+        }
+        None => {}
+    }
+    let offsets = options
+        .offsets
+        .as_ref()
+        .map(|path| -> Result<_, anyhow::Error> {
+            let offsets = match std::fs::read_to_string(path) {
+                Ok(text) => task4::incremental::Offsets::parse(&text)?,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Default::default(),
+                Err(e) => {
+                    return Err(anyhow!(i18n::message(
+                        Message::CantReadOffsets,
+                        &[path, &e]
+                    )))
+                }
+            };
+            Ok((path, Arc::new(offsets)))
+        })
+        .transpose()?;
+    if let Some((_, offsets)) = &offsets {
+        builder = builder.offsets(Arc::clone(offsets));
+    }
+    if let Some(budget) = options.max_time {
+        builder = builder.max_time(budget);
+    }
+    if let Some(max) = options.max_results {
+        builder = builder.max_results(max);
+    }
+    if let Some(max) = options.max_files_scanned {
+        builder = builder.max_files_scanned(max);
+    }
+    if let Some(path) = &options.filter_prog {
+        let program =
+            task_1_and_2::program::Program::new(load_program(path)?).with_config(options.vm);
+        builder = builder.predicate(task4::predicate::Predicate::new(program));
+    }
+    let search = builder.build();
+    let mut reducer = options
+        .reduce
+        .as_deref()
+        .map(|path| -> Result<_, anyhow::Error> {
+            let program =
+                task_1_and_2::program::Program::new(load_program(path)?).with_config(options.vm);
+            Ok(task4::reduce::Reducer::new(program))
+        })
+        .transpose()?;
+    // Only arguments that make a search are worth saving.
+    if let Some(name) = &options.save_as {
+        config::Config::save_bookmark(name, &options.args)?;
+    }
+
+    if options.explain {
+        for explained in search.explain() {
+            match explained {
+                Ok((path, decision)) => reporter.decision(&path, &decision)?,
+                Err(err) => reporter.skipped(&err)?,
+            }
+        }
+        return Ok(0);
+    }
+    if options.empty_dirs {
+        for dir in search.empty_dirs() {
+            match dir {
+                Ok(path) => reporter.empty_dir(&path)?,
+                Err(err) => reporter.skipped(&err)?,
+            }
+        }
+        return Ok(0);
+    }
+    if options.estimate_cost && !confirm_cost(&search, reporter)? {
+        return Ok(0);
+    }
+    if options.age {
+        return report_ages(&search, reporter);
+    }
+
+    hook(config.pre_search.as_deref(), &[], &options.vm)?;
+    ctrlc::set_handler(move || {
+        // A second Ctrl-C means the user doesn't want to wait for the flush.
+        if interrupt.swap(true, Ordering::Relaxed) {
+            process::exit(EXIT_INTERRUPTED);
+        }
+    })?;
+
+    let mut collector = options
+        .collect
+        .map(|path| task4::collect::Collector::create(path, &search))
+        .transpose()?;
+    let mut socket = options
+        .output_socket
+        .map(|path| {
+            json::JsonLines::connect(&path)
+                .map_err(|e| anyhow!(i18n::message(Message::CantOpenSocket, &[&path, &e])))
+        })
+        .transpose()?;
+    let mut current = task4::manifest::Manifest::default();
+    let mut results = search.stream();
+    for file in &mut results {
+        let file = file?;
+        if expected.is_some() {
+            current.insert(search.root(), &file);
+        } else {
+            reporter.file(search.root(), &file)?;
+        }
+        if let Some(collector) = &mut collector {
+            collector.add(search.root(), &file.path)?;
+        }
+        if let Some(socket) = &mut socket {
+            socket.file(&file)?;
+        }
+        if let Some(reducer) = &mut reducer {
+            reducer
+                .add(&file)
+                .map_err(|e| anyhow!("{}: {}", file.path.display(), e))?;
+        }
+    }
+    // Also after an interrupt, so the archive holds everything reported.
+    if let Some(collector) = collector {
+        collector.finish()?;
+    }
+    if let Some((path, offsets)) = offsets {
+        std::fs::write(path, offsets.to_string())
+            .map_err(|e| anyhow!(i18n::message(Message::CantWriteOffsets, &[path, &e])))?;
+    }
+    let summary = results.summary();
+    if let Some(mut socket) = socket {
+        socket.summary(&summary)?;
+        socket.finish()?;
+    }
+    reporter.summary(&summary)?;
+    if let Some(reducer) = reducer {
+        reporter.text(&i18n::message(Message::Reduced, &[&reducer.value()]))?;
+    }
+    let count = |n: usize| Value::Int(ValueType::try_from(n).unwrap_or(ValueType::MAX));
+    hook(
+        config.post_search.as_deref(),
+        &[
+            ("files", count(summary.files)),
+            ("lines", count(summary.lines)),
+            ("interrupted", Value::Int(summary.interrupted.into())),
+            ("truncated", Value::Int(summary.truncated.into())),
+        ],
+        &options.vm,
+    )?;
+    if summary.interrupted {
+        return Ok(EXIT_INTERRUPTED);
+    }
+    if let Some(expected) = expected {
+        if summary.truncated || summary.limit.is_some() {
+            return Err(anyhow!(i18n::text(Message::TruncatedVerify)));
+        }
+        let changes = expected.diff(&current);
+        for change in &changes {
+            reporter.change(change)?;
+        }
+        if !changes.is_empty() {
+            return Ok(EXIT_CHANGED);
+        }
+    }
+    Ok(0)
+}
+
+/// `--estimate-cost`, whether to go on with the search after reporting what it would read.
+/// Only asks on a terminal, scripts get the cost and no search.
+#[cfg(feature = "search")]
+fn confirm_cost(
+    search: &task4::Search,
+    reporter: &mut dyn Reporter,
+) -> Result<bool, anyhow::Error> {
+    use std::io::{IsTerminal, Write};
+
+    reporter.cost(&search.cost()?)?;
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        return Ok(false);
+    }
+    let mut err = io::stderr();
+    write!(err, "{}", i18n::text(Message::ConfirmScan))?;
+    err.flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(
+        answer.trim().to_lowercase().as_str(),
+        "y" | "yes" | "д" | "да"
+    ))
+}
+
+/// `--age`, the line ages of every matched file from git blame and then of the directories
+/// holding them. A file git can't blame is skipped.
+#[cfg(feature = "search")]
+fn report_ages(search: &task4::Search, reporter: &mut dyn Reporter) -> Result<i32, anyhow::Error> {
+    use task4::churn::{self, Age, AgeStats};
+
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let mut stats = AgeStats::default();
+    for file in search.stream() {
+        let file = file?;
+        let mut written = match churn::blame(&file.path) {
+            Ok(written) => written,
+            Err(err) => {
+                reporter.skipped(&task4::FileError {
+                    path: file.path,
+                    source: io::Error::other(err),
+                })?;
+                continue;
+            }
+        };
+        stats.add(search.root(), &file.path, &written);
+        if let Some(age) = Age::of(&mut written, now) {
+            reporter.age(&file.path, &age, false)?;
+        }
+    }
+    for (dir, age) in stats.finish(now) {
+        reporter.age(&dir, &age, true)?;
+    }
+    Ok(0)
+}
+
+#[cfg(not(feature = "search"))]
+fn corpus_stats(_options: &Options, _reporter: &mut dyn Reporter) -> Result<i32, anyhow::Error> {
+    Err(anyhow!(i18n::text(Message::NoSearch)))
+}
+
+/// Opcode statistics over every `.tasm`, `.tbc` and `.tl` program below a directory.
+#[cfg(feature = "search")]
+fn corpus_stats(options: &Options, reporter: &mut dyn Reporter) -> Result<i32, anyhow::Error> {
+    let [_, dir] = &options.positional[..] else {
+        return Err(usage_error(i18n::text(Message::CorpusStatsUsage)));
+    };
+    let fs = task4::fs::for_root(dir)?;
+    let mut stats = task_1_and_2::stats::CorpusStats::default();
+    let mut failed = false;
+    for entry in task4::walk::Walk::new(fs, Path::new(dir), true, None) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                reporter.skipped(&err)?;
+                continue;
+            }
+        };
+        let ext = entry.path.extension().and_then(|ext| ext.to_str());
+        if !entry.is_file() || !matches!(ext, Some("tasm" | "tbc" | "tl")) {
+            continue;
+        }
+        match load_program(&entry.path) {
+            Ok(bytecode) => stats.add(&bytecode),
+            Err(err) => {
+                reporter.error(&err);
+                failed = true;
+            }
+        }
+    }
+    reporter.text(&stats.to_string())?;
+    Ok(if failed { EXIT_FAILURE } else { 0 })
+}
+
+#[cfg(not(feature = "search"))]
+fn test_programs(_options: &Options, _reporter: &mut dyn Reporter) -> Result<i32, anyhow::Error> {
+    Err(anyhow!(i18n::text(Message::NoSearch)))
+}
+
+/// Runs every `.tasm`, `.tbc` and `.tl` program below a directory, in the order of their
+/// paths, and fails when one of them does. A program with a `.expected` file next to it
+/// also fails when it returns something else than that file holds.
+#[cfg(feature = "search")]
+fn test_programs(options: &Options, reporter: &mut dyn Reporter) -> Result<i32, anyhow::Error> {
+    use task_1_and_2::supervisor::{Outcome, Policy, Supervisor};
+
+    let [_, dir] = &options.positional[..] else {
+        return Err(usage_error(i18n::text(Message::TestUsage)));
+    };
+    let fs = task4::fs::for_root(dir)?;
+    let (mut programs, mut expected) = (vec![], std::collections::HashMap::new());
+    let mut failed = false;
+    for entry in task4::walk::Walk::new(fs, Path::new(dir), true, None) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            // Without the directory there is nothing to test, which isn't a pass.
+            Err(err) if err.path == Path::new(dir) => return Err(err.into()),
+            Err(err) => {
+                reporter.skipped(&err)?;
+                continue;
+            }
+        };
+        let ext = entry.path.extension().and_then(|ext| ext.to_str());
+        if !entry.is_file() || !matches!(ext, Some("tasm" | "tbc" | "tl")) {
+            continue;
+        }
+        let name = entry.path.display().to_string();
+        match std::fs::read_to_string(entry.path.with_extension("expected")) {
+            Ok(text) => {
+                expected.insert(name.clone(), text.trim_end().to_owned());
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(anyhow!(i18n::message(
+                    Message::CantReadExpected,
+                    &[&name, &e]
+                )))
+            }
+        }
+        match load_program(&entry.path) {
+            Ok(bytecode) => programs.push((name, bytecode)),
+            Err(err) if options.fail_fast => return Err(err),
+            Err(err) => {
+                reporter.error(&err);
+                failed = true;
+            }
+        }
+    }
+    programs.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut supervisor = Supervisor::new(options.vm).policy(match options.fail_fast {
+        true => Policy::FailFast,
+        false => Policy::RunAll,
+    });
+    if let Some(gas) = options.total_gas {
+        supervisor = supervisor.total_gas(gas);
+    }
+    let report = supervisor.run(&programs);
+    let mut unexpected = 0;
+    for program in &report.programs {
+        let name = &program.name;
+        match &program.outcome {
+            Outcome::Returned(val) => match expected.get(name) {
+                Some(expected) if *expected != val.to_string() => {
+                    unexpected += 1;
+                    reporter.error(&anyhow!(i18n::message(
+                        Message::TestUnexpected,
+                        &[name, expected, val]
+                    )));
+                }
+                _ => reporter.text(&i18n::message(Message::TestReturned, &[name, val]))?,
+            },
+            Outcome::Failed(err) => {
+                reporter.error(&anyhow!(i18n::message(Message::TestFailed, &[name, err])))
+            }
+            Outcome::Panicked(message) => reporter.error(&anyhow!(i18n::message(
+                Message::TestPanicked,
+                &[name, message]
+            ))),
+            Outcome::Skipped => reporter.text(&i18n::message(Message::TestSkipped, &[name]))?,
+        }
+    }
+    reporter.text(&i18n::message(
+        Message::TestSummary,
+        &[
+            &(report.returned() - unexpected),
+            &(report.failed() + unexpected),
+            &report.skipped(),
+            &report.gas_used,
+        ],
+    ))?;
+    Ok(if failed || report.failed() + unexpected > 0 {
+        EXIT_FAILURE
+    } else {
+        0
+    })
+}
+
+/// `stats self`, the subcommand counts from `stats-file`.
+fn show_usage(
+    options: &Options,
+    config: &config::Config,
+    reporter: &mut dyn Reporter,
+) -> Result<i32, anyhow::Error> {
+    if options.positional[1..] != ["self"] {
+        return Err(usage_error(i18n::text(Message::StatsUsage)));
+    }
+    let Some(path) = &config.stats_file else {
+        return Err(anyhow!(i18n::text(Message::NoStatsFile)));
+    };
+    let recorded = usage::Usage::load(path).map_err(|e| {
+        anyhow!(i18n::message(
+            Message::CantReadStats,
+            &[&path.display(), &e]
+        ))
+    })?;
+    reporter.text(&recorded.to_string())?;
+    Ok(0)
+}
+
+/// Times the search over a generated tree, when there is one, and the examples on the VM.
+fn bench_self(options: &Options, reporter: &mut dyn Reporter) -> Result<i32, anyhow::Error> {
+    if options.positional.len() != 1 {
+        return Err(usage_error(i18n::text(Message::BenchSelfUsage)));
+    }
+    #[cfg(feature = "search")]
+    {
+        let tree = bench::Tree::create()?;
+        let run = tree.search()?;
+        reporter.text(&i18n::message(
+            Message::BenchSearch,
+            &[
+                &run.files,
+                &run.lines,
+                &run.bytes,
+                &run.elapsed.as_millis(),
+                &format!("{:.0}", bench::rate(run.files as f64, run.elapsed)),
+                &format!("{:.1}", bench::rate(run.bytes as f64 / 1e6, run.elapsed)),
+            ],
+        ))?;
+    }
+    for run in bench::vm() {
+        reporter.text(&i18n::message(
+            Message::BenchVm,
+            &[
+                &run.name,
+                &run.runs,
+                &run.instructions,
+                &run.elapsed.as_millis(),
+                &format!("{:.0}", bench::rate(run.instructions as f64, run.elapsed)),
+            ],
+        ))?;
+    }
+    Ok(0)
+}
+
+/// Lists the project templates or creates a project from one.
+fn new_project(options: &Options, reporter: &mut dyn Reporter) -> Result<i32, anyhow::Error> {
+    match &options.positional[1..] {
+        [cmd] if cmd == "list" => {
+            for template in scaffold::TEMPLATES {
+                reporter.text(&format!("{:<12} {}", template.name, template.summary))?;
+            }
+        }
+        [name, dir] => {
+            let template = scaffold::find(name)
+                .ok_or_else(|| anyhow!(i18n::message(Message::UnknownTemplate, &[name])))?;
+            template
+                .create(Path::new(dir))
+                .map_err(|e| anyhow!(i18n::message(Message::CantCreateProject, &[dir, &e])))?;
+            reporter.text(&i18n::message(
+                Message::ProjectCreated,
+                &[dir, &template.name, dir],
+            ))?;
+        }
+        _ => return Err(usage_error(i18n::text(Message::NewUsage))),
+    }
+    Ok(0)
+}
+
+/// Checks the installation, exits with 1 when a check failed.
+fn doctor(options: &Options, reporter: &mut dyn Reporter) -> Result<i32, anyhow::Error> {
+    if options.positional.len() != 1 {
+        return Err(usage_error(i18n::text(Message::DoctorUsage)));
+    }
+    let features = [
+        ("search", cfg!(feature = "search")),
+        ("async", cfg!(feature = "async")),
+        ("remote", cfg!(feature = "remote")),
+        ("alloc-stats", cfg!(feature = "alloc-stats")),
+        ("serde", cfg!(feature = "serde")),
+    ];
+    let features: Vec<_> = features
+        .iter()
+        .filter(|(_, on)| *on)
+        .map(|(name, _)| *name)
+        .collect();
+    reporter.text(&i18n::message(
+        Message::DoctorVersion,
+        &[
+            &env!("CARGO_PKG_VERSION"),
+            &std::env::consts::OS,
+            &std::env::consts::ARCH,
+            &features.join(", "),
+        ],
+    ))?;
+    let scratch = std::env::temp_dir().join(format!("testing-doctor-{}", process::id()));
+    let checks = doctor::run(&scratch);
+    for check in &checks {
+        reporter.text(&check.to_string())?;
+    }
+    let count = |status| checks.iter().filter(|check| check.status == status).count();
+    use doctor::Status;
+    let failed = count(Status::Fail);
+    reporter.text(&i18n::message(
+        Message::DoctorSummary,
+        &[&count(Status::Ok), &count(Status::Warn), &failed],
+    ))?;
+    Ok(i32::from(failed > 0))
+}
+
+fn examples(options: &Options, reporter: &mut dyn Reporter) -> Result<i32, anyhow::Error> {
+    let find = |name: &str| {
+        task_1_and_2::examples::find(name)
+            .ok_or_else(|| anyhow!(i18n::message(Message::UnknownExample, &[&name])))
+    };
+    match &options.positional[1..] {
+        [cmd] if cmd == "list" => {
+            for example in task_1_and_2::examples::EXAMPLES {
+                reporter.text(&format!("{:<12} {}", example.name, example.summary))?;
+            }
+        }
+        [cmd, name] if cmd == "show" => {
+            let example = find(name)?;
+            reporter.text(&format!("; {}\n{}", example.summary, example.listing()))?;
+        }
+        [cmd, name] if cmd == "run" => {
+            let example = find(name)?;
+            execute(example.name, example.bytecode(), options, reporter)?;
+        }
+        _ => return Err(usage_error(i18n::text(Message::ExamplesUsage))),
+    }
+    Ok(0)
+}
+
+/// Runs a compiled program, or assembles a text one first. What the verifier finds is
+/// warned about, the program runs anyway since the verifier can't follow every jump.
+fn run_file(
+    options: &Options,
+    config: &config::Config,
+    reporter: &mut dyn Reporter,
+) -> Result<i32, anyhow::Error> {
+    let [_, path] = &options.positional[..] else {
+        return Err(usage_error(i18n::text(Message::RunUsage)));
+    };
+    let bytecode = load_program(path)?;
+    for diagnostic in task_1_and_2::verify::verify(&bytecode) {
+        reporter.warning(&anyhow!("{}: {}", path, diagnostic));
+    }
+    hook(config.pre_run.as_deref(), &[], &options.vm)?;
+    let value = execute(path, bytecode, options, reporter)?;
+    hook(config.post_run.as_deref(), &[("value", value)], &options.vm)?;
+    Ok(0)
+}
+
+/// Runs a hook from the config with `inputs` in its variables, and fails the command when
+/// the hook returns 0.
+fn hook(
+    path: Option<&Path>,
+    inputs: &[(&str, Value)],
+    vm: &task_1_and_2::VmConfig,
+) -> Result<(), anyhow::Error> {
+    let Some(path) = path else {
+        return Ok(());
+    };
+    let program = task_1_and_2::program::Program::new(load_program(path)?).with_config(*vm);
+    let value = program
+        .run_with(inputs)
+        .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+    if !value.is_true() {
+        return Err(anyhow!(i18n::message(
+            Message::HookVetoed,
+            &[&path.display()]
+        )));
+    }
+    Ok(())
+}
+
+/// Compiles a text program into the binary format `run` loads without parsing.
+fn assemble(options: &Options) -> Result<i32, anyhow::Error> {
+    let [_, path, out] = &options.positional[..] else {
+        return Err(usage_error(i18n::text(Message::AssembleUsage)));
+    };
+    let bytes = load_program(path)?.to_bytes();
+    std::fs::write(out, bytes)
+        .map_err(|e| anyhow!(i18n::message(Message::CantWriteProgram, &[out, &e])))?;
+    Ok(0)
+}
+
+/// Prints a program of any format as text with its IPs, see `Bytecode::disassemble`.
+fn disassemble(options: &Options, reporter: &mut dyn Reporter) -> Result<i32, anyhow::Error> {
+    let [_, path] = &options.positional[..] else {
+        return Err(usage_error(i18n::text(Message::DisassembleUsage)));
+    };
+    reporter.text(&load_program(path)?.disassemble())?;
+    Ok(0)
+}
+
+/// Runs a program once per CSV row and prints the table with the results appended.
+fn map_csv(options: &Options, reporter: &mut dyn Reporter) -> Result<i32, anyhow::Error> {
+    let [_, path, input] = &options.positional[..] else {
+        return Err(usage_error(i18n::text(Message::MapUsage)));
+    };
+    let program = task_1_and_2::program::Program::new(load_program(path)?).with_config(options.vm);
+    let csv = std::fs::read_to_string(input)
+        .map_err(|e| anyhow!(i18n::message(Message::CantReadCsv, &[input, &e])))?;
+    let column = options.column.as_deref().unwrap_or(DEFAULT_COLUMN);
+    let transformed = task_1_and_2::csv::transform(&program, &csv, column)
+        .map_err(|e| anyhow!("{}: {}", input, e))?;
+
+    reporter.text(transformed.csv.trim_end())?;
+    for (row, err) in &transformed.failed {
+        reporter.error(&anyhow!(i18n::message(Message::RowFailed, &[row, err])));
+    }
+    Ok(if transformed.failed.is_empty() {
+        0
+    } else {
+        EXIT_FAILURE
+    })
+}
+
+/// Runs a program repeatedly and fails unless every run matches the first.
+fn verify_determinism(
+    options: &Options,
+    reporter: &mut dyn Reporter,
+) -> Result<i32, anyhow::Error> {
+    let [_, path] = &options.positional[..] else {
+        return Err(usage_error(i18n::text(Message::VerifyDeterminismUsage)));
+    };
+    let runs = options.runs.unwrap_or(DEFAULT_RUNS);
+    let result = task_1_and_2::determinism::check(&load_program(path)?, runs, &options.vm)?;
+    let result = match result {
+        Ok(value) => value.to_string(),
+        Err(err) => format!("{} {}", i18n::text(Message::Error), err),
+    };
+    reporter.text(&i18n::message(Message::Deterministic, &[&runs, &result]))?;
+    Ok(0)
+}
+
+/// A program in the binary format, which starts with its magic, compiled from a `.tl` file,
+/// or else assembled.
+fn load_program(path: impl AsRef<Path>) -> Result<task_1_and_2::Bytecode, anyhow::Error> {
+    let path_ref = path.as_ref();
+    let (bytes, path) = (std::fs::read(path_ref), path_ref.display());
+    let bytes =
+        bytes.map_err(|e| anyhow!(i18n::message(Message::CantReadProgram, &[&path, &e])))?;
+    if bytes.starts_with(task_1_and_2::binary::MAGIC) {
+        return task_1_and_2::Bytecode::from_bytes(&bytes).map_err(|e| anyhow!("{}: {}", path, e));
+    }
+    let text = String::from_utf8_lossy(&bytes);
+    if path_ref.extension().is_some_and(|ext| ext == "tl") {
+        return task_1_and_2::compiler::compile(&text).map_err(|e| anyhow!("{}:{}", path, e));
+    }
+    task_1_and_2::asm::parse(&text).map_err(|e| anyhow!("{}:{}", path, e))
+}
+
+/// Runs `bytecode` and prints and returns its value, writing a trace first when `--trace-out` asks
+/// and printing its steps to stderr with `--trace`. With `--profile` the instruction counts
+/// follow the value, with `--gas` the gas left.
+fn execute(
+    name: &str,
+    bytecode: task_1_and_2::Bytecode,
+    options: &Options,
+    reporter: &mut dyn Reporter,
+) -> Result<Value, anyhow::Error> {
+    if options.trace && options.trace_out.is_some() {
+        return Err(usage_error(i18n::text(Message::TraceWithTraceOut)));
+    }
+    if options.profile {
+        if options.trace || options.trace_out.is_some() {
+            return Err(usage_error(i18n::text(Message::ProfileWithTrace)));
+        }
+        let (value, profile) = task_1_and_2::profile::Profile::record(&bytecode, &options.vm);
+        // Also for a run that failed, the counts show where it went.
+        if let Ok(value) = &value {
+            reporter.text(&value.to_string())?;
+        }
+        reporter.text(&profile.to_string())?;
+        return Ok(value?);
+    }
+    let (value, gas_left) = match &options.trace_out {
+        Some(path) => {
+            let (value, trace) = task_1_and_2::trace::Trace::record(name, bytecode, &options.vm);
+            std::fs::write(path, trace.to_json())
+                .map_err(|e| anyhow!(i18n::message(Message::CantWriteTrace, &[path, &e])))?;
+            (value?, None)
+        }
+        None => {
+            let mut vm = task_1_and_2::Vm::new(&bytecode, options.vm);
+            if options.trace {
+                vm.trace_to(io::stderr());
+            }
+            let task_1_and_2::Stop::Returned(value) = vm.run()? else {
+                unreachable!("no breakpoints are set");
+            };
+            (value, vm.gas_left())
+        }
+    };
+    reporter.text(&value.to_string())?;
+    if let Some(left) = gas_left {
+        reporter.text(&i18n::message(Message::GasLeft, &[&left]))?;
+    }
+    Ok(value)
+}
 
-fn main() -> Result<(), anyhow::Error> {
-    let args: Vec<_> = env::args().collect();
-    if args.len() != 3 {
-        eprintln!("USAGE: testing <dir> <ext>");
-        return Err(anyhow!("invalid usage"));
+/// Runs random programs until the time is up, a program that panics the VM fails the run.
+fn soak(options: &Options, reporter: &mut dyn Reporter) -> Result<i32, anyhow::Error> {
+    if options.positional.len() != 1 {
+        return Err(usage_error(i18n::text(Message::SoakUsage)));
     }
-    task4::search_files(&args[1], &args[2])
+    let seed = options.seed.unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(1, |since| since.as_nanos() as u64)
+    });
+    let hours = options.hours.unwrap_or(1.0);
+    let deadline = Duration::try_from_secs_f64(hours * 3600.0)
+        .ok()
+        .and_then(|length| Instant::now().checked_add(length))
+        .ok_or_else(|| usage_error(&i18n::message(Message::SoakTooLong, &[&hours])))?;
+    reporter.text(&i18n::message(Message::SoakSeed, &[&seed]))?;
+
+    let mut soak = task_1_and_2::soak::Soak::new(seed);
+    #[cfg(feature = "alloc-stats")]
+    let start = alloc_stats::AllocStats::now();
+    let mut progress = Instant::now();
+    loop {
+        soak.batch(SOAK_BATCH);
+        let done = Instant::now() >= deadline || soak.panicked().is_some();
+        if done || progress.elapsed() >= SOAK_PROGRESS {
+            progress = Instant::now();
+            #[cfg(feature = "alloc-stats")]
+            let line = i18n::message(
+                Message::SoakHeld,
+                &[
+                    &soak,
+                    &alloc_stats::AllocStats::now().since(&start).growth(),
+                ],
+            );
+            #[cfg(not(feature = "alloc-stats"))]
+            let line = soak.to_string();
+            reporter.text(&line)?;
+        }
+        if done {
+            break;
+        }
+    }
+    if let Some(program) = soak.panicked() {
+        reporter.text(&i18n::message(Message::SoakPanicked, &[&program]))?;
+        return Ok(EXIT_FAILURE);
+    }
+    Ok(0)
+}
+
+/// `500ms`, `10s` or `2m`.
+fn parse_duration(s: &str) -> Option<Duration> {
+    let (n, unit): (u64, fn(u64) -> Duration) = if let Some(n) = s.strip_suffix("ms") {
+        (n.parse().ok()?, Duration::from_millis)
+    } else if let Some(n) = s.strip_suffix('s') {
+        (n.parse().ok()?, Duration::from_secs)
+    } else if let Some(n) = s.strip_suffix('m') {
+        (n.parse().ok()?, |m| Duration::from_secs(m * 60))
+    } else {
+        return None;
+    };
+    Some(unit(n))
+}
+
+/// A positive number, or `None` for `unlimited`.
+fn parse_limit<T: std::str::FromStr + PartialOrd + Default>(
+    flag: &str,
+    arg: Option<String>,
+) -> Result<Option<T>, anyhow::Error> {
+    match arg.as_deref() {
+        Some("unlimited") => Ok(None),
+        Some(n) => n
+            .parse()
+            .ok()
+            .filter(|n| *n > T::default())
+            .map(Some)
+            .ok_or_else(|| expects(flag, Message::OpsLimit)),
+        None => Err(expects(flag, Message::OpsLimit)),
+    }
+}
+
+fn usage_error(msg: &str) -> anyhow::Error {
+    UsageError {
+        usage: i18n::text(Message::Usage),
+        message: msg.to_owned(),
+    }
+    .into()
+}
+
+fn expects(flag: &str, what: Message) -> anyhow::Error {
+    usage_error(&i18n::message(
+        Message::OptionExpects,
+        &[&flag, &i18n::text(what)],
+    ))
 }
@@ -0,0 +1,575 @@
+#[cfg(feature = "search")]
+use std::path::Path;
+use std::{
+    env, fmt,
+    io::{self, IsTerminal, Write},
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+use thiserror::Error;
+
+use crate::i18n::{self, Message};
+use testing::json;
+#[cfg(feature = "search")]
+use testing::task4::{
+    churn::Age,
+    escaped_path,
+    filter::Decision,
+    manifest::{Change, Manifest},
+    FileError, FileLines, Limit, ScanCost, SearchSummary,
+};
+
+/// Everything a command prints goes through a reporter, so all commands look alike.
+///
+/// Results go to stdout, diagnostics and errors to stderr. Entries that couldn't be looked
+/// at are errors too, so that stdout holds nothing but results in every format.
+pub trait Reporter {
+    /// A matched file, with its digest or metrics when the search collected them.
+    #[cfg(feature = "search")]
+    fn file(&mut self, root: &Path, file: &FileLines) -> io::Result<()>;
+
+    #[cfg(feature = "search")]
+    fn decision(&mut self, path: &Path, decision: &Decision) -> io::Result<()>;
+
+    /// A directory with no matched file below it.
+    #[cfg(feature = "search")]
+    fn empty_dir(&mut self, path: &Path) -> io::Result<()>;
+
+    /// How old the lines of a matched file are, or with `dir` those of every file below a
+    /// directory.
+    #[cfg(feature = "search")]
+    fn age(&mut self, path: &Path, age: &Age, dir: bool) -> io::Result<()>;
+
+    /// An entry that couldn't be looked at, on stderr.
+    #[cfg(feature = "search")]
+    fn skipped(&mut self, err: &FileError) -> io::Result<()>;
+
+    #[cfg(feature = "search")]
+    fn change(&mut self, change: &Change) -> io::Result<()>;
+
+    /// What a search would read, for `--estimate-cost`.
+    #[cfg(feature = "search")]
+    fn cost(&mut self, cost: &ScanCost) -> io::Result<()>;
+
+    /// Called once a search is over, also when it was cut short.
+    #[cfg(feature = "search")]
+    fn summary(&mut self, summary: &SearchSummary) -> io::Result<()>;
+
+    /// Free-form output of the smaller commands, `text` may span several lines.
+    fn text(&mut self, text: &str) -> io::Result<()>;
+
+    /// A side remark about the run, kept apart from the results.
+    #[cfg(feature = "alloc-stats")]
+    fn note(&mut self, note: &str) -> io::Result<()>;
+
+    /// Something that may be wrong, which doesn't stop the command.
+    fn warning(&mut self, warning: &anyhow::Error);
+
+    fn error(&mut self, err: &anyhow::Error);
+}
+
+/// A command line that doesn't parse, reporters show the usage along with it.
+#[derive(Error, Debug)]
+#[error("{}", i18n::message(Message::InvalidUsage, &[message]))]
+pub struct UsageError {
+    pub usage: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Human,
+    /// One JSON object per line.
+    Json,
+    /// Errors only, the exit code tells the rest.
+    Quiet,
+    /// Matched paths exactly as the file system has them, each ended by a NUL byte, for
+    /// `xargs -0`. Everything else is shown as by `Human`.
+    Null,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Format::Human),
+            "json" => Ok(Format::Json),
+            "quiet" => Ok(Format::Quiet),
+            "null" => Ok(Format::Null),
+            _ => Err(anyhow!(i18n::message(
+                Message::UnknownFormat,
+                &[&s, &i18n::text(Message::FormatNames)]
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColorChoice {
+    /// Color terminals, unless `NO_COLOR` is set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(anyhow!(i18n::message(
+                Message::UnknownColor,
+                &[&s, &i18n::text(Message::ColorNames)]
+            ))),
+        }
+    }
+}
+
+impl ColorChoice {
+    fn enabled(self, stream: &impl IsTerminal) -> bool {
+        match self {
+            ColorChoice::Auto => stream.is_terminal() && env::var_os("NO_COLOR").is_none(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+pub fn reporter(format: Format, color: ColorChoice) -> Box<dyn Reporter> {
+    let human = || Human {
+        out_color: color.enabled(&io::stdout()),
+        err_color: color.enabled(&io::stderr()),
+    };
+    match format {
+        Format::Human => Box::new(human()),
+        Format::Json => Box::new(Json),
+        Format::Quiet => Box::new(Quiet),
+        Format::Null => Box::new(Null(human())),
+    }
+}
+
+const RED: &str = "31";
+#[cfg(feature = "search")]
+const GREEN: &str = "32";
+const YELLOW: &str = "33";
+#[cfg(feature = "search")]
+const DIM: &str = "2";
+
+struct Paint<T> {
+    color: Option<&'static str>,
+    text: T,
+}
+
+impl<T: fmt::Display> fmt::Display for Paint<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.color {
+            Some(color) => write!(f, "\x1b[{}m{}\x1b[0m", color, self.text),
+            None => write!(f, "{}", self.text),
+        }
+    }
+}
+
+fn paint<T>(enabled: bool, color: &'static str, text: T) -> Paint<T> {
+    Paint {
+        color: enabled.then_some(color),
+        text,
+    }
+}
+
+struct Human {
+    #[cfg_attr(not(feature = "search"), allow(dead_code))]
+    out_color: bool,
+    err_color: bool,
+}
+
+impl Reporter for Human {
+    #[cfg(feature = "search")]
+    fn file(&mut self, root: &Path, file: &FileLines) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        if file.digest.is_some() {
+            return writeln!(out, "{}", Manifest::record(root, file));
+        }
+        write!(out, "{} ", escaped_path(&file.path))?;
+        match &file.estimate {
+            Some(estimate) => write!(out, "~{} ±{}", file.lines, estimate.margin)?,
+            None => write!(out, "{}", file.lines)?,
+        }
+        if let Some(metrics) = &file.metrics {
+            write!(out, " {}", metrics)?;
+        }
+        writeln!(out)
+    }
+
+    #[cfg(feature = "search")]
+    fn decision(&mut self, path: &Path, decision: &Decision) -> io::Result<()> {
+        let color = if decision.is_included() { GREEN } else { DIM };
+        writeln!(
+            io::stdout(),
+            "{} {}",
+            escaped_path(path),
+            paint(self.out_color, color, decision)
+        )
+    }
+
+    #[cfg(feature = "search")]
+    fn empty_dir(&mut self, path: &Path) -> io::Result<()> {
+        writeln!(io::stdout(), "{}", escaped_path(path))
+    }
+
+    #[cfg(feature = "search")]
+    fn age(&mut self, path: &Path, age: &Age, dir: bool) -> io::Result<()> {
+        let slash = if dir { "/" } else { "" };
+        writeln!(
+            io::stdout(),
+            "{}{} {} {}",
+            escaped_path(path),
+            slash,
+            age.lines,
+            age
+        )
+    }
+
+    #[cfg(feature = "search")]
+    fn skipped(&mut self, err: &FileError) -> io::Result<()> {
+        writeln!(
+            io::stderr(),
+            "{} {}",
+            escaped_path(&err.path),
+            paint(
+                self.err_color,
+                YELLOW,
+                i18n::message(Message::Skipped, &[&err.source])
+            )
+        )
+    }
+
+    #[cfg(feature = "search")]
+    fn change(&mut self, change: &Change) -> io::Result<()> {
+        let color = match change {
+            Change::Added(_) => GREEN,
+            Change::Deleted(_) => RED,
+            Change::Modified(_) => YELLOW,
+        };
+        writeln!(io::stdout(), "{}", paint(self.out_color, color, change))
+    }
+
+    #[cfg(feature = "search")]
+    fn cost(&mut self, cost: &ScanCost) -> io::Result<()> {
+        writeln!(
+            io::stdout(),
+            "{}",
+            i18n::message(Message::ScanCost, &[&cost.files, &cost.bytes])
+        )
+    }
+
+    #[cfg(feature = "search")]
+    fn summary(&mut self, summary: &SearchSummary) -> io::Result<()> {
+        let cause = if summary.interrupted {
+            Message::Interrupted
+        } else if summary.truncated {
+            Message::TimeBudgetRanOut
+        } else if let Some(limit) = summary.limit {
+            match limit {
+                Limit::Results => Message::ResultLimit,
+                Limit::FilesScanned => Message::ScanLimit,
+            }
+        } else {
+            return Ok(());
+        };
+        let marker = i18n::message(cause, &[&summary.files, &summary.lines]);
+        writeln!(io::stderr(), "{}", paint(self.err_color, YELLOW, marker))
+    }
+
+    fn text(&mut self, text: &str) -> io::Result<()> {
+        writeln!(io::stdout(), "{}", text.trim_end_matches('\n'))
+    }
+
+    #[cfg(feature = "alloc-stats")]
+    fn note(&mut self, note: &str) -> io::Result<()> {
+        writeln!(io::stderr(), "-- {} --", note)
+    }
+
+    fn warning(&mut self, warning: &anyhow::Error) {
+        let _ = writeln!(
+            io::stderr(),
+            "{} {:#}",
+            paint(self.err_color, YELLOW, i18n::text(Message::Warning)),
+            warning
+        );
+    }
+
+    fn error(&mut self, err: &anyhow::Error) {
+        let mut out = io::stderr().lock();
+        if let Some(usage) = err.downcast_ref::<UsageError>() {
+            let _ = writeln!(out, "{}", usage.usage);
+        }
+        let _ = writeln!(
+            out,
+            "{} {:#}",
+            paint(self.err_color, RED, i18n::text(Message::Error)),
+            err
+        );
+    }
+}
+
+struct Json;
+
+impl Reporter for Json {
+    #[cfg(feature = "search")]
+    fn file(&mut self, _root: &Path, file: &FileLines) -> io::Result<()> {
+        writeln!(io::stdout(), "{}", json::file_json(file))
+    }
+
+    #[cfg(feature = "search")]
+    fn decision(&mut self, path: &Path, decision: &Decision) -> io::Result<()> {
+        writeln!(
+            io::stdout(),
+            "{{\"path\":{},\"included\":{},\"reason\":{}}}",
+            json::path(path),
+            decision.is_included(),
+            json::string(&decision.to_string())
+        )
+    }
+
+    #[cfg(feature = "search")]
+    fn empty_dir(&mut self, path: &Path) -> io::Result<()> {
+        writeln!(
+            io::stdout(),
+            "{{\"path\":{},\"empty\":true}}",
+            json::path(path)
+        )
+    }
+
+    #[cfg(feature = "search")]
+    fn age(&mut self, path: &Path, age: &Age, dir: bool) -> io::Result<()> {
+        writeln!(
+            io::stdout(),
+            "{{\"path\":{},\"dir\":{},\"lines\":{},\"median_age\":{},\"oldest_age\":{}}}",
+            json::path(path),
+            dir,
+            age.lines,
+            age.median,
+            age.oldest
+        )
+    }
+
+    #[cfg(feature = "search")]
+    fn skipped(&mut self, err: &FileError) -> io::Result<()> {
+        writeln!(
+            io::stderr(),
+            "{{\"path\":{},\"skipped\":{}}}",
+            json::path(&err.path),
+            json::string(&err.source.to_string())
+        )
+    }
+
+    #[cfg(feature = "search")]
+    fn change(&mut self, change: &Change) -> io::Result<()> {
+        writeln!(
+            io::stdout(),
+            "{{\"path\":{},\"change\":\"{}\"}}",
+            json::path(change.path()),
+            change.kind()
+        )
+    }
+
+    #[cfg(feature = "search")]
+    fn cost(&mut self, cost: &ScanCost) -> io::Result<()> {
+        writeln!(
+            io::stdout(),
+            "{{\"cost\":{{\"files\":{},\"bytes\":{}}}}}",
+            cost.files,
+            cost.bytes
+        )
+    }
+
+    #[cfg(feature = "search")]
+    fn summary(&mut self, summary: &SearchSummary) -> io::Result<()> {
+        writeln!(io::stdout(), "{}", json::summary_json(summary))
+    }
+
+    fn text(&mut self, text: &str) -> io::Result<()> {
+        writeln!(io::stdout(), "{{\"text\":{}}}", json::string(text))
+    }
+
+    #[cfg(feature = "alloc-stats")]
+    fn note(&mut self, note: &str) -> io::Result<()> {
+        writeln!(io::stderr(), "{{\"note\":{}}}", json::string(note))
+    }
+
+    /// A file that ended the search also gets its path on its own.
+    fn warning(&mut self, warning: &anyhow::Error) {
+        let message = json::string(&format!("{:#}", warning));
+        let _ = writeln!(io::stderr(), "{{\"warning\":{}}}", message);
+    }
+
+    fn error(&mut self, err: &anyhow::Error) {
+        let message = json::string(&format!("{:#}", err));
+        #[cfg(feature = "search")]
+        if let Some(err) = err.downcast_ref::<FileError>() {
+            let _ = writeln!(
+                io::stderr(),
+                "{{\"error\":{},\"path\":{}}}",
+                message,
+                json::path(&err.path)
+            );
+            return;
+        }
+        let _ = writeln!(io::stderr(), "{{\"error\":{}}}", message);
+    }
+}
+
+struct Null(Human);
+
+impl Reporter for Null {
+    #[cfg(feature = "search")]
+    fn file(&mut self, _root: &Path, file: &FileLines) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        out.write_all(file.path.as_os_str().as_encoded_bytes())?;
+        out.write_all(b"\0")
+    }
+
+    #[cfg(feature = "search")]
+    fn decision(&mut self, path: &Path, decision: &Decision) -> io::Result<()> {
+        self.0.decision(path, decision)
+    }
+
+    #[cfg(feature = "search")]
+    fn empty_dir(&mut self, path: &Path) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        out.write_all(path.as_os_str().as_encoded_bytes())?;
+        out.write_all(b"\0")
+    }
+
+    /// Only the files, like a search lists them.
+    #[cfg(feature = "search")]
+    fn age(&mut self, path: &Path, _age: &Age, dir: bool) -> io::Result<()> {
+        if dir {
+            return Ok(());
+        }
+        let mut out = io::stdout().lock();
+        out.write_all(path.as_os_str().as_encoded_bytes())?;
+        out.write_all(b"\0")
+    }
+
+    #[cfg(feature = "search")]
+    fn skipped(&mut self, err: &FileError) -> io::Result<()> {
+        self.0.skipped(err)
+    }
+
+    #[cfg(feature = "search")]
+    fn change(&mut self, change: &Change) -> io::Result<()> {
+        self.0.change(change)
+    }
+
+    #[cfg(feature = "search")]
+    fn cost(&mut self, cost: &ScanCost) -> io::Result<()> {
+        self.0.cost(cost)
+    }
+
+    #[cfg(feature = "search")]
+    fn summary(&mut self, summary: &SearchSummary) -> io::Result<()> {
+        self.0.summary(summary)
+    }
+
+    fn text(&mut self, text: &str) -> io::Result<()> {
+        self.0.text(text)
+    }
+
+    #[cfg(feature = "alloc-stats")]
+    fn note(&mut self, note: &str) -> io::Result<()> {
+        self.0.note(note)
+    }
+
+    fn warning(&mut self, warning: &anyhow::Error) {
+        self.0.warning(warning)
+    }
+
+    fn error(&mut self, err: &anyhow::Error) {
+        self.0.error(err)
+    }
+}
+
+struct Quiet;
+
+impl Reporter for Quiet {
+    #[cfg(feature = "search")]
+    fn file(&mut self, _root: &Path, _file: &FileLines) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "search")]
+    fn decision(&mut self, _path: &Path, _decision: &Decision) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "search")]
+    fn empty_dir(&mut self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "search")]
+    fn age(&mut self, _path: &Path, _age: &Age, _dir: bool) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "search")]
+    fn skipped(&mut self, _err: &FileError) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "search")]
+    fn change(&mut self, _change: &Change) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "search")]
+    fn cost(&mut self, _cost: &ScanCost) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "search")]
+    fn summary(&mut self, _summary: &SearchSummary) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn text(&mut self, _text: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "alloc-stats")]
+    fn note(&mut self, _note: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn warning(&mut self, _warning: &anyhow::Error) {}
+
+    fn error(&mut self, err: &anyhow::Error) {
+        Human {
+            out_color: false,
+            err_color: false,
+        }
+        .error(err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::report::{paint, ColorChoice, Format, RED};
+
+    #[test]
+    fn parses_choices_and_paints() {
+        assert_eq!("json".parse::<Format>().unwrap(), Format::Json);
+        assert_eq!("never".parse::<ColorChoice>().unwrap(), ColorChoice::Never);
+        assert!("loud".parse::<Format>().is_err());
+        assert_eq!(paint(true, RED, "no").to_string(), "\x1b[31mno\x1b[0m");
+        assert_eq!(paint(false, RED, "no").to_string(), "no");
+    }
+}
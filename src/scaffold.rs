@@ -0,0 +1,111 @@
+//! `new`, starter projects to write programs from instead of an empty file.
+//!
+//! A project is a directory holding `main.tasm`, the value `testing test` expects it to
+//! return in `main.expected`, and a `testing.conf` with the settings commented out.
+
+use std::{fs, io, path::Path};
+
+/// One kind of starter project.
+pub struct Template {
+    pub name: &'static str,
+    pub summary: &'static str,
+    main: &'static str,
+    expected: &'static str,
+}
+
+pub const TEMPLATES: &[Template] = &[
+    Template {
+        name: "loop",
+        summary: "a counter that runs down, summing 1 to 10",
+        main: "\
+; Sums 1 to 10, the skeleton of a program that repeats until a counter runs out.
+LoadVal 0
+WriteVar total
+LoadVal 10
+WriteVar n
+loop:
+ReadVar total
+ReadVar n
+Add
+WriteVar total
+; n - 1, Subtract computes the top value minus the one below it.
+LoadVal 1
+ReadVar n
+Subtract
+WriteVar n
+ReadVar n
+JumpIfPos loop
+ReadVar total
+ReturnValue
+",
+        expected: "55\n",
+    },
+    Template {
+        name: "call",
+        summary: "a function that squares its argument, called with 7",
+        main: "\
+; Calls a function, the way to split a program into parts.
+LoadVal 7
+Call square
+ReturnValue
+
+; Leaves the square of its argument in place of it.
+square:
+PushArg 0
+PushArg 0
+Multiply
+; The callee pops the arguments it was given.
+Swap
+Pop
+Ret
+",
+        expected: "49\n",
+    },
+];
+
+const CONFIG: &str = "\
+# Settings for runs and searches started in this directory, `testing doctor` checks them.
+# Hook paths are relative to this file.
+# pre-run = hooks/pre-run.tasm
+# post-run = hooks/post-run.tasm
+# stats-file = .testing-stats
+
+# `testing --use programs` lists the programs with their line counts.
+bookmark.programs = . tasm
+";
+
+pub fn find(name: &str) -> Option<&'static Template> {
+    TEMPLATES.iter().find(|template| template.name == name)
+}
+
+impl Template {
+    /// Creates the project in the directory `dir`, which must not exist yet.
+    pub fn create(&self, dir: &Path) -> io::Result<()> {
+        // Fails when `dir` exists, so nothing of the user's is overwritten.
+        fs::create_dir(dir)?;
+        fs::write(dir.join("main.tasm"), self.main)?;
+        fs::write(dir.join("main.expected"), self.expected)?;
+        fs::write(dir.join("testing.conf"), CONFIG)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use testing::task_1_and_2::{asm, run};
+
+    use crate::scaffold::TEMPLATES;
+
+    #[test]
+    fn templates_return_what_they_expect() {
+        for template in TEMPLATES {
+            let bytecode = asm::parse(template.main).unwrap();
+            let returned = run(bytecode).unwrap();
+            assert_eq!(
+                returned.to_string(),
+                template.expected.trim_end(),
+                "{}",
+                template.name
+            );
+        }
+    }
+}
@@ -1,31 +1,1166 @@
+//! Finds files below a directory and counts their lines, see [`SearchBuilder`].
+#![forbid(unsafe_code)]
+
 use std::{
-    fs::File,
-    io::{BufRead, BufReader},
-    path::Path,
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt::Write as _,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Condvar, Mutex, OnceLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-use walkdir::WalkDir;
-
-pub fn search_files(dir: impl AsRef<Path>, ext: &str) -> Result<(), anyhow::Error> {
-    for entry in WalkDir::new(dir)
-        .follow_links(true)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        if entry
-            .file_name()
-            .to_string_lossy()
-            .ends_with(&[".", ext].concat())
-        {
-            let filepath = entry.path();
-            let file = File::open(filepath)?;
-            let reader = BufReader::new(file);
-            let mut nlines = 0;
-            for _ in reader.lines() {
-                nlines += 1;
-            }
-            println!("{} {}", filepath.to_string_lossy(), nlines);
-        }
-    }
-    Ok(())
+use thiserror::Error;
+
+pub use filetype::FileType;
+pub use filter::Filter;
+
+use churn::Changes;
+use count::{Delimiter, DigestKind};
+use estimate::Estimate;
+use filter::Decision;
+use fs::{FileSystem, RealFs};
+use incremental::Offsets;
+use metrics::Metrics;
+use predicate::Predicate;
+use records::Registry;
+use walk::{EmptyDirs, Entry, Walk};
+
+#[cfg(feature = "async")]
+pub mod async_search;
+pub mod churn;
+pub mod collect;
+pub mod count;
+pub mod estimate;
+pub mod filetype;
+pub mod filter;
+pub mod fs;
+pub mod incremental;
+pub mod manifest;
+pub mod metrics;
+pub mod predicate;
+pub mod records;
+pub mod reduce;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod walk;
+
+/// Files and directories a search has open at once unless
+/// [`SearchBuilder::max_open_files`] says otherwise.
+pub const MAX_OPEN_FILES: usize = 256;
+
+pub fn default_io_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Line count of one matched file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileLines {
+    pub path: PathBuf,
+    /// Records instead for a file [`SearchBuilder::registry`] has a strategy for.
+    pub lines: usize,
+    /// Hex digest of the content, when the search was asked for one.
+    pub digest: Option<String>,
+    /// Line length and indentation statistics, when the search was asked for them.
+    pub metrics: Option<Metrics>,
+    /// Set when `lines` was estimated from samples rather than counted.
+    pub estimate: Option<Estimate>,
+}
+
+impl FileLines {
+    /// A file whose line count is known without reading it.
+    fn unread(path: &Path, lines: usize) -> Self {
+        FileLines {
+            path: path.to_owned(),
+            lines,
+            digest: None,
+            metrics: None,
+            estimate: None,
+        }
+    }
+
+    /// Reports `added` lines in place of the counted ones, when there is a count of them.
+    fn with_added(mut self, added: Option<usize>) -> Self {
+        if let Some(added) = added {
+            self.lines = added;
+        }
+        self
+    }
+}
+
+#[derive(Error, Debug)]
+#[error("{}: {source}", escaped_path(path))]
+pub struct FileError {
+    pub path: PathBuf,
+    #[source]
+    pub source: io::Error,
+}
+
+/// What to do with a matched file that can't be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorPolicy {
+    /// Report the error and stop the search.
+    #[default]
+    Abort,
+    /// Leave the file out of the results.
+    Skip,
+}
+
+/// What a search ended up looking at.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SearchSummary {
+    pub files: usize,
+    pub lines: usize,
+    /// The walk was cut short by the interrupt flag, results cover only part of the tree.
+    pub interrupted: bool,
+    /// The time budget ran out, results cover only part of the tree.
+    pub truncated: bool,
+    /// One of the search's limits was reached, results cover only part of the tree.
+    pub limit: Option<Limit>,
+}
+
+/// What a full search would read, see [`Search::cost`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ScanCost {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// What made a search stop before the walk was done, besides the time budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// As many files as [`SearchBuilder::max_results`] allows were reported, and more matched.
+    Results,
+    /// The walk looked at as many files as [`SearchBuilder::max_files_scanned`] allows,
+    /// and there were more.
+    FilesScanned,
+}
+
+impl Limit {
+    /// The option setting the limit, in snake case.
+    pub fn name(self) -> &'static str {
+        match self {
+            Limit::Results => "max_results",
+            Limit::FilesScanned => "max_files_scanned",
+        }
+    }
+}
+
+/// Configures a [`Search`], only the root and the filter are required.
+#[derive(Debug, Clone)]
+pub struct SearchBuilder {
+    search: Search,
+}
+
+impl SearchBuilder {
+    pub fn new(root: impl Into<PathBuf>, filter: Filter) -> Self {
+        SearchBuilder {
+            search: Search {
+                root: root.into(),
+                filter,
+                fs: Arc::new(RealFs),
+                follow_links: true,
+                max_depth: None,
+                skip_hidden: false,
+                io_threads: default_io_threads(),
+                max_open_files: MAX_OPEN_FILES,
+                digest: None,
+                long_line: None,
+                estimate_above: None,
+                delimiter: Delimiter::default(),
+                max_time: None,
+                max_results: None,
+                max_files_scanned: None,
+                fail_fast: false,
+                predicate: None,
+                changes: None,
+                added_lines: false,
+                offsets: None,
+                registry: Registry::new(),
+                error_policy: ErrorPolicy::default(),
+                interrupt: Arc::new(AtomicBool::new(false)),
+            },
+        }
+    }
+
+    /// Where the tree lives, the local disk unless set.
+    pub fn file_system(mut self, fs: Arc<dyn FileSystem>) -> Self {
+        self.search.fs = fs;
+        self
+    }
+
+    pub fn follow_links(mut self, follow_links: bool) -> Self {
+        self.search.follow_links = follow_links;
+        self
+    }
+
+    /// Entries deeper than `depth` below the root are not visited, the root itself is depth 0.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.search.max_depth = Some(depth);
+        self
+    }
+
+    /// Leave out hidden and system entries and everything below them, the root is always
+    /// searched. See [`Metadata::hidden`](fs::Metadata::hidden) for what counts as hidden.
+    pub fn skip_hidden(mut self, skip_hidden: bool) -> Self {
+        self.search.skip_hidden = skip_hidden;
+        self
+    }
+
+    /// Number of threads counting lines, at least one is always used.
+    pub fn io_threads(mut self, io_threads: usize) -> Self {
+        self.search.io_threads = io_threads.max(1);
+        self
+    }
+
+    /// Files and directories the counting threads and the walk may have open together, at
+    /// least one. Threads wait for one another beyond that, however many there are.
+    pub fn max_open_files(mut self, max: usize) -> Self {
+        self.search.max_open_files = max.max(1);
+        self
+    }
+
+    /// Hash the content of every matched file while counting it.
+    pub fn digest(mut self, digest: DigestKind) -> Self {
+        self.search.digest = Some(digest);
+        self
+    }
+
+    /// Collect [`Metrics`] for every matched file, lines over `long_line` bytes count as long.
+    /// Metrics always measure lines ended by `\n`, see [`SearchBuilder::delimiter`].
+    pub fn metrics(mut self, long_line: usize) -> Self {
+        self.search.long_line = Some(long_line);
+        self
+    }
+
+    /// Count records ended by `delimiter` as the lines of a file, `\n` by default. A record
+    /// without one at the end of a file still counts.
+    pub fn delimiter(mut self, delimiter: Delimiter) -> Self {
+        self.search.delimiter = delimiter;
+        self
+    }
+
+    /// Estimate the line count of files over `len` bytes from blocks spread over them instead
+    /// of reading them whole, see [`estimate`]. Files are always read whole for a digest or
+    /// metrics.
+    pub fn estimate(mut self, len: u64) -> Self {
+        self.search.estimate_above = Some(len);
+        self
+    }
+
+    /// Stop walking once `budget` has passed, the tree is then walked breadth-first so that
+    /// partial results cover the shallow directories.
+    pub fn max_time(mut self, budget: Duration) -> Self {
+        self.search.max_time = Some(budget);
+        self
+    }
+
+    /// Report at most `max` files, the search stops once it has them.
+    pub fn max_results(mut self, max: usize) -> Self {
+        self.search.max_results = Some(max);
+        self
+    }
+
+    /// Stop walking after `max` files, matched or not, have been looked at.
+    pub fn max_files_scanned(mut self, max: usize) -> Self {
+        self.search.max_files_scanned = Some(max);
+        self
+    }
+
+    /// End the search with the first error of any kind, including directories that can't
+    /// be listed, which are passed over otherwise. Overrides [`ErrorPolicy::Skip`].
+    pub fn fail_fast(mut self, fail_fast: bool) -> Self {
+        self.search.fail_fast = fail_fast;
+        self
+    }
+
+    /// Keep only the counted files `predicate` accepts, a file it fails on is an error
+    /// handled by the [`ErrorPolicy`].
+    pub fn predicate(mut self, predicate: Predicate) -> Self {
+        self.search.predicate = Some(predicate);
+        self
+    }
+
+    /// Look only at the files in `changes`, see [`Changes::since`]. The filter still decides
+    /// which of them match.
+    pub fn changes(mut self, changes: Changes) -> Self {
+        self.search.changes = Some(Arc::new(changes));
+        self
+    }
+
+    /// With [`SearchBuilder::changes`], report the lines each file gained instead of all its
+    /// lines. Files are then only read for a digest or metrics, which cover the whole file.
+    pub fn added_lines(mut self, added_lines: bool) -> Self {
+        self.search.added_lines = added_lines;
+        self
+    }
+
+    /// Count files on from where `offsets` says their last count ended, and remember where
+    /// this one ends, see [`Offsets`]. Files are always read whole for a digest or metrics.
+    pub fn offsets(mut self, offsets: Arc<Offsets>) -> Self {
+        self.search.offsets = Some(offsets);
+        self
+    }
+
+    /// Count the files `registry` has a strategy for with it, whole, and the rest as plain
+    /// text. The lines of those files are then their records, never estimated, resumed or
+    /// taken from [`SearchBuilder::added_lines`].
+    pub fn registry(mut self, registry: Registry) -> Self {
+        self.search.registry = registry;
+        self
+    }
+
+    pub fn error_policy(mut self, error_policy: ErrorPolicy) -> Self {
+        self.search.error_policy = error_policy;
+        self
+    }
+
+    /// Setting `interrupt` stops a running search, files already being counted are still reported.
+    pub fn interrupt(mut self, interrupt: Arc<AtomicBool>) -> Self {
+        self.search.interrupt = interrupt;
+        self
+    }
+
+    pub fn build(self) -> Search {
+        self.search
+    }
+}
+
+/// A configured search which can be run any number of times.
+#[derive(Debug, Clone)]
+pub struct Search {
+    root: PathBuf,
+    filter: Filter,
+    fs: Arc<dyn FileSystem>,
+    follow_links: bool,
+    max_depth: Option<usize>,
+    skip_hidden: bool,
+    io_threads: usize,
+    max_open_files: usize,
+    digest: Option<DigestKind>,
+    long_line: Option<usize>,
+    estimate_above: Option<u64>,
+    delimiter: Delimiter,
+    max_time: Option<Duration>,
+    max_results: Option<usize>,
+    max_files_scanned: Option<usize>,
+    fail_fast: bool,
+    predicate: Option<Predicate>,
+    changes: Option<Arc<Changes>>,
+    added_lines: bool,
+    offsets: Option<Arc<Offsets>>,
+    registry: Registry,
+    error_policy: ErrorPolicy,
+    interrupt: Arc<AtomicBool>,
+}
+
+impl Search {
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Collects a line count for every matched file, in walk order.
+    pub fn run(&self) -> Result<Vec<FileLines>, FileError> {
+        self.stream().collect()
+    }
+
+    /// Totals over all matched files without keeping them around.
+    pub fn count(&self) -> Result<SearchSummary, FileError> {
+        let mut results = self.stream();
+        for file in &mut results {
+            file?;
+        }
+        Ok(results.summary())
+    }
+
+    /// Starts the walk in the background and yields the matched files in walk order.
+    ///
+    /// Under [`ErrorPolicy::Abort`] the first error is yielded and ends the stream.
+    pub fn stream(&self) -> Results {
+        let stop = Arc::new(AtomicBool::new(false));
+        let truncated = Arc::new(AtomicBool::new(false));
+        let limit = Arc::new(OnceLock::new());
+        let deadline = self.max_time.map(|budget| Instant::now() + budget);
+        let open_files = Arc::new(Semaphore::new(self.max_open_files));
+
+        let (path_tx, path_rx) = mpsc::channel::<(usize, Entry)>();
+        let path_rx = Arc::new(Mutex::new(path_rx));
+        let (res_tx, res_rx) = mpsc::channel();
+
+        let mut threads: Vec<_> = (0..self.io_threads)
+            .map(|_| {
+                let path_rx = Arc::clone(&path_rx);
+                let res_tx = res_tx.clone();
+                let open_files = Arc::clone(&open_files);
+                let fs = Arc::clone(&self.fs);
+                let (digest, long_line) = (self.digest, self.long_line);
+                let (estimate_above, offsets) = (self.estimate_above(), self.offsets());
+                let (delimiter, registry) = (self.delimiter.clone(), self.registry.clone());
+                let predicate = self.predicate.clone();
+                let (root, added_from) = (self.root.clone(), self.added_from());
+                let stopped = self.stopped(&stop, &truncated, deadline);
+                thread::spawn(move || loop {
+                    if stopped() {
+                        break;
+                    }
+                    let next = path_rx.lock().unwrap().recv();
+                    let Ok((idx, entry)) = next else {
+                        break;
+                    };
+                    let counted = {
+                        let _permit = open_files.acquire();
+                        let (fs, path) = (fs.as_ref(), &entry.path);
+                        let added = added_from.as_ref().map(|from| from.added(&root, path));
+                        let strategy = registry.strategy(path);
+                        match (strategy, added, &offsets, estimate_above) {
+                            (Some(strategy), ..) => count::count_records(
+                                fs, path, strategy, digest, long_line, &delimiter,
+                            ),
+                            (_, Some(lines), _, _) if digest.is_none() && long_line.is_none() => {
+                                Ok(FileLines::unread(path, lines))
+                            }
+                            (_, _, Some(offsets), _) => offsets.count(fs, path, &delimiter),
+                            (_, _, _, Some(len)) if entry.len > len => {
+                                estimate::estimate_lines(fs, path, entry.len, &delimiter)
+                            }
+                            _ => count::count_lines(fs, path, digest, long_line, &delimiter),
+                        }
+                        .map(|file| file.with_added(added))
+                    };
+                    let counted = counted.and_then(|file| match &predicate {
+                        Some(predicate) => match predicate.includes(&entry, file.lines) {
+                            Ok(keep) => Ok(keep.then_some(file)),
+                            Err(err) => Err(io::Error::other(err)),
+                        },
+                        None => Ok(Some(file)),
+                    });
+                    let counted = counted.map_err(|source| FileError {
+                        path: entry.path,
+                        source,
+                    });
+                    if res_tx.send((idx, counted)).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+
+        let walk = self.walk().open_files(Arc::clone(&open_files));
+        let (fs, filter) = (Arc::clone(&self.fs), self.filter.clone());
+        let (root, changes) = (self.root.clone(), self.changes.clone());
+        let stopped = self.stopped(&stop, &truncated, deadline);
+        let (fail_fast, max_files_scanned) = (self.fail_fast, self.max_files_scanned);
+        let scan_limit = Arc::clone(&limit);
+        threads.push(thread::spawn(move || {
+            let (mut idx, mut scanned) = (0, 0);
+            for entry in walk {
+                if stopped() {
+                    break;
+                }
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    // Sent in walk order like a counted file, so it ends the results there.
+                    Err(err) if fail_fast => {
+                        let _ = res_tx.send((idx, Err(err)));
+                        break;
+                    }
+                    Err(_) => continue,
+                };
+                if entry.is_file() {
+                    if max_files_scanned.is_some_and(|max| scanned >= max) {
+                        let _ = scan_limit.set(Limit::FilesScanned);
+                        break;
+                    }
+                    scanned += 1;
+                }
+                // Deciding by type reads the start of the file.
+                let permit = filter.needs_content().then(|| open_files.acquire());
+                if !filter.decide(&entry, fs.as_ref()).is_included() {
+                    continue;
+                }
+                drop(permit);
+                if changes
+                    .as_ref()
+                    .is_some_and(|c| !c.contains(&root, &entry.path))
+                {
+                    continue;
+                }
+                if path_tx.send((idx, entry)).is_err() {
+                    break;
+                }
+                idx += 1;
+            }
+        }));
+
+        Results {
+            rx: res_rx,
+            pending: BTreeMap::new(),
+            next_idx: 0,
+            summary: SearchSummary::default(),
+            error_policy: if self.fail_fast {
+                ErrorPolicy::Abort
+            } else {
+                self.error_policy
+            },
+            max_results: self.max_results,
+            interrupt: Arc::clone(&self.interrupt),
+            truncated,
+            limit,
+            stop,
+            threads,
+        }
+    }
+
+    /// The filter decision for every visited entry, without counting any lines.
+    pub fn explain(&self) -> impl Iterator<Item = Result<(PathBuf, Decision), FileError>> + '_ {
+        self.walk().map(|entry| {
+            entry.map(|entry| {
+                let decision = self.filter.decide(&entry, self.fs.as_ref());
+                (entry.path, decision)
+            })
+        })
+    }
+
+    /// The files [`Search::stream`] would count and their total size, from the walk alone.
+    ///
+    /// No file is opened, so a filter by type can't look at the contents and every file
+    /// counts: the cost is an upper bound then. The limits of the search are left out.
+    pub fn cost(&self) -> Result<ScanCost, FileError> {
+        let mut cost = ScanCost::default();
+        for entry in self.walk() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) if self.fail_fast => return Err(err),
+                Err(_) => continue,
+            };
+            let included = if self.filter.needs_content() {
+                entry.is_file()
+            } else {
+                self.filter.decide(&entry, self.fs.as_ref()).is_included()
+            };
+            let changed = self
+                .changes
+                .as_ref()
+                .is_none_or(|changes| changes.contains(&self.root, &entry.path));
+            if included && changed {
+                cost.files += 1;
+                cost.bytes += entry.len;
+            }
+        }
+        Ok(cost)
+    }
+
+    /// The directories with no matched file anywhere below them, each once its whole
+    /// subtree has been walked, deepest first. Only the filter decides what matched, no
+    /// lines are counted. The walk is depth-first even with a time budget.
+    pub fn empty_dirs(&self) -> impl Iterator<Item = Result<PathBuf, FileError>> + '_ {
+        EmptyDirs::new(self.depth_first_walk(), |entry| {
+            self.filter.decide(entry, self.fs.as_ref()).is_included()
+        })
+    }
+
+    fn walk(&self) -> Walk {
+        let walk = self.depth_first_walk();
+        if self.max_time.is_some() {
+            walk.breadth_first()
+        } else {
+            walk
+        }
+    }
+
+    fn depth_first_walk(&self) -> Walk {
+        let mut walk = Walk::new(
+            Arc::clone(&self.fs),
+            &self.root,
+            self.follow_links,
+            self.max_depth,
+        );
+        if self.skip_hidden {
+            walk = walk.skip_hidden();
+        }
+        walk
+    }
+
+    /// The changes to count added lines from, with [`SearchBuilder::added_lines`].
+    fn added_from(&self) -> Option<Arc<Changes>> {
+        self.changes.clone().filter(|_| self.added_lines)
+    }
+
+    /// The size over which files are sampled, unless every byte is needed anyway.
+    fn estimate_above(&self) -> Option<u64> {
+        self.estimate_above
+            .filter(|_| self.digest.is_none() && self.long_line.is_none())
+    }
+
+    /// Where counts resume from, unless every byte is needed anyway.
+    fn offsets(&self) -> Option<Arc<Offsets>> {
+        self.offsets
+            .clone()
+            .filter(|_| self.digest.is_none() && self.long_line.is_none())
+    }
+
+    /// Checked by every search thread, running past `deadline` marks the search as truncated.
+    fn stopped(
+        &self,
+        stop: &Arc<AtomicBool>,
+        truncated: &Arc<AtomicBool>,
+        deadline: Option<Instant>,
+    ) -> impl Fn() -> bool {
+        let (stop, truncated) = (Arc::clone(stop), Arc::clone(truncated));
+        let interrupt = Arc::clone(&self.interrupt);
+        move || {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                truncated.store(true, Ordering::Relaxed);
+            }
+            stop.load(Ordering::Relaxed)
+                || interrupt.load(Ordering::Relaxed)
+                || truncated.load(Ordering::Relaxed)
+        }
+    }
+}
+
+type Counted = Result<Option<FileLines>, FileError>;
+
+/// Iterator over the files of a running [`Search`].
+///
+/// Dropping it stops the search.
+pub struct Results {
+    /// `None` for a file the predicate left out.
+    rx: mpsc::Receiver<(usize, Counted)>,
+    /// Workers finish out of order, results wait here until their turn in walk order comes.
+    pending: BTreeMap<usize, Counted>,
+    next_idx: usize,
+    summary: SearchSummary,
+    error_policy: ErrorPolicy,
+    max_results: Option<usize>,
+    interrupt: Arc<AtomicBool>,
+    truncated: Arc<AtomicBool>,
+    /// The first limit reached, set by the walk or by `next`.
+    limit: Arc<OnceLock<Limit>>,
+    stop: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Results {
+    /// Totals over the files yielded so far.
+    pub fn summary(&self) -> SearchSummary {
+        SearchSummary {
+            interrupted: self.interrupt.load(Ordering::Relaxed),
+            truncated: self.truncated.load(Ordering::Relaxed),
+            limit: self.limit.get().copied(),
+            ..self.summary.clone()
+        }
+    }
+
+    fn next_in_order(&mut self) -> Option<Counted> {
+        loop {
+            if let Some(counted) = self.pending.remove(&self.next_idx) {
+                self.next_idx += 1;
+                return Some(counted);
+            }
+            match self.rx.recv() {
+                Ok((idx, counted)) => {
+                    self.pending.insert(idx, counted);
+                }
+                // After an interrupt some files were never counted, flush what arrived after the gap.
+                Err(_) => return self.pending.pop_first().map(|(_, counted)| counted),
+            }
+        }
+    }
+}
+
+impl Iterator for Results {
+    type Item = Result<FileLines, FileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.stop.load(Ordering::Relaxed) {
+            return None;
+        }
+        loop {
+            let counted = self.next_in_order()?;
+            let full = self
+                .max_results
+                .is_some_and(|max| self.summary.files >= max);
+            // Only a limit when there was something more to report.
+            let more = match &counted {
+                Ok(file) => file.is_some(),
+                Err(_) => self.error_policy == ErrorPolicy::Abort,
+            };
+            if full && more {
+                let _ = self.limit.set(Limit::Results);
+                self.stop.store(true, Ordering::Relaxed);
+                return None;
+            }
+            match counted {
+                Ok(None) => continue,
+                Ok(Some(file)) => {
+                    self.summary.files += 1;
+                    self.summary.lines += file.lines;
+                    return Some(Ok(file));
+                }
+                Err(_) if self.error_policy == ErrorPolicy::Skip => continue,
+                Err(err) => {
+                    self.stop.store(true, Ordering::Relaxed);
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Results {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for thread in self.threads.drain(..) {
+            thread.join().expect("search thread panicked");
+        }
+    }
+}
+
+/// `path` relative to the search `root`, the file name when the root itself matched.
+pub fn relative_path<'a>(root: &Path, path: &'a Path) -> &'a Path {
+    match path.strip_prefix(root) {
+        Ok(rel) if !rel.as_os_str().is_empty() => rel,
+        _ => Path::new(path.file_name().unwrap_or(path.as_os_str())),
+    }
+}
+
+/// `path` as text, bytes that aren't UTF-8 are written as `\xNN` so that no two names
+/// come out the same. Extended-length paths lose their prefix where Windows allows it,
+/// see [`fs::simplified`].
+pub fn escaped_path(path: &Path) -> Cow<'_, str> {
+    match fs::simplified(path) {
+        Cow::Borrowed(path) => escaped(path),
+        Cow::Owned(path) => Cow::Owned(escaped(&path).into_owned()),
+    }
+}
+
+fn escaped(path: &Path) -> Cow<'_, str> {
+    let bytes = path.as_os_str().as_encoded_bytes();
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Cow::Borrowed(text);
+    }
+    let mut text = String::with_capacity(bytes.len() + 8);
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        for byte in chunk.invalid() {
+            let _ = write!(text, "\\x{:02x}", byte);
+        }
+    }
+    Cow::Owned(text)
+}
+
+/// Counting semaphore, `acquire` blocks until a permit is available.
+struct Semaphore {
+    permits: Mutex<usize>,
+    freed: Condvar,
+}
+
+struct Permit<'a>(&'a Semaphore);
+
+impl Semaphore {
+    fn new(permits: usize) -> Self {
+        Semaphore {
+            permits: Mutex::new(permits),
+            freed: Condvar::new(),
+        }
+    }
+
+    fn acquire(&self) -> Permit<'_> {
+        let mut permits = self.permits.lock().unwrap();
+        while *permits == 0 {
+            permits = self.freed.wait(permits).unwrap();
+        }
+        *permits -= 1;
+        Permit(self)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.0.permits.lock().unwrap() += 1;
+        self.0.freed.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        borrow::Cow,
+        io,
+        path::{Path, PathBuf},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use crate::{
+        task4::{
+            churn::Changes,
+            escaped_path,
+            fs::{FileSystem, MemoryFs, Metadata},
+            predicate::Predicate,
+            ErrorPolicy, FileLines, FileType, Filter, Limit, ScanCost, SearchBuilder, Semaphore,
+        },
+        task_1_and_2::{asm, program::Program},
+    };
+
+    fn tree() -> Arc<MemoryFs> {
+        Arc::new(
+            MemoryFs::new()
+                .file("root/a.rs", "1\n2\n")
+                .file("root/b/c.rs", "1\n")
+                .file("root/b/d/e.rs", "1")
+                .file("root/f.txt", "1\n")
+                .file("root/run", "#!/bin/sh\necho\n"),
+        )
+    }
+
+    #[test]
+    fn search_runs_in_walk_order_and_counts() {
+        let builder = SearchBuilder::new("root", Filter::new("rs"))
+            .file_system(tree())
+            .io_threads(3);
+
+        let found = builder.clone().build().run().unwrap();
+        let expected =
+            [("root/a.rs", 2), ("root/b/c.rs", 1), ("root/b/d/e.rs", 1)].map(|(path, lines)| {
+                FileLines {
+                    path: path.into(),
+                    lines,
+                    digest: None,
+                    metrics: None,
+                    estimate: None,
+                }
+            });
+        assert_eq!(found, expected);
+
+        let summary = builder.clone().max_depth(2).build().count().unwrap();
+        assert_eq!((summary.files, summary.lines), (2, 3));
+
+        let summary = builder.max_time(Duration::ZERO).build().count().unwrap();
+        assert!(summary.truncated);
+        assert_eq!(summary.files, 0);
+    }
+
+    #[test]
+    fn search_by_sniffed_type() {
+        let found = SearchBuilder::new("root", Filter::by_type(FileType::Script))
+            .file_system(tree())
+            .build()
+            .run()
+            .unwrap();
+        assert_eq!(
+            found,
+            [FileLines {
+                path: "root/run".into(),
+                lines: 2,
+                digest: None,
+                metrics: None,
+                estimate: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn costs_what_the_walk_finds() {
+        let cost = |filter| {
+            SearchBuilder::new("root", filter)
+                .file_system(tree())
+                .build()
+                .cost()
+                .unwrap()
+        };
+        assert_eq!(cost(Filter::new("rs")), ScanCost { files: 3, bytes: 7 });
+        // Not sniffed, so every file.
+        assert_eq!(
+            cost(Filter::by_type(FileType::Script)),
+            ScanCost {
+                files: 5,
+                bytes: 24
+            }
+        );
+    }
+
+    #[test]
+    fn search_error_policy() {
+        let fs = Arc::new(
+            MemoryFs::new()
+                .file("root/ok.rs", "1\n")
+                .unreadable("root/no.rs"),
+        );
+        let builder = SearchBuilder::new("root", Filter::new("rs")).file_system(fs);
+
+        let err = builder.clone().build().run().unwrap_err();
+        assert_eq!(err.path, std::path::Path::new("root/no.rs"));
+        let summary = builder
+            .error_policy(ErrorPolicy::Skip)
+            .build()
+            .count()
+            .unwrap();
+        assert_eq!(summary.files, 1);
+    }
+
+    #[test]
+    fn search_keeps_what_the_predicate_accepts() {
+        // At most one directory below the root, or more than one line.
+        let program = asm::parse(
+            "LoadVal 3\nReadVar depth\nLt\nLoadVal 1\nReadVar lines\nGt\nAdd\nReturnValue",
+        )
+        .unwrap();
+        let search = SearchBuilder::new("root", Filter::new("rs"))
+            .file_system(tree())
+            .predicate(Predicate::new(Program::new(program)))
+            .build();
+        let found: Vec<_> = search.run().unwrap().into_iter().map(|f| f.path).collect();
+        assert_eq!(found, ["root/a.rs", "root/b/c.rs"].map(PathBuf::from));
+        assert_eq!(search.count().unwrap().files, 2);
+    }
+
+    #[test]
+    fn inverts_the_filter_and_lists_empty_directories() {
+        let builder =
+            SearchBuilder::new("root", Filter::new("rs").invert(true)).file_system(tree());
+        let found: Vec<_> = builder.clone().build().run().unwrap();
+        let found: Vec<_> = found.into_iter().map(|f| f.path).collect();
+        assert_eq!(found, ["root/f.txt", "root/run"].map(PathBuf::from));
+        let decision = Filter::new("rs")
+            .invert(true)
+            .decide_parts("a.rs".as_ref(), true, None);
+        assert_eq!(decision.to_string(), "exclude: inverted, extension is .rs");
+
+        let empty = |builder: SearchBuilder| {
+            let dirs: Result<Vec<_>, _> = builder.build().empty_dirs().collect();
+            dirs.unwrap()
+        };
+        assert_eq!(empty(builder), ["root/b/d", "root/b"].map(PathBuf::from));
+        let fs = Arc::new(MemoryFs::new().file("root/a/b.rs", "").dir("root/c"));
+        let builder = SearchBuilder::new("root", Filter::new("rs")).file_system(fs);
+        assert_eq!(empty(builder), [PathBuf::from("root/c")]);
+    }
+
+    #[test]
+    fn counts_only_changed_files_and_their_added_lines() {
+        let fs = Arc::new(
+            MemoryFs::new()
+                .file("root/a.rs", "1\n2\n3\n")
+                .file("root/b.rs", "1\n")
+                .file("root/c.txt", "1\n"),
+        );
+        let changes = Changes::parse(b"1\t0\ta.rs\x002\t2\tc.txt\x00").unwrap();
+        let builder = SearchBuilder::new("root", Filter::new("rs"))
+            .file_system(fs)
+            .changes(changes);
+        let counts = |builder: SearchBuilder| -> Vec<_> {
+            let found = builder.build().run().unwrap();
+            found.into_iter().map(|f| (f.path, f.lines)).collect()
+        };
+        assert_eq!(counts(builder.clone()), [(PathBuf::from("root/a.rs"), 3)]);
+        assert_eq!(
+            counts(builder.added_lines(true)),
+            [(PathBuf::from("root/a.rs"), 1)]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn matches_and_shows_names_that_are_not_utf8() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::Path};
+
+        let name = OsStr::from_bytes(b"caf\xe9.rs");
+        assert!(Filter::new("rs")
+            .decide_parts(name, true, None)
+            .is_included());
+        assert!(!Filter::new("rs")
+            .decide_parts(OsStr::from_bytes(b"caf\xe9.r"), true, None)
+            .is_included());
+        assert_eq!(escaped_path(Path::new(name)), "caf\\xe9.rs");
+        assert!(matches!(
+            escaped_path(Path::new("é.rs")),
+            Cow::Borrowed("é.rs")
+        ));
+    }
+
+    #[test]
+    fn stops_at_the_result_and_scan_limits() {
+        let builder = SearchBuilder::new("root", Filter::new("rs"))
+            .file_system(tree())
+            .io_threads(2);
+        let summary = builder.clone().max_results(2).build().count().unwrap();
+        assert_eq!((summary.files, summary.limit), (2, Some(Limit::Results)));
+        // Exactly as many as there are is not cut short.
+        let summary = builder.clone().max_results(3).build().count().unwrap();
+        assert_eq!((summary.files, summary.limit), (3, None));
+        // a.rs and b/c.rs are the first two files the walk finds.
+        let summary = builder
+            .clone()
+            .max_files_scanned(2)
+            .build()
+            .count()
+            .unwrap();
+        assert_eq!(
+            (summary.files, summary.limit),
+            (2, Some(Limit::FilesScanned))
+        );
+        let summary = builder.max_files_scanned(5).build().count().unwrap();
+        assert_eq!((summary.files, summary.limit), (3, None));
+    }
+
+    #[test]
+    fn fails_fast_on_any_error() {
+        let missing = SearchBuilder::new("missing", Filter::new("rs")).file_system(tree());
+        assert_eq!(missing.clone().build().count().unwrap().files, 0);
+        assert!(missing.fail_fast(true).build().count().is_err());
+
+        let unreadable = MemoryFs::new().unreadable("root/no.rs");
+        let skipping = SearchBuilder::new("root", Filter::new("rs"))
+            .file_system(Arc::new(unreadable))
+            .error_policy(ErrorPolicy::Skip);
+        assert!(skipping.clone().build().count().is_ok());
+        assert!(skipping.fail_fast(true).build().count().is_err());
+    }
+
+    #[test]
+    fn skips_hidden_entries_when_asked() {
+        let fs = Arc::new(
+            MemoryFs::new()
+                .file("root/a.rs", "1\n")
+                .file("root/.b.rs", "1\n")
+                .file("root/.cache/c.rs", "1\n"),
+        );
+        let builder = SearchBuilder::new("root", Filter::new("rs")).file_system(fs);
+        assert_eq!(builder.clone().build().count().unwrap().files, 3);
+        assert_eq!(builder.skip_hidden(true).build().count().unwrap().files, 1);
+    }
+
+    #[test]
+    fn matches_extensions_in_any_case_when_asked() {
+        let upper = std::ffi::OsStr::new("MAIN.RS");
+        let decide = |filter: Filter| filter.decide_parts(upper, true, None).is_included();
+        assert!(decide(Filter::new("rs").ignore_case(true)));
+        assert!(decide(Filter::new("Rs").ignore_case(true)));
+        assert!(!decide(Filter::new("rs").ignore_case(false)));
+        assert_eq!(decide(Filter::new("rs")), cfg!(windows));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn shows_extended_length_paths_plainly() {
+        use std::path::Path;
+
+        assert_eq!(escaped_path(Path::new(r"\\?\C:\src\a.rs")), r"C:\src\a.rs");
+        assert!(matches!(
+            escaped_path(Path::new(r"C:\src\a.rs")),
+            Cow::Borrowed(r"C:\src\a.rs")
+        ));
+        // Without the prefix the trailing dot would be dropped and name another file.
+        assert_eq!(escaped_path(Path::new(r"\\?\C:\src\a.")), r"\\?\C:\src\a.");
+    }
+
+    /// Counts how many files and directories are open at once, and how many were at most.
+    #[derive(Debug)]
+    struct Tracked {
+        fs: Arc<MemoryFs>,
+        open: Arc<AtomicUsize>,
+        most: Arc<AtomicUsize>,
+    }
+
+    struct Handle(Box<dyn io::Read + Send>, Arc<AtomicUsize>);
+
+    impl Tracked {
+        fn opened(&self) {
+            let now = self.open.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(2));
+        }
+    }
+
+    impl FileSystem for Tracked {
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+            self.opened();
+            let children = self.fs.read_dir(path);
+            self.open.fetch_sub(1, Ordering::SeqCst);
+            children
+        }
+
+        fn metadata(&self, path: &Path, follow_links: bool) -> io::Result<Metadata> {
+            self.fs.metadata(path, follow_links)
+        }
+
+        fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+            self.fs.canonicalize(path)
+        }
+
+        fn open(&self, path: &Path) -> io::Result<Box<dyn io::Read + Send>> {
+            let file = self.fs.open(path)?;
+            self.opened();
+            Ok(Box::new(Handle(file, Arc::clone(&self.open))))
+        }
+    }
+
+    impl io::Read for Handle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Drop for Handle {
+        fn drop(&mut self) {
+            self.1.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn bounds_open_files_whatever_the_threads() {
+        let fs = (0..20).fold(MemoryFs::new(), |fs, i| {
+            fs.file(format!("root/{}/a.rs", i % 4), "1\n")
+                .file(format!("root/{}.rs", i), "1\n")
+        });
+        let most = Arc::new(AtomicUsize::new(0));
+        let tracked = Tracked {
+            fs: Arc::new(fs),
+            open: Arc::default(),
+            most: Arc::clone(&most),
+        };
+        let summary = SearchBuilder::new("root", Filter::new("rs"))
+            .file_system(Arc::new(tracked))
+            .io_threads(8)
+            .max_open_files(2)
+            .build()
+            .count()
+            .unwrap();
+        assert_eq!(summary.files, 24);
+        assert!(most.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn semaphore_bounds_concurrent_holders() {
+        let sem = Arc::new(Semaphore::new(2));
+        let held = Arc::new(AtomicUsize::new(0));
+        let max_held = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (sem, held, max_held) = (sem.clone(), held.clone(), max_held.clone());
+                thread::spawn(move || {
+                    let _permit = sem.acquire();
+                    let now = held.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_held.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(5));
+                    held.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert!(max_held.load(Ordering::SeqCst) <= 2);
+    }
 }
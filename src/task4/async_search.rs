@@ -0,0 +1,343 @@
+use std::{
+    collections::VecDeque,
+    io::{self, SeekFrom},
+    path::Path,
+    pin::Pin,
+    sync::{atomic::Ordering, Arc},
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use futures_core::Stream;
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::mpsc,
+};
+
+use super::{
+    count::{Delimiter, DigestKind, Tally},
+    estimate::{self, Sampler},
+    filetype::{self, Detection},
+    fs::{self as local, EntryKind},
+    records::{PlainText, Records, Strategy},
+    walk::Entry,
+    ErrorPolicy, FileError, FileLines, Search,
+};
+
+/// How many counted files may wait in the stream before the walk pauses.
+const STREAM_BUFFER: usize = 64;
+
+impl Search {
+    /// Async counterpart of [`Search::stream`], yields matched files as they get counted.
+    ///
+    /// The walk runs as a task on the current tokio runtime and stops once the stream is
+    /// dropped. Files come in the order an asynchronous walk finds them, not in walk order.
+    /// The result and scan limits end the stream the same way they end [`Search::stream`].
+    pub fn stream_async(&self) -> SearchStream {
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(walk(self.clone(), tx));
+        SearchStream { rx }
+    }
+}
+
+pub struct SearchStream {
+    rx: mpsc::Receiver<Result<FileLines, FileError>>,
+}
+
+impl Stream for SearchStream {
+    type Item = Result<FileLines, FileError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+async fn walk(search: Search, tx: mpsc::Sender<Result<FileLines, FileError>>) {
+    let deadline = search.max_time.map(|budget| Instant::now() + budget);
+    let root = match fs::canonicalize(&search.root).await {
+        Ok(canonical) => Arc::new(vec![canonical]),
+        Err(source) if search.fail_fast => {
+            let path = search.root.clone();
+            let _ = tx.send(Err(FileError { path, source })).await;
+            return;
+        }
+        Err(_) => return,
+    };
+    // Each directory with the canonical paths of itself and the ones above, like the
+    // blocking walk keeps them to notice symlink loops.
+    let mut dirs = VecDeque::from([(search.root.clone(), 0, root)]);
+    let (mut scanned, mut reported) = (0, 0);
+    loop {
+        // Breadth-first under a time budget, like the blocking walk.
+        let next = if deadline.is_some() {
+            dirs.pop_front()
+        } else {
+            dirs.pop_back()
+        };
+        let Some((dir, depth, ancestors)) = next else {
+            return;
+        };
+        if search.interrupt.load(Ordering::Relaxed)
+            || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            return;
+        }
+        if search.max_depth.is_some_and(|max| depth >= max) {
+            continue;
+        }
+        // Unreadable directories are skipped, same as the blocking walk does.
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(source) if search.fail_fast => {
+                let _ = tx.send(Err(FileError { path: dir, source })).await;
+                return;
+            }
+            Err(_) => continue,
+        };
+        loop {
+            let entry = match entries.next_entry().await {
+                Ok(Some(entry)) => entry,
+                Ok(None) => break,
+                Err(source) if search.fail_fast => {
+                    let _ = tx.send(Err(FileError { path: dir, source })).await;
+                    return;
+                }
+                Err(_) => break,
+            };
+            let path = entry.path();
+            let meta = if search.follow_links {
+                fs::metadata(&path).await
+            } else {
+                fs::symlink_metadata(&path).await
+            };
+            let meta = match meta {
+                Ok(meta) => meta,
+                Err(source) if search.fail_fast => {
+                    let _ = tx.send(Err(FileError { path, source })).await;
+                    return;
+                }
+                Err(_) => continue,
+            };
+            if search.skip_hidden && local::is_hidden(&path, &meta) {
+                continue;
+            }
+            if meta.is_dir() {
+                let canonical = match fs::canonicalize(&path).await {
+                    Ok(canonical) if ancestors.contains(&canonical) => {
+                        Err(io::Error::other("file system loop found"))
+                    }
+                    canonical => canonical,
+                };
+                match canonical {
+                    Ok(canonical) => {
+                        let ancestors = Arc::new([ancestors.as_slice(), &[canonical]].concat());
+                        dirs.push_back((path.clone(), depth + 1, ancestors));
+                    }
+                    Err(source) if search.fail_fast => {
+                        let _ = tx.send(Err(FileError { path, source })).await;
+                        return;
+                    }
+                    Err(_) => continue,
+                }
+            }
+            if meta.is_file() {
+                if search.max_files_scanned.is_some_and(|max| scanned >= max) {
+                    return;
+                }
+                scanned += 1;
+            }
+
+            let sniffed = if search.filter.needs_content() && meta.is_file() {
+                sniff(&path).await.ok()
+            } else {
+                None
+            };
+            if !search
+                .filter
+                .decide_parts(&entry.file_name(), meta.is_file(), sniffed)
+                .is_included()
+            {
+                continue;
+            }
+            if search
+                .changes
+                .as_ref()
+                .is_some_and(|c| !c.contains(&search.root, &path))
+            {
+                continue;
+            }
+
+            let added = search
+                .added_from()
+                .map(|from| from.added(&search.root, &path));
+            let strategy = search.registry.strategy(&path);
+            let counted = match (strategy, added, search.offsets(), search.estimate_above()) {
+                (Some(strategy), ..) => {
+                    let records = strategy.records(&search.delimiter);
+                    count_lines(&path, records, search.digest, search.long_line).await
+                }
+                (_, Some(lines), _, _) if search.digest.is_none() && search.long_line.is_none() => {
+                    Ok(FileLines::unread(&path, lines))
+                }
+                (_, _, Some(offsets), _) => {
+                    let (path, delimiter) = (path.clone(), search.delimiter.clone());
+                    tokio::task::spawn_blocking(move || {
+                        offsets.count(&local::RealFs, &path, &delimiter)
+                    })
+                    .await
+                    .unwrap_or_else(|err| Err(io::Error::other(err)))
+                }
+                (_, _, _, Some(len)) if meta.len() > len => {
+                    estimate_lines(&path, meta.len(), &search.delimiter).await
+                }
+                _ => {
+                    let lines = PlainText.records(&search.delimiter);
+                    count_lines(&path, lines, search.digest, search.long_line).await
+                }
+            }
+            .map(|file| file.with_added(added));
+            let counted = counted.and_then(|file| match &search.predicate {
+                Some(predicate) => {
+                    let entry = Entry {
+                        path: path.clone(),
+                        depth: depth + 1,
+                        kind: EntryKind::File,
+                        len: meta.len(),
+                        modified: meta.modified().ok(),
+                    };
+                    match predicate.includes(&entry, file.lines) {
+                        Ok(keep) => Ok(keep.then_some(file)),
+                        Err(err) => Err(io::Error::other(err)),
+                    }
+                }
+                None => Ok(Some(file)),
+            });
+            let counted = match counted {
+                Ok(Some(file)) => Ok(file),
+                Ok(None) => continue,
+                Err(_) if search.error_policy == ErrorPolicy::Skip && !search.fail_fast => continue,
+                Err(source) => Err(FileError { path, source }),
+            };
+            if search.max_results.is_some_and(|max| reported >= max) {
+                return;
+            }
+            let failed = counted.is_err();
+            if tx.send(counted).await.is_err() || failed {
+                return;
+            }
+            reported += 1;
+        }
+    }
+}
+
+async fn sniff(path: &Path) -> io::Result<Detection> {
+    let mut head = Vec::with_capacity(filetype::SNIFF_LEN);
+    fs::File::open(path)
+        .await?
+        .take(filetype::SNIFF_LEN as u64)
+        .read_to_end(&mut head)
+        .await?;
+    Ok(filetype::detect_bytes(
+        &head,
+        path.extension().and_then(|e| e.to_str()),
+    ))
+}
+
+async fn count_lines(
+    path: &Path,
+    records: Box<dyn Records>,
+    digest: Option<DigestKind>,
+    long_line: Option<usize>,
+) -> io::Result<FileLines> {
+    let mut file = fs::File::open(path).await?;
+    let mut buf = vec![0; 64 * 1024];
+    let mut tally = Tally::with_records(records, digest, long_line);
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            return Ok(tally.finish(path));
+        }
+        tally.update(&buf[..n]);
+    }
+}
+
+async fn estimate_lines(path: &Path, len: u64, delimiter: &Delimiter) -> io::Result<FileLines> {
+    if !estimate::worthwhile(len) {
+        return count_lines(path, PlainText.records(delimiter), None, None).await;
+    }
+    let mut file = fs::File::open(path).await?;
+    let mut block = Vec::with_capacity(estimate::BLOCK);
+    let mut sampler = Sampler::new(delimiter);
+    for offset in estimate::offsets(len) {
+        file.seek(SeekFrom::Start(offset)).await?;
+        block.clear();
+        (&mut file)
+            .take(estimate::BLOCK as u64)
+            .read_to_end(&mut block)
+            .await?;
+        sampler.add(&block);
+    }
+    Ok(sampler.finish(path, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, future::poll_fn, pin::Pin};
+
+    use futures_core::Stream;
+
+    use crate::task4::{Filter, SearchBuilder};
+
+    #[tokio::test]
+    async fn streams_matching_files() {
+        let dir = std::env::temp_dir().join(format!("testing-async-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.rs"), "one\ntwo\n").unwrap();
+        fs::write(dir.join("sub/b.rs"), "one\ntwo\nthree").unwrap();
+        fs::write(dir.join("c.txt"), "ignored\n").unwrap();
+
+        let mut stream = SearchBuilder::new(&dir, Filter::new("rs"))
+            .build()
+            .stream_async();
+        let mut found = vec![];
+        while let Some(item) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+            let item = item.unwrap();
+            found.push((item.path.strip_prefix(&dir).unwrap().to_owned(), item.lines));
+        }
+        found.sort();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(found, vec![("a.rs".into(), 2), ("sub/b.rs".into(), 3)]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stops_at_symlink_loops() {
+        let dir = std::env::temp_dir().join(format!("testing-async-loop-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.rs"), "one\n").unwrap();
+        std::os::unix::fs::symlink(&dir, dir.join("loop")).unwrap();
+
+        let search = |fail_fast| {
+            SearchBuilder::new(&dir, Filter::new("rs"))
+                .fail_fast(fail_fast)
+                .build()
+                .stream_async()
+        };
+        let mut found = vec![];
+        let mut stream = search(false);
+        while let Some(item) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+            found.push(item.unwrap().path);
+        }
+        let mut stream = search(true);
+        let mut failed = None;
+        while let Some(item) = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await {
+            failed = failed.or(item.err());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(found, [dir.join("a.rs")]);
+        assert_eq!(failed.map(|err| err.path), Some(dir.join("loop")));
+    }
+}
@@ -0,0 +1,284 @@
+//! What git knows about the searched files: which changed since a revision, see
+//! [`SearchBuilder::changes`](super::SearchBuilder::changes), and how old their lines are,
+//! see [`blame`].
+//!
+//! The changes come from `git diff --numstat` between the revision and the working tree, so
+//! they include edits that aren't committed yet but not untracked files. A renamed file
+//! counts as added whole.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{anyhow, bail};
+
+use super::relative_path;
+
+/// The changed files below a search root, keyed by the path relative to it like
+/// [`Manifest`](super::manifest::Manifest).
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Changes {
+    /// Lines added per file, `None` for binary files.
+    added: HashMap<PathBuf, Option<usize>>,
+}
+
+impl Changes {
+    /// Asks git what changed below `root` since `rev`, e.g. `HEAD~10` or a branch name.
+    pub fn since(root: &Path, rev: &str) -> Result<Self, anyhow::Error> {
+        if rev.is_empty() || rev.starts_with('-') {
+            bail!("'{}' isn't a revision", rev);
+        }
+        // A root that is a file is looked up by its name in the directory holding it.
+        let dir = if root.is_dir() {
+            root
+        } else {
+            root.parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(Path::new("."))
+        };
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args([
+                "diff",
+                "--numstat",
+                "-z",
+                "--no-renames",
+                "--relative",
+                rev,
+                "--",
+            ])
+            .output()
+            .map_err(|e| anyhow!("can't run git: {}", e))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!("git diff {} failed: {}", rev, stderr.trim_end());
+        }
+        Changes::parse(&output.stdout)
+    }
+
+    /// Reads the output of `git diff --numstat -z --no-renames`, records of
+    /// `added<TAB>deleted<TAB>path<NUL>` where binary files have `-` for both counts.
+    pub fn parse(numstat: &[u8]) -> Result<Self, anyhow::Error> {
+        let mut added = HashMap::new();
+        for record in numstat.split(|&b| b == 0).filter(|r| !r.is_empty()) {
+            let mut fields = record.splitn(3, |&b| b == b'\t');
+            let (Some(count), Some(_), Some(path)) = (fields.next(), fields.next(), fields.next())
+            else {
+                bail!(
+                    "unexpected git diff record '{}'",
+                    String::from_utf8_lossy(record)
+                );
+            };
+            let count = match count {
+                b"-" => None,
+                count => Some(
+                    std::str::from_utf8(count)
+                        .ok()
+                        .and_then(|count| count.parse().ok())
+                        .ok_or_else(|| anyhow!("bad added line count in git diff output"))?,
+                ),
+            };
+            added.insert(path_from_bytes(path)?, count);
+        }
+        Ok(Changes { added })
+    }
+
+    /// Whether the file at `path` below `root` changed.
+    pub fn contains(&self, root: &Path, path: &Path) -> bool {
+        self.added.contains_key(relative_path(root, path))
+    }
+
+    /// Lines added to the file at `path` below `root`, 0 for binary and unchanged files.
+    pub fn added(&self, root: &Path, path: &Path) -> usize {
+        self.added
+            .get(relative_path(root, path))
+            .copied()
+            .flatten()
+            .unwrap_or(0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.added.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+    }
+}
+
+/// How long ago the lines of a file or directory were written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Age {
+    pub lines: usize,
+    /// Seconds since the median line was written.
+    pub median: u64,
+    /// Seconds since the oldest line was written.
+    pub oldest: u64,
+}
+
+const DAY: u64 = 24 * 60 * 60;
+
+impl Age {
+    /// `written` holds when each line was written in seconds since the Unix epoch, `now` is
+    /// the time to measure from. `None` without lines.
+    pub fn of(written: &mut [u64], now: u64) -> Option<Self> {
+        written.sort_unstable();
+        let (&first, mid) = (written.first()?, written.len() / 2);
+        let median = if written.len().is_multiple_of(2) {
+            (written[mid - 1] + written[mid]) / 2
+        } else {
+            written[mid]
+        };
+        Some(Age {
+            lines: written.len(),
+            median: now.saturating_sub(median),
+            oldest: now.saturating_sub(first),
+        })
+    }
+}
+
+/// `age median 12d oldest 340d`, in whole days.
+impl fmt::Display for Age {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "age median {}d oldest {}d",
+            self.median / DAY,
+            self.oldest / DAY
+        )
+    }
+}
+
+/// When each line of the file at `path` was written according to `git blame`, in seconds
+/// since the Unix epoch. Lines that aren't committed yet count as written now, a file git
+/// doesn't track is an error.
+pub fn blame(path: &Path) -> Result<Vec<u64>, anyhow::Error> {
+    let (dir, name) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) if !dir.as_os_str().is_empty() => (dir, name),
+        (_, Some(name)) => (Path::new("."), name),
+        _ => bail!("{} isn't a file", path.display()),
+    };
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["blame", "--line-porcelain", "--"])
+        .arg(name)
+        .output()
+        .map_err(|e| anyhow!("can't run git: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("git blame failed: {}", stderr.trim_end());
+    }
+    author_times(&output.stdout)
+}
+
+/// The `author-time` of every line in `git blame --line-porcelain` output. The content lines
+/// start with a tab, so they can't be mistaken for one.
+fn author_times(porcelain: &[u8]) -> Result<Vec<u64>, anyhow::Error> {
+    porcelain
+        .split(|&b| b == b'\n')
+        .filter_map(|line| line.strip_prefix(b"author-time "))
+        .map(|time| {
+            std::str::from_utf8(time)
+                .ok()
+                .and_then(|time| time.parse().ok())
+                .ok_or_else(|| anyhow!("bad author-time in git blame output"))
+        })
+        .collect()
+}
+
+/// Line ages of files summed up for the directories holding them, each directory covers
+/// every file below it.
+#[derive(Debug, Default)]
+pub struct AgeStats {
+    written: BTreeMap<PathBuf, Vec<u64>>,
+}
+
+impl AgeStats {
+    /// Adds the lines of the file at `path` to each directory from its own up to `root`.
+    pub fn add(&mut self, root: &Path, path: &Path, written: &[u64]) {
+        for dir in path.ancestors().skip(1) {
+            if !dir.starts_with(root) {
+                break;
+            }
+            self.written
+                .entry(dir.to_owned())
+                .or_default()
+                .extend(written);
+        }
+    }
+
+    /// The age of every directory with lines, in path order.
+    pub fn finish(self, now: u64) -> impl Iterator<Item = (PathBuf, Age)> {
+        self.written
+            .into_iter()
+            .filter_map(move |(dir, mut written)| Some((dir, Age::of(&mut written, now)?)))
+    }
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf, anyhow::Error> {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    Ok(PathBuf::from(OsStr::from_bytes(bytes)))
+}
+
+/// Git writes paths as UTF-8 outside Unix.
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf, anyhow::Error> {
+    let path = std::str::from_utf8(bytes).map_err(|_| anyhow!("git diff path isn't UTF-8"))?;
+    Ok(PathBuf::from(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::task4::churn::{author_times, Age, AgeStats, Changes};
+
+    #[test]
+    fn reads_numstat_records() {
+        let changes =
+            Changes::parse(b"3\t1\tsrc/a.rs\x00-\t-\tlogo.png\x000\t4\tdir/tab\tname.rs\x00")
+                .unwrap();
+        let root = Path::new("repo");
+        assert_eq!(changes.len(), 3);
+        assert!(changes.contains(root, &root.join("src/a.rs")));
+        assert!(!changes.contains(root, &root.join("src/b.rs")));
+        assert_eq!(changes.added(root, &root.join("src/a.rs")), 3);
+        assert_eq!(changes.added(root, &root.join("logo.png")), 0);
+        assert_eq!(changes.added(root, &root.join("dir/tab\tname.rs")), 0);
+
+        assert!(Changes::parse(b"").unwrap().is_empty());
+        assert!(Changes::parse(b"3\tsrc/a.rs\x00").is_err());
+        assert!(Changes::parse(b"x\t1\tsrc/a.rs\x00").is_err());
+    }
+
+    #[test]
+    fn ages_lines_of_files_and_directories() {
+        let porcelain = b"1f2e 1 1 2\nauthor A\nauthor-time 100\nsummary x\n\tauthor-time 5\n\
+                          1f2e 2 2\nauthor A\nauthor-time 300\n\tfn main() {}\n";
+        let mut written = author_times(porcelain).unwrap();
+        assert_eq!(written, [100, 300]);
+        let age = Age::of(&mut written, 1000).unwrap();
+        assert_eq!((age.lines, age.median, age.oldest), (2, 800, 900));
+        assert_eq!(Age::of(&mut [], 1000), None);
+
+        let root = Path::new("repo");
+        let mut stats = AgeStats::default();
+        stats.add(root, &root.join("a.rs"), &[100, 300]);
+        stats.add(root, &root.join("src/b.rs"), &[500]);
+        let dirs: Vec<_> = stats
+            .finish(1000)
+            .map(|(dir, age)| (dir, age.lines, age.median))
+            .collect();
+        assert_eq!(
+            dirs,
+            [(root.to_owned(), 3, 700), (root.join("src"), 1, 500)]
+        );
+    }
+}
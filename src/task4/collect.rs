@@ -0,0 +1,150 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use flate2::{write::GzEncoder, Compression};
+
+use super::{fs::FileSystem, relative_path, FileError, Search};
+
+/// Archives matched files while a search runs, paths are kept relative to the search root.
+pub struct Collector<W: Write> {
+    archive: tar::Builder<Output<W>>,
+    fs: Arc<dyn FileSystem>,
+    mtime: u64,
+}
+
+enum Output<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+}
+
+impl Collector<BufWriter<File>> {
+    /// Creates the archive at `path`, gzip compressed for `.tar.gz` and `.tgz` names.
+    pub fn create(path: impl AsRef<Path>, search: &Search) -> io::Result<Self> {
+        let path = path.as_ref();
+        let name = path.as_os_str().as_encoded_bytes();
+        let compress = name.ends_with(b".tar.gz") || name.ends_with(b".tgz");
+        let file = BufWriter::new(File::create(path)?);
+        Ok(Self::new(file, compress, search))
+    }
+}
+
+impl<W: Write> Collector<W> {
+    pub fn new(writer: W, compress: bool, search: &Search) -> Self {
+        let output = if compress {
+            Output::Gzip(GzEncoder::new(writer, Compression::default()))
+        } else {
+            Output::Plain(writer)
+        };
+        Collector {
+            archive: tar::Builder::new(output),
+            fs: Arc::clone(&search.fs),
+            // Not every backend knows modification times, entries get the time of the scan.
+            mtime: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        }
+    }
+
+    /// Appends the file at `path`, which has to lie below `root`.
+    pub fn add(&mut self, root: &Path, path: &Path) -> Result<(), FileError> {
+        let fail = |source| FileError {
+            path: path.to_owned(),
+            source,
+        };
+        let mut content = vec![];
+        self.fs
+            .open(path)
+            .and_then(|mut file| file.read_to_end(&mut content))
+            .map_err(fail)?;
+
+        let name = relative_path(root, path);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(self.mtime);
+        self.archive
+            .append_data(&mut header, name, content.as_slice())
+            .map_err(fail)
+    }
+
+    /// Writes the archive trailer and flushes, hands back the underlying writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self.archive.into_inner()? {
+            Output::Plain(mut writer) => {
+                writer.flush()?;
+                Ok(writer)
+            }
+            Output::Gzip(encoder) => {
+                let mut writer = encoder.finish()?;
+                writer.flush()?;
+                Ok(writer)
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for Output<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Plain(w) => w.write(buf),
+            Output::Gzip(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Plain(w) => w.flush(),
+            Output::Gzip(w) => w.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, sync::Arc};
+
+    use flate2::read::GzDecoder;
+
+    use crate::task4::{collect::Collector, fs::MemoryFs, Filter, SearchBuilder};
+
+    #[test]
+    fn collects_matches_with_relative_paths() {
+        let fs = MemoryFs::new()
+            .file("root/a.rs", "fn a() {}\n")
+            .file("root/sub/b.rs", "fn b() {}\n")
+            .file("root/c.txt", "skip\n");
+        let search = SearchBuilder::new("root", Filter::new("rs"))
+            .file_system(Arc::new(fs))
+            .build();
+
+        let mut collector = Collector::new(vec![], true, &search);
+        for file in search.run().unwrap() {
+            collector.add("root".as_ref(), &file.path).unwrap();
+        }
+        let gz = collector.finish().unwrap();
+
+        let mut archive = tar::Archive::new(GzDecoder::new(gz.as_slice()));
+        let entries: Vec<_> = archive
+            .entries()
+            .unwrap()
+            .map(|e| {
+                let mut e = e.unwrap();
+                let mut content = String::new();
+                e.read_to_string(&mut content).unwrap();
+                (e.path().unwrap().to_string_lossy().into_owned(), content)
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("a.rs".to_owned(), "fn a() {}\n".to_owned()),
+                ("sub/b.rs".to_owned(), "fn b() {}\n".to_owned()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,362 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Read},
+    path::Path,
+    str::FromStr,
+};
+
+use anyhow::anyhow;
+use sha2::{Digest as _, Sha256};
+
+use super::{
+    fs::FileSystem,
+    metrics::LineStats,
+    records::{PlainText, Records, Strategy},
+    FileLines,
+};
+
+/// Hash computed over the content of every matched file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestKind {
+    Sha256,
+}
+
+impl FromStr for DigestKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(DigestKind::Sha256),
+            _ => Err(anyhow!("unknown digest '{}', expected sha256", s)),
+        }
+    }
+}
+
+/// What ends a line, `\n` unless a search is given another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delimiter {
+    bytes: Vec<u8>,
+    /// For every prefix of `bytes`, how long its longest proper prefix that is also a
+    /// suffix is, so that matching never has to look at a byte twice.
+    fallback: Vec<usize>,
+}
+
+impl Delimiter {
+    /// `None` for an empty sequence.
+    pub fn new(bytes: impl Into<Vec<u8>>) -> Option<Self> {
+        let bytes = bytes.into();
+        if bytes.is_empty() {
+            return None;
+        }
+        let mut fallback = vec![0; bytes.len()];
+        let mut len = 0;
+        for i in 1..bytes.len() {
+            while len > 0 && bytes[i] != bytes[len] {
+                len = fallback[len - 1];
+            }
+            if bytes[i] == bytes[len] {
+                len += 1;
+            }
+            fallback[i] = len;
+        }
+        Some(Delimiter { bytes, fallback })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl Default for Delimiter {
+    fn default() -> Self {
+        Delimiter::new(*b"\n").expect("not empty")
+    }
+}
+
+/// `lf`, `cr`, `crlf`, `nul` and `nel` (U+0085 in UTF-8) by name, anything else as the
+/// bytes it spells with `\n`, `\r`, `\t`, `\0`, `\\` and `\xNN` escapes.
+impl FromStr for Delimiter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = match s {
+            "lf" => b"\n".to_vec(),
+            "cr" => b"\r".to_vec(),
+            "crlf" => b"\r\n".to_vec(),
+            "nul" => b"\0".to_vec(),
+            "nel" => "\u{85}".as_bytes().to_vec(),
+            _ => unescape(s)?,
+        };
+        Delimiter::new(bytes).ok_or_else(|| anyhow!("a delimiter can't be empty"))
+    }
+}
+
+fn unescape(s: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&b, after)) = rest.split_first() {
+        rest = after;
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        let (&escape, after) = rest
+            .split_first()
+            .ok_or_else(|| anyhow!("'{}' ends in a lone \\", s))?;
+        rest = after;
+        bytes.push(match escape {
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'0' => b'\0',
+            b'\\' => b'\\',
+            b'x' => {
+                let hex = rest.get(..2).and_then(|hex| std::str::from_utf8(hex).ok());
+                let byte = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                rest = rest.get(2..).unwrap_or_default();
+                byte.ok_or_else(|| anyhow!("'{}' has a \\x without two hex digits", s))?
+            }
+            _ => return Err(anyhow!("unknown escape \\{} in '{}'", escape as char, s)),
+        });
+    }
+    Ok(bytes)
+}
+
+/// Counts the delimiters in a stream fed in chunks, they may span chunk boundaries.
+#[derive(Debug, Clone)]
+pub struct Splitter {
+    delimiter: Delimiter,
+    /// How much of the delimiter the last bytes fed matched.
+    matched: usize,
+    /// Something came after the last delimiter.
+    open: bool,
+}
+
+impl Splitter {
+    pub fn new(delimiter: Delimiter) -> Self {
+        Splitter {
+            delimiter,
+            matched: 0,
+            open: false,
+        }
+    }
+
+    /// The delimiters `chunk` completes.
+    pub fn count(&mut self, chunk: &[u8]) -> usize {
+        let Some(&last) = chunk.last() else {
+            return 0;
+        };
+        let bytes = &self.delimiter.bytes;
+        if let &[delimiter] = bytes.as_slice() {
+            self.open = last != delimiter;
+            return chunk.iter().filter(|&&b| b == delimiter).count();
+        }
+        let mut found = 0;
+        for &b in chunk {
+            while self.matched > 0 && bytes[self.matched] != b {
+                self.matched = self.delimiter.fallback[self.matched - 1];
+            }
+            if bytes[self.matched] == b {
+                self.matched += 1;
+            }
+            self.open = self.matched < bytes.len();
+            if !self.open {
+                found += 1;
+                self.matched = 0;
+            }
+        }
+        found
+    }
+
+    /// Goes on where a splitter left off that [`Splitter::matched`] and [`Splitter::open`]
+    /// described, `None` when `matched` is a whole delimiter or more.
+    pub fn resume(delimiter: Delimiter, matched: usize, open: bool) -> Option<Self> {
+        (matched < delimiter.bytes.len()).then_some(Splitter {
+            delimiter,
+            matched,
+            open,
+        })
+    }
+
+    pub fn delimiter(&self) -> &Delimiter {
+        &self.delimiter
+    }
+
+    /// How much of a delimiter the bytes fed so far end with.
+    pub fn matched(&self) -> usize {
+        self.matched
+    }
+
+    /// Whether there is a line without a delimiter at the end of what was fed so far.
+    pub fn open(&self) -> bool {
+        self.open
+    }
+
+    /// Starts over as if nothing had been fed.
+    pub fn reset(&mut self) {
+        self.matched = 0;
+        self.open = false;
+    }
+}
+
+/// Counts lines over a file fed in chunks, hashing and measuring it along the way if asked to.
+///
+/// Lines are counted the way `BufRead::lines` does: a trailing line without a delimiter
+/// still counts. [`Tally::with_records`] counts the records of a [`Strategy`] instead.
+pub struct Tally {
+    records: Box<dyn Records>,
+    hasher: Option<Sha256>,
+    stats: Option<LineStats>,
+}
+
+impl Tally {
+    /// With `long_line` set, [`Metrics`](super::metrics::Metrics) are collected as well.
+    /// They always measure lines ended by `\n`, whatever the `delimiter`.
+    pub fn new(
+        digest: Option<DigestKind>,
+        long_line: Option<usize>,
+        delimiter: &Delimiter,
+    ) -> Self {
+        Tally::with_records(PlainText.records(delimiter), digest, long_line)
+    }
+
+    /// Reports `records` as the lines of the file.
+    pub fn with_records(
+        records: Box<dyn Records>,
+        digest: Option<DigestKind>,
+        long_line: Option<usize>,
+    ) -> Self {
+        Tally {
+            records,
+            hasher: digest.map(|DigestKind::Sha256| Sha256::new()),
+            stats: long_line.map(LineStats::new),
+        }
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        if chunk.is_empty() {
+            return;
+        }
+        self.records.update(chunk);
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(chunk);
+        }
+        if let Some(stats) = &mut self.stats {
+            stats.update(chunk);
+        }
+    }
+
+    /// The counted file, its digest is in lowercase hex.
+    pub fn finish(self, path: &Path) -> FileLines {
+        let lines = self.records.finish();
+        let digest = self.hasher.map(|hasher| {
+            hasher
+                .finalize()
+                .iter()
+                .fold(String::with_capacity(64), |mut hex, b| {
+                    let _ = write!(hex, "{:02x}", b);
+                    hex
+                })
+        });
+        FileLines {
+            path: path.to_owned(),
+            lines,
+            digest,
+            metrics: self.stats.map(LineStats::finish),
+            estimate: None,
+        }
+    }
+}
+
+pub fn count_lines(
+    fs: &dyn FileSystem,
+    path: &Path,
+    digest: Option<DigestKind>,
+    long_line: Option<usize>,
+    delimiter: &Delimiter,
+) -> io::Result<FileLines> {
+    count_records(fs, path, &PlainText, digest, long_line, delimiter)
+}
+
+/// Like [`count_lines`], the lines being the records `strategy` counts.
+pub fn count_records(
+    fs: &dyn FileSystem,
+    path: &Path,
+    strategy: &dyn Strategy,
+    digest: Option<DigestKind>,
+    long_line: Option<usize>,
+    delimiter: &Delimiter,
+) -> io::Result<FileLines> {
+    let mut file = fs.open(path)?;
+    let mut buf = vec![0; 64 * 1024];
+    let records = strategy.records(delimiter);
+    let mut tally = Tally::with_records(records, digest, long_line);
+    loop {
+        // Read errors (e.g. on a directory) end the count instead of being retried forever.
+        let n = match file.read(&mut buf) {
+            Ok(0) => return Ok(tally.finish(path)),
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        tally.update(&buf[..n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task4::count::{Delimiter, DigestKind, Tally};
+
+    fn tally(chunks: &[&str], digest: Option<DigestKind>) -> (usize, Option<String>) {
+        lines_split_on(chunks, digest, &Delimiter::default())
+    }
+
+    fn lines_split_on(
+        chunks: &[&str],
+        digest: Option<DigestKind>,
+        delimiter: &Delimiter,
+    ) -> (usize, Option<String>) {
+        let mut tally = Tally::new(digest, None, delimiter);
+        for chunk in chunks {
+            tally.update(chunk.as_bytes());
+        }
+        let file = tally.finish("file".as_ref());
+        (file.lines, file.digest)
+    }
+
+    #[test]
+    fn counts_like_buf_read_lines() {
+        assert_eq!(tally(&[], None), (0, None));
+        assert_eq!(tally(&["a\nb"], None), (2, None));
+        assert_eq!(tally(&["a\n", "", "b\n"], None), (2, None));
+        assert_eq!(tally(&["\n\n"], None), (2, None));
+    }
+
+    #[test]
+    fn splits_on_other_delimiters_across_chunks() {
+        let lines = |chunks: &[&str], delimiter: &str| {
+            lines_split_on(chunks, None, &delimiter.parse().unwrap()).0
+        };
+        assert_eq!(lines(&["a\rb\r", "c"], "cr"), 3);
+        assert_eq!(lines(&["a\r", "\nb\r\r\n"], "crlf"), 2);
+        assert_eq!(lines(&["a\nb\n"], "crlf"), 1);
+        assert_eq!(lines(&["rec\0", "rec\0"], "nul"), 2);
+        assert_eq!(lines(&["a\u{85}b"], "nel"), 2);
+        // A partial match that falls back onto a shorter one.
+        assert_eq!(lines(&["x--", "-|y"], "--|"), 2);
+        assert_eq!(lines(&["a\x1e", "b\x1e"], "\\x1e"), 2);
+
+        for bad in ["", "\\", "\\x4", "\\q"] {
+            assert!(bad.parse::<Delimiter>().is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn hashes_across_chunks() {
+        let (_, digest) = tally(&["ab", "c"], Some(DigestKind::Sha256));
+        assert_eq!(
+            digest.as_deref(),
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Line counts of large files estimated from evenly spaced blocks instead of every byte,
+//! see [`SearchBuilder::estimate`](super::SearchBuilder::estimate).
+//!
+//! Each block gives a newline density, their mean times the file length is the estimate.
+//! The margin is the 95% confidence interval of that mean, narrowed by how much of the
+//! file was read.
+
+use std::{io, path::Path};
+
+use super::{
+    count::{self, Delimiter, Splitter},
+    fs::FileSystem,
+    FileLines,
+};
+
+/// Bytes read per sample.
+pub const BLOCK: usize = 64 * 1024;
+/// How many blocks an estimated file is sampled at.
+const SAMPLES: u64 = 32;
+/// The two-sided 95% quantile of the normal distribution.
+const Z_95: f64 = 1.96;
+
+/// How far off an estimated line count may be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Estimate {
+    /// Half the width of the 95% confidence interval around the count.
+    pub margin: usize,
+    /// Bytes read to come up with the count.
+    pub sampled: u64,
+}
+
+/// Whether a file `len` bytes long is large enough for sampling to read less than counting.
+pub fn worthwhile(len: u64) -> bool {
+    len > SAMPLES * BLOCK as u64
+}
+
+/// Where the samples of a file `len` bytes long start, in order. The first is at 0 and the
+/// last ends the file.
+pub fn offsets(len: u64) -> impl Iterator<Item = u64> {
+    let span = u128::from(len.saturating_sub(BLOCK as u64));
+    (0..SAMPLES).map(move |i| (span * u128::from(i) / u128::from(SAMPLES - 1)) as u64)
+}
+
+/// Collects the blocks of one file, in the order of [`offsets`].
+#[derive(Debug)]
+pub struct Sampler {
+    splitter: Splitter,
+    densities: Vec<f64>,
+    sampled: u64,
+    /// The last block added ends in the middle of a line.
+    open: bool,
+}
+
+impl Sampler {
+    /// Blocks are split on `delimiter`, one is never found across two blocks.
+    pub fn new(delimiter: &Delimiter) -> Self {
+        Sampler {
+            splitter: Splitter::new(delimiter.clone()),
+            densities: vec![],
+            sampled: 0,
+            open: false,
+        }
+    }
+
+    pub fn add(&mut self, block: &[u8]) {
+        if block.is_empty() {
+            return;
+        }
+        self.splitter.reset();
+        let delimiters = self.splitter.count(block);
+        self.densities.push(delimiters as f64 / block.len() as f64);
+        self.sampled += block.len() as u64;
+        self.open = self.splitter.open();
+    }
+
+    /// `len` is the length of the whole file. Like a full count, a trailing line without
+    /// a delimiter counts.
+    pub fn finish(self, path: &Path, len: u64) -> FileLines {
+        let k = self.densities.len() as f64;
+        let mean = self.densities.iter().sum::<f64>() / k.max(1.0);
+        let variance = if k > 1.0 {
+            self.densities
+                .iter()
+                .map(|d| (d - mean).powi(2))
+                .sum::<f64>()
+                / (k - 1.0)
+        } else {
+            0.0
+        };
+        let unread = (1.0 - self.sampled as f64 / len.max(1) as f64).max(0.0);
+        let error = (variance / k.max(1.0) * unread).sqrt() * len as f64;
+        FileLines {
+            path: path.to_owned(),
+            lines: (mean * len as f64).round() as usize + usize::from(self.open),
+            digest: None,
+            metrics: None,
+            estimate: Some(Estimate {
+                margin: (Z_95 * error).ceil() as usize,
+                sampled: self.sampled,
+            }),
+        }
+    }
+}
+
+/// The estimated line count of the file at `path`, `len` bytes long. Files too small to be
+/// [`worthwhile`] and files on backends that can't read from the middle are counted whole.
+pub fn estimate_lines(
+    fs: &dyn FileSystem,
+    path: &Path,
+    len: u64,
+    delimiter: &Delimiter,
+) -> io::Result<FileLines> {
+    if !worthwhile(len) {
+        return count::count_lines(fs, path, None, None, delimiter);
+    }
+    let mut sampler = Sampler::new(delimiter);
+    let mut buf = vec![0; BLOCK];
+    for offset in offsets(len) {
+        match fs.read_at(path, offset, &mut buf) {
+            Ok(n) => sampler.add(&buf[..n]),
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => {
+                return count::count_lines(fs, path, None, None, delimiter);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(sampler.finish(path, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task4::{
+        count::{count_lines, Delimiter},
+        estimate::{estimate_lines, offsets, BLOCK},
+        fs::MemoryFs,
+    };
+
+    #[test]
+    fn samples_cover_the_whole_file() {
+        let len = 100 * BLOCK as u64 + 7;
+        let offsets: Vec<_> = offsets(len).collect();
+        assert_eq!(offsets.len(), 32);
+        assert_eq!(offsets[0], 0);
+        assert_eq!(offsets[31] + BLOCK as u64, len);
+        assert!(offsets.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn estimates_within_the_margin() {
+        // Lines of 10 to 99 bytes, the length cycling so that blocks differ.
+        let mut content = Vec::new();
+        for i in 0..200_000 {
+            content.extend(std::iter::repeat_n(b'x', 9 + i % 91));
+            content.push(b'\n');
+        }
+        content.extend(b"no newline");
+        let len = content.len() as u64;
+        let fs = MemoryFs::new()
+            .file("big.log", content)
+            .file("small.log", "a\nb");
+
+        let lf = Delimiter::default();
+        let exact = count_lines(&fs, "big.log".as_ref(), None, None, &lf).unwrap();
+        let estimated = estimate_lines(&fs, "big.log".as_ref(), len, &lf).unwrap();
+        let estimate = estimated.estimate.unwrap();
+        assert!(estimate.sampled < len / 4, "{:?}", estimate);
+        assert!(
+            estimated.lines.abs_diff(exact.lines) <= estimate.margin,
+            "{} vs {} ±{}",
+            estimated.lines,
+            exact.lines,
+            estimate.margin
+        );
+
+        let small = estimate_lines(&fs, "small.log".as_ref(), 3, &lf).unwrap();
+        assert_eq!((small.lines, small.estimate), (2, None));
+    }
+}
@@ -0,0 +1,125 @@
+use std::{fmt, io::Read, path::Path, str::FromStr};
+
+use anyhow::anyhow;
+
+use super::fs::FileSystem;
+
+/// How many leading bytes are looked at when sniffing a file.
+pub const SNIFF_LEN: usize = 512;
+
+/// Coarse file kinds that `--type` can select.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    Rust,
+    Script,
+    Binary,
+    Text,
+}
+
+/// A detected [`FileType`] along with what gave it away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Detection {
+    pub file_type: FileType,
+    pub evidence: &'static str,
+}
+
+impl FromStr for FileType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rust" => Ok(FileType::Rust),
+            "script" => Ok(FileType::Script),
+            "binary" => Ok(FileType::Binary),
+            "text" => Ok(FileType::Text),
+            _ => Err(anyhow!(
+                "unknown type '{}', expected rust, script, binary or text",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            FileType::Rust => "rust",
+            FileType::Script => "script",
+            FileType::Binary => "binary",
+            FileType::Text => "text",
+        })
+    }
+}
+
+/// Sniffs the beginning of the file at `path`, falling back to its extension for plain text.
+pub fn detect(fs: &dyn FileSystem, path: &Path) -> std::io::Result<Detection> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    fs.open(path)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut head)?;
+    Ok(detect_bytes(
+        &head,
+        path.extension().and_then(|e| e.to_str()),
+    ))
+}
+
+pub fn detect_bytes(head: &[u8], ext: Option<&str>) -> Detection {
+    let found = |file_type, evidence| Detection {
+        file_type,
+        evidence,
+    };
+
+    if head.starts_with(b"\x7fELF") {
+        return found(FileType::Binary, "ELF header");
+    }
+    if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return found(FileType::Binary, "PNG header");
+    }
+    // UTF-16 is full of NULs, the BOM has to be checked before the NUL heuristic.
+    if head.starts_with(&[0xff, 0xfe]) || head.starts_with(&[0xfe, 0xff]) {
+        return found(FileType::Text, "UTF-16 byte order mark");
+    }
+    if let Some(shebang) = head.strip_prefix(b"#!") {
+        let line = shebang.split(|&b| b == b'\n').next().unwrap_or_default();
+        let line = String::from_utf8_lossy(line);
+        return if line.contains("rust-script") || line.contains("cargo") {
+            found(FileType::Rust, "rust shebang")
+        } else {
+            found(FileType::Script, "shebang")
+        };
+    }
+    if head.contains(&0) {
+        return found(FileType::Binary, "NUL byte");
+    }
+    match ext {
+        Some("rs") => found(FileType::Rust, ".rs extension"),
+        Some("sh" | "bash" | "py" | "pl" | "rb") => found(FileType::Script, "script extension"),
+        _ => found(FileType::Text, "no binary content"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task4::filetype::{detect_bytes, FileType};
+
+    #[test]
+    fn detects_by_magic_bytes() {
+        let cases: &[(&[u8], Option<&str>, FileType)] = &[
+            (b"\x7fELF\x02\x01\x01", None, FileType::Binary),
+            (b"\x89PNG\r\n\x1a\n\0\0", Some("rs"), FileType::Binary),
+            (b"\xff\xfeh\0i\0", None, FileType::Text),
+            (b"#!/bin/sh\necho hi\n", None, FileType::Script),
+            (
+                b"#!/usr/bin/env rust-script\nfn main() {}\n",
+                None,
+                FileType::Rust,
+            ),
+            (b"fn main() {}\n", Some("rs"), FileType::Rust),
+            (b"abc\0def", None, FileType::Binary),
+            (b"just words\n", None, FileType::Text),
+        ];
+        for &(head, ext, expected) in cases {
+            assert_eq!(detect_bytes(head, ext).file_type, expected, "{:?}", head);
+        }
+    }
+}
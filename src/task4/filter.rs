@@ -0,0 +1,192 @@
+use std::{ffi::OsStr, fmt};
+
+use super::{
+    filetype::{self, Detection, FileType},
+    fs::FileSystem,
+    walk::Entry,
+};
+
+/// Decides which walked entries get their lines counted.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    selector: Selector,
+    invert: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Selector {
+    Extension {
+        ext: String,
+        suffix: String,
+        ignore_case: bool,
+    },
+    Type(FileType),
+}
+
+/// The outcome of running an entry through a [`Filter`], with the rule that settled it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    Included(Rule),
+    Excluded(Rule),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Rule {
+    Extension(String),
+    /// Content sniffing, `detected` is `None` when the file couldn't be read.
+    Type {
+        wanted: FileType,
+        detected: Option<Detection>,
+    },
+    NotAFile,
+    /// The filter was inverted, the verdict is the opposite of what `0` alone says.
+    Inverted(Box<Rule>),
+}
+
+impl Filter {
+    pub fn new(ext: &str) -> Self {
+        Filter {
+            selector: Selector::Extension {
+                ext: ext.to_owned(),
+                suffix: [".", ext].concat(),
+                ignore_case: cfg!(windows),
+            },
+            invert: false,
+        }
+    }
+
+    /// Whether `A.RS` has the extension `rs`. By default only on Windows, whose file names
+    /// don't keep case apart. Only ASCII letters are folded.
+    pub fn ignore_case(mut self, yes: bool) -> Self {
+        if let Selector::Extension { ignore_case, .. } = &mut self.selector {
+            *ignore_case = yes;
+        }
+        self
+    }
+
+    /// Selects files by sniffed content instead of by name.
+    pub fn by_type(file_type: FileType) -> Self {
+        Filter {
+            selector: Selector::Type(file_type),
+            invert: false,
+        }
+    }
+
+    /// Select the files the filter would leave out instead, anything that isn't a file
+    /// stays excluded.
+    pub fn invert(mut self, yes: bool) -> Self {
+        self.invert = yes;
+        self
+    }
+
+    /// Content sniffing reads the head of files through `fs`.
+    pub fn decide(&self, entry: &Entry, fs: &dyn FileSystem) -> Decision {
+        let is_file = entry.is_file();
+        let sniffed = if self.needs_content() && is_file {
+            filetype::detect(fs, &entry.path).ok()
+        } else {
+            None
+        };
+        self.decide_parts(entry.file_name(), is_file, sniffed)
+    }
+
+    /// Whether deciding needs the head of the file, not just its name.
+    pub(super) fn needs_content(&self) -> bool {
+        matches!(self.selector, Selector::Type(_))
+    }
+
+    /// `sniffed` is only looked at for files when [`Filter::needs_content`] holds.
+    pub(super) fn decide_parts(
+        &self,
+        file_name: &OsStr,
+        is_file: bool,
+        sniffed: Option<Detection>,
+    ) -> Decision {
+        let decision = self.select(file_name, is_file, sniffed);
+        if !self.invert {
+            return decision;
+        }
+        match decision {
+            _ if !is_file => Decision::Excluded(Rule::NotAFile),
+            Decision::Included(rule) => Decision::Excluded(Rule::Inverted(Box::new(rule))),
+            Decision::Excluded(rule) => Decision::Included(Rule::Inverted(Box::new(rule))),
+        }
+    }
+
+    fn select(&self, file_name: &OsStr, is_file: bool, sniffed: Option<Detection>) -> Decision {
+        match &self.selector {
+            Selector::Extension {
+                ext,
+                suffix,
+                ignore_case,
+            } => {
+                let rule = Rule::Extension(ext.clone());
+                // Compared as bytes, so names that aren't UTF-8 match too.
+                let name = file_name.as_encoded_bytes();
+                let matched = if *ignore_case {
+                    name.len()
+                        .checked_sub(suffix.len())
+                        .is_some_and(|start| name[start..].eq_ignore_ascii_case(suffix.as_bytes()))
+                } else {
+                    name.ends_with(suffix.as_bytes())
+                };
+                if matched {
+                    Decision::Included(rule)
+                } else {
+                    Decision::Excluded(rule)
+                }
+            }
+            Selector::Type(_) if !is_file => Decision::Excluded(Rule::NotAFile),
+            &Selector::Type(wanted) => {
+                let rule = Rule::Type {
+                    wanted,
+                    detected: sniffed,
+                };
+                if sniffed.map(|d| d.file_type) == Some(wanted) {
+                    Decision::Included(rule)
+                } else {
+                    Decision::Excluded(rule)
+                }
+            }
+        }
+    }
+}
+
+impl Decision {
+    pub fn is_included(&self) -> bool {
+        matches!(self, Decision::Included(_))
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (verdict, rule) = match self {
+            Decision::Included(rule) => ("include", rule),
+            Decision::Excluded(rule) => ("exclude", rule),
+        };
+        write!(f, "{}: ", verdict)?;
+        write_rule(f, rule, self.is_included())
+    }
+}
+
+/// `holds` is whether the rule's own test passed.
+fn write_rule(f: &mut fmt::Formatter<'_>, rule: &Rule, holds: bool) -> fmt::Result {
+    let is = if holds { "is" } else { "is not" };
+    match rule {
+        Rule::Extension(ext) => write!(f, "extension {} .{}", is, ext),
+        Rule::Type {
+            wanted,
+            detected: Some(detected),
+        } => write!(
+            f,
+            "content is {} ({}), wanted {}",
+            detected.file_type, detected.evidence, wanted
+        ),
+        Rule::Type { detected: None, .. } => write!(f, "content could not be read"),
+        Rule::NotAFile => write!(f, "not a file"),
+        Rule::Inverted(rule) => {
+            write!(f, "inverted, ")?;
+            write_rule(f, rule, !holds)
+        }
+    }
+}
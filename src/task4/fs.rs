@@ -0,0 +1,483 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt,
+    fs::{self, File},
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// How many times opening a file is retried when the process is out of descriptors.
+const EMFILE_RETRIES: u32 = 10;
+
+#[cfg(unix)]
+const EMFILE: i32 = 24;
+#[cfg(windows)]
+const EMFILE: i32 = 4; // ERROR_TOO_MANY_OPEN_FILES
+
+/// Everything the walker and the line counters need from a storage backend.
+pub trait FileSystem: fmt::Debug + Send + Sync {
+    /// Paths of the direct children of the directory at `path`.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// With `follow_links` a symlink or junction reports what it points to, otherwise the
+    /// link itself.
+    fn metadata(&self, path: &Path, follow_links: bool) -> io::Result<Metadata>;
+
+    /// A path identifying the same object for every way of reaching it, used to detect loops.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>>;
+
+    /// Fills `buf` from `offset` bytes into the file, short only at the end of it. Backends
+    /// that can't start in the middle fail with [`io::ErrorKind::Unsupported`].
+    fn read_at(&self, _path: &Path, _offset: u64, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    /// An NTFS directory junction, a link only Windows has.
+    Junction,
+    Other,
+}
+
+impl EntryKind {
+    pub fn is_link(self) -> bool {
+        matches!(self, EntryKind::Symlink | EntryKind::Junction)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub kind: EntryKind,
+    pub len: u64,
+    /// Last modification, when the backend knows it.
+    pub modified: Option<SystemTime>,
+    /// The hidden attribute on Windows, a name starting with `.` elsewhere.
+    pub hidden: bool,
+    /// The system attribute, always unset outside Windows.
+    pub system: bool,
+}
+
+/// Whether the last component of `path` starts with a dot, how Unix hides files.
+pub fn dot_name(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.as_encoded_bytes().starts_with(b"."))
+}
+
+/// Whether the local file at `path` is hidden or a system file, see [`Metadata`].
+#[cfg(windows)]
+pub fn is_hidden(_path: &Path, meta: &fs::Metadata) -> bool {
+    let meta = windows::metadata(meta);
+    meta.hidden || meta.system
+}
+
+#[cfg(not(windows))]
+pub fn is_hidden(path: &Path, _meta: &fs::Metadata) -> bool {
+    dot_name(path)
+}
+
+/// `path` without the `\\?\` extended-length prefix when the plain form names the same file,
+/// which is how Windows canonicalizes paths but not how anyone writes them. Elsewhere `path`
+/// is returned as is.
+pub fn simplified(path: &Path) -> Cow<'_, Path> {
+    #[cfg(windows)]
+    if let Some(simple) = windows::without_verbatim(path) {
+        return Cow::Owned(simple);
+    }
+    Cow::Borrowed(path)
+}
+
+/// Picks the backend for a root given on the command line, URLs go to an object store.
+pub fn for_root(root: &str) -> Result<Arc<dyn FileSystem>, anyhow::Error> {
+    if !root.starts_with("s3://") && !root.starts_with("gs://") {
+        return Ok(Arc::new(RealFs));
+    }
+    #[cfg(feature = "remote")]
+    return Ok(Arc::new(super::remote::ObjectStoreFs::open(root)?));
+    #[cfg(not(feature = "remote"))]
+    Err(anyhow::anyhow!(
+        "'{}' needs remote storage support, rebuild with the `remote` feature",
+        root
+    ))
+}
+
+/// The local disk.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+impl FileSystem for RealFs {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect()
+    }
+
+    fn metadata(&self, path: &Path, follow_links: bool) -> io::Result<Metadata> {
+        let meta = if follow_links {
+            fs::metadata(path)?
+        } else {
+            fs::symlink_metadata(path)?
+        };
+        let file_type = meta.file_type();
+        let kind = if file_type.is_symlink() {
+            #[cfg(windows)]
+            if windows::is_junction(path, &meta) {
+                return Ok(Metadata {
+                    kind: EntryKind::Junction,
+                    ..windows::metadata(&meta)
+                });
+            }
+            EntryKind::Symlink
+        } else if file_type.is_dir() {
+            EntryKind::Dir
+        } else if file_type.is_file() {
+            EntryKind::File
+        } else {
+            EntryKind::Other
+        };
+        #[cfg(windows)]
+        return Ok(Metadata {
+            kind,
+            ..windows::metadata(&meta)
+        });
+        #[cfg(not(windows))]
+        Ok(Metadata {
+            kind,
+            len: meta.len(),
+            modified: meta.modified().ok(),
+            hidden: dot_name(path),
+            system: false,
+        })
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(open_with_retry(path)?))
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut file = open_with_retry(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        fill(&mut file, buf)
+    }
+}
+
+/// Reads until `buf` is full or the reader runs out.
+fn fill(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+/// Opens a file, backing off and retrying while the process has run out of descriptors.
+fn open_with_retry(filepath: &Path) -> io::Result<File> {
+    let mut delay = Duration::from_millis(1);
+    let mut attempt = 0;
+    loop {
+        match File::open(filepath) {
+            Err(err) if err.raw_os_error() == Some(EMFILE) && attempt < EMFILE_RETRIES => {
+                attempt += 1;
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// An in-memory tree for tests, directories are created implicitly for every added file.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFs {
+    nodes: BTreeMap<PathBuf, Node>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Dir,
+    File(Vec<u8>),
+    /// Shows up in listings but fails to open, to exercise error paths.
+    Unreadable,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn file(mut self, path: impl AsRef<Path>, content: impl Into<Vec<u8>>) -> Self {
+        self.insert(path.as_ref(), Node::File(content.into()));
+        self
+    }
+
+    pub fn dir(mut self, path: impl AsRef<Path>) -> Self {
+        self.insert(path.as_ref(), Node::Dir);
+        self
+    }
+
+    pub fn unreadable(mut self, path: impl AsRef<Path>) -> Self {
+        self.insert(path.as_ref(), Node::Unreadable);
+        self
+    }
+
+    fn insert(&mut self, path: &Path, node: Node) {
+        for ancestor in path.ancestors().skip(1) {
+            self.nodes.insert(ancestor.to_owned(), Node::Dir);
+        }
+        self.nodes.insert(path.to_owned(), node);
+    }
+
+    fn node(&self, path: &Path) -> io::Result<&Node> {
+        self.nodes
+            .get(path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file or directory"))
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        match self.node(path)? {
+            Node::Dir => Ok(self
+                .nodes
+                .keys()
+                .filter(|p| p.parent() == Some(path) && p.as_path() != path)
+                .cloned()
+                .collect()),
+            _ => Err(io::Error::other("not a directory")),
+        }
+    }
+
+    fn metadata(&self, path: &Path, _follow_links: bool) -> io::Result<Metadata> {
+        Ok(match self.node(path)? {
+            Node::Dir => Metadata {
+                kind: EntryKind::Dir,
+                len: 0,
+                modified: None,
+                hidden: dot_name(path),
+                system: false,
+            },
+            Node::File(content) => Metadata {
+                kind: EntryKind::File,
+                len: content.len() as u64,
+                modified: None,
+                hidden: dot_name(path),
+                system: false,
+            },
+            Node::Unreadable => Metadata {
+                kind: EntryKind::File,
+                len: 0,
+                modified: None,
+                hidden: dot_name(path),
+                system: false,
+            },
+        })
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.node(path).map(|_| path.to_owned())
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        match self.node(path)? {
+            Node::File(content) => Ok(Box::new(Cursor::new(content.clone()))),
+            Node::Dir => Err(io::Error::other("is a directory")),
+            Node::Unreadable => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "permission denied",
+            )),
+        }
+    }
+
+    fn read_at(&self, path: &Path, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let mut file = self.open(path)?;
+        io::copy(&mut file.by_ref().take(offset), &mut io::sink())?;
+        fill(&mut file, buf)
+    }
+}
+
+/// What std leaves out about Windows files: attributes, junctions and verbatim paths.
+#[cfg(windows)]
+mod windows {
+    use std::{
+        ffi::OsString,
+        fs,
+        os::windows::fs::{FileTypeExt, MetadataExt},
+        path::{Component, Path, PathBuf, Prefix},
+    };
+
+    use super::{EntryKind, Metadata};
+
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    const FILE_ATTRIBUTE_SYSTEM: u32 = 0x4;
+    /// The longest path Win32 functions take without the `\\?\` prefix.
+    const MAX_PATH: usize = 260;
+    /// Names that open a device in a plain path, whatever the extension.
+    const DEVICES: [&str; 4] = ["CON", "PRN", "AUX", "NUL"];
+
+    /// Everything but the kind, which the caller knows better.
+    pub(super) fn metadata(meta: &fs::Metadata) -> Metadata {
+        let attributes = meta.file_attributes();
+        Metadata {
+            kind: EntryKind::Other,
+            len: meta.len(),
+            modified: meta.modified().ok(),
+            hidden: attributes & FILE_ATTRIBUTE_HIDDEN != 0,
+            system: attributes & FILE_ATTRIBUTE_SYSTEM != 0,
+        }
+    }
+
+    /// std reports junctions as directory symlinks, only the reparse tag tells them apart.
+    pub(super) fn is_junction(path: &Path, meta: &fs::Metadata) -> bool {
+        meta.file_type().is_symlink_dir() && junction::exists(path).unwrap_or(false)
+    }
+
+    /// `None` unless `path` is an extended-length disk or UNC path whose plain form is
+    /// short enough and doesn't rely on the name rules `\\?\` turns off.
+    pub(super) fn without_verbatim(path: &Path) -> Option<PathBuf> {
+        let mut components = path.components();
+        let Some(Component::Prefix(prefix)) = components.next() else {
+            return None;
+        };
+        let mut simple = match prefix.kind() {
+            Prefix::VerbatimDisk(drive) => OsString::from(format!("{}:", char::from(drive))),
+            Prefix::VerbatimUNC(server, share) => {
+                let mut unc = OsString::from(r"\\");
+                unc.push(server);
+                unc.push(r"\");
+                unc.push(share);
+                unc
+            }
+            _ => return None,
+        };
+        let rest = components.as_path();
+        // `\\?\C:` without a separator would turn into the drive-relative `C:`.
+        if !rest.as_os_str().as_encoded_bytes().starts_with(b"\\") {
+            return None;
+        }
+        let plain = components.all(|component| match component {
+            Component::RootDir => true,
+            Component::Normal(name) => plain_name(name.as_encoded_bytes()),
+            _ => false,
+        });
+        simple.push(rest);
+        (plain && simple.len() < MAX_PATH).then(|| PathBuf::from(simple))
+    }
+
+    /// Without the prefix, trailing dots and spaces are dropped, `/` separates and the
+    /// device names mean devices.
+    fn plain_name(name: &[u8]) -> bool {
+        let stem = name.split(|&b| b == b'.').next().unwrap_or_default();
+        let device = DEVICES
+            .iter()
+            .any(|d| stem.eq_ignore_ascii_case(d.as_bytes()))
+            || (stem.len() == 4
+                && (stem[..3].eq_ignore_ascii_case(b"COM")
+                    || stem[..3].eq_ignore_ascii_case(b"LPT"))
+                && stem[3].is_ascii_digit());
+        !device && !name.ends_with(b".") && !name.ends_with(b" ") && !name.contains(&b'/')
+    }
+}
+
+#[cfg(all(test, windows))]
+mod tests {
+    use std::{
+        fs,
+        os::windows::fs::OpenOptionsExt,
+        path::{Path, PathBuf},
+    };
+
+    use crate::task4::fs::{simplified, EntryKind, FileSystem, RealFs};
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("testing-fs-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn drops_the_verbatim_prefix_when_harmless() {
+        let simple = |path: &str| simplified(Path::new(path)).into_owned();
+        assert_eq!(simple(r"\\?\C:\src\a.rs"), PathBuf::from(r"C:\src\a.rs"));
+        assert_eq!(
+            simple(r"\\?\UNC\server\share\a.rs"),
+            PathBuf::from(r"\\server\share\a.rs")
+        );
+        // Each of these means something else, or nothing, without the prefix.
+        for kept in [
+            r"\\?\C:\src\a.",
+            r"\\?\C:\src\a ",
+            r"\\?\C:\src\nul.rs",
+            r"\\?\C:\src\com1",
+            r"\\?\C:\src\..\a.rs",
+            r"\\?\C:",
+            r"\\?\Volume{0}\a.rs",
+        ] {
+            assert_eq!(simple(kept), PathBuf::from(kept));
+        }
+        let long = format!(r"\\?\C:\{}", "a".repeat(300));
+        assert_eq!(simple(&long), PathBuf::from(&long));
+    }
+
+    #[test]
+    fn tells_junctions_from_symlinks() {
+        let dir = scratch("junction");
+        fs::create_dir_all(dir.join("target")).unwrap();
+        junction::create(dir.join("target"), dir.join("junction")).unwrap();
+        assert_eq!(
+            RealFs.metadata(&dir.join("junction"), false).unwrap().kind,
+            EntryKind::Junction
+        );
+        assert_eq!(
+            RealFs.metadata(&dir.join("junction"), true).unwrap().kind,
+            EntryKind::Dir
+        );
+        // Creating a symlink needs developer mode or elevation, not every machine has it.
+        if std::os::windows::fs::symlink_dir(dir.join("target"), dir.join("symlink")).is_ok() {
+            assert_eq!(
+                RealFs.metadata(&dir.join("symlink"), false).unwrap().kind,
+                EntryKind::Symlink
+            );
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reads_hidden_and_system_attributes() {
+        let dir = scratch("attributes");
+        for (name, attributes) in [("plain", 0), ("hidden", 0x2), ("system", 0x4)] {
+            fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .attributes(attributes)
+                .open(dir.join(name))
+                .unwrap();
+        }
+        let meta = |name| RealFs.metadata(&dir.join(name), false).unwrap();
+        assert!(!meta("plain").hidden && !meta("plain").system);
+        assert!(meta("hidden").hidden && !meta("hidden").system);
+        assert!(meta("system").system);
+        // A leading dot means nothing to Windows.
+        fs::write(dir.join(".dotted"), "").unwrap();
+        assert!(!meta(".dotted").hidden);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
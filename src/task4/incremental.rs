@@ -0,0 +1,256 @@
+//! Counting files that only grow, like logs, by what was appended since they were last
+//! counted, see [`Offsets`].
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::anyhow;
+
+use super::{
+    count::{Delimiter, Splitter},
+    escaped_path,
+    fs::FileSystem,
+    FileLines,
+};
+
+/// Bytes remembered from the start of a file and from right before where its count ended.
+/// When either changed the file was rewritten, truncated or rotated and is counted again.
+const KEPT: usize = 64;
+
+/// Where the count of one file ended.
+#[derive(Debug, Clone)]
+struct Seen {
+    len: u64,
+    /// Delimiters in the first `len` bytes.
+    lines: usize,
+    splitter: Splitter,
+    head: Vec<u8>,
+    tail: Vec<u8>,
+}
+
+impl Seen {
+    fn new(delimiter: &Delimiter) -> Self {
+        Seen {
+            len: 0,
+            lines: 0,
+            splitter: Splitter::new(delimiter.clone()),
+            head: vec![],
+            tail: vec![],
+        }
+    }
+
+    fn feed(&mut self, chunk: &[u8]) {
+        self.len += chunk.len() as u64;
+        self.lines += self.splitter.count(chunk);
+        let head = (KEPT - self.head.len()).min(chunk.len());
+        self.head.extend_from_slice(&chunk[..head]);
+        self.tail
+            .extend_from_slice(&chunk[chunk.len() - chunk.len().min(KEPT)..]);
+        let over = self.tail.len().saturating_sub(KEPT);
+        self.tail.drain(..over);
+    }
+
+    /// Reads what was appended since, `None` when the file doesn't start and end the way it
+    /// did at `len` or isn't counted with `delimiter`.
+    fn resume(
+        mut self,
+        fs: &dyn FileSystem,
+        path: &Path,
+        delimiter: &Delimiter,
+    ) -> io::Result<Option<Seen>> {
+        if self.splitter.delimiter() != delimiter {
+            return Ok(None);
+        }
+        let mut buf = vec![0; 64 * 1024];
+        let tail_at = self.len - self.tail.len() as u64;
+        for (offset, kept) in [(0, &self.head), (tail_at, &self.tail)] {
+            let n = fs.read_at(path, offset, &mut buf[..kept.len()])?;
+            if buf[..n] != kept[..] {
+                return Ok(None);
+            }
+        }
+        loop {
+            let n = fs.read_at(path, self.len, &mut buf)?;
+            if n == 0 {
+                return Ok(Some(self));
+            }
+            self.feed(&buf[..n]);
+        }
+    }
+
+    fn count(fs: &dyn FileSystem, path: &Path, delimiter: &Delimiter) -> io::Result<Seen> {
+        let mut file = fs.open(path)?;
+        let mut buf = vec![0; 64 * 1024];
+        let mut seen = Seen::new(delimiter);
+        loop {
+            let n = match file.read(&mut buf) {
+                Ok(0) => return Ok(seen),
+                Ok(n) => n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            seen.feed(&buf[..n]);
+        }
+    }
+}
+
+/// Where the counts of files ended, so that counting them again only reads what was
+/// appended, see [`SearchBuilder::offsets`](super::SearchBuilder::offsets).
+///
+/// A file is counted from the start the first time, when it shrank, when its first bytes or
+/// the ones before where the last count ended are not the same anymore, which is how a
+/// rewrite or rotation shows, and when its file system can't read from an offset.
+///
+/// The text form has a line for every file counted with them or parsed into them,
+/// `path<TAB>len<TAB>lines<TAB>matched<TAB>open<TAB>delimiter<TAB>head<TAB>tail` with the
+/// bytes in hex, see [`Splitter`] for `matched` and `open`.
+#[derive(Debug, Default)]
+pub struct Offsets {
+    files: Mutex<HashMap<PathBuf, Seen>>,
+}
+
+impl Offsets {
+    pub fn new() -> Self {
+        Offsets::default()
+    }
+
+    /// Counts the lines of `path` ended by `delimiter`, and remembers where the count ended.
+    pub fn count(
+        &self,
+        fs: &dyn FileSystem,
+        path: &Path,
+        delimiter: &Delimiter,
+    ) -> io::Result<FileLines> {
+        let seen = self.files.lock().unwrap().remove(path);
+        let resumed = match seen.map(|seen| seen.resume(fs, path, delimiter)) {
+            Some(Ok(resumed)) => resumed,
+            Some(Err(err)) if err.kind() != io::ErrorKind::Unsupported => return Err(err),
+            _ => None,
+        };
+        let seen = match resumed {
+            Some(seen) => seen,
+            None => Seen::count(fs, path, delimiter)?,
+        };
+        let lines = seen.lines + usize::from(seen.splitter.open());
+        self.files.lock().unwrap().insert(path.to_owned(), seen);
+        Ok(FileLines::unread(path, lines))
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let mut files = HashMap::new();
+        for (line_no, line) in text.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let bad = || anyhow!("offsets line {}: expected 8 fields", line_no + 1);
+            // Split from the right, the path is the only field that may contain tabs.
+            let fields: Vec<_> = line.rsplitn(8, '\t').collect();
+            let &[tail, head, delimiter, open, matched, lines, len, path] = &fields[..] else {
+                return Err(bad());
+            };
+            let seen = (|| {
+                let delimiter = Delimiter::new(unhex(delimiter)?)?;
+                let open = match open {
+                    "0" => false,
+                    "1" => true,
+                    _ => return None,
+                };
+                Some(Seen {
+                    len: len.parse().ok()?,
+                    lines: lines.parse().ok()?,
+                    splitter: Splitter::resume(delimiter, matched.parse().ok()?, open)?,
+                    head: unhex(head)?,
+                    tail: unhex(tail)?,
+                })
+            })();
+            files.insert(PathBuf::from(path), seen.ok_or_else(bad)?);
+        }
+        Ok(Offsets {
+            files: Mutex::new(files),
+        })
+    }
+}
+
+/// The text form, sorted by path.
+impl fmt::Display for Offsets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let files = self.files.lock().unwrap();
+        let mut paths: Vec<_> = files.keys().collect();
+        paths.sort();
+        for path in paths {
+            let seen = &files[path];
+            writeln!(
+                f,
+                "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                escaped_path(path),
+                seen.len,
+                seen.lines,
+                seen.splitter.matched(),
+                u8::from(seen.splitter.open()),
+                hex(seen.splitter.delimiter().as_bytes()),
+                hex(&seen.head),
+                hex(&seen.tail)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::task4::{count::Delimiter, fs::MemoryFs, incremental::Offsets};
+
+    #[test]
+    fn reads_only_what_was_appended() {
+        let path = Path::new("/log");
+        let (lf, crlf) = (Delimiter::default(), "crlf".parse().unwrap());
+        let count = |offsets: &Offsets, content: String, delimiter| {
+            let fs = MemoryFs::new().file(path, content);
+            offsets.count(&fs, path, delimiter).unwrap().lines
+        };
+        let (head, tail) = ("h".repeat(64), "t".repeat(64));
+        let offsets = Offsets::new();
+        assert_eq!(count(&offsets, format!("{head}\n\n{tail}"), &lf), 3);
+        // The bytes between those kept changed, counting from the start would find 3.
+        assert_eq!(count(&offsets, format!("{head}\n-{tail}\nz\n"), &lf), 4);
+        let offsets = Offsets::parse(&offsets.to_string()).unwrap();
+        assert_eq!(offsets.len(), 1);
+        assert_eq!(count(&offsets, format!("{head}\n-{tail}\nz\ne"), &lf), 5);
+
+        assert_eq!(count(&offsets, "x\n".repeat(100), &lf), 100);
+        assert_eq!(count(&offsets, "x\n".to_owned(), &lf), 1);
+        assert_eq!(count(&offsets, "a\r".to_owned(), &crlf), 1);
+        assert_eq!(count(&offsets, "a\r\nb".to_owned(), &crlf), 2);
+
+        assert!(Offsets::parse("/log\t1\t2\n").is_err());
+    }
+}
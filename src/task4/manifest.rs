@@ -0,0 +1,177 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+
+use super::{escaped_path, relative_path, FileLines};
+
+/// Digest and line count per file, keyed by the path relative to the search root.
+///
+/// The text form has one `path<TAB>digest<TAB>lines` record per line.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    entries: BTreeMap<PathBuf, Record>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Record {
+    digest: String,
+    lines: usize,
+}
+
+/// How a file differs between a manifest and the current tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(PathBuf),
+    Deleted(PathBuf),
+    Modified(PathBuf),
+}
+
+impl Manifest {
+    /// Formats the record for a counted file, `file` must have been searched with a digest.
+    pub fn record(root: &Path, file: &FileLines) -> String {
+        format!(
+            "{}\t{}\t{}",
+            escaped_path(relative_path(root, &file.path)),
+            file.digest.as_deref().unwrap_or_default(),
+            file.lines
+        )
+    }
+
+    pub fn insert(&mut self, root: &Path, file: &FileLines) {
+        self.entries.insert(
+            relative_path(root, &file.path).to_owned(),
+            Record {
+                digest: file.digest.clone().unwrap_or_default(),
+                lines: file.lines,
+            },
+        );
+    }
+
+    pub fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let mut entries = BTreeMap::new();
+        for (line_no, line) in text.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            // Split from the right, the path is the only field that may contain tabs.
+            let mut fields = line.rsplitn(3, '\t');
+            let (Some(lines), Some(digest), Some(path)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(anyhow!("manifest line {}: expected 3 fields", line_no + 1));
+            };
+            let lines = lines.parse().map_err(|_| {
+                anyhow!("manifest line {}: bad line count '{}'", line_no + 1, lines)
+            })?;
+            entries.insert(
+                PathBuf::from(path),
+                Record {
+                    digest: digest.to_owned(),
+                    lines,
+                },
+            );
+        }
+        Ok(Manifest { entries })
+    }
+
+    /// What changed going from `self` to `current`, ordered by path.
+    pub fn diff(&self, current: &Manifest) -> Vec<Change> {
+        let mut changes = vec![];
+        for (path, record) in &self.entries {
+            match current.entries.get(path) {
+                None => changes.push(Change::Deleted(path.clone())),
+                Some(now) if now != record => changes.push(Change::Modified(path.clone())),
+                Some(_) => {}
+            }
+        }
+        for path in current.entries.keys() {
+            if !self.entries.contains_key(path) {
+                changes.push(Change::Added(path.clone()));
+            }
+        }
+        changes.sort_by(|a, b| a.path().cmp(b.path()));
+        changes
+    }
+}
+
+impl Change {
+    pub fn path(&self) -> &Path {
+        match self {
+            Change::Added(path) | Change::Deleted(path) | Change::Modified(path) => path,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Change::Added(_) => "added",
+            Change::Deleted(_) => "deleted",
+            Change::Modified(_) => "modified",
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.kind(), escaped_path(self.path()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::task4::{
+        manifest::{Change, Manifest},
+        FileLines,
+    };
+
+    fn file(path: &str, digest: &str, lines: usize) -> FileLines {
+        FileLines {
+            path: Path::new("root").join(path),
+            lines,
+            digest: Some(digest.to_owned()),
+            metrics: None,
+            estimate: None,
+        }
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let root = Path::new("root");
+        let files = [file("a.rs", "aa", 1), file("dir/tab\tname.rs", "bb", 2)];
+        let text: String = files
+            .iter()
+            .map(|f| Manifest::record(root, f) + "\n")
+            .collect();
+        assert_eq!(text, "a.rs\taa\t1\ndir/tab\tname.rs\tbb\t2\n");
+
+        let mut expected = Manifest::default();
+        for f in &files {
+            expected.insert(root, f);
+        }
+        assert_eq!(Manifest::parse(&text).unwrap(), expected);
+        assert!(Manifest::parse("a.rs\t1\n").is_err());
+    }
+
+    #[test]
+    fn diff_reports_changes() {
+        let root = Path::new("root");
+        let old = Manifest::parse("a.rs\taa\t1\nb.rs\tbb\t2\nc.rs\tcc\t3\n").unwrap();
+        let mut current = Manifest::default();
+        current.insert(root, &file("a.rs", "aa", 1));
+        current.insert(root, &file("b.rs", "b2", 2));
+        current.insert(root, &file("d.rs", "dd", 4));
+        assert_eq!(
+            old.diff(&current),
+            vec![
+                Change::Modified("b.rs".into()),
+                Change::Deleted("c.rs".into()),
+                Change::Added("d.rs".into()),
+            ]
+        );
+    }
+}
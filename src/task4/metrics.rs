@@ -0,0 +1,155 @@
+use std::fmt;
+
+/// Columns a tab advances the indentation by.
+const TAB_WIDTH: usize = 4;
+
+/// Line length and indentation statistics of one file, lengths are in bytes without the line ending.
+///
+/// Blank lines count towards the lengths but not towards the indentation.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Metrics {
+    pub lines: usize,
+    pub max_len: usize,
+    pub total_len: usize,
+    /// Lines longer than the threshold the metrics were collected with.
+    pub long_lines: usize,
+    pub indented_lines: usize,
+    pub max_indent: usize,
+    pub total_indent: usize,
+}
+
+impl Metrics {
+    pub fn avg_len(&self) -> f64 {
+        average(self.total_len, self.lines)
+    }
+
+    /// Average over the non-blank lines.
+    pub fn avg_indent(&self) -> f64 {
+        average(self.total_indent, self.indented_lines)
+    }
+}
+
+fn average(total: usize, count: usize) -> f64 {
+    if count == 0 {
+        0.0
+    } else {
+        total as f64 / count as f64
+    }
+}
+
+impl fmt::Display for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "len max {} avg {:.1} long {} indent max {} avg {:.1}",
+            self.max_len,
+            self.avg_len(),
+            self.long_lines,
+            self.max_indent,
+            self.avg_indent()
+        )
+    }
+}
+
+/// Collects [`Metrics`] over a file fed in chunks, lines may span chunk boundaries.
+#[derive(Debug)]
+pub struct LineStats {
+    long_line: usize,
+    metrics: Metrics,
+    len: usize,
+    indent: usize,
+    in_indent: bool,
+    last: u8,
+}
+
+impl LineStats {
+    /// Lines longer than `long_line` bytes are counted as long.
+    pub fn new(long_line: usize) -> Self {
+        LineStats {
+            long_line,
+            metrics: Metrics::default(),
+            len: 0,
+            indent: 0,
+            in_indent: true,
+            last: b'\n',
+        }
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        for &b in chunk {
+            if b == b'\n' {
+                self.end_line();
+                continue;
+            }
+            self.len += 1;
+            self.last = b;
+            if self.in_indent {
+                match b {
+                    b' ' => self.indent += 1,
+                    b'\t' => self.indent += TAB_WIDTH,
+                    b'\r' => {}
+                    _ => self.in_indent = false,
+                }
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> Metrics {
+        if self.len > 0 {
+            self.end_line();
+        }
+        self.metrics
+    }
+
+    fn end_line(&mut self) {
+        let len = self.len - usize::from(self.len > 0 && self.last == b'\r');
+        let m = &mut self.metrics;
+        m.lines += 1;
+        m.max_len = m.max_len.max(len);
+        m.total_len += len;
+        m.long_lines += usize::from(len > self.long_line);
+        // Whitespace-only lines have no indentation to speak of.
+        if !self.in_indent {
+            m.indented_lines += 1;
+            m.max_indent = m.max_indent.max(self.indent);
+            m.total_indent += self.indent;
+        }
+        self.len = 0;
+        self.indent = 0;
+        self.in_indent = true;
+        self.last = b'\n';
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task4::metrics::{LineStats, Metrics};
+
+    fn stats(chunks: &[&str], long_line: usize) -> Metrics {
+        let mut stats = LineStats::new(long_line);
+        for chunk in chunks {
+            stats.update(chunk.as_bytes());
+        }
+        stats.finish()
+    }
+
+    #[test]
+    fn measures_lines_across_chunks() {
+        let metrics = stats(&["fn main() {\r\n    le", "t x = 1;\n\n\t}"], 10);
+        assert_eq!(
+            metrics,
+            Metrics {
+                lines: 4,
+                max_len: 14,
+                total_len: 27,
+                long_lines: 2,
+                indented_lines: 3,
+                max_indent: 4,
+                total_indent: 8,
+            }
+        );
+        assert_eq!(metrics.avg_len(), 27.0 / 4.0);
+        assert_eq!(stats(&[], 10), Metrics::default());
+        assert_eq!(stats(&["   \n"], 10).indented_lines, 0);
+    }
+}
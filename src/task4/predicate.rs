@@ -0,0 +1,102 @@
+//! Selects counted files with a user supplied VM program.
+//!
+//! The program runs once per file that passed the [`Filter`](super::Filter) and its line
+//! count, and sees these variables:
+//!
+//! | variable | value |
+//! |----------|-------|
+//! | `size` | the file's length in bytes |
+//! | `lines` | the file's line count |
+//! | `depth` | how far below the root the file is, a matched root is 0 |
+//! | `mtime` | last modification in seconds since the Unix epoch, unset when the backend doesn't know |
+//!
+//! A nonzero return value keeps the file. Values too large for a `ValueType` are capped at
+//! `ValueType::MAX`.
+
+use std::time::SystemTime;
+
+use super::walk::Entry;
+use crate::task_1_and_2::{program::Program, InterpretationError, Value, ValueType};
+
+#[derive(Debug, Clone)]
+pub struct Predicate {
+    program: Program,
+}
+
+impl Predicate {
+    pub fn new(program: Program) -> Self {
+        Predicate { program }
+    }
+
+    /// Whether the counted file at `entry` with `lines` lines stays in the results.
+    pub fn includes(&self, entry: &Entry, lines: usize) -> Result<bool, InterpretationError> {
+        let mut inputs = vec![
+            ("size", value(entry.len)),
+            ("lines", value(lines)),
+            ("depth", value(entry.depth)),
+        ];
+        let since_epoch = entry
+            .modified
+            .and_then(|at| at.duration_since(SystemTime::UNIX_EPOCH).ok());
+        if let Some(since) = since_epoch {
+            inputs.push(("mtime", value(since.as_secs())));
+        }
+        Ok(self.program.run_with(&inputs)?.is_true())
+    }
+}
+
+fn value(count: impl TryInto<ValueType>) -> Value {
+    Value::Int(count.try_into().unwrap_or(ValueType::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        time::{Duration, SystemTime},
+    };
+
+    use crate::{
+        task4::{fs::EntryKind, predicate::Predicate, walk::Entry},
+        task_1_and_2::{asm, program::Program, InterpretationError},
+    };
+
+    fn predicate(text: &str) -> Predicate {
+        Predicate::new(Program::new(asm::parse(text).unwrap()))
+    }
+
+    fn entry(len: u64, depth: usize, modified: Option<SystemTime>) -> Entry {
+        Entry {
+            path: PathBuf::from("a.rs"),
+            depth,
+            kind: EntryKind::File,
+            len,
+            modified,
+        }
+    }
+
+    #[test]
+    fn keeps_files_the_program_returns_nonzero_for() {
+        // More than 10 lines and at most one directory down.
+        let pred = predicate(
+            "LoadVal 10\nReadVar lines\nGt\nLoadVal 1\nReadVar depth\nLe\nMultiply\nReturnValue",
+        );
+        assert_eq!(pred.includes(&entry(0, 1, None), 11), Ok(true));
+        assert_eq!(pred.includes(&entry(0, 1, None), 10), Ok(false));
+        assert_eq!(pred.includes(&entry(0, 2, None), 11), Ok(false));
+    }
+
+    #[test]
+    fn binds_mtime_only_when_known() {
+        let pred = predicate("ReadVar mtime\nReadVar size\nAdd\nReturnValue");
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(pred.includes(&entry(5, 0, Some(modified)), 0), Ok(true));
+        assert_eq!(
+            pred.includes(&entry(5, 0, None), 0),
+            Err(InterpretationError::UnknownVariable {
+                var_name: "mtime".to_owned(),
+                ip: 0
+            })
+        );
+    }
+}
@@ -0,0 +1,302 @@
+//! What the lines of a file are, by its extension, see [`Registry`].
+//!
+//! Plain text is split on the delimiter of the search. The built-in strategies for
+//! structured formats count records instead: the elements of a JSON array, the rows of a
+//! CSV file or the cells of a Jupyter notebook.
+
+use std::{collections::HashMap, fmt, path::Path, sync::Arc};
+
+use super::count::{Delimiter, Splitter};
+
+/// Counts the records of one file fed in chunks, as they come.
+pub trait Records: Send {
+    fn update(&mut self, chunk: &[u8]);
+
+    /// The records in everything fed, a record cut off by the end of the file included.
+    fn finish(self: Box<Self>) -> usize;
+}
+
+/// A way of counting the records of a kind of file.
+pub trait Strategy: fmt::Debug + Send + Sync {
+    /// A counter for one file, `delimiter` is the one the search was given.
+    fn records(&self, delimiter: &Delimiter) -> Box<dyn Records>;
+}
+
+/// Strategies by extension, files with an extension that has none are plain text.
+///
+/// [`Registry::new`] knows no extension and [`Registry::builtin`] the structured formats
+/// below. Extensions are matched ignoring ASCII case.
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    by_extension: HashMap<String, Arc<dyn Strategy>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// `json` with [`JsonRecords`], `csv` with [`CsvRows`] and `ipynb` with [`NotebookCells`].
+    pub fn builtin() -> Self {
+        Registry::new()
+            .register("json", JsonRecords)
+            .register("csv", CsvRows)
+            .register("ipynb", NotebookCells)
+    }
+
+    /// Counts files ending in `.extension` with `strategy`, replacing any earlier one.
+    pub fn register(mut self, extension: &str, strategy: impl Strategy + 'static) -> Self {
+        self.by_extension
+            .insert(extension.to_ascii_lowercase(), Arc::new(strategy));
+        self
+    }
+
+    /// The strategy for `path`, `None` for plain text.
+    pub fn strategy(&self, path: &Path) -> Option<&dyn Strategy> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        self.by_extension
+            .get(&extension)
+            .map(|strategy| &**strategy)
+    }
+}
+
+/// Lines ended by the delimiter of the search, counted the way `BufRead::lines` does.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlainText;
+
+impl Strategy for PlainText {
+    fn records(&self, delimiter: &Delimiter) -> Box<dyn Records> {
+        Box::new(Lines::new(delimiter))
+    }
+}
+
+struct Lines {
+    lines: usize,
+    splitter: Splitter,
+}
+
+impl Lines {
+    fn new(delimiter: &Delimiter) -> Self {
+        Lines {
+            lines: 0,
+            splitter: Splitter::new(delimiter.clone()),
+        }
+    }
+}
+
+impl Records for Lines {
+    fn update(&mut self, chunk: &[u8]) {
+        self.lines += self.splitter.count(chunk);
+    }
+
+    fn finish(self: Box<Self>) -> usize {
+        self.lines + usize::from(self.splitter.open())
+    }
+}
+
+/// The elements of a top-level array, or the top-level values when there is none, so one
+/// record per object of JSON Lines.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonRecords;
+
+impl Strategy for JsonRecords {
+    fn records(&self, _: &Delimiter) -> Box<dyn Records> {
+        Box::new(Json::new(None))
+    }
+}
+
+/// The elements of the `cells` array of a Jupyter notebook.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NotebookCells;
+
+impl Strategy for NotebookCells {
+    fn records(&self, _: &Delimiter) -> Box<dyn Records> {
+        Box::new(Json::new(Some(b"cells")))
+    }
+}
+
+/// Finds where values start without parsing them, malformed JSON just counts oddly.
+struct Json {
+    records: usize,
+    /// Arrays and objects open around the next byte.
+    depth: usize,
+    /// Records are the values starting at this depth, `usize::MAX` when not in them.
+    record_depth: usize,
+    /// Records are in the array under this key of the top-level object, or in a
+    /// top-level array when `None`.
+    field: Option<&'static [u8]>,
+    /// The start of the string being read, as much as it takes to compare with `field`.
+    string: Vec<u8>,
+    /// The last string ended at depth 1 and was `field`.
+    keyed: bool,
+    in_string: bool,
+    escaped: bool,
+    /// In a number, `true`, `false` or `null`.
+    in_scalar: bool,
+}
+
+impl Json {
+    fn new(field: Option<&'static [u8]>) -> Self {
+        Json {
+            records: 0,
+            depth: 0,
+            record_depth: if field.is_some() { usize::MAX } else { 0 },
+            field,
+            string: vec![],
+            keyed: false,
+            in_string: false,
+            escaped: false,
+            in_scalar: false,
+        }
+    }
+
+    fn value_starts(&mut self) {
+        self.records += usize::from(self.depth == self.record_depth);
+        self.keyed = false;
+    }
+}
+
+impl Records for Json {
+    fn update(&mut self, chunk: &[u8]) {
+        for &b in chunk {
+            if self.in_string {
+                match b {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => {
+                        self.in_string = false;
+                        self.keyed = self.depth == 1 && self.field == Some(&self.string[..]);
+                        continue;
+                    }
+                    _ => {}
+                }
+                if self
+                    .field
+                    .is_some_and(|field| self.string.len() <= field.len())
+                {
+                    self.string.push(b);
+                }
+                continue;
+            }
+            if self.in_scalar {
+                if !matches!(b, b' ' | b'\t' | b'\n' | b'\r' | b',' | b':' | b']' | b'}') {
+                    continue;
+                }
+                self.in_scalar = false;
+            }
+            match b {
+                b' ' | b'\t' | b'\n' | b'\r' | b',' | b':' => {}
+                b'"' => {
+                    self.value_starts();
+                    self.in_string = true;
+                    self.string.clear();
+                }
+                b'[' if self.depth == 0 && self.field.is_none() => {
+                    self.depth = 1;
+                    self.record_depth = 1;
+                }
+                b'[' | b'{' => {
+                    let opens_records = b == b'[' && self.keyed;
+                    self.value_starts();
+                    self.depth += 1;
+                    if opens_records {
+                        self.record_depth = self.depth;
+                    }
+                }
+                b']' | b'}' => {
+                    if self.depth == self.record_depth {
+                        self.record_depth = if self.field.is_some() { usize::MAX } else { 0 };
+                    }
+                    self.depth = self.depth.saturating_sub(1);
+                }
+                _ => {
+                    self.value_starts();
+                    self.in_scalar = true;
+                }
+            }
+        }
+    }
+
+    fn finish(self: Box<Self>) -> usize {
+        self.records
+    }
+}
+
+/// Rows ended by `\n` outside quoted fields, the header included.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CsvRows;
+
+impl Strategy for CsvRows {
+    fn records(&self, _: &Delimiter) -> Box<dyn Records> {
+        Box::new(Csv::default())
+    }
+}
+
+#[derive(Default)]
+struct Csv {
+    rows: usize,
+    quoted: bool,
+    /// Something came after the last row.
+    open: bool,
+}
+
+impl Records for Csv {
+    fn update(&mut self, chunk: &[u8]) {
+        for &b in chunk {
+            match b {
+                // A doubled quote inside a quoted field toggles twice.
+                b'"' => self.quoted = !self.quoted,
+                b'\n' if !self.quoted => {
+                    self.rows += 1;
+                    self.open = false;
+                    continue;
+                }
+                _ => {}
+            }
+            self.open = true;
+        }
+    }
+
+    fn finish(self: Box<Self>) -> usize {
+        self.rows + usize::from(self.open)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::task4::{
+        count::Delimiter,
+        records::{Registry, Strategy},
+    };
+
+    #[test]
+    fn counts_records_by_extension() {
+        let registry = Registry::builtin();
+        let count = |path: &str, chunks: &[&str]| {
+            let strategy = registry.strategy(Path::new(path));
+            let mut records = strategy
+                .unwrap_or(&super::PlainText as &dyn Strategy)
+                .records(&Delimiter::default());
+            for chunk in chunks {
+                records.update(chunk.as_bytes());
+            }
+            records.finish()
+        };
+
+        assert_eq!(count("a.txt", &["x\ny"]), 2);
+        assert_eq!(
+            count(
+                "a.json",
+                &["[{\"a\": [1, 2]}, \"x,]", "\", 3", ", nu", "ll]"]
+            ),
+            4
+        );
+        assert_eq!(count("a.json", &["[]"]), 0);
+        assert_eq!(count("a.JSON", &["{\"a\": 1}\n{\"a\": 2}\n", "7 true"]), 4);
+        assert_eq!(count("a.csv", &["a,b\n1,\"x\n", "y\"\"\"\n2,3"]), 3);
+        let notebook = r#"{"metadata": {"cells": [1]}, "cells": [{"source": ["a", "b"]}, {}]}"#;
+        assert_eq!(count("a.ipynb", &[notebook]), 2);
+    }
+}
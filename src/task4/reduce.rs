@@ -0,0 +1,117 @@
+//! Folds search results with a user supplied VM program.
+//!
+//! The program runs once per file in the order they are reported and sees these variables:
+//!
+//! | variable | value |
+//! |----------|-------|
+//! | `acc` | what the previous run returned, 0 for the first file |
+//! | `index` | the file's position in the results, from 0 |
+//! | `lines` | the file's line count |
+//! | `max_len`, `total_len`, `long_lines` | with `--metrics`, as in [`Metrics`](super::metrics::Metrics) |
+//! | `indented_lines`, `max_indent`, `total_indent` | with `--metrics`, likewise |
+//!
+//! Whatever it returns becomes the next `acc`, the last one is the result of the search.
+//! Counts too large for a value are capped at `ValueType::MAX`.
+
+use super::FileLines;
+use crate::task_1_and_2::{program::Program, InterpretationError, Value, ValueType};
+
+pub struct Reducer {
+    program: Program,
+    acc: Value,
+    index: usize,
+}
+
+impl Reducer {
+    pub fn new(program: Program) -> Self {
+        Reducer {
+            program,
+            acc: Value::Int(0),
+            index: 0,
+        }
+    }
+
+    pub fn add(&mut self, file: &FileLines) -> Result<(), InterpretationError> {
+        let mut inputs = vec![
+            ("acc", self.acc.clone()),
+            ("index", value(self.index)),
+            ("lines", value(file.lines)),
+        ];
+        if let Some(m) = &file.metrics {
+            inputs.extend([
+                ("max_len", value(m.max_len)),
+                ("total_len", value(m.total_len)),
+                ("long_lines", value(m.long_lines)),
+                ("indented_lines", value(m.indented_lines)),
+                ("max_indent", value(m.max_indent)),
+                ("total_indent", value(m.total_indent)),
+            ]);
+        }
+        self.acc = self.program.run_with(&inputs)?;
+        self.index += 1;
+        Ok(())
+    }
+
+    /// The accumulated value, 0 before the first file.
+    pub fn value(&self) -> &Value {
+        &self.acc
+    }
+}
+
+fn value(count: usize) -> Value {
+    Value::Int(ValueType::try_from(count).unwrap_or(ValueType::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::{
+        task4::{metrics::Metrics, reduce::Reducer, FileLines},
+        task_1_and_2::{asm, program::Program, InterpretationError, Value},
+    };
+
+    fn file(lines: usize, metrics: Option<Metrics>) -> FileLines {
+        FileLines {
+            path: PathBuf::from("a.rs"),
+            lines,
+            digest: None,
+            metrics,
+            estimate: None,
+        }
+    }
+
+    fn reducer(text: &str) -> Reducer {
+        Reducer::new(Program::new(asm::parse(text).unwrap()))
+    }
+
+    #[test]
+    fn accumulates_a_weighted_total() {
+        // acc + lines * (index + 1)
+        let mut weighted = reducer(
+            "LoadVal 1\nReadVar index\nAdd\nReadVar lines\nMultiply\nReadVar acc\nAdd\nReturnValue",
+        );
+        for lines in [10, 20, 30] {
+            weighted.add(&file(lines, None)).unwrap();
+        }
+        assert_eq!(weighted.value(), &Value::Int(10 + 2 * 20 + 3 * 30));
+    }
+
+    #[test]
+    fn binds_metrics_only_when_collected() {
+        let mut longest = reducer("ReadVar max_len\nReturnValue");
+        let metrics = Metrics {
+            max_len: 80,
+            ..Metrics::default()
+        };
+        longest.add(&file(1, Some(metrics))).unwrap();
+        assert_eq!(longest.value(), &Value::Int(80));
+        assert_eq!(
+            longest.add(&file(1, None)),
+            Err(InterpretationError::UnknownVariable {
+                var_name: "max_len".to_owned(),
+                ip: 0
+            })
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+
+use super::fs::{dot_name, EntryKind, FileSystem, Metadata};
+
+/// Flat key/object storage as offered by S3-style buckets.
+pub trait ObjectStore: fmt::Debug + Send + Sync {
+    /// Every object whose key starts with `prefix`.
+    fn list(&self, prefix: &str) -> io::Result<Vec<Object>>;
+
+    fn get(&self, key: &str) -> io::Result<Box<dyn Read + Send>>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Object {
+    pub key: String,
+    pub size: u64,
+}
+
+/// Anonymous client for the S3 `ListObjectsV2` API, which the GCS XML API serves as well.
+///
+/// Only public buckets can be read, requests are not signed.
+#[derive(Debug)]
+pub struct HttpStore {
+    endpoint: String,
+}
+
+impl HttpStore {
+    pub fn s3(bucket: &str) -> Self {
+        HttpStore {
+            endpoint: format!("https://{}.s3.amazonaws.com", bucket),
+        }
+    }
+
+    pub fn gcs(bucket: &str) -> Self {
+        HttpStore {
+            endpoint: format!("https://storage.googleapis.com/{}", bucket),
+        }
+    }
+}
+
+impl ObjectStore for HttpStore {
+    fn list(&self, prefix: &str) -> io::Result<Vec<Object>> {
+        let mut objects = vec![];
+        let mut token: Option<String> = None;
+        loop {
+            let mut request = ureq::get(&format!("{}/", self.endpoint))
+                .query("list-type", "2")
+                .query("prefix", prefix);
+            if let Some(token) = &token {
+                request = request.query("continuation-token", token);
+            }
+            let body = request.call().map_err(io::Error::other)?.into_string()?;
+            let page = parse_list_page(&body);
+            objects.extend(page.objects);
+            match page.next_token {
+                Some(next) => token = Some(next),
+                None => return Ok(objects),
+            }
+        }
+    }
+
+    fn get(&self, key: &str) -> io::Result<Box<dyn Read + Send>> {
+        let url = format!("{}/{}", self.endpoint, encode_key(key));
+        let response = ureq::get(&url).call().map_err(io::Error::other)?;
+        Ok(Box::new(response.into_reader()))
+    }
+}
+
+struct ListPage {
+    objects: Vec<Object>,
+    next_token: Option<String>,
+}
+
+/// Pulls the few fields needed out of a `ListBucketResult` document.
+fn parse_list_page(xml: &str) -> ListPage {
+    let objects = xml
+        .split("<Contents>")
+        .skip(1)
+        .filter_map(|contents| {
+            Some(Object {
+                key: xml_field(contents, "Key")?,
+                size: xml_field(contents, "Size")?.parse().ok()?,
+            })
+        })
+        .collect();
+    ListPage {
+        objects,
+        next_token: xml_field(xml, "NextContinuationToken"),
+    }
+}
+
+fn xml_field(xml: &str, tag: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let len = xml[start..].find(&format!("</{}>", tag))?;
+    Some(
+        xml[start..start + len]
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&"),
+    )
+}
+
+fn encode_key(key: &str) -> String {
+    key.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Presents the objects below a bucket prefix as a directory tree, `/` separating levels.
+///
+/// The whole listing is fetched once up front, reads go to the store.
+#[derive(Debug)]
+pub struct ObjectStoreFs {
+    store: Box<dyn ObjectStore>,
+    nodes: BTreeMap<PathBuf, Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Dir,
+    Object { key: String, size: u64 },
+}
+
+impl ObjectStoreFs {
+    /// Opens an `s3://bucket/prefix` or `gs://bucket/prefix` URL.
+    pub fn open(url: &str) -> Result<Self, anyhow::Error> {
+        let (store, rest): (Box<dyn ObjectStore>, _) = if let Some(rest) = url.strip_prefix("s3://")
+        {
+            (Box::new(HttpStore::s3(bucket_of(rest))), rest)
+        } else if let Some(rest) = url.strip_prefix("gs://") {
+            (Box::new(HttpStore::gcs(bucket_of(rest))), rest)
+        } else {
+            return Err(anyhow!("'{}' is not an s3:// or gs:// URL", url));
+        };
+        let prefix = rest.split_once('/').map_or("", |(_, prefix)| prefix);
+        let root = url
+            .strip_suffix(prefix)
+            .unwrap_or(url)
+            .trim_end_matches('/');
+        Ok(Self::new(store, root, prefix)?)
+    }
+
+    /// Lists everything under `prefix`, the objects show up below the `root` path.
+    pub fn new(store: Box<dyn ObjectStore>, root: &str, prefix: &str) -> io::Result<Self> {
+        let root = PathBuf::from(root);
+        let prefix = prefix.trim_end_matches('/');
+        let dir_prefix = if prefix.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", prefix)
+        };
+
+        let mut nodes = BTreeMap::new();
+        nodes.insert(root.join(prefix), Node::Dir);
+        for object in store.list(&dir_prefix)? {
+            let path = root.join(object.key.trim_end_matches('/'));
+            for dir in path.ancestors().skip(1) {
+                if dir.as_os_str().len() <= root.as_os_str().len() {
+                    break;
+                }
+                nodes.insert(dir.to_owned(), Node::Dir);
+            }
+            // Keys ending in `/` are placeholders some tools create for empty folders.
+            let node = if object.key.ends_with('/') {
+                Node::Dir
+            } else {
+                Node::Object {
+                    key: object.key,
+                    size: object.size,
+                }
+            };
+            nodes.insert(path, node);
+        }
+        Ok(ObjectStoreFs { store, nodes })
+    }
+
+    fn node(&self, path: &Path) -> io::Result<&Node> {
+        self.nodes
+            .get(path)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such object"))
+    }
+}
+
+fn bucket_of(rest: &str) -> &str {
+    rest.split('/').next().unwrap_or(rest)
+}
+
+impl FileSystem for ObjectStoreFs {
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        match self.node(path)? {
+            Node::Dir => Ok(self
+                .nodes
+                .keys()
+                .filter(|p| p.parent() == Some(path))
+                .cloned()
+                .collect()),
+            Node::Object { .. } => Err(io::Error::other("not a directory")),
+        }
+    }
+
+    fn metadata(&self, path: &Path, _follow_links: bool) -> io::Result<Metadata> {
+        Ok(match self.node(path)? {
+            Node::Dir => Metadata {
+                kind: EntryKind::Dir,
+                len: 0,
+                modified: None,
+                hidden: dot_name(path),
+                system: false,
+            },
+            &Node::Object { size, .. } => Metadata {
+                kind: EntryKind::File,
+                len: size,
+                modified: None,
+                hidden: dot_name(path),
+                system: false,
+            },
+        })
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.node(path).map(|_| path.to_owned())
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        match self.node(path)? {
+            Node::Object { key, .. } => self.store.get(key),
+            Node::Dir => Err(io::Error::other("is a directory")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Cursor, Read},
+        path::PathBuf,
+        sync::Arc,
+    };
+
+    use crate::task4::{
+        remote::{encode_key, parse_list_page, Object, ObjectStore, ObjectStoreFs},
+        Filter, SearchBuilder,
+    };
+
+    #[derive(Debug)]
+    struct FakeStore(Vec<(&'static str, &'static str)>);
+
+    impl ObjectStore for FakeStore {
+        fn list(&self, prefix: &str) -> io::Result<Vec<Object>> {
+            Ok(self
+                .0
+                .iter()
+                .filter(|(key, _)| key.starts_with(prefix))
+                .map(|(key, body)| Object {
+                    key: key.to_string(),
+                    size: body.len() as u64,
+                })
+                .collect())
+        }
+
+        fn get(&self, key: &str) -> io::Result<Box<dyn Read + Send>> {
+            let (_, body) = self.0.iter().find(|(k, _)| *k == key).unwrap();
+            Ok(Box::new(Cursor::new(body.as_bytes())))
+        }
+    }
+
+    #[test]
+    fn searches_objects_as_a_tree() {
+        let store = FakeStore(vec![
+            ("src/main.rs", "fn main() {}\n"),
+            ("src/vm/mod.rs", "1\n2\n"),
+            ("src/empty/", ""),
+            ("docs/readme.md", "hi\n"),
+        ]);
+        let fs = ObjectStoreFs::new(Box::new(store), "s3://bucket", "src/").unwrap();
+        let found: Vec<_> = SearchBuilder::new("s3://bucket/src", Filter::new("rs"))
+            .file_system(Arc::new(fs))
+            .build()
+            .run()
+            .unwrap()
+            .into_iter()
+            .map(|f| (f.path, f.lines))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (PathBuf::from("s3://bucket/src/main.rs"), 1),
+                (PathBuf::from("s3://bucket/src/vm/mod.rs"), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_list_results() {
+        let page = parse_list_page(
+            "<ListBucketResult><IsTruncated>true</IsTruncated>\
+             <Contents><Key>a&amp;b.rs</Key><Size>12</Size></Contents>\
+             <Contents><Key>c.rs</Key><Size>0</Size></Contents>\
+             <NextContinuationToken>tok</NextContinuationToken></ListBucketResult>",
+        );
+        assert_eq!(
+            page.objects,
+            vec![
+                Object {
+                    key: "a&b.rs".to_owned(),
+                    size: 12
+                },
+                Object {
+                    key: "c.rs".to_owned(),
+                    size: 0
+                },
+            ]
+        );
+        assert_eq!(page.next_token.as_deref(), Some("tok"));
+        assert_eq!(encode_key("dir/a b+c.rs"), "dir/a%20b%2Bc.rs");
+    }
+}
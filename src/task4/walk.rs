@@ -0,0 +1,364 @@
+use std::{
+    collections::VecDeque,
+    ffi::OsStr,
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use super::{
+    fs::{EntryKind, FileSystem},
+    FileError, Semaphore,
+};
+
+/// One visited path, the root is at depth 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub depth: usize,
+    pub kind: EntryKind,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl Entry {
+    pub fn file_name(&self) -> &OsStr {
+        self.path.file_name().unwrap_or(self.path.as_os_str())
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.kind == EntryKind::File
+    }
+}
+
+/// Walk yielding every entry before its children, depth-first like `find` unless
+/// [`Walk::breadth_first`] is asked for.
+pub struct Walk {
+    fs: Arc<dyn FileSystem>,
+    follow_links: bool,
+    max_depth: Option<usize>,
+    breadth_first: bool,
+    skip_hidden: bool,
+    /// Paths still to visit, with the canonical paths of the directories above them.
+    queue: VecDeque<(PathBuf, usize, Arc<Vec<PathBuf>>)>,
+    /// Shared with the threads counting what the walk finds, see
+    /// [`SearchBuilder::max_open_files`](super::SearchBuilder::max_open_files).
+    open_files: Option<Arc<Semaphore>>,
+}
+
+impl Walk {
+    pub fn new(
+        fs: Arc<dyn FileSystem>,
+        root: &Path,
+        follow_links: bool,
+        max_depth: Option<usize>,
+    ) -> Self {
+        Walk {
+            fs,
+            follow_links,
+            max_depth,
+            breadth_first: false,
+            skip_hidden: false,
+            queue: VecDeque::from([(root.to_owned(), 0, Arc::default())]),
+            open_files: None,
+        }
+    }
+
+    /// Visit all entries at one depth before going deeper, so a walk cut short has
+    /// seen a bit of everything near the root.
+    pub fn breadth_first(mut self) -> Self {
+        self.breadth_first = true;
+        self
+    }
+
+    /// Leave out hidden and system entries and everything below them, except the root.
+    pub fn skip_hidden(mut self) -> Self {
+        self.skip_hidden = true;
+        self
+    }
+
+    /// Holds a permit of `open_files` while reading a directory.
+    pub(super) fn open_files(mut self, open_files: Arc<Semaphore>) -> Self {
+        self.open_files = Some(open_files);
+        self
+    }
+
+    fn visit(
+        &mut self,
+        path: PathBuf,
+        depth: usize,
+        ancestors: Arc<Vec<PathBuf>>,
+    ) -> Result<Option<Entry>, FileError> {
+        let fail = |path: &Path, source| FileError {
+            path: path.to_owned(),
+            source,
+        };
+        // The root is always followed, otherwise a symlinked root would yield nothing.
+        let meta = self
+            .fs
+            .metadata(&path, self.follow_links || depth == 0)
+            .map_err(|e| fail(&path, e))?;
+        if self.skip_hidden && depth > 0 && (meta.hidden || meta.system) {
+            return Ok(None);
+        }
+        let entry = Entry {
+            path,
+            depth,
+            kind: meta.kind,
+            len: meta.len,
+            modified: meta.modified,
+        };
+
+        if entry.kind == EntryKind::Dir && self.max_depth.is_none_or(|max| depth < max) {
+            let canonical = self
+                .fs
+                .canonicalize(&entry.path)
+                .map_err(|e| fail(&entry.path, e))?;
+            if ancestors.contains(&canonical) {
+                return Err(fail(
+                    &entry.path,
+                    io::Error::other("file system loop found"),
+                ));
+            }
+            let permit = self.open_files.as_deref().map(Semaphore::acquire);
+            let children = self
+                .fs
+                .read_dir(&entry.path)
+                .map_err(|e| fail(&entry.path, e))?;
+            drop(permit);
+            let ancestors = Arc::new([ancestors.as_slice(), &[canonical]].concat());
+            let children = children
+                .into_iter()
+                .map(|child| (child, depth + 1, Arc::clone(&ancestors)));
+            if self.breadth_first {
+                self.queue.extend(children);
+            } else {
+                // Reversed so that popping from the front visits children in listing order.
+                for child in children.rev() {
+                    self.queue.push_front(child);
+                }
+            }
+        }
+        Ok(Some(entry))
+    }
+}
+
+impl Iterator for Walk {
+    type Item = Result<Entry, FileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (path, depth, ancestors) = self.queue.pop_front()?;
+            if let Some(visited) = self.visit(path, depth, ancestors).transpose() {
+                return Some(visited);
+            }
+        }
+    }
+}
+
+/// The directories of a depth-first [`Walk`] with no file `matches` accepts anywhere below
+/// them, each yielded once everything below it has been walked.
+///
+/// Directories at the depth limit weren't looked into and are never yielded, nor are the
+/// parents of an entry the walk failed on. Walk errors are passed through.
+pub struct EmptyDirs<F> {
+    walk: Walk,
+    matches: F,
+    /// The directories above the current entry, with whether something below them matched.
+    open: Vec<(Entry, bool)>,
+    /// Left over from the last entry, every directory it closed.
+    closed: VecDeque<Entry>,
+}
+
+impl<F: FnMut(&Entry) -> bool> EmptyDirs<F> {
+    pub fn new(walk: Walk, matches: F) -> Self {
+        debug_assert!(
+            !walk.breadth_first,
+            "directories only close in depth-first order"
+        );
+        EmptyDirs {
+            walk,
+            matches,
+            open: vec![],
+            closed: VecDeque::new(),
+        }
+    }
+
+    /// Finishes the open directories at `depth` and below, a match marks the parent too.
+    fn close(&mut self, depth: usize) {
+        while self.open.last().is_some_and(|(dir, _)| dir.depth >= depth) {
+            let (dir, matched) = self.open.pop().unwrap();
+            if matched {
+                self.mark();
+            } else {
+                self.closed.push_back(dir);
+            }
+        }
+    }
+
+    fn mark(&mut self) {
+        if let Some((_, matched)) = self.open.last_mut() {
+            *matched = true;
+        }
+    }
+}
+
+impl<F: FnMut(&Entry) -> bool> Iterator for EmptyDirs<F> {
+    type Item = Result<PathBuf, FileError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(dir) = self.closed.pop_front() {
+                return Some(Ok(dir.path));
+            }
+            let entry = match self.walk.next() {
+                Some(Ok(entry)) => entry,
+                // Whatever failed is below the innermost open directory.
+                Some(Err(err)) => {
+                    self.mark();
+                    return Some(Err(err));
+                }
+                None if self.open.is_empty() => return None,
+                None => {
+                    self.close(0);
+                    continue;
+                }
+            };
+            self.close(entry.depth);
+            if entry.kind == EntryKind::Dir {
+                let looked_into = self.walk.max_depth.is_none_or(|max| entry.depth < max);
+                self.open.push((entry, !looked_into));
+            } else if entry.is_file() && (self.matches)(&entry) {
+                self.mark();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use crate::task4::{
+        fs::MemoryFs,
+        walk::{EmptyDirs, Walk},
+    };
+
+    fn walk(fs: MemoryFs, max_depth: Option<usize>) -> Vec<(PathBuf, usize)> {
+        paths(Walk::new(Arc::new(fs), "root".as_ref(), true, max_depth))
+    }
+
+    fn paths(walk: Walk) -> Vec<(PathBuf, usize)> {
+        walk.map(|e| e.unwrap())
+            .map(|e| (e.path, e.depth))
+            .collect()
+    }
+
+    #[test]
+    fn walks_parents_before_children() {
+        let fs = MemoryFs::new()
+            .file("root/b/c.rs", "")
+            .file("root/a.rs", "")
+            .dir("root/d");
+        assert_eq!(
+            walk(fs, None),
+            vec![
+                ("root".into(), 0),
+                ("root/a.rs".into(), 1),
+                ("root/b".into(), 1),
+                ("root/b/c.rs".into(), 2),
+                ("root/d".into(), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn walks_shallow_entries_first() {
+        let fs = MemoryFs::new()
+            .file("root/a/b/c.rs", "")
+            .file("root/a/d.rs", "")
+            .file("root/e.rs", "");
+        let walk = Walk::new(Arc::new(fs), "root".as_ref(), true, None).breadth_first();
+        assert_eq!(
+            paths(walk),
+            vec![
+                ("root".into(), 0),
+                ("root/a".into(), 1),
+                ("root/e.rs".into(), 1),
+                ("root/a/b".into(), 2),
+                ("root/a/d.rs".into(), 2),
+                ("root/a/b/c.rs".into(), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_at_max_depth() {
+        let fs = MemoryFs::new()
+            .file("root/b/c.rs", "")
+            .file("root/a.rs", "");
+        assert_eq!(
+            walk(fs, Some(1)),
+            vec![
+                ("root".into(), 0),
+                ("root/a.rs".into(), 1),
+                ("root/b".into(), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_hidden_entries_and_their_children() {
+        let fs = MemoryFs::new()
+            .file("root/.git/config", "")
+            .file("root/.env", "")
+            .file("root/a.rs", "");
+        let walk = Walk::new(Arc::new(fs), "root".as_ref(), true, None).skip_hidden();
+        assert_eq!(
+            paths(walk),
+            vec![("root".into(), 0), ("root/a.rs".into(), 1)]
+        );
+        // A hidden root is still searched.
+        let fs = MemoryFs::new().file(".root/a.rs", "");
+        let walk = Walk::new(Arc::new(fs), ".root".as_ref(), true, None).skip_hidden();
+        assert_eq!(paths(walk).len(), 2);
+    }
+
+    #[test]
+    fn finds_directories_without_matching_files() {
+        let fs = MemoryFs::new()
+            .file("root/a/b.rs", "")
+            .file("root/a/c/d.txt", "")
+            .dir("root/a/c/e")
+            .file("root/f/g/h.txt", "")
+            .file("root/i/j/k.rs", "")
+            .file("root/l/m.txt", "");
+        let matched = |max_depth| {
+            let walk = Walk::new(Arc::new(fs.clone()), "root".as_ref(), true, max_depth);
+            EmptyDirs::new(walk, |e| e.path.extension().is_some_and(|ext| ext == "rs"))
+                .map(|dir| dir.unwrap())
+                .collect::<Vec<PathBuf>>()
+        };
+        assert_eq!(
+            matched(None),
+            vec![
+                PathBuf::from("root/a/c/e"),
+                "root/a/c".into(),
+                "root/f/g".into(),
+                "root/f".into(),
+                "root/l".into(),
+            ]
+        );
+        // Nothing is known about what lies below the limit.
+        assert_eq!(matched(Some(2)), vec![PathBuf::from("root/l")]);
+    }
+
+    #[test]
+    fn reports_missing_root() {
+        let fs = MemoryFs::new().file("root/a.rs", "");
+        let mut walk = Walk::new(Arc::new(fs), "missing".as_ref(), true, None);
+        assert!(walk.next().unwrap().is_err());
+        assert!(walk.next().is_none());
+    }
+}
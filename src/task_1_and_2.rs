@@ -1,42 +1,500 @@
-#![allow(dead_code)]
+//! A stack machine over integer, floating point, string and array values with named
+//! variables and conditional jumps.
+//!
+//! Build a [`Bytecode`] in code, with [`asm::parse`] or [`Bytecode::from_bytes`], then
+//! [`run`] it, or step through it with a [`Vm`].
+#![forbid(unsafe_code)]
 
-use std::collections::HashMap;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap, VecDeque},
+    fmt, io, iter, mem,
+    num::ParseFloatError,
+    str::FromStr,
+};
 
 use thiserror::Error;
 
-type VariableName = String;
-type LabelName = String;
+use gas::Gas;
+pub use vars::{Slot, Variables};
 
-type Instructions = Vec<Instruction>;
-type Labels = HashMap<LabelName, usize>;
+pub mod asm;
+pub mod binary;
+pub mod builder;
+pub mod compiler;
+pub mod csv;
+pub mod determinism;
+pub mod examples;
+pub mod gas;
+pub mod meta;
+pub mod optimize;
+pub mod profile;
+pub mod program;
+pub mod snapshot;
+pub mod soak;
+pub mod stats;
+pub mod supervisor;
+pub mod trace;
+pub mod vars;
+pub mod verify;
 
+pub type VariableName = String;
+pub type LabelName = String;
+pub type ChannelName = String;
+pub type HostName = String;
+/// What a host function returns, an `Err` says why it failed.
+pub type HostResult = Result<Value, String>;
+
+pub type Instructions = Vec<Instruction>;
+/// Where each label points, as an index into the instructions.
+pub type Labels = HashMap<LabelName, usize>;
+
+/// A program: instructions and the labels its jumps go to.
 #[derive(Debug, Clone)]
-struct Bytecode {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bytecode {
     pub instrs: Instructions,
     pub labels: Labels,
 }
 
-type ValueType = i64;
+/// The payload of [`Value::Int`].
+pub type ValueType = i64;
+
+/// What the machine computes with.
+///
+/// Arithmetic on two ints stays exact and fails on overflow, as soon as one operand is a
+/// float both are computed in `f64` and follow IEEE 754, dividing by zero included.
+/// Bitwise operations and shifts take ints only and fail with `TypeMismatch` otherwise.
+///
+/// Strings go into `Concat`, `StrLen`, `StrEq`, `StrCmp`, `StartsWith`, `Eq` and `Ne`,
+/// arrays into `ArrayGet`, `ArraySet`, `ArrayLen`, `ArraySlice`, `ArrayNext`, `Eq` and
+/// `Ne`, see [`ArrayRef`]. Arithmetic, comparisons, jumps and the other instructions that
+/// compute with their operands fail with `TypeMismatch` on either, the ones that only move
+/// values, `Dup`, `WriteVar`, `Print`, `CallHost` and `SendChannel` among them, take any.
+///
+/// Values are equal when they are the same variant with the same bits, so a NaN equals
+/// itself and runs can be compared exactly. `Eq` and the other comparison instructions
+/// compare numerically instead, `1` and `1.0` are the same number to them.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+pub enum Value {
+    Int(ValueType),
+    Float(f64),
+    Str(String),
+    Array(ArrayRef),
+}
+
+/// An array on the heap of the run that made it with `NewArray`.
+///
+/// Copying the reference doesn't copy the array, `ArraySet` through one is seen through
+/// all of them, and two references are only equal when they are to the same array. Arrays
+/// live until the run ends, a reference returned from a run means nothing outside it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArrayRef {
+    /// Arrays are numbered from 0 in the order a run makes them.
+    pub array: usize,
+}
+
+/// Which variant a [`Value`] is, for errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ValueKind {
+    Int,
+    Float,
+    Str,
+    Array,
+}
+
+impl Value {
+    pub fn kind(&self) -> ValueKind {
+        match self {
+            Value::Int(_) => ValueKind::Int,
+            Value::Float(_) => ValueKind::Float,
+            Value::Str(_) => ValueKind::Str,
+            Value::Array(_) => ValueKind::Array,
+        }
+    }
+
+    /// How the value compares to zero, `None` for NaN, strings and arrays.
+    pub fn sign(&self) -> Option<Ordering> {
+        match *self {
+            Value::Int(val) => Some(val.cmp(&0)),
+            Value::Float(val) => val.partial_cmp(&0.0),
+            Value::Str(_) | Value::Array(_) => None,
+        }
+    }
+
+    /// Anything but zero is true, NaN, strings and arrays included.
+    pub fn is_true(&self) -> bool {
+        self.sign() != Some(Ordering::Equal)
+    }
+
+    /// The number as a float, `None` for strings and arrays.
+    fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Int(val) => Some(val as f64),
+            Value::Float(val) => Some(val),
+            Value::Str(_) | Value::Array(_) => None,
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Array(a), Value::Array(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Value {}
+
+impl From<ValueType> for Value {
+    fn from(val: ValueType) -> Self {
+        Value::Int(val)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(val: f64) -> Self {
+        Value::Float(val)
+    }
+}
+
+impl From<String> for Value {
+    fn from(val: String) -> Self {
+        Value::Str(val)
+    }
+}
+
+/// Floats always show a fraction or exponent, so they read back as floats. Strings are
+/// shown as they are, without quotes, arrays as `array#` and their number.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(val) => write!(f, "{}", val),
+            Value::Float(val) => write!(f, "{:?}", val),
+            Value::Str(val) => f.write_str(val),
+            Value::Array(val) => write!(f, "array#{}", val.array),
+        }
+    }
+}
+
+/// An int when `s` is one, a float otherwise, never a string.
+impl FromStr for Value {
+    type Err = ParseFloatError;
 
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(val) => Ok(Value::Int(val)),
+            Err(_) => s.parse().map(Value::Float),
+        }
+    }
+}
+
+impl fmt::Display for ValueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ValueKind::Int => "int",
+            ValueKind::Float => "float",
+            ValueKind::Str => "str",
+            ValueKind::Array => "array",
+        })
+    }
+}
+
+/// Binary operations pop the top value first and compute `top op below`, comparisons
+/// push 1 when `top op below` holds and 0 otherwise, jumps pop the value they test. The
+/// `Peek` jumps test the same but leave the value on the stack.
+/// `Modulo` leaves the remainder with the sign of the top value, `Negate` flips the sign
+/// of the top value. Ints and floats mix as described on [`Value`].
+///
+/// `Concat` joins the text of the top value and the one below, numbers included but not
+/// arrays, and `StrLen` counts the characters of a string. `StrEq`, `StrCmp` and
+/// `StartsWith` take two strings and fail on anything else: `StrEq` pushes 1 when they are
+/// the same text, `StrCmp` pushes -1, 0 or 1 as the top one sorts before, with or after the
+/// one below, by code point, and `StartsWith` pushes 1 when the top one starts with the one
+/// below.
+///
+/// `NewArray` pops a length and pushes a new array of that many zeros. `ArrayGet` pops an
+/// array and the index below it and pushes the element, `ArraySet` pops an array, an index
+/// and the value to store, in that order, and pushes nothing. `ArrayLen` pushes the length.
+/// Indexes start at 0, anything outside the array fails with `IndexOutOfBounds`.
+/// `ArraySlice` pops an array, a start and a length and pushes a new array with a copy of
+/// those elements, failing with `SliceOutOfBounds` when they aren't all in the array.
+///
+/// `ArrayNext` steps through an array: it pops the array and an index below it, and while
+/// the index is in the array pushes the next index, the array and the element, so that the
+/// element can be used and the same `ArrayNext` reached again. Past the end it jumps to its
+/// label instead, a negative index fails with `IndexOutOfBounds`.
+///
+/// `Shl` and `Shr` shift the top value by the one below, `Shr` keeps the sign. Shifting
+/// by 64 or more moves every bit out, leaving 0, or -1 for `Shr` of a negative value.
+///
+/// `Call` jumps to a label and `Ret` comes back to the instruction after it, variables are
+/// shared between caller and callee. The stack is too, and where it ends at the `Call` is
+/// the frame of the callee: a caller pushes the arguments before the call, `PushArg(n)`
+/// pushes a copy of argument `n`, 0 the last one pushed, and `PeekFrame(offset)` a copy of
+/// the value `offset` above the frame, 0 the first one the callee pushed. Outside a call
+/// the frame starts at the bottom of the stack and there are no arguments. Reaching past
+/// either end fails with `OutsideFrame`, the callee pops the arguments it takes.
+///
+/// `CallHost(name, arity)` pops `arity` arguments and pushes what the host function
+/// registered as `name` with [`Vm::host`] returns for them, the first one pushed first. An
+/// unknown name fails with `UnknownHost` and an error from the function with `HostFailed`,
+/// in a [`deterministic`](Vm::deterministic) run one not known to be pure with `ImpureHost`.
+/// `Print` pops a value and writes it on a line of its own to the output of the VM, see
+/// [`Vm::output`]. `ReadInput` pushes the next value of its input, see [`Vm::input`], and
+/// fails with `InputEnded` once there is none left.
+///
+/// `Spawn` starts another context at a label, with a stack and calls of its own but the
+/// variables and arrays of the rest. `SendChannel` pops a value and waits until some other
+/// context takes it with `RecvChannel` on the same channel, which pushes it. A context
+/// runs until it waits or ends, then the one that has waited longest of those that can go
+/// on takes over, so runs stay deterministic. `ReturnValue` in a spawned context just ends
+/// it, in the first one it ends the run. When no context can go on the run fails with
+/// `Deadlock`.
 #[derive(Debug, Clone)]
-enum Instruction {
-    LoadVal(ValueType),
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Instruction {
+    LoadVal(Value),
     WriteVar(VariableName),
     ReadVar(VariableName),
+    Dup,
+    Swap,
+    Pop,
     Add,
     Multiply,
     Subtract,
     Divide,
+    Modulo,
+    Negate,
+    And,
+    Or,
+    Xor,
+    Not,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Concat,
+    StrLen,
+    StrEq,
+    StrCmp,
+    StartsWith,
+    NewArray,
+    ArrayGet,
+    ArraySet,
+    ArrayLen,
+    ArraySlice,
+    ArrayNext(LabelName),
     ReturnValue,
     JumpIfNeg(LabelName),
     JumpIfPos(LabelName),
     JumpIfZero(LabelName),
     JumpIfNotZero(LabelName),
+    JumpIfNegPeek(LabelName),
+    JumpIfPosPeek(LabelName),
+    JumpIfZeroPeek(LabelName),
+    JumpIfNotZeroPeek(LabelName),
+    Call(LabelName),
+    Ret,
+    PushArg(usize),
+    PeekFrame(usize),
+    CallHost(HostName, usize),
+    Print,
+    ReadInput,
+    Spawn(LabelName),
+    SendChannel(ChannelName),
+    RecvChannel(ChannelName),
+}
+
+impl Instruction {
+    /// Every mnemonic, at the index [`Instruction::opcode`] gives.
+    pub const NAMES: [&'static str; 54] = [
+        "LoadVal",
+        "WriteVar",
+        "ReadVar",
+        "Dup",
+        "Swap",
+        "Pop",
+        "Add",
+        "Multiply",
+        "Subtract",
+        "Divide",
+        "Modulo",
+        "Negate",
+        "And",
+        "Or",
+        "Xor",
+        "Not",
+        "Shl",
+        "Shr",
+        "Eq",
+        "Ne",
+        "Lt",
+        "Le",
+        "Gt",
+        "Ge",
+        "Concat",
+        "StrLen",
+        "NewArray",
+        "ArrayGet",
+        "ArraySet",
+        "ArrayLen",
+        "ReturnValue",
+        "JumpIfNeg",
+        "JumpIfPos",
+        "JumpIfZero",
+        "JumpIfNotZero",
+        "Call",
+        "Ret",
+        "Spawn",
+        "SendChannel",
+        "RecvChannel",
+        "StrEq",
+        "StrCmp",
+        "StartsWith",
+        "ArraySlice",
+        "ArrayNext",
+        "JumpIfNegPeek",
+        "JumpIfPosPeek",
+        "JumpIfZeroPeek",
+        "JumpIfNotZeroPeek",
+        "PushArg",
+        "PeekFrame",
+        "CallHost",
+        "Print",
+        "ReadInput",
+    ];
+
+    /// The label a jump, call or spawn goes to.
+    pub fn label(&self) -> Option<&LabelName> {
+        match self {
+            Instruction::JumpIfNeg(label)
+            | Instruction::JumpIfPos(label)
+            | Instruction::JumpIfZero(label)
+            | Instruction::JumpIfNotZero(label)
+            | Instruction::JumpIfNegPeek(label)
+            | Instruction::JumpIfPosPeek(label)
+            | Instruction::JumpIfZeroPeek(label)
+            | Instruction::JumpIfNotZeroPeek(label)
+            | Instruction::ArrayNext(label)
+            | Instruction::Call(label)
+            | Instruction::Spawn(label) => Some(label),
+            _ => None,
+        }
+    }
+
+    /// The mnemonic without its operand.
+    pub fn name(&self) -> &'static str {
+        Instruction::NAMES[self.opcode()]
+    }
+
+    /// A number for each kind of instruction, from 0 up to `NAMES.len()`.
+    pub fn opcode(&self) -> usize {
+        match self {
+            Instruction::LoadVal(_) => 0,
+            Instruction::WriteVar(_) => 1,
+            Instruction::ReadVar(_) => 2,
+            Instruction::Dup => 3,
+            Instruction::Swap => 4,
+            Instruction::Pop => 5,
+            Instruction::Add => 6,
+            Instruction::Multiply => 7,
+            Instruction::Subtract => 8,
+            Instruction::Divide => 9,
+            Instruction::Modulo => 10,
+            Instruction::Negate => 11,
+            Instruction::And => 12,
+            Instruction::Or => 13,
+            Instruction::Xor => 14,
+            Instruction::Not => 15,
+            Instruction::Shl => 16,
+            Instruction::Shr => 17,
+            Instruction::Eq => 18,
+            Instruction::Ne => 19,
+            Instruction::Lt => 20,
+            Instruction::Le => 21,
+            Instruction::Gt => 22,
+            Instruction::Ge => 23,
+            Instruction::Concat => 24,
+            Instruction::StrLen => 25,
+            Instruction::NewArray => 26,
+            Instruction::ArrayGet => 27,
+            Instruction::ArraySet => 28,
+            Instruction::ArrayLen => 29,
+            Instruction::ReturnValue => 30,
+            Instruction::JumpIfNeg(_) => 31,
+            Instruction::JumpIfPos(_) => 32,
+            Instruction::JumpIfZero(_) => 33,
+            Instruction::JumpIfNotZero(_) => 34,
+            Instruction::Call(_) => 35,
+            Instruction::Ret => 36,
+            Instruction::Spawn(_) => 37,
+            Instruction::SendChannel(_) => 38,
+            Instruction::RecvChannel(_) => 39,
+            Instruction::StrEq => 40,
+            Instruction::StrCmp => 41,
+            Instruction::StartsWith => 42,
+            Instruction::ArraySlice => 43,
+            Instruction::ArrayNext(_) => 44,
+            Instruction::JumpIfNegPeek(_) => 45,
+            Instruction::JumpIfPosPeek(_) => 46,
+            Instruction::JumpIfZeroPeek(_) => 47,
+            Instruction::JumpIfNotZeroPeek(_) => 48,
+            Instruction::PushArg(_) => 49,
+            Instruction::PeekFrame(_) => 50,
+            Instruction::CallHost(..) => 51,
+            Instruction::Print => 52,
+            Instruction::ReadInput => 53,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::LoadVal(Value::Str(val)) => write!(f, "{} {:?}", self.name(), val),
+            Instruction::LoadVal(val) => write!(f, "{} {}", self.name(), val),
+            Instruction::WriteVar(name)
+            | Instruction::ReadVar(name)
+            | Instruction::JumpIfNeg(name)
+            | Instruction::JumpIfPos(name)
+            | Instruction::JumpIfZero(name)
+            | Instruction::JumpIfNotZero(name)
+            | Instruction::JumpIfNegPeek(name)
+            | Instruction::JumpIfPosPeek(name)
+            | Instruction::JumpIfZeroPeek(name)
+            | Instruction::JumpIfNotZeroPeek(name)
+            | Instruction::Call(name)
+            | Instruction::Spawn(name)
+            | Instruction::ArrayNext(name)
+            | Instruction::SendChannel(name)
+            | Instruction::RecvChannel(name) => write!(f, "{} {}", self.name(), name),
+            Instruction::PushArg(offset) | Instruction::PeekFrame(offset) => {
+                write!(f, "{} {}", self.name(), offset)
+            }
+            Instruction::CallHost(name, arity) => write!(f, "{} {} {}", self.name(), name, arity),
+            _ => f.write_str(self.name()),
+        }
+    }
 }
 
-type IpType = usize;
+pub type IpType = usize;
 
-#[derive(Error, Debug, PartialEq, Eq)]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum InterpretationError {
     #[error("operations limit exceeded")]
     OperationsLimitExceeded,
@@ -63,168 +521,1381 @@ pub enum InterpretationError {
         val2: ValueType,
         ip: IpType,
     },
+
+    #[error("stack is full at {depth} values (IP={ip})")]
+    StackOverflow { depth: usize, ip: IpType },
+
+    #[error("too many variables (IP={0})")]
+    TooManyVariables(IpType),
+
+    #[error("{instr} {offset} reaches outside the frame (IP={ip})")]
+    OutsideFrame {
+        instr: String,
+        offset: usize,
+        ip: IpType,
+    },
+
+    #[error("calls nested too deep (IP={0})")]
+    CallStackOverflow(IpType),
+
+    #[error("ret outside of a call (IP={0})")]
+    RetWithoutCall(IpType),
+
+    #[error("can't shift by {amount} (IP={ip})")]
+    NegativeShift { amount: ValueType, ip: IpType },
+
+    #[error("string longer than the limit (IP={0})")]
+    StringTooLong(IpType),
+
+    #[error("can't make an array of {len} values (IP={ip})")]
+    InvalidArrayLength { len: ValueType, ip: IpType },
+
+    #[error("index {index} is out of bounds for an array of {len} (IP={ip})")]
+    IndexOutOfBounds {
+        index: ValueType,
+        len: usize,
+        ip: IpType,
+    },
+
+    #[error("{len} values from {start} are out of bounds for an array of {array_len} (IP={ip})")]
+    SliceOutOfBounds {
+        start: ValueType,
+        len: ValueType,
+        array_len: usize,
+        ip: IpType,
+    },
+
+    #[error("no array #{array} in this run (IP={ip})")]
+    UnknownArray { array: usize, ip: IpType },
+
+    #[error("arrays take more than the heap limit (IP={0})")]
+    HeapExhausted(IpType),
+
+    #[error("too many contexts (IP={0})")]
+    TooManyContexts(IpType),
+
+    #[error("every context is waiting on a channel (IP={0})")]
+    Deadlock(IpType),
+
+    #[error("out of gas (IP={0})")]
+    OutOfGas(IpType),
+
+    #[error("{instr} doesn't take {found} values (IP={ip})")]
+    TypeMismatch {
+        instr: String,
+        found: ValueKind,
+        ip: IpType,
+    },
+
+    #[error("no host function '{name}' (IP={ip})")]
+    UnknownHost { name: HostName, ip: IpType },
+
+    #[error("host function '{name}' isn't pure, a deterministic run can't call it (IP={ip})")]
+    ImpureHost { name: HostName, ip: IpType },
+
+    #[error("host function '{name}' failed: {message} (IP={ip})")]
+    HostFailed {
+        name: HostName,
+        message: String,
+        ip: IpType,
+    },
+
+    #[error("can't write output: {message} (IP={ip})")]
+    OutputFailed { message: String, ip: IpType },
+
+    #[error("no input left (IP={0})")]
+    InputEnded(IpType),
+
+    #[error("can't read input: {message} (IP={ip})")]
+    InputFailed { message: String, ip: IpType },
+}
+
+/// Limits of a run, `None` means no limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmConfig {
+    /// Instructions executed before giving up with `OperationsLimitExceeded`.
+    pub max_ops: Option<u64>,
+    /// Values the stack of each context may hold before `StackOverflow`.
+    pub max_stack: Option<usize>,
+    /// Distinct variables a run may hold, inputs included.
+    pub max_vars: Option<usize>,
+    /// Calls that may be in progress at once before `CallStackOverflow`.
+    pub max_calls: Option<usize>,
+    /// Bytes a string built by `Concat` may have before `StringTooLong`.
+    pub max_string_len: Option<usize>,
+    /// Elements all arrays of a run may have together before `HeapExhausted`.
+    pub max_heap: Option<usize>,
+    /// Contexts a run may have at once, the first one included, before `TooManyContexts`.
+    pub max_contexts: Option<usize>,
+    /// A budget instructions spend by their cost, checked before each one runs and on top
+    /// of `max_ops`.
+    pub gas: Option<Gas>,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        VmConfig {
+            max_ops: Some(1_000),
+            max_stack: Some(1_024),
+            max_vars: Some(1_024),
+            max_calls: Some(256),
+            max_string_len: Some(65_536),
+            max_heap: Some(65_536),
+            max_contexts: Some(64),
+            gas: None,
+        }
+    }
+}
+
+impl VmConfig {
+    pub const fn unlimited() -> Self {
+        VmConfig {
+            max_ops: None,
+            max_stack: None,
+            max_vars: None,
+            max_calls: None,
+            max_string_len: None,
+            max_heap: None,
+            max_contexts: None,
+            gas: None,
+        }
+    }
+}
+
+/// Runs a program until `ReturnValue` within the default limits, 1000 instructions.
+pub fn run(bytecode: Bytecode) -> Result<Value, InterpretationError> {
+    run_with_config(bytecode, &VmConfig::default())
+}
+
+pub fn run_with_config(
+    bytecode: Bytecode,
+    config: &VmConfig,
+) -> Result<Value, InterpretationError> {
+    run_observed(&bytecode, config, &mut State::default(), |_| ())
+}
+
+/// What a run returned, and the variables it ended with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunOutput {
+    pub value: Value,
+    pub vars: HashMap<VariableName, Value>,
+}
+
+/// Runs a program within the default limits with the variables of `env` already written,
+/// for programs that compute from parameters and leave more than one result.
+pub fn run_with_env(
+    bytecode: Bytecode,
+    env: HashMap<VariableName, Value>,
+) -> Result<RunOutput, InterpretationError> {
+    let mut state = State {
+        vars: env.into_iter().collect(),
+        ..State::default()
+    };
+    let value = run_observed(&bytecode, &VmConfig::default(), &mut state, |_| ())?;
+    let vars = state
+        .vars
+        .iter()
+        .map(|(name, val)| (name.clone(), val.clone()))
+        .collect();
+    Ok(RunOutput { value, vars })
+}
+
+/// What a run works on, kept between runs so their allocations can be reused.
+#[derive(Debug, Default)]
+struct State {
+    stack: Vec<Value>,
+    vars: Variables,
+    /// The calls in progress, the innermost last.
+    calls: Vec<Frame>,
+    /// The arrays made so far, indexed by [`ArrayRef::array`].
+    heap: Vec<Vec<Value>>,
+    /// Every context but the running one, in the order they get to run.
+    contexts: VecDeque<Context>,
+}
+
+/// A context that isn't running, see `Spawn`.
+#[derive(Debug, Default)]
+struct Context {
+    ip: IpType,
+    /// Whether returning from this context ends the run.
+    main: bool,
+    stack: Vec<Value>,
+    calls: Vec<Frame>,
+    wait: Option<Wait>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Frame {
+    /// Where the call returns to.
+    ret: IpType,
+    /// How many values the stack held at the call, the arguments are right below.
+    base: usize,
+}
+
+#[derive(Debug)]
+enum Wait {
+    /// Holding a value for whoever receives on the channel.
+    Send(ChannelName, Value),
+    Recv(ChannelName),
+}
+
+impl Context {
+    /// Trades places with the running context, whose state the other arguments are.
+    fn swap(
+        &mut self,
+        ip: &mut IpType,
+        main: &mut bool,
+        stack: &mut Vec<Value>,
+        calls: &mut Vec<Frame>,
+    ) {
+        mem::swap(&mut self.ip, ip);
+        mem::swap(&mut self.main, main);
+        mem::swap(&mut self.stack, stack);
+        mem::swap(&mut self.calls, calls);
+    }
+}
+
+/// Like `run`, starting with the variables in `state` and calling `observe` with the IP of
+/// every instruction before it executes.
+fn run_observed(
+    bytecode: &Bytecode,
+    config: &VmConfig,
+    state: &mut State,
+    observe: impl FnMut(IpType),
+) -> Result<Value, InterpretationError> {
+    let vm = Vm::with_state(bytecode, *config, mem::take(state));
+    run_to_end(vm, state, observe)
+}
+
+/// Like `run_observed` without observing, for programs run inside a search, a hook or
+/// another tool, whose stdin and stdout aren't theirs: `Print` writes nowhere and
+/// `ReadInput` fails with `InputEnded`.
+fn run_embedded(
+    bytecode: &Bytecode,
+    config: &VmConfig,
+    state: &mut State,
+) -> Result<Value, InterpretationError> {
+    let mut vm = Vm::with_state(bytecode, *config, mem::take(state));
+    vm.output(io::sink());
+    vm.input(iter::empty());
+    run_to_end(vm, state, |_| ())
+}
+
+fn run_to_end(
+    mut vm: Vm<'_>,
+    state: &mut State,
+    mut observe: impl FnMut(IpType),
+) -> Result<Value, InterpretationError> {
+    let result = loop {
+        match vm.execute(&mut observe) {
+            Ok(None) => continue,
+            Ok(Some(val)) => break Ok(val),
+            Err(err) => break Err(err),
+        }
+    };
+    // Handed back so that the next run reuses the allocations.
+    *state = vm.state;
+    result
+}
+
+/// A run that goes one instruction at a time, so that callers can do other work between
+/// steps, look at what the program is doing and stop it when they like.
+///
+/// [`Vm::run`] goes on until the program ends or reaches a breakpoint, and resumes from
+/// there when called again.
+///
+/// ```
+/// use testing::task_1_and_2::{asm, Stop, Value, Vm, VmConfig};
+///
+/// let bytecode = asm::parse("LoadVal 2\nLoadVal 3\nAdd\nReturnValue").unwrap();
+/// let mut vm = Vm::new(&bytecode, VmConfig::default());
+/// vm.break_at(2);
+/// assert_eq!(vm.run(), Ok(Stop::Paused(2)));
+/// assert_eq!(vm.stack(), [Value::Int(2), Value::Int(3)]);
+/// assert_eq!(vm.run(), Ok(Stop::Returned(Value::Int(5))));
+/// assert!(vm.is_finished());
+/// ```
+#[derive(Debug)]
+pub struct Vm<'a> {
+    bytecode: &'a Bytecode,
+    config: VmConfig,
+    state: State,
+    ip: IpType,
+    /// Whether the running context is the first one, see `Spawn`.
+    main: bool,
+    executed: u64,
+    /// Elements in all arrays together, checked against `max_heap`.
+    heap_len: usize,
+    /// How the run ended, once it has.
+    outcome: Option<Result<Value, InterpretationError>>,
+    /// IPs `run` pauses at before executing the instruction there.
+    breakpoints: BTreeSet<IpType>,
+    /// Stopped at the breakpoint at `ip`, so resuming doesn't stop there again at once.
+    paused: bool,
+    /// What is left of `config.gas`.
+    gas_left: Option<u64>,
+    tracer: Option<Tracer<'a>>,
+    /// Where the label of the instruction at each IP points, looked up once for the run.
+    targets: Vec<Option<IpType>>,
+    /// The slot of the variable of the instruction at each IP, given out once for the run.
+    slots: Vec<Option<Slot>>,
+    hosts: HashMap<HostName, HostFn<'a>>,
+    /// Only pure host functions may be called, see [`Vm::deterministic`].
+    deterministic: bool,
+    out: Output<'a>,
+    input: Input<'a>,
+}
+
+/// Where `Print` writes, see [`Vm::output`].
+struct Output<'a>(Box<dyn io::Write + 'a>);
+
+impl fmt::Debug for Output<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Output")
+    }
+}
+
+/// What `ReadInput` reads, see [`Vm::input`].
+struct Input<'a>(Box<InputValues<'a>>);
+
+type InputValues<'a> = dyn Iterator<Item = io::Result<Value>> + 'a;
+
+impl<'a> Input<'a> {
+    /// A value per line `read_line` appends, see [`Vm::input_lines`].
+    fn lines(mut read_line: impl FnMut(&mut String) -> io::Result<usize> + 'a) -> Self {
+        Input(Box::new(iter::from_fn(move || {
+            let mut line = String::new();
+            match read_line(&mut line) {
+                Ok(0) => None,
+                Ok(_) => {
+                    let line = line.strip_suffix('\n').unwrap_or(&line);
+                    let line = line.strip_suffix('\r').unwrap_or(line);
+                    // `inf` and `nan` parse as floats, but a user typing them means words.
+                    let number = line.bytes().any(|b| b.is_ascii_digit());
+                    Some(Ok(match line.parse() {
+                        Ok(val) if number => val,
+                        _ => Value::Str(line.to_owned()),
+                    }))
+                }
+                Err(e) => Some(Err(e)),
+            }
+        })))
+    }
+}
+
+impl fmt::Debug for Input<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Input")
+    }
+}
+
+/// What `CallHost` calls, see [`Vm::host`].
+struct HostFn<'a> {
+    call: Box<HostCall<'a>>,
+    /// Registered with [`Vm::pure_host`].
+    pure: bool,
+}
+
+type HostCall<'a> = dyn FnMut(&[Value]) -> HostResult + 'a;
+
+impl fmt::Debug for HostFn<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("HostFn")
+    }
+}
+
+/// Called after every instruction that executed, see [`Vm::trace`].
+struct Tracer<'a>(Box<dyn FnMut(&TraceStep<'_>) + 'a>);
+
+impl fmt::Debug for Tracer<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Tracer")
+    }
+}
+
+/// One executed instruction, as a tracer sees it.
+#[derive(Debug, Clone, Copy)]
+pub struct TraceStep<'t> {
+    pub ip: IpType,
+    pub instr: &'t Instruction,
+    /// The values it popped, the first popped first.
+    pub consumed: &'t [Value],
+    /// The top of the stack afterwards, of the context that runs next. For the final
+    /// `ReturnValue` it's the value returned.
+    pub top: Option<&'t Value>,
+}
+
+/// `   3 Add [5, 2] -> 7`, the IP right aligned.
+impl fmt::Display for TraceStep<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:>4} {}", self.ip, self.instr)?;
+        if !self.consumed.is_empty() {
+            f.write_str(" [")?;
+            for (i, val) in self.consumed.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{}", val)?;
+            }
+            f.write_str("]")?;
+        }
+        match self.top {
+            Some(top) => write!(f, " -> {}", top),
+            None => f.write_str(" -> empty"),
+        }
+    }
+}
+
+/// Where [`Vm::run`] stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stop {
+    Returned(Value),
+    /// At a breakpoint, the instruction there hasn't executed yet.
+    Paused(IpType),
 }
 
-fn run(bytecode: Bytecode) -> Result<ValueType, InterpretationError> {
-    const MAX_OPS: u64 = 1_000;
+impl<'a> Vm<'a> {
+    pub fn new(bytecode: &'a Bytecode, config: VmConfig) -> Self {
+        Vm::with_state(bytecode, config, State::default())
+    }
+
+    /// Keeps the variables of `state`, everything else starts out empty.
+    fn with_state(bytecode: &'a Bytecode, config: VmConfig, mut state: State) -> Self {
+        state.stack.clear();
+        state.calls.clear();
+        state.heap.clear();
+        state.contexts.clear();
+        let slots = bytecode
+            .instrs
+            .iter()
+            .map(|instr| match instr {
+                Instruction::WriteVar(name) | Instruction::ReadVar(name) => {
+                    Some(state.vars.slot(name))
+                }
+                _ => None,
+            })
+            .collect();
+        Vm {
+            bytecode,
+            config,
+            state,
+            ip: 0,
+            main: true,
+            executed: 0,
+            heap_len: 0,
+            outcome: None,
+            breakpoints: BTreeSet::new(),
+            paused: false,
+            gas_left: config.gas.map(|gas| gas.limit),
+            tracer: None,
+            targets: bytecode
+                .instrs
+                .iter()
+                .map(|instr| bytecode.labels.get(instr.label()?).copied())
+                .collect(),
+            slots,
+            hosts: HashMap::new(),
+            deterministic: false,
+            out: Output(Box::new(io::stdout())),
+            input: Input::lines(|line| io::stdin().read_line(line)),
+        }
+    }
+
+    /// Executes the next instruction, breakpoints or not. `Ok(Some(_))` is the value the run
+    /// returned, a step after the end gives the same outcome again.
+    pub fn step(&mut self) -> Result<Option<Value>, InterpretationError> {
+        if let Some(outcome) = &self.outcome {
+            return outcome.clone().map(Some);
+        }
+        self.paused = false;
+        let outcome = match self.execute(&mut |_| ()) {
+            Ok(None) => return Ok(None),
+            Ok(Some(val)) => Ok(val),
+            Err(err) => Err(err),
+        };
+        self.outcome = Some(outcome.clone());
+        outcome.map(Some)
+    }
+
+    /// Steps until the run ends or gets to a breakpoint. Called again after a pause, it
+    /// goes on from the breakpoint.
+    pub fn run(&mut self) -> Result<Stop, InterpretationError> {
+        loop {
+            if !self.paused && !self.is_finished() && self.breakpoints.contains(&self.ip) {
+                self.paused = true;
+                return Ok(Stop::Paused(self.ip));
+            }
+            if let Some(val) = self.step()? {
+                return Ok(Stop::Returned(val));
+            }
+        }
+    }
+
+    /// Pause before the instruction at `ip`, in whichever context gets there.
+    pub fn break_at(&mut self, ip: IpType) {
+        self.breakpoints.insert(ip);
+    }
+
+    /// Pause at the instruction `label` points to, which is returned. `None` when there is
+    /// no such label.
+    pub fn break_at_label(&mut self, label: &str) -> Option<IpType> {
+        let ip = *self.bytecode.labels.get(label)?;
+        self.break_at(ip);
+        Some(ip)
+    }
+
+    /// Whether there was a breakpoint at `ip`.
+    pub fn clear_breakpoint(&mut self, ip: IpType) -> bool {
+        self.breakpoints.remove(&ip)
+    }
+
+    /// Calls `tracer` after each instruction that executes, replacing any earlier one. An
+    /// instruction that fails isn't traced, the error says where it was.
+    pub fn trace(&mut self, tracer: impl FnMut(&TraceStep<'_>) + 'a) {
+        self.tracer = Some(Tracer(Box::new(tracer)));
+    }
+
+    /// Lets `CallHost` call `host` by `name`, replacing any earlier one of that name. It gets
+    /// the arguments the first one pushed first, an `Err` fails the run with `HostFailed`.
+    /// Snapshots don't keep host functions, a resumed run needs them registered again.
+    pub fn host(&mut self, name: &str, host: impl FnMut(&[Value]) -> HostResult + 'a) {
+        self.hosts.insert(
+            name.to_owned(),
+            HostFn {
+                call: Box::new(host),
+                pure: false,
+            },
+        );
+    }
+
+    /// Like [`Vm::host`], for a `host` whose result only depends on its arguments, which a
+    /// [`deterministic`](Vm::deterministic) run may call.
+    pub fn pure_host(&mut self, name: &str, host: impl FnMut(&[Value]) -> HostResult + 'a) {
+        self.hosts.insert(
+            name.to_owned(),
+            HostFn {
+                call: Box::new(host),
+                pure: true,
+            },
+        );
+    }
+
+    /// Fails `CallHost` with `ImpureHost` on host functions not registered with
+    /// [`Vm::pure_host`], so the value and trace of the run only depend on the bytecode, the
+    /// variables it starts with and its input. The VM has no clock or random numbers.
+    pub fn deterministic(&mut self) {
+        self.deterministic = true;
+    }
+
+    /// Makes `Print` write to `out` instead of stdout. A write error fails the run with
+    /// `OutputFailed`.
+    pub fn output(&mut self, out: impl io::Write + 'a) {
+        self.out = Output(Box::new(out));
+    }
+
+    /// Makes `ReadInput` push `values` in turn instead of reading stdin.
+    pub fn input(&mut self, values: impl Iterator<Item = Value> + 'a) {
+        self.input = Input(Box::new(values.map(Ok)));
+    }
+
+    /// Makes `ReadInput` read a line of `reader` instead of stdin, the way it does stdin: a
+    /// line holding a number written in digits pushes that number, any other line the string
+    /// without its line ending, `inf` and `nan` included. A read error fails the run with
+    /// `InputFailed`.
+    pub fn input_lines(&mut self, mut reader: impl io::BufRead + 'a) {
+        self.input = Input::lines(move |line| reader.read_line(line));
+    }
+
+    /// Traces to `out`, a [`TraceStep`] per line. Write errors are ignored, the run goes on
+    /// without them.
+    pub fn trace_to(&mut self, mut out: impl io::Write + 'a) {
+        self.trace(move |step| {
+            let _ = writeln!(out, "{}", step);
+        });
+    }
+
+    /// Whether the program returned or failed, further steps change nothing.
+    pub fn is_finished(&self) -> bool {
+        self.outcome.is_some()
+    }
+
+    /// The instruction the next step executes, or the one that failed.
+    pub fn ip(&self) -> IpType {
+        self.ip
+    }
+
+    /// The stack of the running context, the top last.
+    pub fn stack(&self) -> &[Value] {
+        &self.state.stack
+    }
+
+    pub fn vars(&self) -> &Variables {
+        &self.state.vars
+    }
+
+    /// For setting inputs before the first step or changing them between steps, `max_vars`
+    /// isn't checked.
+    pub fn vars_mut(&mut self) -> &mut Variables {
+        &mut self.state.vars
+    }
+
+    /// Instructions executed so far, the count `max_ops` limits.
+    pub fn executed(&self) -> u64 {
+        self.executed
+    }
 
-    let mut stack = vec![];
-    let mut vars = HashMap::new();
-    let mut ip = 0;
-    let mut executed = 0;
+    /// The gas not spent yet, `None` without [`VmConfig::gas`]. After `OutOfGas` it's what
+    /// wasn't enough for the instruction that failed.
+    pub fn gas_left(&self) -> Option<u64> {
+        self.gas_left
+    }
 
-    loop {
-        executed += 1;
-        if executed > MAX_OPS {
+    /// One instruction, calling `observe` with its IP first.
+    fn execute(
+        &mut self,
+        observe: &mut impl FnMut(IpType),
+    ) -> Result<Option<Value>, InterpretationError> {
+        self.executed += 1;
+        if self.config.max_ops.is_some_and(|max| self.executed > max) {
             return Err(InterpretationError::OperationsLimitExceeded);
         }
+        let Vm {
+            bytecode,
+            config,
+            state:
+                State {
+                    stack,
+                    vars,
+                    calls,
+                    heap,
+                    contexts,
+                },
+            heap_len,
+            gas_left,
+            tracer,
+            targets,
+            slots,
+            hosts,
+            deterministic,
+            out,
+            input,
+            ..
+        } = self;
+        let ip = self.ip;
+        let mut main = self.main;
 
         let instr = bytecode
             .instrs
             .get(ip)
-            .cloned()
             .ok_or(InterpretationError::ReturnDoesntExist)?;
+        if let (Some(left), Some(gas)) = (gas_left.as_mut(), &config.gas) {
+            *left = left
+                .checked_sub(gas.costs.cost(instr))
+                .ok_or(InterpretationError::OutOfGas(ip))?;
+        }
+        observe(ip);
+        let mut next = ip + 1;
+
+        let meta = instr.meta();
+        if meta.stack_out > meta.stack_in
+            && config
+                .max_stack
+                .is_some_and(|max| stack.len().saturating_sub(meta.stack_in) + meta.stack_out > max)
+        {
+            return Err(InterpretationError::StackOverflow {
+                depth: stack.len(),
+                ip,
+            });
+        }
 
-        let mut pop_stack = || stack.pop().ok_or(InterpretationError::StackIsEmpty(ip));
+        // Only filled when tracing, an empty `Vec` doesn't allocate.
+        let tracing = tracer.is_some();
+        let mut consumed = Vec::new();
+        let mut returned = None;
+        let mut pop_stack = || {
+            let val = stack.pop().ok_or(InterpretationError::StackIsEmpty(ip))?;
+            if tracing {
+                consumed.push(val.clone());
+            }
+            Ok(val)
+        };
 
         match instr {
-            Instruction::LoadVal(val) => stack.push(val),
+            Instruction::LoadVal(val) => stack.push(val.clone()),
 
-            Instruction::WriteVar(var_name) => {
-                vars.insert(var_name, pop_stack()?);
+            Instruction::WriteVar(_) => {
+                let val = pop_stack()?;
+                let slot = slots[ip].expect("every WriteVar has a slot");
+                let new = vars.get_slot(slot).is_none();
+                if new && config.max_vars.is_some_and(|max| vars.len() >= max) {
+                    return Err(InterpretationError::TooManyVariables(ip));
+                }
+                vars.set_slot(slot, val);
             }
 
             Instruction::ReadVar(var_name) => {
-                stack.push(
-                    vars.get(&var_name)
-                        .cloned()
-                        .ok_or(InterpretationError::UnknownVariable { var_name, ip })?,
-                );
+                let slot = slots[ip].expect("every ReadVar has a slot");
+                stack.push(vars.get_slot(slot).cloned().ok_or_else(|| {
+                    InterpretationError::UnknownVariable {
+                        var_name: var_name.clone(),
+                        ip,
+                    }
+                })?);
+            }
+
+            Instruction::Dup => {
+                let val = stack.last().ok_or(InterpretationError::StackIsEmpty(ip))?;
+                stack.push(val.clone());
+            }
+
+            Instruction::Swap => {
+                let (val1, val2) = (pop_stack()?, pop_stack()?);
+                stack.push(val1);
+                stack.push(val2);
+            }
+
+            Instruction::Pop => {
+                pop_stack()?;
             }
 
             Instruction::Add => {
-                let val1 = pop_stack()?;
-                let val2 = pop_stack()?;
-                stack.push(
-                    val1.checked_add(val2)
-                        .ok_or(InterpretationError::Overflow {
-                            op: '+',
-                            val1,
-                            val2,
-                            ip,
-                        })?,
-                );
+                let (val1, val2) = (pop_stack()?, pop_stack()?);
+                stack.push(arithmetic(
+                    instr,
+                    '+',
+                    val1,
+                    val2,
+                    ip,
+                    i64::checked_add,
+                    |a, b| a + b,
+                )?);
             }
 
             Instruction::Subtract => {
-                let val1 = pop_stack()?;
-                let val2 = pop_stack()?;
-                stack.push(
-                    val1.checked_sub(val2)
-                        .ok_or(InterpretationError::Overflow {
-                            op: '-',
-                            val1,
-                            val2,
-                            ip,
-                        })?,
-                );
+                let (val1, val2) = (pop_stack()?, pop_stack()?);
+                stack.push(arithmetic(
+                    instr,
+                    '-',
+                    val1,
+                    val2,
+                    ip,
+                    i64::checked_sub,
+                    |a, b| a - b,
+                )?);
             }
 
             Instruction::Multiply => {
-                let val1 = pop_stack()?;
-                let val2 = pop_stack()?;
-                stack.push(
-                    val1.checked_mul(val2)
-                        .ok_or(InterpretationError::Overflow {
-                            op: '*',
-                            val1,
-                            val2,
-                            ip,
-                        })?,
-                );
+                let (val1, val2) = (pop_stack()?, pop_stack()?);
+                stack.push(arithmetic(
+                    instr,
+                    '*',
+                    val1,
+                    val2,
+                    ip,
+                    i64::checked_mul,
+                    |a, b| a * b,
+                )?);
             }
 
             Instruction::Divide => {
-                let val1 = pop_stack()?;
-                let val2 = pop_stack()?;
-                if val2 == 0 {
+                let (val1, val2) = (pop_stack()?, pop_stack()?);
+                if let (Value::Int(_), Value::Int(0)) = (&val1, &val2) {
                     return Err(InterpretationError::DivisionByZero { ip });
                 }
-                stack.push(
-                    val1.checked_div(val2)
-                        .ok_or(InterpretationError::Overflow {
-                            op: '/',
-                            val1,
-                            val2,
-                            ip,
-                        })?,
-                );
+                stack.push(arithmetic(
+                    instr,
+                    '/',
+                    val1,
+                    val2,
+                    ip,
+                    i64::checked_div,
+                    |a, b| a / b,
+                )?);
             }
 
-            Instruction::JumpIfZero(label) => {
-                let val = pop_stack()?;
-                if val == 0 {
-                    ip = bytecode.labels.get(&label).cloned().ok_or(
-                        InterpretationError::UnknownLabel {
-                            lbl_name: label,
-                            ip,
-                        },
-                    )?;
-                    continue;
+            Instruction::Modulo => {
+                let (val1, val2) = (pop_stack()?, pop_stack()?);
+                if let (Value::Int(_), Value::Int(0)) = (&val1, &val2) {
+                    return Err(InterpretationError::DivisionByZero { ip });
                 }
+                stack.push(arithmetic(
+                    instr,
+                    '%',
+                    val1,
+                    val2,
+                    ip,
+                    i64::checked_rem,
+                    |a, b| a % b,
+                )?);
             }
 
-            Instruction::JumpIfNotZero(label) => {
-                let val = pop_stack()?;
-                if val != 0 {
-                    ip = bytecode.labels.get(&label).cloned().ok_or(
-                        InterpretationError::UnknownLabel {
-                            lbl_name: label,
+            Instruction::Negate => match pop_stack()? {
+                Value::Int(val) => {
+                    stack.push(Value::Int(val.checked_neg().ok_or(
+                        InterpretationError::Overflow {
+                            op: '-',
+                            val1: 0,
+                            val2: val,
                             ip,
                         },
-                    )?;
-                    continue;
+                    )?));
                 }
+                Value::Float(val) => stack.push(Value::Float(-val)),
+                other => return Err(mismatch(instr, &other, ip)),
+            },
+
+            Instruction::And => {
+                let (val1, val2) = (pop_stack()?, pop_stack()?);
+                stack.push(Value::Int(int(instr, val1, ip)? & int(instr, val2, ip)?));
             }
 
-            Instruction::JumpIfNeg(label) => {
-                let val = pop_stack()?;
-                if val < 0 {
-                    ip = bytecode.labels.get(&label).cloned().ok_or(
-                        InterpretationError::UnknownLabel {
-                            lbl_name: label,
-                            ip,
-                        },
-                    )?;
-                    continue;
+            Instruction::Or => {
+                let (val1, val2) = (pop_stack()?, pop_stack()?);
+                stack.push(Value::Int(int(instr, val1, ip)? | int(instr, val2, ip)?));
+            }
+
+            Instruction::Xor => {
+                let (val1, val2) = (pop_stack()?, pop_stack()?);
+                stack.push(Value::Int(int(instr, val1, ip)? ^ int(instr, val2, ip)?));
+            }
+
+            Instruction::Not => {
+                let val = pop_stack()?;
+                stack.push(Value::Int(!int(instr, val, ip)?));
+            }
+
+            Instruction::Shl => {
+                let (val, amount) = (pop_stack()?, pop_stack()?);
+                let (val, amount) = (int(instr, val, ip)?, int(instr, amount, ip)?);
+                let amount = shift_amount(amount, ip)?;
+                stack.push(Value::Int(val.checked_shl(amount).unwrap_or(0)));
+            }
+
+            Instruction::Shr => {
+                let (val, amount) = (pop_stack()?, pop_stack()?);
+                let (val, amount) = (int(instr, val, ip)?, int(instr, amount, ip)?);
+                let amount = shift_amount(amount, ip)?;
+                stack.push(Value::Int(val >> amount.min(ValueType::BITS - 1)));
+            }
+
+            Instruction::Eq => {
+                let (val1, val2) = (pop_stack()?, pop_stack()?);
+                stack.push(truth(equal(&val1, &val2)));
+            }
+
+            Instruction::Ne => {
+                let (val1, val2) = (pop_stack()?, pop_stack()?);
+                stack.push(truth(!equal(&val1, &val2)));
+            }
+
+            Instruction::Lt => {
+                let (val1, val2) = (pop_stack()?, pop_stack()?);
+                stack.push(truth(
+                    compare(instr, &val1, &val2, ip)? == Some(Ordering::Less),
+                ));
+            }
+
+            Instruction::Le => {
+                let (val1, val2) = (pop_stack()?, pop_stack()?);
+                stack.push(truth(matches!(
+                    compare(instr, &val1, &val2, ip)?,
+                    Some(Ordering::Less | Ordering::Equal)
+                )));
+            }
+
+            Instruction::Gt => {
+                let (val1, val2) = (pop_stack()?, pop_stack()?);
+                stack.push(truth(
+                    compare(instr, &val1, &val2, ip)? == Some(Ordering::Greater),
+                ));
+            }
+
+            Instruction::Ge => {
+                let (val1, val2) = (pop_stack()?, pop_stack()?);
+                stack.push(truth(matches!(
+                    compare(instr, &val1, &val2, ip)?,
+                    Some(Ordering::Greater | Ordering::Equal)
+                )));
+            }
+
+            Instruction::Concat => {
+                let (val1, val2) = (pop_stack()?, pop_stack()?);
+                if let Some(array) = [&val1, &val2]
+                    .into_iter()
+                    .find(|val| matches!(val, Value::Array(_)))
+                {
+                    return Err(mismatch(instr, array, ip));
+                }
+                let joined = format!("{}{}", val1, val2);
+                if config.max_string_len.is_some_and(|max| joined.len() > max) {
+                    return Err(InterpretationError::StringTooLong(ip));
+                }
+                stack.push(Value::Str(joined));
+            }
+
+            Instruction::StrLen => match pop_stack()? {
+                Value::Str(val) => stack.push(Value::Int(val.chars().count() as ValueType)),
+                other => return Err(mismatch(instr, &other, ip)),
+            },
+
+            Instruction::StrEq | Instruction::StrCmp | Instruction::StartsWith => {
+                let (val1, val2) = (pop_stack()?, pop_stack()?);
+                let (a, b) = match (&val1, &val2) {
+                    (Value::Str(a), Value::Str(b)) => (a, b),
+                    (Value::Str(_), other) | (other, _) => return Err(mismatch(instr, other, ip)),
+                };
+                let result = match instr {
+                    Instruction::StrEq => ValueType::from(a == b),
+                    Instruction::StrCmp => a.cmp(b) as ValueType,
+                    _ => ValueType::from(a.starts_with(b.as_str())),
+                };
+                stack.push(Value::Int(result));
+            }
+
+            Instruction::NewArray => {
+                let len = int(instr, pop_stack()?, ip)?;
+                let len = usize::try_from(len)
+                    .map_err(|_| InterpretationError::InvalidArrayLength { len, ip })?;
+                *heap_len = heap_len
+                    .checked_add(len)
+                    .ok_or(InterpretationError::HeapExhausted(ip))?;
+                if config.max_heap.is_some_and(|max| *heap_len > max) {
+                    return Err(InterpretationError::HeapExhausted(ip));
+                }
+                stack.push(Value::Array(ArrayRef { array: heap.len() }));
+                heap.push(vec![Value::Int(0); len]);
+            }
+
+            Instruction::ArrayGet => {
+                let (array, index) = (pop_stack()?, pop_stack()?);
+                let array = array_mut(instr, heap, array, ip)?;
+                let index = index_into(instr, array, index, ip)?;
+                stack.push(array[index].clone());
+            }
+
+            Instruction::ArraySet => {
+                let (array, index, val) = (pop_stack()?, pop_stack()?, pop_stack()?);
+                let array = array_mut(instr, heap, array, ip)?;
+                let index = index_into(instr, array, index, ip)?;
+                array[index] = val;
+            }
+
+            Instruction::ArrayLen => {
+                let array = pop_stack()?;
+                let len = array_mut(instr, heap, array, ip)?.len();
+                stack.push(Value::Int(len as ValueType));
+            }
+
+            Instruction::ArraySlice => {
+                let (array, start, len) = (pop_stack()?, pop_stack()?, pop_stack()?);
+                let (start, len) = (int(instr, start, ip)?, int(instr, len, ip)?);
+                let array = array_mut(instr, heap, array, ip)?;
+                let range = usize::try_from(start)
+                    .ok()
+                    .zip(usize::try_from(len).ok())
+                    .and_then(|(start, len)| Some(start..start.checked_add(len)?))
+                    .filter(|range| range.end <= array.len())
+                    .ok_or(InterpretationError::SliceOutOfBounds {
+                        start,
+                        len,
+                        array_len: array.len(),
+                        ip,
+                    })?;
+                let slice = array[range].to_vec();
+                *heap_len = heap_len
+                    .checked_add(slice.len())
+                    .ok_or(InterpretationError::HeapExhausted(ip))?;
+                if config.max_heap.is_some_and(|max| *heap_len > max) {
+                    return Err(InterpretationError::HeapExhausted(ip));
+                }
+                stack.push(Value::Array(ArrayRef { array: heap.len() }));
+                heap.push(slice);
+            }
+
+            Instruction::ArrayNext(label) => {
+                let (array, index) = (pop_stack()?, pop_stack()?);
+                let elements = array_mut(instr, heap, array.clone(), ip)?;
+                let index = int(instr, index, ip)?;
+                match usize::try_from(index) {
+                    Ok(at) if at < elements.len() => {
+                        let element = elements[at].clone();
+                        stack.push(Value::Int(index + 1));
+                        stack.push(array);
+                        stack.push(element);
+                    }
+                    Ok(_) => {
+                        next = target(targets, label, ip)?;
+                    }
+                    Err(_) => {
+                        return Err(InterpretationError::IndexOutOfBounds {
+                            index,
+                            len: elements.len(),
+                            ip,
+                        })
+                    }
+                }
+            }
+
+            Instruction::JumpIfZero(label) => {
+                let val = pop_stack()?;
+                if sign(instr, &val, ip)? == Some(Ordering::Equal) {
+                    next = target(targets, label, ip)?;
+                }
+            }
+
+            Instruction::JumpIfNotZero(label) => {
+                let val = pop_stack()?;
+                if sign(instr, &val, ip)? != Some(Ordering::Equal) {
+                    next = target(targets, label, ip)?;
+                }
+            }
+
+            Instruction::JumpIfNeg(label) => {
+                let val = pop_stack()?;
+                if sign(instr, &val, ip)? == Some(Ordering::Less) {
+                    next = target(targets, label, ip)?;
                 }
             }
 
             Instruction::JumpIfPos(label) => {
                 let val = pop_stack()?;
-                if val > 0 {
-                    ip = bytecode.labels.get(&label).cloned().ok_or(
-                        InterpretationError::UnknownLabel {
-                            lbl_name: label,
-                            ip,
-                        },
-                    )?;
-                    continue;
+                if sign(instr, &val, ip)? == Some(Ordering::Greater) {
+                    next = target(targets, label, ip)?;
+                }
+            }
+
+            Instruction::JumpIfNegPeek(label)
+            | Instruction::JumpIfPosPeek(label)
+            | Instruction::JumpIfZeroPeek(label)
+            | Instruction::JumpIfNotZeroPeek(label) => {
+                let val = stack.last().ok_or(InterpretationError::StackIsEmpty(ip))?;
+                let sign = sign(instr, val, ip)?;
+                let taken = match instr {
+                    Instruction::JumpIfNegPeek(_) => sign == Some(Ordering::Less),
+                    Instruction::JumpIfPosPeek(_) => sign == Some(Ordering::Greater),
+                    Instruction::JumpIfZeroPeek(_) => sign == Some(Ordering::Equal),
+                    _ => sign != Some(Ordering::Equal),
+                };
+                if taken {
+                    next = target(targets, label, ip)?;
+                }
+            }
+
+            Instruction::Call(label) => {
+                if config.max_calls.is_some_and(|max| calls.len() >= max) {
+                    return Err(InterpretationError::CallStackOverflow(ip));
+                }
+                calls.push(Frame {
+                    ret: ip + 1,
+                    base: stack.len(),
+                });
+                next = target(targets, label, ip)?;
+            }
+
+            Instruction::Ret => {
+                next = calls
+                    .pop()
+                    .ok_or(InterpretationError::RetWithoutCall(ip))?
+                    .ret;
+            }
+
+            Instruction::PushArg(offset) | Instruction::PeekFrame(offset) => {
+                let base = calls.last().map(|frame| frame.base);
+                let at = match instr {
+                    Instruction::PushArg(_) => base
+                        .zip(offset.checked_add(1))
+                        .and_then(|(base, back)| base.checked_sub(back)),
+                    _ => base.unwrap_or(0).checked_add(*offset),
+                };
+                let val = at.and_then(|at| stack.get(at)).cloned().ok_or_else(|| {
+                    InterpretationError::OutsideFrame {
+                        instr: instr.name().to_owned(),
+                        offset: *offset,
+                        ip,
+                    }
+                })?;
+                stack.push(val);
+            }
+
+            Instruction::CallHost(name, arity) => {
+                let host = hosts
+                    .get_mut(name)
+                    .ok_or_else(|| InterpretationError::UnknownHost {
+                        name: name.clone(),
+                        ip,
+                    })?;
+                if *deterministic && !host.pure {
+                    return Err(InterpretationError::ImpureHost {
+                        name: name.clone(),
+                        ip,
+                    });
+                }
+                let mut args = (0..*arity)
+                    .map(|_| pop_stack())
+                    .collect::<Result<Vec<_>, _>>()?;
+                args.reverse();
+                let val =
+                    (host.call)(&args).map_err(|message| InterpretationError::HostFailed {
+                        name: name.clone(),
+                        message,
+                        ip,
+                    })?;
+                stack.push(val);
+            }
+
+            Instruction::Print => {
+                let val = pop_stack()?;
+                writeln!(out.0, "{}", val).map_err(|e| InterpretationError::OutputFailed {
+                    message: e.to_string(),
+                    ip,
+                })?;
+            }
+
+            Instruction::ReadInput => {
+                let val = input.0.next().ok_or(InterpretationError::InputEnded(ip))?;
+                stack.push(val.map_err(|e| InterpretationError::InputFailed {
+                    message: e.to_string(),
+                    ip,
+                })?);
+            }
+
+            Instruction::Spawn(label) => {
+                if config
+                    .max_contexts
+                    .is_some_and(|max| contexts.len() + 1 >= max)
+                {
+                    return Err(InterpretationError::TooManyContexts(ip));
+                }
+                let start = target(targets, label, ip)?;
+                contexts.push_back(Context {
+                    ip: start,
+                    ..Context::default()
+                });
+            }
+
+            Instruction::SendChannel(channel) => {
+                let val = pop_stack()?;
+                let receiver = contexts.iter_mut().find(
+                    |context| matches!(&context.wait, Some(Wait::Recv(name)) if name == channel),
+                );
+                match receiver {
+                    Some(receiver) => {
+                        receiver.stack.push(val);
+                        receiver.wait = None;
+                    }
+                    None => {
+                        let wait = Wait::Send(channel.clone(), val);
+                        switch(contexts, Some(wait), ip, &mut next, &mut main, stack, calls)?;
+                    }
+                }
+            }
+
+            Instruction::RecvChannel(channel) => {
+                let sender = contexts.iter_mut().find(
+                    |context| matches!(&context.wait, Some(Wait::Send(name, _)) if name == channel),
+                );
+                match sender.and_then(|sender| sender.wait.take()) {
+                    Some(Wait::Send(_, val)) => stack.push(val),
+                    _ => {
+                        let wait = Wait::Recv(channel.clone());
+                        switch(contexts, Some(wait), ip, &mut next, &mut main, stack, calls)?;
+                    }
                 }
             }
 
             Instruction::ReturnValue => {
-                return pop_stack();
+                let val = pop_stack()?;
+                if main {
+                    returned = Some(val);
+                } else {
+                    switch(contexts, None, ip, &mut next, &mut main, stack, calls)?;
+                }
             }
         };
 
-        ip += 1;
+        if let Some(Tracer(tracer)) = tracer {
+            tracer(&TraceStep {
+                ip,
+                instr,
+                consumed: &consumed,
+                top: returned.as_ref().or(stack.last()),
+            });
+        }
+        if returned.is_some() {
+            return Ok(returned);
+        }
+        self.ip = next;
+        self.main = main;
+        Ok(None)
+    }
+}
+
+/// Parks the running context waiting on `wait`, or ends it without one, and runs the
+/// first parked context that isn't waiting. `at` is the IP the running context stopped at.
+fn switch(
+    contexts: &mut VecDeque<Context>,
+    wait: Option<Wait>,
+    at: IpType,
+    ip: &mut IpType,
+    main: &mut bool,
+    stack: &mut Vec<Value>,
+    calls: &mut Vec<Frame>,
+) -> Result<(), InterpretationError> {
+    let next = contexts
+        .iter()
+        .position(|context| context.wait.is_none())
+        .ok_or(InterpretationError::Deadlock(at))?;
+    let mut context = contexts.remove(next).expect("position is in range");
+    context.swap(ip, main, stack, calls);
+    if let Some(wait) = wait {
+        context.wait = Some(wait);
+        contexts.push_back(context);
     }
+    Ok(())
+}
+
+/// Where `label`, of the instruction at `ip`, points.
+fn target(
+    targets: &[Option<IpType>],
+    label: &LabelName,
+    ip: IpType,
+) -> Result<IpType, InterpretationError> {
+    targets[ip].ok_or_else(|| InterpretationError::UnknownLabel {
+        lbl_name: label.clone(),
+        ip,
+    })
+}
+
+/// `int` on two ints, `float` on both as floats otherwise.
+fn arithmetic(
+    instr: &Instruction,
+    op: char,
+    val1: Value,
+    val2: Value,
+    ip: IpType,
+    int: fn(ValueType, ValueType) -> Option<ValueType>,
+    float: fn(f64, f64) -> f64,
+) -> Result<Value, InterpretationError> {
+    match (val1, val2) {
+        (Value::Int(val1), Value::Int(val2)) => int(val1, val2)
+            .map(Value::Int)
+            .ok_or(InterpretationError::Overflow { op, val1, val2, ip }),
+        (val1, val2) => Ok(Value::Float(float(
+            number(instr, &val1, ip)?,
+            number(instr, &val2, ip)?,
+        ))),
+    }
+}
+
+fn mismatch(instr: &Instruction, val: &Value, ip: IpType) -> InterpretationError {
+    InterpretationError::TypeMismatch {
+        instr: instr.name().to_owned(),
+        found: val.kind(),
+        ip,
+    }
+}
+
+/// The operand of an instruction that only takes ints.
+fn int(instr: &Instruction, val: Value, ip: IpType) -> Result<ValueType, InterpretationError> {
+    match val {
+        Value::Int(val) => Ok(val),
+        other => Err(mismatch(instr, &other, ip)),
+    }
+}
+
+/// The operand of an instruction that only takes numbers, as a float.
+fn number(instr: &Instruction, val: &Value, ip: IpType) -> Result<f64, InterpretationError> {
+    val.as_f64().ok_or_else(|| mismatch(instr, val, ip))
+}
+
+/// The array `val` refers to, it has to be one made by this run.
+fn array_mut<'a>(
+    instr: &Instruction,
+    heap: &'a mut [Vec<Value>],
+    val: Value,
+    ip: IpType,
+) -> Result<&'a mut Vec<Value>, InterpretationError> {
+    let Value::Array(ArrayRef { array }) = val else {
+        return Err(mismatch(instr, &val, ip));
+    };
+    heap.get_mut(array)
+        .ok_or(InterpretationError::UnknownArray { array, ip })
+}
+
+/// `val` as an index into `array`.
+fn index_into(
+    instr: &Instruction,
+    array: &[Value],
+    val: Value,
+    ip: IpType,
+) -> Result<usize, InterpretationError> {
+    let index = int(instr, val, ip)?;
+    usize::try_from(index)
+        .ok()
+        .filter(|&i| i < array.len())
+        .ok_or(InterpretationError::IndexOutOfBounds {
+            index,
+            len: array.len(),
+            ip,
+        })
+}
+
+/// What a jump tests, it doesn't take strings or arrays.
+fn sign(
+    instr: &Instruction,
+    val: &Value,
+    ip: IpType,
+) -> Result<Option<Ordering>, InterpretationError> {
+    match val {
+        Value::Str(_) | Value::Array(_) => Err(mismatch(instr, val, ip)),
+        val => Ok(val.sign()),
+    }
+}
+
+/// Compares numbers, ints with floats as floats.
+fn compare(
+    instr: &Instruction,
+    val1: &Value,
+    val2: &Value,
+    ip: IpType,
+) -> Result<Option<Ordering>, InterpretationError> {
+    match (val1, val2) {
+        (Value::Int(a), Value::Int(b)) => Ok(Some(a.cmp(b))),
+        _ => Ok(number(instr, val1, ip)?.partial_cmp(&number(instr, val2, ip)?)),
+    }
+}
+
+/// What `Eq` tests, numbers by value, strings by text and arrays by identity, values of
+/// different kinds are never equal except for ints and floats.
+fn equal(val1: &Value, val2: &Value) -> bool {
+    match (val1, val2) {
+        (Value::Int(a), Value::Int(b)) => a == b,
+        (Value::Str(a), Value::Str(b)) => a == b,
+        (Value::Array(a), Value::Array(b)) => a == b,
+        (Value::Str(_) | Value::Array(_), _) | (_, Value::Str(_) | Value::Array(_)) => false,
+        _ => val1.as_f64() == val2.as_f64(),
+    }
+}
+
+/// What comparisons push.
+fn truth(holds: bool) -> Value {
+    Value::Int(ValueType::from(holds))
+}
+
+/// Amounts past the width are capped, so `checked_shl` and `>>` see at most 64.
+fn shift_amount(amount: ValueType, ip: IpType) -> Result<u32, InterpretationError> {
+    if amount < 0 {
+        return Err(InterpretationError::NegativeShift { amount, ip });
+    }
+    Ok(amount.min(ValueType::BITS.into()) as u32)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::task_1_and_2::{run, Bytecode, Instruction, InterpretationError, Labels};
+    use crate::task_1_and_2::{
+        asm, builder::BytecodeBuilder, run, run_with_config, run_with_env, ArrayRef, Bytecode,
+        Instruction, InterpretationError, Labels, Stop, TraceStep, Value, ValueKind, Vm, VmConfig,
+    };
 
     #[test]
     fn run_fails_when_empty_bytecode() {
@@ -256,8 +1927,8 @@ mod tests {
     fn run_fails_if_overflow() {
         let b = Bytecode {
             instrs: vec![
-                Instruction::LoadVal(i64::MAX),
-                Instruction::LoadVal(i64::MAX),
+                Instruction::LoadVal(Value::Int(i64::MAX)),
+                Instruction::LoadVal(Value::Int(i64::MAX)),
                 Instruction::Add,
             ],
             labels: Labels::new(),
@@ -278,8 +1949,8 @@ mod tests {
     fn run_fails_if_div_by_zero() {
         let b = Bytecode {
             instrs: vec![
-                Instruction::LoadVal(0),
-                Instruction::LoadVal(i64::MAX),
+                Instruction::LoadVal(Value::Int(0)),
+                Instruction::LoadVal(Value::Int(i64::MAX)),
                 Instruction::Divide,
             ],
             labels: Labels::new(),
@@ -292,7 +1963,7 @@ mod tests {
     fn run_fails_if_unknown_label() {
         let b = Bytecode {
             instrs: vec![
-                Instruction::LoadVal(0),
+                Instruction::LoadVal(Value::Int(0)),
                 Instruction::JumpIfZero("x".to_owned()),
             ],
             labels: Labels::new(),
@@ -313,7 +1984,7 @@ mod tests {
         labels.insert("x".to_owned(), 0);
         let b = Bytecode {
             instrs: vec![
-                Instruction::LoadVal(0),
+                Instruction::LoadVal(Value::Int(0)),
                 Instruction::JumpIfZero("x".to_owned()),
             ],
             labels,
@@ -325,7 +1996,7 @@ mod tests {
     #[test]
     fn run_fails_if_empty_stack() {
         let b = Bytecode {
-            instrs: vec![Instruction::LoadVal(0), Instruction::Add],
+            instrs: vec![Instruction::LoadVal(Value::Int(0)), Instruction::Add],
             labels: Labels::new(),
         };
         let r = run(b);
@@ -340,17 +2011,17 @@ mod tests {
         let (x_var, y_var, z_var) = ("x".to_owned(), "y".to_owned(), "z".to_owned());
         let b = Bytecode {
             instrs: vec![
-                Instruction::LoadVal(1),
+                Instruction::LoadVal(Value::Int(1)),
                 Instruction::WriteVar(x_var.clone()),
-                Instruction::LoadVal(2),
+                Instruction::LoadVal(Value::Int(2)),
                 Instruction::WriteVar(y_var.clone()),
-                Instruction::LoadVal(3),
+                Instruction::LoadVal(Value::Int(3)),
                 Instruction::WriteVar(z_var.clone()),
                 Instruction::ReadVar(x_var.clone()),
-                Instruction::LoadVal(1),
+                Instruction::LoadVal(Value::Int(1)),
                 Instruction::Add,
                 Instruction::WriteVar(x_var.clone()),
-                Instruction::LoadVal(1),
+                Instruction::LoadVal(Value::Int(1)),
                 Instruction::ReadVar(z_var.clone()),
                 Instruction::Subtract,
                 Instruction::WriteVar(z_var.clone()),
@@ -364,6 +2035,1157 @@ mod tests {
             labels,
         };
         let r = run(b);
-        assert_eq!(r, Ok(8));
+        assert_eq!(r, Ok(Value::Int(8)));
+    }
+
+    #[test]
+    fn run_with_env_returns_the_variables() {
+        let bytecode = asm::parse(
+            "ReadVar price\nReadVar qty\nMultiply\nDup\nWriteVar total\nLoadVal 10\nMultiply\nReturnValue",
+        )
+        .unwrap();
+        let env = [("price", 3), ("qty", 4)]
+            .map(|(name, val)| (name.to_owned(), Value::Int(val)))
+            .into();
+        let output = run_with_env(bytecode, env).unwrap();
+        assert_eq!(output.value, Value::Int(120));
+        let mut vars: Vec<_> = output.vars.into_iter().collect();
+        vars.sort_by(|a, b| a.0.cmp(&b.0));
+        let int = |name: &str, val| (name.to_owned(), Value::Int(val));
+        assert_eq!(vars, [int("price", 3), int("qty", 4), int("total", 12)]);
+
+        let bytecode = asm::parse("ReadVar missing\nReturnValue").unwrap();
+        assert!(run_with_env(bytecode, Default::default()).is_err());
+    }
+
+    #[test]
+    fn run_respects_config_limits() {
+        let countdown = BytecodeBuilder::new()
+            .load_val(500)
+            .write_var("n")
+            .label("loop")
+            .load_val(1)
+            .read_var("n")
+            .subtract()
+            .write_var("n")
+            .read_var("n")
+            .jump_if_pos("loop")
+            .read_var("n")
+            .return_value()
+            .build()
+            .unwrap();
+        assert_eq!(
+            run(countdown.clone()),
+            Err(InterpretationError::OperationsLimitExceeded)
+        );
+        assert_eq!(
+            run_with_config(countdown, &VmConfig::unlimited()),
+            Ok(Value::Int(0))
+        );
+
+        let tight = VmConfig {
+            max_stack: Some(2),
+            max_vars: Some(1),
+            ..VmConfig::default()
+        };
+        let deep = BytecodeBuilder::new()
+            .load_val(1)
+            .load_val(2)
+            .load_val(3)
+            .build();
+        assert_eq!(
+            run_with_config(deep.unwrap(), &tight),
+            Err(InterpretationError::StackOverflow { depth: 2, ip: 2 })
+        );
+        let wide = BytecodeBuilder::new()
+            .load_val(1)
+            .write_var("a")
+            .load_val(2)
+            .write_var("a")
+            .load_val(3)
+            .write_var("b")
+            .build();
+        assert_eq!(
+            run_with_config(wide.unwrap(), &tight),
+            Err(InterpretationError::TooManyVariables(5))
+        );
+    }
+
+    #[test]
+    fn dup_swap_and_pop() {
+        // 7 * 7 - 2 without a temporary variable.
+        let bytecode = BytecodeBuilder::new()
+            .load_val(2)
+            .load_val(7)
+            .dup()
+            .multiply()
+            .swap()
+            .load_val(100)
+            .pop()
+            .swap()
+            .subtract()
+            .return_value()
+            .build()
+            .unwrap();
+        assert_eq!(run(bytecode), Ok(Value::Int(47)));
+        assert_eq!(
+            run(BytecodeBuilder::new().load_val(1).swap().build().unwrap()),
+            Err(InterpretationError::StackIsEmpty(1))
+        );
+        assert_eq!(
+            run(BytecodeBuilder::new().dup().build().unwrap()),
+            Err(InterpretationError::StackIsEmpty(0))
+        );
+        let tight = VmConfig {
+            max_stack: Some(1),
+            ..VmConfig::default()
+        };
+        assert_eq!(
+            run_with_config(
+                BytecodeBuilder::new().load_val(1).dup().build().unwrap(),
+                &tight
+            ),
+            Err(InterpretationError::StackOverflow { depth: 1, ip: 1 })
+        );
+    }
+
+    #[test]
+    fn modulo_and_negate() {
+        let modulo = |below, top| {
+            run(BytecodeBuilder::new()
+                .load_val(below)
+                .load_val(top)
+                .modulo()
+                .return_value()
+                .build()
+                .unwrap())
+        };
+        assert_eq!(modulo(3, 10), Ok(Value::Int(1)));
+        assert_eq!(modulo(3, -10), Ok(Value::Int(-1)));
+        assert_eq!(
+            modulo(0, 10),
+            Err(InterpretationError::DivisionByZero { ip: 2 })
+        );
+        assert_eq!(
+            modulo(-1, i64::MIN),
+            Err(InterpretationError::Overflow {
+                op: '%',
+                val1: i64::MIN,
+                val2: -1,
+                ip: 2
+            })
+        );
+
+        let negate = |val| {
+            run(BytecodeBuilder::new()
+                .load_val(val)
+                .negate()
+                .return_value()
+                .build()
+                .unwrap())
+        };
+        assert_eq!(negate(5), Ok(Value::Int(-5)));
+        assert_eq!(
+            negate(i64::MIN),
+            Err(InterpretationError::Overflow {
+                op: '-',
+                val1: 0,
+                val2: i64::MIN,
+                ip: 1
+            })
+        );
+    }
+
+    #[test]
+    fn bitwise_and_shifts() {
+        let binary = |below, top, instr| {
+            run(BytecodeBuilder::new()
+                .load_val(below)
+                .load_val(top)
+                .instr(instr)
+                .return_value()
+                .build()
+                .unwrap())
+        };
+        assert_eq!(
+            binary(0b1100, 0b1010, Instruction::And),
+            Ok(Value::Int(0b1000))
+        );
+        assert_eq!(
+            binary(0b1100, 0b1010, Instruction::Or),
+            Ok(Value::Int(0b1110))
+        );
+        assert_eq!(
+            binary(0b1100, 0b1010, Instruction::Xor),
+            Ok(Value::Int(0b0110))
+        );
+        assert_eq!(
+            run(BytecodeBuilder::new()
+                .load_val(0)
+                .bit_not()
+                .return_value()
+                .build()
+                .unwrap()),
+            Ok(Value::Int(-1))
+        );
+
+        assert_eq!(binary(4, 1, Instruction::Shl), Ok(Value::Int(16)));
+        assert_eq!(binary(63, 1, Instruction::Shl), Ok(Value::Int(i64::MIN)));
+        assert_eq!(binary(64, 1, Instruction::Shl), Ok(Value::Int(0)));
+        assert_eq!(binary(2, -8, Instruction::Shr), Ok(Value::Int(-2)));
+        assert_eq!(binary(1000, -8, Instruction::Shr), Ok(Value::Int(-1)));
+        assert_eq!(binary(1000, 8, Instruction::Shr), Ok(Value::Int(0)));
+        assert_eq!(
+            binary(-1, 8, Instruction::Shl),
+            Err(InterpretationError::NegativeShift { amount: -1, ip: 2 })
+        );
+    }
+
+    #[test]
+    fn comparisons_push_one_or_zero() {
+        let compare = |below, top, instr| {
+            run(BytecodeBuilder::new()
+                .load_val(below)
+                .load_val(top)
+                .instr(instr)
+                .return_value()
+                .build()
+                .unwrap())
+        };
+        // Like Subtract, the top of the stack is on the left.
+        assert_eq!(compare(5, 3, Instruction::Lt), Ok(Value::Int(1)));
+        assert_eq!(compare(3, 5, Instruction::Lt), Ok(Value::Int(0)));
+        assert_eq!(compare(3, 3, Instruction::Le), Ok(Value::Int(1)));
+        assert_eq!(compare(5, 3, Instruction::Gt), Ok(Value::Int(0)));
+        assert_eq!(compare(3, 3, Instruction::Ge), Ok(Value::Int(1)));
+        assert_eq!(compare(3, 3, Instruction::Eq), Ok(Value::Int(1)));
+        assert_eq!(compare(3, 4, Instruction::Ne), Ok(Value::Int(1)));
+        assert_eq!(
+            run(BytecodeBuilder::new().load_val(1).eq().build().unwrap()),
+            Err(InterpretationError::StackIsEmpty(1))
+        );
+    }
+
+    #[test]
+    fn floats_mix_with_ints() {
+        let binary = |below: Value, top: Value, instr| {
+            run(BytecodeBuilder::new()
+                .instr(Instruction::LoadVal(below))
+                .instr(Instruction::LoadVal(top))
+                .instr(instr)
+                .return_value()
+                .build()
+                .unwrap())
+        };
+        let (int, float) = (Value::Int, Value::Float);
+        assert_eq!(
+            binary(int(2), float(7.0), Instruction::Divide),
+            Ok(float(3.5))
+        );
+        assert_eq!(binary(int(2), int(7), Instruction::Divide), Ok(int(3)));
+        assert_eq!(binary(float(0.5), int(1), Instruction::Add), Ok(float(1.5)));
+        assert_eq!(
+            binary(int(0), float(1.0), Instruction::Divide),
+            Ok(float(f64::INFINITY))
+        );
+        assert_eq!(binary(int(1), float(1.0), Instruction::Eq), Ok(int(1)));
+        assert_eq!(
+            binary(float(f64::NAN), float(f64::NAN), Instruction::Eq),
+            Ok(int(0))
+        );
+        assert_eq!(
+            binary(int(i64::MAX), float(1.0), Instruction::Multiply),
+            Ok(float(i64::MAX as f64))
+        );
+
+        // -0.5 is negative and not zero, so this returns 1.
+        let sign = BytecodeBuilder::new()
+            .load_float(-0.5)
+            .jump_if_neg("neg")
+            .load_val(0)
+            .return_value()
+            .label("neg")
+            .load_val(1)
+            .return_value()
+            .build()
+            .unwrap();
+        assert_eq!(run(sign), Ok(int(1)));
+    }
+
+    #[test]
+    fn bitwise_operations_reject_floats() {
+        let bytecode = BytecodeBuilder::new()
+            .load_val(1)
+            .load_float(2.0)
+            .and()
+            .build()
+            .unwrap();
+        assert_eq!(
+            run(bytecode),
+            Err(InterpretationError::TypeMismatch {
+                instr: "And".to_owned(),
+                found: ValueKind::Float,
+                ip: 2
+            })
+        );
+        let shift = BytecodeBuilder::new()
+            .load_float(1.0)
+            .load_val(1)
+            .shl()
+            .build()
+            .unwrap();
+        assert_eq!(
+            run(shift).unwrap_err().to_string(),
+            "Shl doesn't take float values (IP=2)"
+        );
+    }
+
+    #[test]
+    fn peek_jumps_leave_the_value() {
+        let countdown = BytecodeBuilder::new()
+            .load_val(3)
+            .write_var("steps")
+            .load_val(3)
+            .label("loop")
+            .jump_if_zero_peek("done")
+            .load_val(-1)
+            .add()
+            .read_var("steps")
+            .load_val(1)
+            .add()
+            .write_var("steps")
+            .load_val(0)
+            .jump_if_zero("loop")
+            .label("done")
+            .read_var("steps")
+            .add()
+            .return_value()
+            .build()
+            .unwrap();
+        assert_eq!(run(countdown), Ok(Value::Int(6)));
+
+        let empty = BytecodeBuilder::new()
+            .jump_if_not_zero_peek("end")
+            .label("end")
+            .return_value()
+            .build()
+            .unwrap();
+        assert_eq!(run(empty), Err(InterpretationError::StackIsEmpty(0)));
+        let taken = BytecodeBuilder::new()
+            .load_val(-2)
+            .jump_if_neg_peek("end")
+            .load_val(1)
+            .label("end")
+            .return_value()
+            .build()
+            .unwrap();
+        assert_eq!(run(taken), Ok(Value::Int(-2)));
+    }
+
+    #[test]
+    fn strings_concatenate_and_count() {
+        // "n = " and 2.5 * 2, then the length of that appended. Like Subtract, the top of
+        // the stack comes first.
+        let bytecode = BytecodeBuilder::new()
+            .load_val(2)
+            .load_float(2.5)
+            .multiply()
+            .load_str("n = ")
+            .concat()
+            .dup()
+            .write_var("text")
+            .str_len()
+            .read_var("text")
+            .concat()
+            .return_value()
+            .build()
+            .unwrap();
+        assert_eq!(run(bytecode), Ok(Value::Str("n = 5.07".to_owned())));
+
+        let equal = |below: &str, top: &str| {
+            run(BytecodeBuilder::new()
+                .load_str(below)
+                .load_str(top)
+                .eq()
+                .return_value()
+                .build()
+                .unwrap())
+        };
+        assert_eq!(equal("ab", "ab"), Ok(Value::Int(1)));
+        assert_eq!(equal("ab", "b"), Ok(Value::Int(0)));
+
+        let doubling = BytecodeBuilder::new()
+            .load_str("x")
+            .label("again")
+            .dup()
+            .concat()
+            .load_val(0)
+            .jump_if_zero("again")
+            .build()
+            .unwrap();
+        assert_eq!(
+            run_with_config(
+                doubling,
+                &VmConfig {
+                    max_string_len: Some(1_000),
+                    ..VmConfig::unlimited()
+                }
+            ),
+            Err(InterpretationError::StringTooLong(2))
+        );
+
+        // Numbers are written out, arrays are refused.
+        let array =
+            asm::parse("LoadVal 2\nLoadVal 0\nNewArray\nLoadVal \"x\"\nConcat\nReturnValue");
+        assert_eq!(
+            run(array.unwrap()),
+            Err(InterpretationError::TypeMismatch {
+                instr: "Concat".to_owned(),
+                found: ValueKind::Array,
+                ip: 4
+            })
+        );
+    }
+
+    #[test]
+    fn strings_compare_and_order() {
+        let with = |below: Value, top: Value, instr| {
+            run(BytecodeBuilder::new()
+                .instr(Instruction::LoadVal(below))
+                .instr(Instruction::LoadVal(top))
+                .instr(instr)
+                .return_value()
+                .build()
+                .unwrap())
+        };
+        let str = |text: &str| Value::Str(text.to_owned());
+        assert_eq!(
+            with(str("b"), str("a"), Instruction::StrCmp),
+            Ok(Value::Int(-1))
+        );
+        assert_eq!(
+            with(str("a"), str("a"), Instruction::StrCmp),
+            Ok(Value::Int(0))
+        );
+        assert_eq!(
+            with(str("a"), str("ab"), Instruction::StrCmp),
+            Ok(Value::Int(1))
+        );
+        assert_eq!(
+            with(str("ab"), str("ab"), Instruction::StrEq),
+            Ok(Value::Int(1))
+        );
+        assert_eq!(
+            with(str("a"), str("ab"), Instruction::StrEq),
+            Ok(Value::Int(0))
+        );
+        assert_eq!(
+            with(str("ab"), str("abc"), Instruction::StartsWith),
+            Ok(Value::Int(1))
+        );
+        assert_eq!(
+            with(str("b"), str("abc"), Instruction::StartsWith),
+            Ok(Value::Int(0))
+        );
+
+        // Unlike Eq, a number is an error and not just another value.
+        let err = with(Value::Int(1), str("1"), Instruction::StrEq).unwrap_err();
+        assert_eq!(
+            err,
+            InterpretationError::TypeMismatch {
+                instr: "StrEq".to_owned(),
+                found: ValueKind::Int,
+                ip: 2
+            }
+        );
+        assert_eq!(err.to_string(), "StrEq doesn't take int values (IP=2)");
+        assert!(with(str("1"), Value::Float(1.0), Instruction::StrCmp).is_err());
+    }
+
+    #[test]
+    fn arithmetic_and_jumps_reject_strings() {
+        let with_str = |instr| {
+            run(BytecodeBuilder::new()
+                .load_val(1)
+                .load_str("1")
+                .instr(instr)
+                .return_value()
+                .build()
+                .unwrap())
+        };
+        for instr in [Instruction::Add, Instruction::Divide, Instruction::Lt] {
+            let name = instr.name().to_owned();
+            assert_eq!(
+                with_str(instr),
+                Err(InterpretationError::TypeMismatch {
+                    instr: name,
+                    found: ValueKind::Str,
+                    ip: 2
+                })
+            );
+        }
+        assert_eq!(with_str(Instruction::Ne), Ok(Value::Int(1)));
+        let jump = BytecodeBuilder::new()
+            .load_str("")
+            .jump_if_zero("end")
+            .label("end")
+            .build()
+            .unwrap();
+        assert_eq!(
+            run(jump).unwrap_err().to_string(),
+            "JumpIfZero doesn't take str values (IP=1)"
+        );
+    }
+
+    #[test]
+    fn arrays_are_shared_by_reference() {
+        // a = [0, 0, 0], a[1] = 7, then a[1] + len(a) + a[0].
+        let bytecode = BytecodeBuilder::new()
+            .load_val(3)
+            .new_array()
+            .write_var("a")
+            .load_val(7)
+            .load_val(1)
+            .read_var("a")
+            .array_set()
+            .load_val(1)
+            .read_var("a")
+            .array_get()
+            .read_var("a")
+            .array_len()
+            .add()
+            .load_val(0)
+            .read_var("a")
+            .array_get()
+            .add()
+            .return_value()
+            .build()
+            .unwrap();
+        assert_eq!(run(bytecode), Ok(Value::Int(10)));
+
+        let same = |second: BytecodeBuilder| run(second.eq().return_value().build().unwrap());
+        let one = || BytecodeBuilder::new().load_val(1).new_array();
+        assert_eq!(same(one().dup()), Ok(Value::Int(1)));
+        assert_eq!(same(one().load_val(1).new_array()), Ok(Value::Int(0)));
+    }
+
+    #[test]
+    fn arrays_check_bounds_and_the_heap() {
+        let get = |index| {
+            run(BytecodeBuilder::new()
+                .load_val(index)
+                .load_val(3)
+                .new_array()
+                .array_get()
+                .return_value()
+                .build()
+                .unwrap())
+        };
+        assert_eq!(
+            get(3),
+            Err(InterpretationError::IndexOutOfBounds {
+                index: 3,
+                len: 3,
+                ip: 3
+            })
+        );
+        assert_eq!(
+            get(-1).unwrap_err().to_string(),
+            "index -1 is out of bounds for an array of 3 (IP=3)"
+        );
+        assert_eq!(get(2), Ok(Value::Int(0)));
+
+        let new = |len| {
+            run(BytecodeBuilder::new()
+                .load_val(len)
+                .new_array()
+                .return_value()
+                .build()
+                .unwrap())
+        };
+        assert_eq!(
+            new(-1),
+            Err(InterpretationError::InvalidArrayLength { len: -1, ip: 1 })
+        );
+        assert_eq!(new(i64::MAX), Err(InterpretationError::HeapExhausted(1)));
+        assert_eq!(new(0), Ok(Value::Array(ArrayRef { array: 0 })));
+
+        let reference = BytecodeBuilder::new()
+            .load_val(0)
+            .load_val(1)
+            .new_array()
+            .add()
+            .build()
+            .unwrap();
+        assert_eq!(
+            run(reference).unwrap_err().to_string(),
+            "Add doesn't take array values (IP=3)"
+        );
+        // A reference that didn't come from this run.
+        let foreign = BytecodeBuilder::new()
+            .instr(Instruction::LoadVal(Value::Array(ArrayRef { array: 5 })))
+            .array_len()
+            .build()
+            .unwrap();
+        assert_eq!(
+            run(foreign),
+            Err(InterpretationError::UnknownArray { array: 5, ip: 1 })
+        );
+
+        // A heap count that would overflow is exhausted too, with or without `max_heap`.
+        let bytecode = BytecodeBuilder::new()
+            .load_val(1)
+            .load_val(0)
+            .load_val(1)
+            .new_array()
+            .array_slice()
+            .return_value()
+            .build()
+            .unwrap();
+        let mut vm = Vm::new(&bytecode, VmConfig::default());
+        for _ in 0..4 {
+            assert_eq!(vm.step(), Ok(None));
+        }
+        vm.heap_len = usize::MAX;
+        assert_eq!(vm.run(), Err(InterpretationError::HeapExhausted(4)));
+        let mut vm = Vm::new(&bytecode, VmConfig::default());
+        vm.heap_len = usize::MAX;
+        for _ in 0..3 {
+            assert_eq!(vm.step(), Ok(None));
+        }
+        assert_eq!(vm.run(), Err(InterpretationError::HeapExhausted(3)));
+    }
+
+    #[test]
+    fn arrays_slice_and_iterate() {
+        // The sum of a[1..3] of [0, 5, 7, 0].
+        let bytecode = BytecodeBuilder::new()
+            .load_val(4)
+            .new_array()
+            .write_var("a")
+            .load_val(5)
+            .load_val(1)
+            .read_var("a")
+            .array_set()
+            .load_val(7)
+            .load_val(2)
+            .read_var("a")
+            .array_set()
+            .load_val(0)
+            .write_var("sum")
+            .load_val(0)
+            .load_val(2)
+            .load_val(1)
+            .read_var("a")
+            .array_slice()
+            .label("each")
+            .array_next("done")
+            .read_var("sum")
+            .add()
+            .write_var("sum")
+            .load_val(0)
+            .jump_if_zero("each")
+            .label("done")
+            .read_var("sum")
+            .return_value()
+            .build()
+            .unwrap();
+        assert_eq!(run(bytecode), Ok(Value::Int(12)));
+
+        let slice = |start, len| {
+            run(BytecodeBuilder::new()
+                .load_val(len)
+                .load_val(start)
+                .load_val(4)
+                .new_array()
+                .array_slice()
+                .array_len()
+                .return_value()
+                .build()
+                .unwrap())
+        };
+        assert_eq!(slice(4, 0), Ok(Value::Int(0)));
+        assert_eq!(slice(1, 3), Ok(Value::Int(3)));
+        assert_eq!(
+            slice(2, 3),
+            Err(InterpretationError::SliceOutOfBounds {
+                start: 2,
+                len: 3,
+                array_len: 4,
+                ip: 4
+            })
+        );
+        assert_eq!(
+            slice(-1, 2).unwrap_err().to_string(),
+            "2 values from -1 are out of bounds for an array of 4 (IP=4)"
+        );
+        assert!(slice(1, i64::MAX).is_err());
+
+        let negative = BytecodeBuilder::new()
+            .load_val(-1)
+            .load_val(1)
+            .new_array()
+            .array_next("end")
+            .label("end")
+            .build()
+            .unwrap();
+        assert_eq!(
+            run(negative),
+            Err(InterpretationError::IndexOutOfBounds {
+                index: -1,
+                len: 1,
+                ip: 3
+            })
+        );
+    }
+
+    #[test]
+    fn spawned_contexts_exchange_values() {
+        // The producer sends 1, 2 and 3 and sets x, the main context adds up what it gets.
+        let bytecode = BytecodeBuilder::new()
+            .spawn("producer")
+            .recv_channel("c")
+            .recv_channel("c")
+            .add()
+            .recv_channel("c")
+            .add()
+            .read_var("x")
+            .add()
+            .return_value()
+            .label("producer")
+            .load_val(1)
+            .send_channel("c")
+            .load_val(2)
+            .send_channel("c")
+            .load_val(10)
+            .write_var("x")
+            .load_val(3)
+            .send_channel("c")
+            .load_val(0)
+            .return_value()
+            .build()
+            .unwrap();
+        assert_eq!(run(bytecode), Ok(Value::Int(16)));
+    }
+
+    #[test]
+    fn fails_when_every_context_waits() {
+        let alone = BytecodeBuilder::new()
+            .load_val(1)
+            .send_channel("c")
+            .build()
+            .unwrap();
+        assert_eq!(run(alone), Err(InterpretationError::Deadlock(1)));
+
+        // Both wait to receive, on different channels.
+        let crossed = BytecodeBuilder::new()
+            .spawn("other")
+            .recv_channel("a")
+            .return_value()
+            .label("other")
+            .recv_channel("b")
+            .return_value()
+            .build()
+            .unwrap();
+        assert_eq!(
+            run(crossed).unwrap_err().to_string(),
+            "every context is waiting on a channel (IP=3)"
+        );
+
+        let forever = BytecodeBuilder::new()
+            .label("again")
+            .spawn("again")
+            .load_val(0)
+            .jump_if_zero("again")
+            .build()
+            .unwrap();
+        assert_eq!(
+            run_with_config(
+                forever,
+                &VmConfig {
+                    max_contexts: Some(4),
+                    ..VmConfig::unlimited()
+                }
+            ),
+            Err(InterpretationError::TooManyContexts(0))
+        );
+    }
+
+    #[test]
+    fn steps_one_instruction_at_a_time() {
+        let bytecode = BytecodeBuilder::new()
+            .read_var("x")
+            .load_val(1)
+            .add()
+            .write_var("x")
+            .read_var("x")
+            .load_val(3)
+            .swap()
+            .jump_if_zero("never")
+            .return_value()
+            .label("never")
+            .return_value()
+            .build()
+            .unwrap();
+        let mut vm = Vm::new(&bytecode, VmConfig::default());
+        vm.vars_mut().insert("x".to_owned(), Value::Int(41));
+        for _ in 0..4 {
+            assert_eq!(vm.step(), Ok(None));
+        }
+        assert_eq!((vm.ip(), vm.executed()), (4, 4));
+        assert_eq!(vm.vars()["x"], Value::Int(42));
+        assert!(vm.stack().is_empty());
+        assert_eq!(vm.run(), Ok(Stop::Returned(Value::Int(3))));
+        assert!(vm.is_finished());
+        assert_eq!(vm.step(), Ok(Some(Value::Int(3))));
+        assert_eq!(vm.executed(), 9);
+
+        // A failed step leaves the IP at the instruction that failed.
+        let bytecode = BytecodeBuilder::new().load_val(1).add().build().unwrap();
+        let mut vm = Vm::new(&bytecode, VmConfig::default());
+        vm.step().unwrap();
+        assert_eq!(vm.step(), Err(InterpretationError::StackIsEmpty(1)));
+        assert_eq!((vm.ip(), vm.stack()), (1, &[][..]));
+        assert_eq!(vm.step(), Err(InterpretationError::StackIsEmpty(1)));
+    }
+
+    #[test]
+    fn pauses_at_breakpoints_and_resumes() {
+        // Adds 1 to x until it is 3, the loop body starts at "again".
+        let bytecode = BytecodeBuilder::new()
+            .load_val(0)
+            .write_var("x")
+            .label("again")
+            .load_val(3)
+            .read_var("x")
+            .load_val(1)
+            .add()
+            .dup()
+            .write_var("x")
+            .subtract()
+            .jump_if_neg("again")
+            .read_var("x")
+            .return_value()
+            .build()
+            .unwrap();
+        let mut vm = Vm::new(&bytecode, VmConfig::default());
+        assert_eq!(vm.break_at_label("again"), Some(2));
+        assert_eq!(vm.break_at_label("missing"), None);
+        vm.break_at(0);
+
+        assert_eq!(vm.run(), Ok(Stop::Paused(0)));
+        assert_eq!(vm.executed(), 0);
+        let mut seen = vec![];
+        while let Ok(Stop::Paused(ip)) = vm.run() {
+            assert_eq!(ip, 2);
+            seen.push(vm.vars()["x"].clone());
+            if seen.len() == 2 {
+                vm.vars_mut().insert("x".to_owned(), Value::Int(2));
+                assert!(vm.clear_breakpoint(2));
+            }
+        }
+        assert_eq!(seen, [Value::Int(0), Value::Int(1)]);
+        assert_eq!(vm.step(), Ok(Some(Value::Int(3))));
+        assert_eq!(vm.run(), Ok(Stop::Returned(Value::Int(3))));
+    }
+
+    #[test]
+    fn traces_executed_instructions() {
+        let bytecode = BytecodeBuilder::new()
+            .load_val(5)
+            .load_val(2)
+            .subtract()
+            .write_var("x")
+            .read_var("x")
+            .return_value()
+            .build()
+            .unwrap();
+        let mut out = Vec::new();
+        let mut vm = Vm::new(&bytecode, VmConfig::default());
+        vm.trace_to(&mut out);
+        assert_eq!(vm.run(), Ok(Stop::Returned(Value::Int(-3))));
+        drop(vm);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "   0 LoadVal 5 -> 5\n\
+             \x20  1 LoadVal 2 -> 2\n\
+             \x20  2 Subtract [2, 5] -> -3\n\
+             \x20  3 WriteVar x [-3] -> empty\n\
+             \x20  4 ReadVar x -> -3\n\
+             \x20  5 ReturnValue [-3] -> -3\n"
+        );
+
+        // A failing instruction is left to the error.
+        let failing = BytecodeBuilder::new().load_val(1).add().build().unwrap();
+        let mut ips = vec![];
+        let mut vm = Vm::new(&failing, VmConfig::default());
+        vm.trace(|step: &TraceStep<'_>| ips.push(step.ip));
+        assert_eq!(vm.run(), Err(InterpretationError::StackIsEmpty(1)));
+        drop(vm);
+        assert_eq!(ips, [0]);
+    }
+
+    #[test]
+    fn calls_return_after_the_call() {
+        // square(n) called twice: 3 * 3 + 4 * 4.
+        let bytecode = BytecodeBuilder::new()
+            .load_val(3)
+            .write_var("n")
+            .call("square")
+            .load_val(4)
+            .write_var("n")
+            .call("square")
+            .add()
+            .return_value()
+            .label("square")
+            .read_var("n")
+            .read_var("n")
+            .multiply()
+            .ret()
+            .build()
+            .unwrap();
+        assert_eq!(run(bytecode), Ok(Value::Int(25)));
+
+        let forever = BytecodeBuilder::new()
+            .label("again")
+            .call("again")
+            .build()
+            .unwrap();
+        let shallow = VmConfig {
+            max_calls: Some(3),
+            ..VmConfig::default()
+        };
+        assert_eq!(
+            run_with_config(forever, &shallow),
+            Err(InterpretationError::CallStackOverflow(0))
+        );
+        let stray = BytecodeBuilder::new().load_val(1).ret().build().unwrap();
+        assert_eq!(run(stray), Err(InterpretationError::RetWithoutCall(1)));
+    }
+
+    #[test]
+    fn resolves_labels_before_running() {
+        let mut bytecode = BytecodeBuilder::new()
+            .load_val(0)
+            .jump_if_zero("end")
+            .label("end")
+            .load_val(1)
+            .return_value()
+            .build()
+            .unwrap();
+        bytecode.instrs.push(Instruction::Call("gone".into()));
+        let mut vm = Vm::new(&bytecode, VmConfig::default());
+        assert_eq!(vm.targets, [None, Some(2), None, None, None]);
+        assert_eq!(bytecode.instrs[1].label().map(String::as_str), Some("end"));
+        assert_eq!(vm.run(), Ok(Stop::Returned(Value::Int(1))));
+    }
+
+    #[test]
+    fn calls_reach_arguments_through_the_frame() {
+        // (a - b) squared for a = 10 and b = 3, the callee pops both arguments.
+        let bytecode = BytecodeBuilder::new()
+            .load_val(10)
+            .load_val(3)
+            .call("square_difference")
+            .return_value()
+            .label("square_difference")
+            .push_arg(0)
+            .push_arg(1)
+            .subtract()
+            .peek_frame(0)
+            .multiply()
+            .swap()
+            .pop()
+            .swap()
+            .pop()
+            .ret()
+            .build()
+            .unwrap();
+        let mut vm = Vm::new(&bytecode, VmConfig::default());
+        assert_eq!(vm.run(), Ok(Stop::Returned(Value::Int(49))));
+        assert_eq!(vm.stack(), []);
+
+        let outside = |instr| {
+            run(BytecodeBuilder::new()
+                .load_val(1)
+                .instr(instr)
+                .return_value()
+                .build()
+                .unwrap())
+        };
+        assert_eq!(outside(Instruction::PeekFrame(0)), Ok(Value::Int(1)));
+        assert_eq!(
+            outside(Instruction::PeekFrame(1)),
+            Err(InterpretationError::OutsideFrame {
+                instr: "PeekFrame".to_owned(),
+                offset: 1,
+                ip: 1
+            })
+        );
+        assert_eq!(
+            outside(Instruction::PushArg(0)).unwrap_err().to_string(),
+            "PushArg 0 reaches outside the frame (IP=1)"
+        );
+        // Offsets too large to add to the frame's base are outside it as well.
+        let in_call = |instr: Instruction| {
+            run(BytecodeBuilder::new()
+                .load_val(1)
+                .call("f")
+                .label("f")
+                .instr(instr)
+                .return_value()
+                .build()
+                .unwrap())
+        };
+        for instr in [
+            Instruction::PushArg(usize::MAX),
+            Instruction::PeekFrame(usize::MAX),
+        ] {
+            assert_eq!(
+                in_call(instr.clone()),
+                Err(InterpretationError::OutsideFrame {
+                    instr: instr.name().to_owned(),
+                    offset: usize::MAX,
+                    ip: 2
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn calls_host_functions() {
+        let bytecode =
+            asm::parse("LoadVal 10\nLoadVal 3\nCallHost sub 2\nCallHost twice 1\nReturnValue")
+                .unwrap();
+        assert_eq!(bytecode.instrs[2].to_string(), "CallHost sub 2");
+        let decoded = Bytecode::from_bytes(&bytecode.to_bytes()).unwrap();
+        assert_eq!(decoded.disassemble(), bytecode.disassemble());
+
+        let mut calls = 0;
+        let mut vm = Vm::new(&bytecode, VmConfig::default());
+        vm.host("sub", |args| match args {
+            [Value::Int(a), Value::Int(b)] => Ok(Value::Int(a - b)),
+            _ => Err("expected two ints".to_owned()),
+        });
+        vm.host("twice", |args| {
+            calls += 1;
+            match &args[0] {
+                Value::Int(a) => Ok(Value::Int(a * 2)),
+                _ => Err("expected an int".to_owned()),
+            }
+        });
+        assert_eq!(vm.run(), Ok(Stop::Returned(Value::Int(14))));
+        drop(vm);
+        assert_eq!(calls, 1);
+
+        let mut vm = Vm::new(&bytecode, VmConfig::default());
+        assert_eq!(
+            vm.run(),
+            Err(InterpretationError::UnknownHost {
+                name: "sub".to_owned(),
+                ip: 2
+            })
+        );
+        let mut vm = Vm::new(&bytecode, VmConfig::default());
+        vm.host("sub", |_| Err("unavailable".to_owned()));
+        assert_eq!(
+            vm.run().unwrap_err().to_string(),
+            "host function 'sub' failed: unavailable (IP=2)"
+        );
+
+        // A deterministic run only calls the pure ones.
+        let mut vm = Vm::new(&bytecode, VmConfig::default());
+        vm.deterministic();
+        vm.pure_host("sub", |args| match args {
+            [Value::Int(a), Value::Int(b)] => Ok(Value::Int(a - b)),
+            _ => Err("expected two ints".to_owned()),
+        });
+        vm.host("twice", |_| Ok(Value::Int(0)));
+        assert_eq!(
+            vm.run(),
+            Err(InterpretationError::ImpureHost {
+                name: "twice".to_owned(),
+                ip: 3
+            })
+        );
+        assert_eq!(vm.stack(), [Value::Int(7)]);
+    }
+
+    #[test]
+    fn prints_to_the_output() {
+        let bytecode = BytecodeBuilder::new()
+            .load_val(3)
+            .print()
+            .instr(Instruction::LoadVal(Value::Str("hi".into())))
+            .print()
+            .load_val(0)
+            .return_value()
+            .build()
+            .unwrap();
+        let mut out = vec![];
+        let mut vm = Vm::new(&bytecode, VmConfig::default());
+        vm.output(&mut out);
+        assert_eq!(vm.run(), Ok(Stop::Returned(Value::Int(0))));
+        drop(vm);
+        assert_eq!(String::from_utf8(out).unwrap(), "3\nhi\n");
+
+        // Room for the 3 but not for its newline.
+        let mut full = [0u8; 1];
+        let mut vm = Vm::new(&bytecode, VmConfig::default());
+        vm.output(&mut full[..]);
+        assert_eq!(
+            vm.run(),
+            Err(InterpretationError::OutputFailed {
+                message: "failed to write whole buffer".to_owned(),
+                ip: 1
+            })
+        );
+    }
+
+    #[test]
+    fn reads_values_from_the_input() {
+        let bytecode = asm::parse("ReadInput\nReadInput\nConcat\nReadInput\nReturnValue").unwrap();
+        let mut vm = Vm::new(&bytecode, VmConfig::default());
+        vm.input([Value::Int(1), Value::Int(2), Value::Int(3)].into_iter());
+        assert_eq!(vm.run(), Ok(Stop::Returned(Value::Int(3))));
+        assert_eq!(vm.stack(), [Value::Str("21".into())]);
+
+        let mut vm = Vm::new(&bytecode, VmConfig::default());
+        vm.input_lines(&b"a\r\n-4\n"[..]);
+        assert_eq!(vm.run(), Err(InterpretationError::InputEnded(3)));
+        assert_eq!(vm.stack(), [Value::Str("-4a".into())]);
+
+        let read = |line: &'static str| {
+            let bytecode = asm::parse("ReadInput\nReturnValue").unwrap();
+            let mut vm = Vm::new(&bytecode, VmConfig::default());
+            vm.input_lines(line.as_bytes());
+            vm.run()
+        };
+        for word in ["inf", "-infinity", "NaN"] {
+            assert_eq!(read(word), Ok(Stop::Returned(Value::Str(word.into()))));
+        }
+        assert_eq!(read("1e3"), Ok(Stop::Returned(Value::Float(1000.0))));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn programs_and_errors_round_trip_through_json() {
+        let bytecode = crate::task_1_and_2::examples::find("sum")
+            .unwrap()
+            .bytecode();
+        let json = serde_json::to_string(&bytecode).unwrap();
+        assert!(json.starts_with(r#"{"instrs":[{"LoadVal":10},{"WriteVar":"n"}"#));
+        let decoded: Bytecode = serde_json::from_str(&json).unwrap();
+        assert_eq!(run(decoded), Ok(Value::Int(55)));
+
+        let error = InterpretationError::UnknownVariable {
+            var_name: "x".to_owned(),
+            ip: 3,
+        };
+        let json = serde_json::to_string(&error).unwrap();
+        assert_eq!(
+            serde_json::from_str::<InterpretationError>(&json).unwrap(),
+            error
+        );
     }
 }
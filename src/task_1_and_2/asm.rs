@@ -0,0 +1,468 @@
+use std::fmt::Write as _;
+
+use thiserror::Error;
+
+use super::{Bytecode, Instruction, Labels, Value};
+
+use Instruction::*;
+
+/// A line that doesn't assemble, `line` and `column` count from 1.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("{line}:{column}: {kind}")]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: AsmErrorKind,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    #[error("unknown instruction '{0}'")]
+    UnknownInstruction(String),
+
+    #[error("{0} expects {1}")]
+    MissingOperand(&'static str, &'static str),
+
+    #[error("unexpected '{0}'")]
+    UnexpectedOperand(String),
+
+    #[error("'{0}' is not a number")]
+    InvalidNumber(String),
+
+    #[error("{0} is not a valid string")]
+    InvalidString(String),
+
+    #[error("'{0}' is not a valid name")]
+    InvalidName(String),
+
+    #[error("label '{0}' is defined twice")]
+    DuplicateLabel(String),
+
+    #[error("label '{0}' is not defined")]
+    UndefinedLabel(String),
+}
+
+/// What follows a mnemonic, with the instruction to build from it.
+enum Operand {
+    None(Instruction),
+    Value,
+    Var(fn(String) -> Instruction),
+    Label(fn(String) -> Instruction),
+    Channel(fn(String) -> Instruction),
+    Offset(fn(usize) -> Instruction),
+    /// A host function and its arity.
+    Host,
+}
+
+/// Reads the format `examples show` prints: one instruction per line, `name:` lines
+/// for labels and `;` comments.
+///
+/// Mnemonics ignore case and underscores, so `LoadVal 1` and `LOAD_VAL 1` are the same
+/// instruction, and names may be quoted as in `WRITE_VAR 'x'`.
+///
+/// `LoadVal` takes a number or a string in double quotes, with the escapes Rust's `{:?}`
+/// writes, so listings of string constants read back.
+pub fn parse(text: &str) -> Result<Bytecode, AsmError> {
+    let mut instrs = vec![];
+    let mut labels = Labels::new();
+    let mut jumps = vec![];
+
+    for (line, source) in text.lines().enumerate() {
+        let at = |column, kind| AsmError {
+            line: line + 1,
+            column,
+            kind,
+        };
+        let mut tokens = tokens(strip_comment(source)).into_iter().peekable();
+
+        if let Some(&(column, token)) = tokens.peek() {
+            if let Some(label) = token.strip_suffix(':') {
+                let label = name(label)
+                    .ok_or_else(|| at(column, AsmErrorKind::InvalidName(label.to_owned())))?;
+                if labels.insert(label.clone(), instrs.len()).is_some() {
+                    return Err(at(column, AsmErrorKind::DuplicateLabel(label)));
+                }
+                tokens.next();
+            }
+        }
+        let Some((column, mnemonic)) = tokens.next() else {
+            continue;
+        };
+
+        let (kind, operand) = match mnemonic.replace('_', "").to_ascii_lowercase().as_str() {
+            "loadval" => ("LoadVal", Operand::Value),
+            "writevar" => ("WriteVar", Operand::Var(WriteVar)),
+            "readvar" => ("ReadVar", Operand::Var(ReadVar)),
+            "dup" => ("Dup", Operand::None(Dup)),
+            "swap" => ("Swap", Operand::None(Swap)),
+            "pop" | "drop" => ("Pop", Operand::None(Pop)),
+            "add" => ("Add", Operand::None(Add)),
+            "multiply" => ("Multiply", Operand::None(Multiply)),
+            "subtract" => ("Subtract", Operand::None(Subtract)),
+            "divide" => ("Divide", Operand::None(Divide)),
+            "modulo" => ("Modulo", Operand::None(Modulo)),
+            "negate" => ("Negate", Operand::None(Negate)),
+            "and" => ("And", Operand::None(And)),
+            "or" => ("Or", Operand::None(Or)),
+            "xor" => ("Xor", Operand::None(Xor)),
+            "not" => ("Not", Operand::None(Not)),
+            "shl" => ("Shl", Operand::None(Shl)),
+            "shr" => ("Shr", Operand::None(Shr)),
+            "eq" => ("Eq", Operand::None(Eq)),
+            "ne" => ("Ne", Operand::None(Ne)),
+            "lt" => ("Lt", Operand::None(Lt)),
+            "le" => ("Le", Operand::None(Le)),
+            "gt" => ("Gt", Operand::None(Gt)),
+            "ge" => ("Ge", Operand::None(Ge)),
+            "concat" => ("Concat", Operand::None(Concat)),
+            "strlen" => ("StrLen", Operand::None(StrLen)),
+            "streq" => ("StrEq", Operand::None(StrEq)),
+            "strcmp" => ("StrCmp", Operand::None(StrCmp)),
+            "startswith" => ("StartsWith", Operand::None(StartsWith)),
+            "newarray" => ("NewArray", Operand::None(NewArray)),
+            "arrayget" => ("ArrayGet", Operand::None(ArrayGet)),
+            "arrayset" => ("ArraySet", Operand::None(ArraySet)),
+            "arraylen" => ("ArrayLen", Operand::None(ArrayLen)),
+            "arrayslice" => ("ArraySlice", Operand::None(ArraySlice)),
+            "arraynext" => ("ArrayNext", Operand::Label(ArrayNext)),
+            "returnvalue" => ("ReturnValue", Operand::None(ReturnValue)),
+            "jumpifneg" => ("JumpIfNeg", Operand::Label(JumpIfNeg)),
+            "jumpifpos" => ("JumpIfPos", Operand::Label(JumpIfPos)),
+            "jumpifzero" => ("JumpIfZero", Operand::Label(JumpIfZero)),
+            "jumpifnotzero" => ("JumpIfNotZero", Operand::Label(JumpIfNotZero)),
+            "jumpifnegpeek" => ("JumpIfNegPeek", Operand::Label(JumpIfNegPeek)),
+            "jumpifpospeek" => ("JumpIfPosPeek", Operand::Label(JumpIfPosPeek)),
+            "jumpifzeropeek" => ("JumpIfZeroPeek", Operand::Label(JumpIfZeroPeek)),
+            "jumpifnotzeropeek" => ("JumpIfNotZeroPeek", Operand::Label(JumpIfNotZeroPeek)),
+            "call" => ("Call", Operand::Label(Call)),
+            "ret" => ("Ret", Operand::None(Ret)),
+            "pusharg" => ("PushArg", Operand::Offset(PushArg)),
+            "peekframe" => ("PeekFrame", Operand::Offset(PeekFrame)),
+            "callhost" => ("CallHost", Operand::Host),
+            "print" => ("Print", Operand::None(Print)),
+            "readinput" => ("ReadInput", Operand::None(ReadInput)),
+            "spawn" => ("Spawn", Operand::Label(Spawn)),
+            "sendchannel" => ("SendChannel", Operand::Channel(SendChannel)),
+            "recvchannel" => ("RecvChannel", Operand::Channel(RecvChannel)),
+            _ => {
+                return Err(at(
+                    column,
+                    AsmErrorKind::UnknownInstruction(mnemonic.to_owned()),
+                ))
+            }
+        };
+
+        let end = column + mnemonic.chars().count();
+        let mut arg = |what| {
+            tokens
+                .next()
+                .ok_or_else(|| at(end, AsmErrorKind::MissingOperand(kind, what)))
+        };
+        let mut name_arg = |what| {
+            let (column, token) = arg(what)?;
+            let name = name(token)
+                .ok_or_else(|| at(column, AsmErrorKind::InvalidName(token.to_owned())))?;
+            Ok((column, name))
+        };
+        let instr = match operand {
+            Operand::None(instr) => instr,
+            Operand::Value => {
+                let (column, value) = arg("a value")?;
+                let value = if value.starts_with('"') {
+                    unquote(value)
+                        .map(Value::Str)
+                        .ok_or_else(|| at(column, AsmErrorKind::InvalidString(value.to_owned())))?
+                } else {
+                    value
+                        .parse()
+                        .map_err(|_| at(column, AsmErrorKind::InvalidNumber(value.to_owned())))?
+                };
+                LoadVal(value)
+            }
+            Operand::Var(instr) => instr(name_arg("a variable")?.1),
+            Operand::Channel(instr) => instr(name_arg("a channel")?.1),
+            Operand::Offset(instr) => {
+                let (column, offset) = arg("an offset")?;
+                let offset: u32 = offset
+                    .parse()
+                    .map_err(|_| at(column, AsmErrorKind::InvalidNumber(offset.to_owned())))?;
+                instr(offset as usize)
+            }
+            Operand::Host => {
+                let (_, name) = name_arg("a host function")?;
+                let (column, arity) = arg("an arity")?;
+                let arity: u32 = arity
+                    .parse()
+                    .map_err(|_| at(column, AsmErrorKind::InvalidNumber(arity.to_owned())))?;
+                CallHost(name, arity as usize)
+            }
+            Operand::Label(instr) => {
+                let (column, label) = name_arg("a label")?;
+                jumps.push((line + 1, column, label.clone()));
+                instr(label)
+            }
+        };
+        if let Some((column, extra)) = tokens.next() {
+            return Err(at(
+                column,
+                AsmErrorKind::UnexpectedOperand(extra.to_owned()),
+            ));
+        }
+        instrs.push(instr);
+    }
+
+    // The VM would only notice once it takes the jump, makes the call or spawns.
+    if let Some((line, column, label)) = jumps
+        .into_iter()
+        .find(|(_, _, label)| !labels.contains_key(label))
+    {
+        return Err(AsmError {
+            line,
+            column,
+            kind: AsmErrorKind::UndefinedLabel(label),
+        });
+    }
+    Ok(Bytecode { instrs, labels })
+}
+
+impl Bytecode {
+    /// The program as text [`parse`] reads back: each label on a line of its own before
+    /// the instruction it points at, and after every instruction a comment with its IP.
+    /// Labels pointing past the end show up as comments at the end.
+    pub fn disassemble(&self) -> String {
+        let mut labels: Vec<_> = self.labels.iter().map(|(label, &ip)| (ip, label)).collect();
+        labels.sort();
+        let mut labels = labels.into_iter().peekable();
+        let mut out = String::new();
+        for (ip, instr) in self.instrs.iter().enumerate() {
+            while let Some((_, label)) = labels.next_if(|&(at, _)| at == ip) {
+                let _ = writeln!(out, "{}:", label);
+            }
+            let _ = writeln!(out, "    {:<20} ; {}", instr.to_string(), ip);
+        }
+        for (ip, label) in labels {
+            let _ = match ip == self.instrs.len() {
+                true => writeln!(out, "{}:", label),
+                false => writeln!(out, "; {}: points at {}, past the end", label, ip),
+            };
+        }
+        out
+    }
+}
+
+/// `line` up to a `;` that isn't inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut quotes = Quotes::default();
+    match line
+        .char_indices()
+        .find(|&(_, c)| !quotes.inside(c) && c == ';')
+    {
+        Some((end, _)) => &line[..end],
+        None => line,
+    }
+}
+
+/// Whitespace separated tokens with the column each starts at, whitespace inside a
+/// string doesn't end one.
+fn tokens(code: &str) -> Vec<(usize, &str)> {
+    let mut tokens = vec![];
+    let mut start = None;
+    let mut quotes = Quotes::default();
+    for (column, (at, c)) in code.char_indices().enumerate() {
+        let inside = quotes.inside(c);
+        match (start, c.is_whitespace() && !inside) {
+            (None, false) => start = Some((column + 1, at)),
+            (Some((column, from)), true) => {
+                tokens.push((column, &code[from..at]));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some((column, from)) = start {
+        tokens.push((column, &code[from..]));
+    }
+    tokens
+}
+
+/// Follows `"` strings through a line one character at a time.
+#[derive(Default)]
+struct Quotes {
+    open: bool,
+    escaped: bool,
+}
+
+impl Quotes {
+    /// Whether `c` is part of a string, its quotes included.
+    fn inside(&mut self, c: char) -> bool {
+        let was_open = self.open;
+        match (self.open, self.escaped, c) {
+            (true, true, _) => self.escaped = false,
+            (true, false, '\\') => self.escaped = true,
+            (_, false, '"') => self.open = !self.open,
+            _ => {}
+        }
+        was_open || self.open
+    }
+}
+
+/// The text of a `"` string with `\\`, `\"`, `\'`, `\n`, `\r`, `\t`, `\0` and `\u{..}`
+/// escapes.
+pub(super) fn unquote(token: &str) -> Option<String> {
+    let inner = token.strip_prefix('"')?.strip_suffix('"')?;
+    let mut text = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '"' => return None,
+            '\\' => match chars.next()? {
+                '\\' => '\\',
+                '"' => '"',
+                '\'' => '\'',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                '0' => '\0',
+                'u' => {
+                    let hex = chars.as_str().strip_prefix('{')?;
+                    let (digits, rest) = hex.split_once('}')?;
+                    chars = rest.chars();
+                    char::from_u32(u32::from_str_radix(digits, 16).ok()?)?
+                }
+                _ => return None,
+            },
+            c => c,
+        };
+        text.push(c);
+    }
+    Some(text)
+}
+
+/// A variable or label name, optionally in single or double quotes.
+fn name(token: &str) -> Option<String> {
+    let bare = ['\'', '"']
+        .iter()
+        .find_map(|&q| token.strip_prefix(q)?.strip_suffix(q))
+        .unwrap_or(token);
+    let valid = !bare.is_empty() && bare.chars().all(|c| c.is_alphanumeric() || c == '_');
+    valid.then(|| bare.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::{
+        asm::{parse, AsmError, AsmErrorKind},
+        examples::EXAMPLES,
+        run, Value,
+    };
+
+    #[test]
+    fn reads_back_example_listings() {
+        for example in EXAMPLES {
+            let bytecode = parse(&example.listing()).unwrap();
+            assert_eq!(run(bytecode), example.run(), "{}", example.name);
+        }
+    }
+
+    #[test]
+    fn disassembles_what_it_reads() {
+        for example in EXAMPLES {
+            let bytecode = example.bytecode();
+            let text = bytecode.disassemble();
+            assert_eq!(
+                parse(&text).unwrap().disassemble(),
+                text,
+                "{}",
+                example.name
+            );
+        }
+
+        let mut bytecode = parse("start:\nLoadVal \"a ; b\"\nJumpIfZero start\nend:").unwrap();
+        bytecode.labels.insert("far".into(), 9);
+        assert_eq!(
+            bytecode.disassemble(),
+            "start:\n    LoadVal \"a ; b\"      ; 0\n    JumpIfZero start     ; 1\nend:\n\
+             ; far: points at 9, past the end\n"
+        );
+    }
+
+    #[test]
+    fn accepts_upper_case_mnemonics_and_quoted_names() {
+        let program = "\
+LOAD_VAL 3      ; x = 3
+WRITE_VAR 'x'
+top: READ_VAR \"x\"
+    JUMP_IF_ZERO done
+  load_val -1
+READ_VAR x
+ADD
+WRITE_VAR x
+LOAD_VAL 0
+JUMP_IF_ZERO top
+done:
+LOAD_VAL 7
+RETURN_VALUE
+";
+        assert_eq!(run(parse(program).unwrap()), Ok(Value::Int(7)));
+    }
+
+    #[test]
+    fn reads_floats_and_shows_them_as_floats() {
+        let bytecode = parse("LoadVal 2.0\nLoadVal -1e-3\nLoadVal 3\nLoadVal NaN").unwrap();
+        let listing: Vec<_> = bytecode.instrs.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            listing,
+            ["LoadVal 2.0", "LoadVal -0.001", "LoadVal 3", "LoadVal NaN"]
+        );
+        let again = parse(&listing.join("\n")).unwrap();
+        assert_eq!(again.to_bytes(), bytecode.to_bytes());
+    }
+
+    #[test]
+    fn reads_strings_with_spaces_semicolons_and_escapes() {
+        let bytecode = parse("LoadVal \"a; \\\"b\\\"\\t\\u{e9}\" ; comment").unwrap();
+        let crate::task_1_and_2::Instruction::LoadVal(Value::Str(text)) = &bytecode.instrs[0]
+        else {
+            panic!("{:?}", bytecode.instrs);
+        };
+        assert_eq!(text, "a; \"b\"\té");
+        let listing = bytecode.instrs[0].to_string();
+        assert_eq!(listing, r#"LoadVal "a; \"b\"\té""#);
+        assert_eq!(parse(&listing).unwrap().to_bytes(), bytecode.to_bytes());
+        assert_eq!(
+            parse("LoadVal \"open").unwrap_err().kind,
+            AsmErrorKind::InvalidString("\"open".to_owned())
+        );
+        assert_eq!(
+            parse("LoadVal \"a\" 1").unwrap_err().kind,
+            AsmErrorKind::UnexpectedOperand("1".to_owned())
+        );
+    }
+
+    #[test]
+    fn reports_line_and_column() {
+        let error = |text| parse(text).unwrap_err();
+        assert_eq!(
+            error("LoadVal 1\n  LodeVal 2"),
+            AsmError {
+                line: 2,
+                column: 3,
+                kind: AsmErrorKind::UnknownInstruction("LodeVal".to_owned())
+            }
+        );
+        assert_eq!(error("LoadVal x1").to_string(), "1:9: 'x1' is not a number");
+        assert_eq!(
+            error("ReadVar").to_string(),
+            "1:8: ReadVar expects a variable"
+        );
+        assert_eq!(error("Add 1").to_string(), "1:5: unexpected '1'");
+        assert_eq!(
+            error("a:\na:").to_string(),
+            "2:1: label 'a' is defined twice"
+        );
+        assert_eq!(
+            error("LoadVal 0\n\tJumpIfZero nowhere").to_string(),
+            "2:13: label 'nowhere' is not defined"
+        );
+    }
+}
@@ -0,0 +1,395 @@
+use thiserror::Error;
+
+use super::{ArrayRef, Bytecode, Instruction, Labels, Value, ValueType};
+
+use Instruction::*;
+
+/// First bytes of every compiled program.
+pub const MAGIC: &[u8; 4] = b"TBC\0";
+/// Bumped whenever the layout changes, older files are rejected instead of misread.
+pub const VERSION: u16 = 1;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    #[error("not a compiled program")]
+    BadMagic,
+
+    #[error("format version {0} is not supported, expected {VERSION}")]
+    UnsupportedVersion(u16),
+
+    #[error("unexpected end of data at byte {0}")]
+    Truncated(usize),
+
+    #[error("unknown opcode {opcode} at byte {offset}")]
+    UnknownOpcode { opcode: u8, offset: usize },
+
+    #[error("name at byte {0} is not valid UTF-8")]
+    InvalidName(usize),
+
+    #[error("label '{label}' points past the end of the program ({ip})")]
+    LabelOutOfRange { label: String, ip: usize },
+
+    #[error("label '{0}' is defined twice")]
+    DuplicateLabel(String),
+
+    #[error("unexpected data after the program at byte {0}")]
+    TrailingBytes(usize),
+}
+
+/// Layout, all integers little-endian:
+///
+/// ```text
+/// magic "TBC\0", version u16,
+/// instruction count u32, then per instruction an opcode u8 and its operand,
+/// label count u32, then per label its name and IP u32
+/// ```
+///
+/// `LoadVal` carries an i64 after opcode 0, the bits of an f64 after opcode 31, a string
+/// laid out like a name after opcode 32 or an array number as u64 after opcode 39, names
+/// are a u32 byte length followed by UTF-8. `PushArg` and `PeekFrame` carry a u32 offset,
+/// `CallHost` a name and then a u32 arity.
+impl Bytecode {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend(VERSION.to_le_bytes());
+        put_len(&mut out, self.instrs.len());
+        for instr in &self.instrs {
+            match instr {
+                LoadVal(val) => put_value(&mut out, val),
+                WriteVar(name) => put_named(&mut out, 1, name),
+                ReadVar(name) => put_named(&mut out, 2, name),
+                Add => out.push(3),
+                Multiply => out.push(4),
+                Subtract => out.push(5),
+                Divide => out.push(6),
+                ReturnValue => out.push(7),
+                JumpIfNeg(label) => put_named(&mut out, 8, label),
+                JumpIfPos(label) => put_named(&mut out, 9, label),
+                JumpIfZero(label) => put_named(&mut out, 10, label),
+                JumpIfNotZero(label) => put_named(&mut out, 11, label),
+                Call(label) => put_named(&mut out, 12, label),
+                Ret => out.push(13),
+                Eq => out.push(14),
+                Ne => out.push(15),
+                Lt => out.push(16),
+                Le => out.push(17),
+                Gt => out.push(18),
+                Ge => out.push(19),
+                Modulo => out.push(20),
+                Negate => out.push(21),
+                And => out.push(22),
+                Or => out.push(23),
+                Xor => out.push(24),
+                Not => out.push(25),
+                Shl => out.push(26),
+                Shr => out.push(27),
+                Dup => out.push(28),
+                Swap => out.push(29),
+                Pop => out.push(30),
+                Concat => out.push(33),
+                StrLen => out.push(34),
+                NewArray => out.push(35),
+                ArrayGet => out.push(36),
+                ArraySet => out.push(37),
+                ArrayLen => out.push(38),
+                Spawn(label) => put_named(&mut out, 40, label),
+                SendChannel(channel) => put_named(&mut out, 41, channel),
+                RecvChannel(channel) => put_named(&mut out, 42, channel),
+                StrEq => out.push(43),
+                StrCmp => out.push(44),
+                StartsWith => out.push(45),
+                ArraySlice => out.push(46),
+                ArrayNext(label) => put_named(&mut out, 47, label),
+                JumpIfNegPeek(label) => put_named(&mut out, 48, label),
+                JumpIfPosPeek(label) => put_named(&mut out, 49, label),
+                JumpIfZeroPeek(label) => put_named(&mut out, 50, label),
+                JumpIfNotZeroPeek(label) => put_named(&mut out, 51, label),
+                PushArg(offset) => {
+                    out.push(52);
+                    put_len(&mut out, *offset);
+                }
+                PeekFrame(offset) => {
+                    out.push(53);
+                    put_len(&mut out, *offset);
+                }
+                CallHost(name, arity) => {
+                    put_named(&mut out, 54, name);
+                    put_len(&mut out, *arity);
+                }
+                Print => out.push(55),
+                ReadInput => out.push(56),
+            }
+        }
+
+        // Sorted, so the same program always gives the same bytes.
+        let mut labels: Vec<_> = self.labels.iter().collect();
+        labels.sort();
+        put_len(&mut out, labels.len());
+        for (label, &ip) in labels {
+            put_name(&mut out, label);
+            put_len(&mut out, ip);
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Bytecode, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(DecodeError::BadMagic);
+        }
+        let version = u16::from_le_bytes(reader.array()?);
+        if version != VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let count = reader.len()?;
+        let mut instrs = Vec::new();
+        for _ in 0..count {
+            let offset = reader.offset;
+            let instr = match reader.array::<1>()?[0] {
+                tag @ (0 | 31 | 32 | 39) => LoadVal(reader.value_of(tag, offset)?),
+                1 => WriteVar(reader.name()?),
+                2 => ReadVar(reader.name()?),
+                3 => Add,
+                4 => Multiply,
+                5 => Subtract,
+                6 => Divide,
+                7 => ReturnValue,
+                8 => JumpIfNeg(reader.name()?),
+                9 => JumpIfPos(reader.name()?),
+                10 => JumpIfZero(reader.name()?),
+                11 => JumpIfNotZero(reader.name()?),
+                12 => Call(reader.name()?),
+                13 => Ret,
+                14 => Eq,
+                15 => Ne,
+                16 => Lt,
+                17 => Le,
+                18 => Gt,
+                19 => Ge,
+                20 => Modulo,
+                21 => Negate,
+                22 => And,
+                23 => Or,
+                24 => Xor,
+                25 => Not,
+                26 => Shl,
+                27 => Shr,
+                28 => Dup,
+                29 => Swap,
+                30 => Pop,
+                33 => Concat,
+                34 => StrLen,
+                35 => NewArray,
+                36 => ArrayGet,
+                37 => ArraySet,
+                38 => ArrayLen,
+                40 => Spawn(reader.name()?),
+                41 => SendChannel(reader.name()?),
+                42 => RecvChannel(reader.name()?),
+                43 => StrEq,
+                44 => StrCmp,
+                45 => StartsWith,
+                46 => ArraySlice,
+                47 => ArrayNext(reader.name()?),
+                48 => JumpIfNegPeek(reader.name()?),
+                49 => JumpIfPosPeek(reader.name()?),
+                50 => JumpIfZeroPeek(reader.name()?),
+                51 => JumpIfNotZeroPeek(reader.name()?),
+                52 => PushArg(reader.len()?),
+                53 => PeekFrame(reader.len()?),
+                54 => CallHost(reader.name()?, reader.len()?),
+                55 => Print,
+                56 => ReadInput,
+                opcode => return Err(DecodeError::UnknownOpcode { opcode, offset }),
+            };
+            instrs.push(instr);
+        }
+
+        let count = reader.len()?;
+        let mut labels = Labels::new();
+        for _ in 0..count {
+            let label = reader.name()?;
+            let ip = reader.len()?;
+            // One past the last instruction is fine, jumping there ends the program.
+            if ip > instrs.len() {
+                return Err(DecodeError::LabelOutOfRange { label, ip });
+            }
+            if labels.contains_key(&label) {
+                return Err(DecodeError::DuplicateLabel(label));
+            }
+            labels.insert(label, ip);
+        }
+
+        if reader.offset != bytes.len() {
+            return Err(DecodeError::TrailingBytes(reader.offset));
+        }
+        Ok(Bytecode { instrs, labels })
+    }
+}
+
+/// A value tagged with the opcode `LoadVal` has for it.
+pub(super) fn put_value(out: &mut Vec<u8>, val: &Value) {
+    match val {
+        Value::Int(val) => {
+            out.push(0);
+            out.extend(val.to_le_bytes());
+        }
+        Value::Float(val) => {
+            out.push(31);
+            out.extend(val.to_bits().to_le_bytes());
+        }
+        Value::Str(val) => put_named(out, 32, val),
+        Value::Array(val) => {
+            out.push(39);
+            out.extend((val.array as u64).to_le_bytes());
+        }
+    }
+}
+
+pub(super) fn put_len(out: &mut Vec<u8>, len: usize) {
+    let len = u32::try_from(len).expect("programs are far smaller than 4G entries");
+    out.extend(len.to_le_bytes());
+}
+
+fn put_named(out: &mut Vec<u8>, opcode: u8, name: &str) {
+    out.push(opcode);
+    put_name(out, name);
+}
+
+pub(super) fn put_name(out: &mut Vec<u8>, name: &str) {
+    put_len(out, name.len());
+    out.extend(name.as_bytes());
+}
+
+pub(super) struct Reader<'a> {
+    bytes: &'a [u8],
+    pub(super) offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(super) fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, offset: 0 }
+    }
+
+    /// Checks the length before slicing, so a corrupt count can't make it allocate.
+    pub(super) fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|&end| end <= self.bytes.len())
+            .ok_or(DecodeError::Truncated(self.bytes.len()))?;
+        let taken = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(taken)
+    }
+
+    pub(super) fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    pub(super) fn len(&mut self) -> Result<usize, DecodeError> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    pub(super) fn name(&mut self) -> Result<String, DecodeError> {
+        let len = self.len()?;
+        let offset = self.offset;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| DecodeError::InvalidName(offset))
+    }
+
+    pub(super) fn value(&mut self) -> Result<Value, DecodeError> {
+        let offset = self.offset;
+        let tag = self.array::<1>()?[0];
+        self.value_of(tag, offset)
+    }
+
+    /// The value after `tag`, which was read at `offset`.
+    fn value_of(&mut self, tag: u8, offset: usize) -> Result<Value, DecodeError> {
+        Ok(match tag {
+            0 => Value::Int(ValueType::from_le_bytes(self.array()?)),
+            31 => Value::Float(f64::from_bits(u64::from_le_bytes(self.array()?))),
+            32 => Value::Str(self.name()?),
+            39 => {
+                let array = u64::from_le_bytes(self.array()?);
+                let array = usize::try_from(array).unwrap_or(usize::MAX);
+                Value::Array(ArrayRef { array })
+            }
+            opcode => return Err(DecodeError::UnknownOpcode { opcode, offset }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::{
+        binary::{DecodeError, MAGIC},
+        examples::EXAMPLES,
+        run, Bytecode,
+    };
+
+    #[test]
+    fn round_trips_examples() {
+        for example in EXAMPLES {
+            let bytes = example.bytecode().to_bytes();
+            let decoded = Bytecode::from_bytes(&bytes).unwrap();
+            assert_eq!(decoded.to_bytes(), bytes, "{}", example.name);
+            assert_eq!(run(decoded), example.run(), "{}", example.name);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let bytes = EXAMPLES[0].bytecode().to_bytes();
+        for len in 0..bytes.len() {
+            assert!(
+                Bytecode::from_bytes(&bytes[..len]).is_err(),
+                "prefix {}",
+                len
+            );
+        }
+        assert!(matches!(
+            Bytecode::from_bytes(b"\x7fELF\x01\x00"),
+            Err(DecodeError::BadMagic)
+        ));
+
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        assert!(matches!(
+            Bytecode::from_bytes(&newer),
+            Err(DecodeError::UnsupportedVersion(2))
+        ));
+
+        let mut opcode = bytes.clone();
+        opcode[10] = 200;
+        assert!(matches!(
+            Bytecode::from_bytes(&opcode),
+            Err(DecodeError::UnknownOpcode {
+                opcode: 200,
+                offset: 10
+            })
+        ));
+
+        let mut past_end = bytes.clone();
+        let last = past_end.len() - 4;
+        past_end[last] = 0xff;
+        assert!(matches!(
+            Bytecode::from_bytes(&past_end),
+            Err(DecodeError::LabelOutOfRange { ip: 255, .. })
+        ));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            Bytecode::from_bytes(&trailing),
+            Err(DecodeError::TrailingBytes(_))
+        ));
+
+        let mut huge = MAGIC.to_vec();
+        huge.extend([1, 0, 1, 0, 0, 0, 1, 0xff, 0xff, 0xff, 0xff]);
+        assert!(matches!(
+            Bytecode::from_bytes(&huge),
+            Err(DecodeError::Truncated(_))
+        ));
+    }
+}
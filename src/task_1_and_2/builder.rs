@@ -0,0 +1,361 @@
+use thiserror::Error;
+
+use super::{Bytecode, Instruction, Labels, Value, ValueType};
+
+use Instruction::*;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum BuildError {
+    #[error("label '{0}' is defined twice")]
+    DuplicateLabel(String),
+
+    #[error("label '{0}' is jumped to but never defined")]
+    MissingLabel(String),
+}
+
+/// Builds [`Bytecode`] one instruction at a time, labels point at whatever comes next.
+///
+/// ```
+/// use testing::{run, task_1_and_2::builder::BytecodeBuilder, Value};
+///
+/// let bytecode = BytecodeBuilder::new()
+///     .load_val(3)
+///     .write_var("n")
+///     .label("loop")
+///     .load_val(1)
+///     .read_var("n")
+///     .subtract()
+///     .write_var("n")
+///     .read_var("n")
+///     .jump_if_pos("loop")
+///     .read_var("n")
+///     .return_value()
+///     .build()
+///     .unwrap();
+/// assert_eq!(run(bytecode), Ok(Value::Int(0)));
+/// ```
+#[derive(Debug, Default)]
+pub struct BytecodeBuilder {
+    instrs: Vec<Instruction>,
+    labels: Labels,
+    duplicate: Option<String>,
+}
+
+impl BytecodeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn label(mut self, name: &str) -> Self {
+        if self
+            .labels
+            .insert(name.to_owned(), self.instrs.len())
+            .is_some()
+        {
+            self.duplicate.get_or_insert_with(|| name.to_owned());
+        }
+        self
+    }
+
+    pub fn instr(mut self, instr: Instruction) -> Self {
+        self.instrs.push(instr);
+        self
+    }
+
+    pub fn load_val(self, val: ValueType) -> Self {
+        self.instr(LoadVal(Value::Int(val)))
+    }
+
+    pub fn load_float(self, val: f64) -> Self {
+        self.instr(LoadVal(Value::Float(val)))
+    }
+
+    pub fn load_str(self, val: &str) -> Self {
+        self.instr(LoadVal(Value::Str(val.to_owned())))
+    }
+
+    pub fn write_var(self, name: &str) -> Self {
+        self.instr(WriteVar(name.to_owned()))
+    }
+
+    pub fn read_var(self, name: &str) -> Self {
+        self.instr(ReadVar(name.to_owned()))
+    }
+
+    pub fn dup(self) -> Self {
+        self.instr(Dup)
+    }
+
+    pub fn swap(self) -> Self {
+        self.instr(Swap)
+    }
+
+    pub fn pop(self) -> Self {
+        self.instr(Pop)
+    }
+
+    pub fn add(self) -> Self {
+        self.instr(Add)
+    }
+
+    pub fn multiply(self) -> Self {
+        self.instr(Multiply)
+    }
+
+    pub fn subtract(self) -> Self {
+        self.instr(Subtract)
+    }
+
+    pub fn divide(self) -> Self {
+        self.instr(Divide)
+    }
+
+    pub fn modulo(self) -> Self {
+        self.instr(Modulo)
+    }
+
+    pub fn negate(self) -> Self {
+        self.instr(Negate)
+    }
+
+    pub fn and(self) -> Self {
+        self.instr(And)
+    }
+
+    pub fn or(self) -> Self {
+        self.instr(Or)
+    }
+
+    pub fn xor(self) -> Self {
+        self.instr(Xor)
+    }
+
+    pub fn bit_not(self) -> Self {
+        self.instr(Not)
+    }
+
+    pub fn shl(self) -> Self {
+        self.instr(Shl)
+    }
+
+    pub fn shr(self) -> Self {
+        self.instr(Shr)
+    }
+
+    pub fn eq(self) -> Self {
+        self.instr(Eq)
+    }
+
+    pub fn ne(self) -> Self {
+        self.instr(Ne)
+    }
+
+    pub fn lt(self) -> Self {
+        self.instr(Lt)
+    }
+
+    pub fn le(self) -> Self {
+        self.instr(Le)
+    }
+
+    pub fn gt(self) -> Self {
+        self.instr(Gt)
+    }
+
+    pub fn ge(self) -> Self {
+        self.instr(Ge)
+    }
+
+    pub fn concat(self) -> Self {
+        self.instr(Concat)
+    }
+
+    pub fn str_len(self) -> Self {
+        self.instr(StrLen)
+    }
+
+    pub fn str_eq(self) -> Self {
+        self.instr(StrEq)
+    }
+
+    pub fn str_cmp(self) -> Self {
+        self.instr(StrCmp)
+    }
+
+    pub fn starts_with(self) -> Self {
+        self.instr(StartsWith)
+    }
+
+    pub fn new_array(self) -> Self {
+        self.instr(NewArray)
+    }
+
+    pub fn array_get(self) -> Self {
+        self.instr(ArrayGet)
+    }
+
+    pub fn array_set(self) -> Self {
+        self.instr(ArraySet)
+    }
+
+    pub fn array_len(self) -> Self {
+        self.instr(ArrayLen)
+    }
+
+    pub fn array_slice(self) -> Self {
+        self.instr(ArraySlice)
+    }
+
+    pub fn array_next(self, label: &str) -> Self {
+        self.instr(ArrayNext(label.to_owned()))
+    }
+
+    pub fn return_value(self) -> Self {
+        self.instr(ReturnValue)
+    }
+
+    pub fn jump_if_neg(self, label: &str) -> Self {
+        self.instr(JumpIfNeg(label.to_owned()))
+    }
+
+    pub fn jump_if_pos(self, label: &str) -> Self {
+        self.instr(JumpIfPos(label.to_owned()))
+    }
+
+    pub fn jump_if_zero(self, label: &str) -> Self {
+        self.instr(JumpIfZero(label.to_owned()))
+    }
+
+    pub fn jump_if_not_zero(self, label: &str) -> Self {
+        self.instr(JumpIfNotZero(label.to_owned()))
+    }
+
+    pub fn jump_if_neg_peek(self, label: &str) -> Self {
+        self.instr(JumpIfNegPeek(label.to_owned()))
+    }
+
+    pub fn jump_if_pos_peek(self, label: &str) -> Self {
+        self.instr(JumpIfPosPeek(label.to_owned()))
+    }
+
+    pub fn jump_if_zero_peek(self, label: &str) -> Self {
+        self.instr(JumpIfZeroPeek(label.to_owned()))
+    }
+
+    pub fn jump_if_not_zero_peek(self, label: &str) -> Self {
+        self.instr(JumpIfNotZeroPeek(label.to_owned()))
+    }
+
+    pub fn call(self, label: &str) -> Self {
+        self.instr(Call(label.to_owned()))
+    }
+
+    pub fn ret(self) -> Self {
+        self.instr(Ret)
+    }
+
+    pub fn push_arg(self, offset: usize) -> Self {
+        self.instr(PushArg(offset))
+    }
+
+    pub fn peek_frame(self, offset: usize) -> Self {
+        self.instr(PeekFrame(offset))
+    }
+
+    pub fn call_host(self, name: &str, arity: usize) -> Self {
+        self.instr(CallHost(name.to_owned(), arity))
+    }
+
+    pub fn print(self) -> Self {
+        self.instr(Print)
+    }
+
+    pub fn read_input(self) -> Self {
+        self.instr(ReadInput)
+    }
+
+    pub fn spawn(self, label: &str) -> Self {
+        self.instr(Spawn(label.to_owned()))
+    }
+
+    pub fn send_channel(self, channel: &str) -> Self {
+        self.instr(SendChannel(channel.to_owned()))
+    }
+
+    pub fn recv_channel(self, channel: &str) -> Self {
+        self.instr(RecvChannel(channel.to_owned()))
+    }
+
+    /// Fails on the first label defined twice, or else on the first jump, call or spawn to
+    /// a label that was never defined.
+    pub fn build(self) -> Result<Bytecode, BuildError> {
+        if let Some(label) = self.duplicate {
+            return Err(BuildError::DuplicateLabel(label));
+        }
+        let missing = self
+            .instrs
+            .iter()
+            .filter_map(Instruction::label)
+            .find(|label| !self.labels.contains_key(*label))
+            .cloned();
+        if let Some(label) = missing {
+            return Err(BuildError::MissingLabel(label));
+        }
+        Ok(Bytecode {
+            instrs: self.instrs,
+            labels: self.labels,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::{
+        builder::{BuildError, BytecodeBuilder},
+        run, Value,
+    };
+
+    #[test]
+    fn resolves_labels_where_they_are_placed() {
+        let bytecode = BytecodeBuilder::new()
+            .load_val(1)
+            .write_var("x")
+            .load_val(2)
+            .write_var("y")
+            .load_val(3)
+            .write_var("z")
+            .label("a")
+            .read_var("x")
+            .load_val(1)
+            .add()
+            .write_var("x")
+            .load_val(1)
+            .read_var("z")
+            .subtract()
+            .write_var("z")
+            .read_var("z")
+            .jump_if_not_zero("a")
+            .read_var("x")
+            .read_var("y")
+            .multiply()
+            .return_value()
+            .build()
+            .unwrap();
+        assert_eq!(bytecode.labels["a"], 6);
+        assert_eq!(run(bytecode), Ok(Value::Int(8)));
+    }
+
+    #[test]
+    fn rejects_duplicate_and_missing_labels() {
+        let duplicate = BytecodeBuilder::new().label("a").add().label("a").build();
+        assert_eq!(
+            duplicate.unwrap_err(),
+            BuildError::DuplicateLabel("a".to_owned())
+        );
+        let missing = BytecodeBuilder::new().label("a").jump_if_zero("b").build();
+        assert_eq!(
+            missing.unwrap_err(),
+            BuildError::MissingLabel("b".to_owned())
+        );
+    }
+}
@@ -0,0 +1,435 @@
+//! A small language that compiles to [`Bytecode`], so that programs don't have to be
+//! written as stack instructions, see [`compile`].
+
+use thiserror::Error;
+
+use super::{asm, Bytecode, Instruction, Labels, Value};
+
+use Instruction::*;
+
+/// Where `source` doesn't compile, `line` and `column` count from 1.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("{line}:{column}: {kind}")]
+pub struct CompileError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: CompileErrorKind,
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum CompileErrorKind {
+    #[error("unexpected character '{0}'")]
+    UnexpectedChar(char),
+
+    #[error("'{0}' is not a number")]
+    InvalidNumber(String),
+
+    #[error("{0} is not a valid string")]
+    InvalidString(String),
+
+    #[error("expected {expected}, found {found}")]
+    Expected {
+        expected: &'static str,
+        found: String,
+    },
+}
+
+/// Binary operators from the loosest to the tightest binding.
+const LEVELS: &[&[(&str, Instruction)]] = &[
+    &[
+        ("==", Eq),
+        ("!=", Ne),
+        ("<=", Le),
+        (">=", Ge),
+        ("<", Lt),
+        (">", Gt),
+    ],
+    &[("+", Add), ("-", Subtract)],
+    &[("*", Multiply), ("/", Divide), ("%", Modulo)],
+];
+/// Longer ones first, so that `<=` isn't read as `<` and `=`.
+const PUNCTUATION: &[&str] = &[
+    "==", "!=", "<=", ">=", "=", "<", ">", "+", "-", "*", "/", "%", "(", ")", "{", "}", ";",
+];
+const KEYWORDS: &[&str] = &["while", "if", "else", "return"];
+
+/// Compiles a program like
+///
+/// ```text
+/// x = 1; y = 2
+/// while x < 10 { x = x * 2 }
+/// if x == 16 { y = 3 } else { y = 4 }
+/// return x * y
+/// ```
+///
+/// Statements are assignments, `while`, `if` with an optional `else` and `return`, any of
+/// them may end with `;`. Expressions are made of numbers, strings in double quotes,
+/// variables, parentheses and unary `-`, then `*`, `/` and `%`, which bind tighter than `+`
+/// and `-`, which bind tighter than the comparisons `==`, `!=`, `<`, `<=`, `>` and `>=`.
+/// Operators group from the left and do what the instruction of the same name does, a
+/// condition holds when it isn't 0. `//` comments run to the end of the line.
+///
+/// A program that ends without `return` fails when the run gets there.
+pub fn compile(source: &str) -> Result<Bytecode, CompileError> {
+    let mut compiler = Compiler {
+        end: end_of(source),
+        tokens: lex(source)?,
+        at: 0,
+        instrs: vec![],
+        labels: Labels::new(),
+        blocks: 0,
+    };
+    while compiler.at < compiler.tokens.len() {
+        compiler.statement()?;
+    }
+    Ok(Bytecode {
+        instrs: compiler.instrs,
+        labels: compiler.labels,
+    })
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    line: usize,
+    column: usize,
+    text: String,
+    kind: TokenKind,
+}
+
+#[derive(Debug, Clone)]
+enum TokenKind {
+    Value(Value),
+    Name(String),
+    Punct(&'static str),
+}
+
+enum Expr {
+    Value(Value),
+    Var(String),
+    Negate(Box<Expr>),
+    Binary(Instruction, Box<Expr>, Box<Expr>),
+}
+
+struct Compiler {
+    tokens: Vec<Token>,
+    at: usize,
+    /// Line and column just past the last character, where a missing token is reported.
+    end: (usize, usize),
+    instrs: Vec<Instruction>,
+    labels: Labels,
+    /// `while` and `if` statements so far, to give each its own labels.
+    blocks: usize,
+}
+
+impl Compiler {
+    fn statement(&mut self) -> Result<(), CompileError> {
+        let token = self.next("a statement")?;
+        match &token.kind {
+            TokenKind::Name(word) if word == "while" => {
+                let (start, end) = self.block_labels("while", "end");
+                self.label(&start);
+                self.condition(&end)?;
+                self.block()?;
+                self.jump(&start);
+                self.label(&end);
+            }
+            TokenKind::Name(word) if word == "if" => {
+                let (otherwise, end) = self.block_labels("else", "end");
+                self.condition(&otherwise)?;
+                self.block()?;
+                if self.eat("else") {
+                    self.jump(&end);
+                    self.label(&otherwise);
+                    self.block()?;
+                } else {
+                    self.label(&otherwise);
+                }
+                self.label(&end);
+            }
+            TokenKind::Name(word) if word == "return" => {
+                let expr = self.expression()?;
+                self.emit(expr);
+                self.instrs.push(ReturnValue);
+            }
+            TokenKind::Name(var) if !KEYWORDS.contains(&var.as_str()) => {
+                self.expect("=", "'='")?;
+                let expr = self.expression()?;
+                self.emit(expr);
+                self.instrs.push(WriteVar(var.clone()));
+            }
+            _ => return Err(unexpected(&token, "a statement")),
+        }
+        while self.eat(";") {}
+        Ok(())
+    }
+
+    /// `{`, statements up to the matching `}`, and that.
+    fn block(&mut self) -> Result<(), CompileError> {
+        self.expect("{", "'{'")?;
+        while !self.eat("}") {
+            if self.at == self.tokens.len() {
+                return Err(self.missing("'}'"));
+            }
+            self.statement()?;
+        }
+        Ok(())
+    }
+
+    /// An expression and a jump to `otherwise` when it is 0.
+    fn condition(&mut self, otherwise: &str) -> Result<(), CompileError> {
+        let expr = self.expression()?;
+        self.emit(expr);
+        self.instrs.push(JumpIfZero(otherwise.to_owned()));
+        Ok(())
+    }
+
+    fn expression(&mut self) -> Result<Expr, CompileError> {
+        self.binary(0)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, CompileError> {
+        let Some(ops) = LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut left = self.binary(level + 1)?;
+        while let Some((_, op)) = ops.iter().find(|(punct, _)| self.peek_is(punct)) {
+            self.at += 1;
+            let right = self.binary(level + 1)?;
+            left = Expr::Binary(op.clone(), Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, CompileError> {
+        if self.eat("-") {
+            return Ok(Expr::Negate(Box::new(self.unary()?)));
+        }
+        let token = self.next("an expression")?;
+        match &token.kind {
+            TokenKind::Value(val) => Ok(Expr::Value(val.clone())),
+            TokenKind::Name(var) if !KEYWORDS.contains(&var.as_str()) => Ok(Expr::Var(var.clone())),
+            TokenKind::Punct("(") => {
+                let expr = self.expression()?;
+                self.expect(")", "')'")?;
+                Ok(expr)
+            }
+            _ => Err(unexpected(&token, "an expression")),
+        }
+    }
+
+    /// Instructions leaving the value of `expr` on the stack. Operations compute `top op
+    /// below`, so the right operand goes first.
+    fn emit(&mut self, expr: Expr) {
+        match expr {
+            Expr::Value(val) => self.instrs.push(LoadVal(val)),
+            Expr::Var(var) => self.instrs.push(ReadVar(var)),
+            Expr::Negate(expr) => {
+                self.emit(*expr);
+                self.instrs.push(Negate);
+            }
+            Expr::Binary(op, left, right) => {
+                self.emit(*right);
+                self.emit(*left);
+                self.instrs.push(op);
+            }
+        }
+    }
+
+    fn block_labels(&mut self, first: &str, second: &str) -> (String, String) {
+        self.blocks += 1;
+        (
+            format!("{}{}", first, self.blocks),
+            format!("{}{}", second, self.blocks),
+        )
+    }
+
+    fn label(&mut self, label: &str) {
+        self.labels.insert(label.to_owned(), self.instrs.len());
+    }
+
+    fn jump(&mut self, label: &str) {
+        self.instrs.push(LoadVal(Value::Int(0)));
+        self.instrs.push(JumpIfZero(label.to_owned()));
+    }
+
+    fn next(&mut self, expected: &'static str) -> Result<Token, CompileError> {
+        let token = self.tokens.get(self.at).cloned();
+        self.at += 1;
+        token.ok_or_else(|| self.missing(expected))
+    }
+
+    /// Whether the next token is the punctuation or keyword `text`.
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens
+            .get(self.at)
+            .is_some_and(|token| token.text == text)
+    }
+
+    /// Takes the next token when it is `text`.
+    fn eat(&mut self, text: &str) -> bool {
+        let next = self.peek_is(text);
+        self.at += usize::from(next);
+        next
+    }
+
+    fn expect(&mut self, text: &str, expected: &'static str) -> Result<(), CompileError> {
+        let token = self.next(expected)?;
+        match token.text == text {
+            true => Ok(()),
+            false => Err(unexpected(&token, expected)),
+        }
+    }
+
+    fn missing(&self, expected: &'static str) -> CompileError {
+        CompileError {
+            line: self.end.0,
+            column: self.end.1,
+            kind: CompileErrorKind::Expected {
+                expected,
+                found: "the end".to_owned(),
+            },
+        }
+    }
+}
+
+fn unexpected(token: &Token, expected: &'static str) -> CompileError {
+    CompileError {
+        line: token.line,
+        column: token.column,
+        kind: CompileErrorKind::Expected {
+            expected,
+            found: format!("'{}'", token.text),
+        },
+    }
+}
+
+fn end_of(source: &str) -> (usize, usize) {
+    let lines = source.lines().count().max(1);
+    let last = source.lines().last().unwrap_or("");
+    (lines, last.chars().count() + 1)
+}
+
+fn lex(source: &str) -> Result<Vec<Token>, CompileError> {
+    let mut tokens = vec![];
+    for (line, text) in source.lines().enumerate() {
+        let mut from = 0;
+        while let Some(c) = text[from..].chars().next() {
+            let column = text[..from].chars().count() + 1;
+            let at = |kind| CompileError {
+                line: line + 1,
+                column,
+                kind,
+            };
+            let rest = &text[from..];
+            if c.is_whitespace() {
+                from += c.len_utf8();
+                continue;
+            }
+            if rest.starts_with("//") {
+                break;
+            }
+            let (len, kind) = if c.is_ascii_digit() {
+                let len = rest
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '.')
+                    .unwrap_or(rest.len());
+                let val = rest[..len]
+                    .parse()
+                    .map_err(|_| at(CompileErrorKind::InvalidNumber(rest[..len].to_owned())))?;
+                (len, TokenKind::Value(val))
+            } else if c.is_alphabetic() || c == '_' {
+                let len = rest
+                    .find(|c: char| !c.is_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                (len, TokenKind::Name(rest[..len].to_owned()))
+            } else if c == '"' {
+                let len = string_len(rest);
+                let val = asm::unquote(&rest[..len])
+                    .ok_or_else(|| at(CompileErrorKind::InvalidString(rest[..len].to_owned())))?;
+                (len, TokenKind::Value(Value::Str(val)))
+            } else {
+                let punct = PUNCTUATION
+                    .iter()
+                    .find(|punct| rest.starts_with(*punct))
+                    .ok_or_else(|| at(CompileErrorKind::UnexpectedChar(c)))?;
+                (punct.len(), TokenKind::Punct(punct))
+            };
+            tokens.push(Token {
+                line: line + 1,
+                column,
+                text: rest[..len].to_owned(),
+                kind,
+            });
+            from += len;
+        }
+    }
+    Ok(tokens)
+}
+
+/// Bytes of the string `rest` starts with up to its closing quote, or all of `rest` when
+/// it isn't closed.
+fn string_len(rest: &str) -> usize {
+    let mut escaped = false;
+    for (at, c) in rest.char_indices().skip(1) {
+        match (escaped, c) {
+            (true, _) => escaped = false,
+            (false, '\\') => escaped = true,
+            (false, '"') => return at + 1,
+            _ => {}
+        }
+    }
+    rest.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::{
+        compiler::{compile, CompileError, CompileErrorKind},
+        run, Value,
+    };
+
+    #[test]
+    fn compiles_programs_that_run() {
+        let bytecode = compile(
+            "x = 1; y = 2\n\
+             while x < 10 { x = x * 2 }  // 16\n\
+             if x == 16 { y = 3 } else { y = 4 }\n\
+             return (x - 1) * y - -y % 2",
+        )
+        .unwrap();
+        assert_eq!(run(bytecode), Ok(Value::Int(46)));
+
+        let bytecode = compile("s = \"a b\"; if 2 > 3 { return 1 } return s").unwrap();
+        assert_eq!(run(bytecode), Ok(Value::Str("a b".to_owned())));
+        assert_eq!(run(compile("return 7 - 2 - 1").unwrap()), Ok(Value::Int(4)));
+    }
+
+    #[test]
+    fn reports_where_it_fails() {
+        let kind = |source| compile(source).unwrap_err().kind;
+        assert_eq!(kind("x = 1 $"), CompileErrorKind::UnexpectedChar('$'));
+        assert_eq!(kind("x = 1x"), CompileErrorKind::InvalidNumber("1x".into()));
+        assert_eq!(
+            kind("x = \"ab"),
+            CompileErrorKind::InvalidString("\"ab".into())
+        );
+        assert_eq!(
+            compile("x = 1\nwhile x { x = 0\n").unwrap_err(),
+            CompileError {
+                line: 2,
+                column: 16,
+                kind: CompileErrorKind::Expected {
+                    expected: "'}'",
+                    found: "the end".to_owned()
+                }
+            }
+        );
+        assert_eq!(
+            compile("x = (1 + 2;").unwrap_err().to_string(),
+            "1:11: expected ')', found ';'"
+        );
+        assert_eq!(
+            compile("return = 2").unwrap_err().to_string(),
+            "1:8: expected an expression, found '='"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use thiserror::Error;
+
+use super::{program::Program, InterpretationError, Value};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum CsvError {
+    #[error("the input has no header row")]
+    NoHeader,
+
+    #[error("row {row}: expected {expected} fields, found {found}")]
+    FieldCount {
+        row: usize,
+        expected: usize,
+        found: usize,
+    },
+
+    #[error("row {0}: unterminated quote")]
+    UnterminatedQuote(usize),
+}
+
+/// The transformed table and the rows the program failed on, rows count from 1 after
+/// the header.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Transformed {
+    pub csv: String,
+    pub failed: Vec<(usize, InterpretationError)>,
+}
+
+/// Runs `program` once per row of a CSV table with every field in the variable its header
+/// names, and appends the result as `column`.
+///
+/// Fields with a fraction or exponent become floats, other numbers ints and everything
+/// else strings. `inf` and `NaN` stay strings, a digit is needed to count as a number.
+/// Rows the program fails on
+/// get an empty cell and are listed in `failed`.
+pub fn transform(program: &Program, input: &str, column: &str) -> Result<Transformed, CsvError> {
+    let mut rows = input.lines().filter(|line| !line.trim().is_empty());
+    let header = parse_row(rows.next().ok_or(CsvError::NoHeader)?, 0)?;
+    let names: Vec<_> = header.iter().map(|name| name.trim()).collect();
+
+    let mut out = String::new();
+    write_row(&mut out, header.iter().map(String::as_str).chain([column]));
+    let mut failed = vec![];
+    for (i, line) in rows.enumerate() {
+        let row = i + 1;
+        let fields = parse_row(line, row)?;
+        if fields.len() != names.len() {
+            return Err(CsvError::FieldCount {
+                row,
+                expected: names.len(),
+                found: fields.len(),
+            });
+        }
+        let inputs: Vec<_> = names
+            .iter()
+            .zip(&fields)
+            .map(|(&name, value)| (name, field_value(value.trim())))
+            .collect();
+
+        let result = match program.run_with(&inputs) {
+            Ok(value) => value.to_string(),
+            Err(err) => {
+                failed.push((row, err));
+                String::new()
+            }
+        };
+        write_row(
+            &mut out,
+            fields.iter().map(String::as_str).chain([result.as_str()]),
+        );
+    }
+    Ok(Transformed { csv: out, failed })
+}
+
+fn field_value(field: &str) -> Value {
+    let number = field.bytes().any(|b| b.is_ascii_digit());
+    match field.parse() {
+        Ok(value) if number => value,
+        _ => Value::Str(field.to_owned()),
+    }
+}
+
+/// Comma separated fields, `"` quotes a field and `""` is a quote inside one.
+fn parse_row(line: &str, row: usize) -> Result<Vec<String>, CsvError> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(CsvError::UnterminatedQuote(row));
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+fn write_row<'a>(out: &mut String, fields: impl Iterator<Item = &'a str>) {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        if field.contains([',', '"']) {
+            out.push('"');
+            out.push_str(&field.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(field);
+        }
+    }
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::{
+        asm,
+        csv::{transform, CsvError},
+        program::Program,
+        InterpretationError,
+    };
+
+    #[test]
+    fn appends_a_result_column() {
+        let program =
+            Program::new(asm::parse("ReadVar price\nReadVar qty\nMultiply\nReturnValue").unwrap());
+        let input = "name,qty,price\n\"nuts, \"\"salted\"\"\",3,20\nbolts,2,5\n";
+        let out = transform(&program, input, "total").unwrap();
+        assert_eq!(
+            out.csv,
+            "name,qty,price,total\n\"nuts, \"\"salted\"\"\",3,20,60\nbolts,2,5,10\n"
+        );
+        assert!(out.failed.is_empty());
+        assert_eq!(
+            transform(&program, "qty\n\"3\n", "total"),
+            Err(CsvError::UnterminatedQuote(1))
+        );
+    }
+
+    #[test]
+    fn leaves_failed_rows_empty() {
+        let program =
+            Program::new(asm::parse("ReadVar b\nReadVar a\nDivide\nReturnValue").unwrap());
+        let out = transform(&program, "a,b\n10,2\n1,0\n", "q").unwrap();
+        assert_eq!(out.csv, "a,b,q\n10,2,5\n1,0,\n");
+        assert_eq!(
+            out.failed,
+            vec![(2, InterpretationError::DivisionByZero { ip: 2 })]
+        );
+        assert_eq!(
+            transform(&program, "a,b\n1\n", "q"),
+            Err(CsvError::FieldCount {
+                row: 1,
+                expected: 2,
+                found: 1
+            })
+        );
+    }
+
+    #[test]
+    fn binds_other_fields_as_strings() {
+        let program = Program::new(
+            asm::parse("ReadVar name\nLoadVal \": \"\nConcat\nReadVar qty\nConcat\nReturnValue")
+                .unwrap(),
+        );
+        let out = transform(&program, "name,qty\nnuts,1.5\nNaN,2\n", "label").unwrap();
+        assert_eq!(
+            out.csv,
+            "name,qty,label\nnuts,1.5,1.5: nuts\nNaN,2,2: NaN\n"
+        );
+    }
+}
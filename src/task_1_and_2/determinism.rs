@@ -0,0 +1,68 @@
+use std::thread;
+
+use thiserror::Error;
+
+use super::{trace::Trace, Bytecode, InterpretationError, Value, VmConfig};
+
+/// The first run that didn't match the first one.
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("run {run} differs from run 1 in its {what}")]
+pub struct Divergence {
+    pub run: usize,
+    pub what: &'static str,
+}
+
+/// Runs `bytecode` `runs` times, at least once, and checks every run gives the same result and trace.
+///
+/// Each run gets its own thread and a differently sized heap allocation held during it,
+/// so anything depending on addresses or thread state would show up. The instruction set
+/// has no clock or randomness and no host functions are registered, so there is nothing
+/// else to vary yet. `ReadInput` reads stdin, which the first run uses up.
+pub fn check(
+    bytecode: &Bytecode,
+    runs: usize,
+    config: &VmConfig,
+) -> Result<Result<Value, InterpretationError>, Divergence> {
+    let record = |run: usize| {
+        let bytecode = bytecode.clone();
+        let config = *config;
+        let (result, trace) = thread::spawn(move || {
+            let ballast = vec![0u8; run * 4096];
+            let recorded = Trace::record("check", bytecode, &config);
+            drop(ballast);
+            recorded
+        })
+        .join()
+        .expect("the VM doesn't panic");
+        (result, trace.to_json())
+    };
+
+    let (result, trace) = record(1);
+    for run in 2..=runs {
+        let (other_result, other_trace) = record(run);
+        if other_result != result {
+            return Err(Divergence {
+                run,
+                what: "result",
+            });
+        }
+        if other_trace != trace {
+            return Err(Divergence { run, what: "trace" });
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::{determinism::check, examples::find, Value, VmConfig};
+
+    #[test]
+    fn examples_are_deterministic() {
+        let bytecode = find("gcd").unwrap().bytecode();
+        assert_eq!(
+            check(&bytecode, 5, &VmConfig::default()),
+            Ok(Ok(Value::Int(6)))
+        );
+    }
+}
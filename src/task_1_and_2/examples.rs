@@ -0,0 +1,239 @@
+use std::fmt::Write as _;
+
+use super::{builder::BytecodeBuilder, run, Bytecode, Instruction, InterpretationError, Value};
+
+use Instruction::*;
+use Line::{Instr, Label};
+
+/// An annotated program shipped with the binary, for learning the instruction set.
+pub struct Example {
+    pub name: &'static str,
+    pub summary: &'static str,
+    listing: fn() -> Vec<Line>,
+}
+
+/// One line of a listing, labels mark the position of the next instruction.
+enum Line {
+    Label(&'static str),
+    Instr(Instruction, &'static str),
+}
+
+fn var(name: &str) -> String {
+    name.to_owned()
+}
+
+pub const EXAMPLES: &[Example] = &[
+    Example {
+        name: "factorial",
+        summary: "5! by multiplying a running product, counting n down to zero",
+        listing: factorial,
+    },
+    Example {
+        name: "fibonacci",
+        summary: "the first 13 Fibonacci numbers in an array, each the sum of the two before",
+        listing: fibonacci,
+    },
+    Example {
+        name: "gcd",
+        summary: "greatest common divisor of 48 and 18 by repeated subtraction",
+        listing: gcd,
+    },
+    Example {
+        name: "sum",
+        summary: "1 + 2 + ... + 10 with a loop",
+        listing: sum,
+    },
+];
+
+pub fn find(name: &str) -> Option<&'static Example> {
+    EXAMPLES.iter().find(|example| example.name == name)
+}
+
+impl Example {
+    /// The program as text, one instruction per line with its comment.
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+        for line in (self.listing)() {
+            let _ = match line {
+                Label(label) => writeln!(out, "{}:", label),
+                Instr(instr, "") => writeln!(out, "    {}", instr),
+                Instr(instr, comment) => {
+                    writeln!(out, "    {:<20} ; {}", instr.to_string(), comment)
+                }
+            };
+        }
+        out
+    }
+
+    pub fn run(&self) -> Result<Value, InterpretationError> {
+        run(self.bytecode())
+    }
+
+    pub fn bytecode(&self) -> Bytecode {
+        let mut builder = BytecodeBuilder::new();
+        for line in (self.listing)() {
+            builder = match line {
+                Label(label) => builder.label(label),
+                Instr(instr, _) => builder.instr(instr),
+            };
+        }
+        builder.build().expect("examples have valid labels")
+    }
+}
+
+fn factorial() -> Vec<Line> {
+    vec![
+        Instr(LoadVal(Value::Int(5)), "n = 5"),
+        Instr(WriteVar(var("n")), ""),
+        Instr(LoadVal(Value::Int(1)), "acc = 1"),
+        Instr(WriteVar(var("acc")), ""),
+        Label("loop"),
+        Instr(ReadVar(var("acc")), "acc = acc * n"),
+        Instr(ReadVar(var("n")), ""),
+        Instr(Multiply, ""),
+        Instr(WriteVar(var("acc")), ""),
+        Instr(
+            LoadVal(Value::Int(1)),
+            "n = n - 1, Subtract takes the top minus the one below",
+        ),
+        Instr(ReadVar(var("n")), ""),
+        Instr(Subtract, ""),
+        Instr(WriteVar(var("n")), ""),
+        Instr(ReadVar(var("n")), "again while n != 0"),
+        Instr(JumpIfNotZero(var("loop")), ""),
+        Instr(ReadVar(var("acc")), "120"),
+        Instr(ReturnValue, ""),
+    ]
+}
+
+fn fibonacci() -> Vec<Line> {
+    vec![
+        Instr(LoadVal(Value::Int(13)), "fib = an array of 13 zeros"),
+        Instr(NewArray, ""),
+        Instr(WriteVar(var("fib")), ""),
+        Instr(
+            LoadVal(Value::Int(1)),
+            "fib[1] = 1, ArraySet pops the array, the index and the value",
+        ),
+        Instr(LoadVal(Value::Int(1)), ""),
+        Instr(ReadVar(var("fib")), ""),
+        Instr(ArraySet, ""),
+        Instr(LoadVal(Value::Int(2)), "i = 2"),
+        Instr(WriteVar(var("i")), ""),
+        Label("loop"),
+        Instr(LoadVal(Value::Int(1)), "fib[i - 1]"),
+        Instr(ReadVar(var("i")), ""),
+        Instr(Subtract, ""),
+        Instr(ReadVar(var("fib")), ""),
+        Instr(ArrayGet, ""),
+        Instr(LoadVal(Value::Int(2)), "+ fib[i - 2]"),
+        Instr(ReadVar(var("i")), ""),
+        Instr(Subtract, ""),
+        Instr(ReadVar(var("fib")), ""),
+        Instr(ArrayGet, ""),
+        Instr(Add, ""),
+        Instr(ReadVar(var("i")), "goes into fib[i]"),
+        Instr(ReadVar(var("fib")), ""),
+        Instr(ArraySet, ""),
+        Instr(LoadVal(Value::Int(1)), "i = i + 1"),
+        Instr(ReadVar(var("i")), ""),
+        Instr(Add, ""),
+        Instr(WriteVar(var("i")), ""),
+        Instr(ReadVar(var("i")), "again while the length - i > 0"),
+        Instr(ReadVar(var("fib")), ""),
+        Instr(ArrayLen, ""),
+        Instr(Subtract, ""),
+        Instr(JumpIfPos(var("loop")), ""),
+        Instr(LoadVal(Value::Int(12)), "fib[12] is 144"),
+        Instr(ReadVar(var("fib")), ""),
+        Instr(ArrayGet, ""),
+        Instr(ReturnValue, ""),
+    ]
+}
+
+fn gcd() -> Vec<Line> {
+    vec![
+        Instr(LoadVal(Value::Int(48)), "a = 48"),
+        Instr(WriteVar(var("a")), ""),
+        Instr(LoadVal(Value::Int(18)), "b = 18"),
+        Instr(WriteVar(var("b")), ""),
+        Label("loop"),
+        Instr(ReadVar(var("b")), "d = a - b"),
+        Instr(ReadVar(var("a")), ""),
+        Instr(Subtract, ""),
+        Instr(WriteVar(var("d")), ""),
+        Instr(ReadVar(var("d")), "a == b, that's the answer"),
+        Instr(JumpIfZero(var("done")), ""),
+        Instr(ReadVar(var("d")), "b is the larger one"),
+        Instr(JumpIfNeg(var("shrink_b")), ""),
+        Instr(ReadVar(var("d")), "a = a - b"),
+        Instr(WriteVar(var("a")), ""),
+        Instr(
+            LoadVal(Value::Int(0)),
+            "there is no plain jump, test a zero instead",
+        ),
+        Instr(JumpIfZero(var("loop")), ""),
+        Label("shrink_b"),
+        Instr(ReadVar(var("a")), "b = b - a"),
+        Instr(ReadVar(var("b")), ""),
+        Instr(Subtract, ""),
+        Instr(WriteVar(var("b")), ""),
+        Instr(LoadVal(Value::Int(0)), ""),
+        Instr(JumpIfZero(var("loop")), ""),
+        Label("done"),
+        Instr(ReadVar(var("a")), "6"),
+        Instr(ReturnValue, ""),
+    ]
+}
+
+fn sum() -> Vec<Line> {
+    vec![
+        Instr(LoadVal(Value::Int(10)), "n = 10"),
+        Instr(WriteVar(var("n")), ""),
+        Instr(LoadVal(Value::Int(0)), "total = 0"),
+        Instr(WriteVar(var("total")), ""),
+        Label("loop"),
+        Instr(ReadVar(var("total")), "total = total + n"),
+        Instr(ReadVar(var("n")), ""),
+        Instr(Add, ""),
+        Instr(WriteVar(var("total")), ""),
+        Instr(LoadVal(Value::Int(1)), "n = n - 1"),
+        Instr(ReadVar(var("n")), ""),
+        Instr(Subtract, ""),
+        Instr(WriteVar(var("n")), ""),
+        Instr(ReadVar(var("n")), "again while n > 0"),
+        Instr(JumpIfPos(var("loop")), ""),
+        Instr(ReadVar(var("total")), "55"),
+        Instr(ReturnValue, ""),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::{
+        examples::{find, EXAMPLES},
+        Value,
+    };
+
+    #[test]
+    fn examples_compute_what_they_say() {
+        let results: Vec<_> = EXAMPLES.iter().map(|e| (e.name, e.run())).collect();
+        assert_eq!(
+            results,
+            vec![
+                ("factorial", Ok(Value::Int(120))),
+                ("fibonacci", Ok(Value::Int(144))),
+                ("gcd", Ok(Value::Int(6))),
+                ("sum", Ok(Value::Int(55)))
+            ]
+        );
+    }
+
+    #[test]
+    fn listing_shows_labels_and_comments() {
+        let listing = find("sum").unwrap().listing();
+        assert!(listing.starts_with("    LoadVal 10           ; n = 10\n"));
+        assert!(listing.contains("\nloop:\n"));
+        assert!(find("fizzbuzz").is_none());
+    }
+}
@@ -0,0 +1,117 @@
+//! Metering runs by what their instructions cost instead of how many there are, see
+//! [`VmConfig::gas`](super::VmConfig::gas).
+
+use super::Instruction;
+
+/// A budget a run spends as it goes, running out is `OutOfGas`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gas {
+    pub limit: u64,
+    pub costs: GasTable,
+}
+
+impl Gas {
+    /// `limit` with the default costs.
+    pub fn new(limit: u64) -> Self {
+        Gas {
+            limit,
+            costs: GasTable::default(),
+        }
+    }
+}
+
+/// What each kind of instruction costs.
+///
+/// By default 1, arithmetic that takes longer than an addition and instructions that
+/// allocate, call or switch contexts cost more.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasTable {
+    costs: [u64; Instruction::NAMES.len()],
+}
+
+impl Default for GasTable {
+    fn default() -> Self {
+        let mut table = GasTable::uniform(1);
+        for (name, cost) in [
+            ("Multiply", 2),
+            ("Divide", 4),
+            ("Modulo", 4),
+            ("Concat", 4),
+            ("NewArray", 8),
+            ("ArraySlice", 8),
+            ("ArrayGet", 2),
+            ("ArraySet", 2),
+            ("Call", 4),
+            ("CallHost", 4),
+            ("Spawn", 16),
+            ("SendChannel", 2),
+            ("RecvChannel", 2),
+        ] {
+            table.set(name, cost);
+        }
+        table
+    }
+}
+
+impl GasTable {
+    /// Every instruction costs `cost`, 1 meters like `max_ops`.
+    pub const fn uniform(cost: u64) -> Self {
+        GasTable {
+            costs: [cost; Instruction::NAMES.len()],
+        }
+    }
+
+    pub fn cost(&self, instr: &Instruction) -> u64 {
+        self.costs[instr.opcode()]
+    }
+
+    /// Sets the cost of the instructions with mnemonic `name`, false for an unknown one.
+    pub fn set(&mut self, name: &str, cost: u64) -> bool {
+        match Instruction::NAMES.iter().position(|&known| known == name) {
+            Some(opcode) => {
+                self.costs[opcode] = cost;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::{
+        asm,
+        gas::{Gas, GasTable},
+        InterpretationError, Stop, Value, Vm, VmConfig,
+    };
+
+    #[test]
+    fn charges_each_instruction_its_cost() {
+        let bytecode = asm::parse("LoadVal 2\nLoadVal 8\nDivide\nReturnValue").unwrap();
+        let config = |limit| VmConfig {
+            gas: Some(Gas::new(limit)),
+            ..VmConfig::default()
+        };
+        let mut vm = Vm::new(&bytecode, config(10));
+        assert_eq!(vm.gas_left(), Some(10));
+        assert_eq!(vm.run(), Ok(Stop::Returned(Value::Int(4))));
+        assert_eq!(vm.gas_left(), Some(3));
+
+        // LoadVal, LoadVal, then 1 left for a Divide that costs 4.
+        let mut vm = Vm::new(&bytecode, config(3));
+        assert_eq!(vm.run(), Err(InterpretationError::OutOfGas(2)));
+        assert_eq!(vm.gas_left(), Some(1));
+
+        let mut costs = GasTable::uniform(1);
+        assert!(costs.set("Divide", 10));
+        assert!(!costs.set("Sqrt", 10));
+        let config = VmConfig {
+            gas: Some(Gas { limit: 13, costs }),
+            ..VmConfig::default()
+        };
+        let mut vm = Vm::new(&bytecode, config);
+        assert!(vm.run().is_ok());
+        assert_eq!(vm.gas_left(), Some(0));
+        assert_eq!(Vm::new(&bytecode, VmConfig::default()).gas_left(), None);
+    }
+}
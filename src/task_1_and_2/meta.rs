@@ -0,0 +1,92 @@
+//! What is known about instructions without running them, for tools that look at
+//! programs, see [`Bytecode::iter`].
+
+use super::{Bytecode, Instruction, IpType};
+
+use Instruction::*;
+
+/// How an instruction uses the stack and whether it may go on elsewhere than the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstrMeta {
+    /// Values it pops.
+    pub stack_in: usize,
+    /// Values it pushes.
+    pub stack_out: usize,
+    /// Jumps, `ArrayNext`, `Call` and `Ret`. `ReturnValue` ends the run or context instead.
+    pub may_jump: bool,
+}
+
+impl Instruction {
+    /// `ArrayNext` counts as pushing its three values, past the end of the array it
+    /// pushes none and jumps. A `Call` or `Ret` leaves the stack to the code it goes to.
+    pub fn meta(&self) -> InstrMeta {
+        let (stack_in, stack_out) = match self {
+            LoadVal(_) | ReadVar(_) | RecvChannel(_) | PushArg(_) | PeekFrame(_) | ReadInput => {
+                (0, 1)
+            }
+            WriteVar(_) | Pop | SendChannel(_) | ReturnValue | Print => (1, 0),
+            JumpIfNeg(_) | JumpIfPos(_) | JumpIfZero(_) | JumpIfNotZero(_) => (1, 0),
+            Dup => (1, 2),
+            JumpIfNegPeek(_) | JumpIfPosPeek(_) | JumpIfZeroPeek(_) | JumpIfNotZeroPeek(_) => {
+                (1, 1)
+            }
+            Swap => (2, 2),
+            Negate | Not | StrLen | NewArray | ArrayLen => (1, 1),
+            Add | Multiply | Subtract | Divide | Modulo | And | Or | Xor | Shl | Shr | Eq | Ne
+            | Lt | Le | Gt | Ge | Concat | StrEq | StrCmp | StartsWith | ArrayGet => (2, 1),
+            ArraySet => (3, 0),
+            ArraySlice => (3, 1),
+            ArrayNext(_) => (2, 3),
+            Call(_) | Ret | Spawn(_) => (0, 0),
+            CallHost(_, arity) => (*arity, 1),
+        };
+        InstrMeta {
+            stack_in,
+            stack_out,
+            may_jump: matches!(self, Ret) || self.label().is_some() && !matches!(self, Spawn(_)),
+        }
+    }
+}
+
+impl Bytecode {
+    /// Each instruction with its IP and [`InstrMeta`].
+    pub fn iter(&self) -> impl Iterator<Item = (IpType, &Instruction, InstrMeta)> + '_ {
+        self.instrs
+            .iter()
+            .enumerate()
+            .map(|(ip, instr)| (ip, instr, instr.meta()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::{asm, meta::InstrMeta};
+
+    #[test]
+    fn describes_each_instruction() {
+        let bytecode = asm::parse(
+            "start:\nLoadVal 1\nDup\nJumpIfZeroPeek start\nSpawn start\nCallHost f 2\nRet",
+        )
+        .unwrap();
+        let metas: Vec<_> = bytecode
+            .iter()
+            .map(|(ip, instr, meta)| (ip, instr.name(), meta))
+            .collect();
+        let meta = |stack_in, stack_out, may_jump| InstrMeta {
+            stack_in,
+            stack_out,
+            may_jump,
+        };
+        assert_eq!(
+            metas,
+            [
+                (0, "LoadVal", meta(0, 1, false)),
+                (1, "Dup", meta(1, 2, false)),
+                (2, "JumpIfZeroPeek", meta(1, 1, true)),
+                (3, "Spawn", meta(0, 0, false)),
+                (4, "CallHost", meta(2, 1, false)),
+                (5, "Ret", meta(0, 0, true)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,382 @@
+//! A peephole pass over programs, see [`Bytecode::optimize`].
+
+use std::{collections::BTreeSet, fmt};
+
+use super::{run, Bytecode, Instruction, IpType, LabelName, Labels, Value};
+
+use Instruction::*;
+
+/// How much [`Bytecode::optimize`] shrank a program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Optimization {
+    pub before: usize,
+    pub after: usize,
+}
+
+/// `12 -> 8 instructions`
+impl fmt::Display for Optimization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} -> {} instructions", self.before, self.after)
+    }
+}
+
+impl Bytecode {
+    /// Rewrites short runs of instructions into fewer doing the same, until none are left:
+    ///
+    /// - an operation on values loaded right before it becomes a `LoadVal` of the result,
+    /// - a `LoadVal` whose value is popped right away goes,
+    /// - a jump on a loaded value goes when it is never taken or lands on the next
+    ///   instruction anyway,
+    /// - a jump to a jump that is always taken goes straight to where that one leads,
+    /// - a `Dup` right before a jump becomes the `Peek` form of the jump,
+    ///
+    /// and then drops the code no run gets to, see [`Bytecode::strip_unreachable`].
+    ///
+    /// Each result is worked out by the VM, so an operation that would fail, like a division
+    /// by zero, is kept to fail at run time. Runs a label points into the middle of are left
+    /// alone. `Concat` isn't folded, the strings it builds are checked against the limits of
+    /// the run.
+    pub fn optimize(&mut self) -> Optimization {
+        let before = self.instrs.len();
+        while self.thread_jumps() | self.rewrite() {}
+        self.strip_unreachable();
+        Optimization {
+            before,
+            after: self.instrs.len(),
+        }
+    }
+
+    /// Which instructions a run can get to, by IP: from the start along every jump, call and
+    /// spawn as well as past it, but not past a return.
+    pub fn reachable(&self) -> Vec<bool> {
+        let mut reached = vec![false; self.instrs.len()];
+        let mut pending = vec![0];
+        while let Some(ip) = pending.pop() {
+            let Some(instr) = self.instrs.get(ip) else {
+                continue;
+            };
+            if std::mem::replace(&mut reached[ip], true) {
+                continue;
+            }
+            match instr {
+                ReturnValue | Ret => {}
+                JumpIfNeg(label)
+                | JumpIfPos(label)
+                | JumpIfZero(label)
+                | JumpIfNotZero(label)
+                | JumpIfNegPeek(label)
+                | JumpIfPosPeek(label)
+                | JumpIfZeroPeek(label)
+                | JumpIfNotZeroPeek(label)
+                | Call(label)
+                | Spawn(label)
+                | ArrayNext(label) => {
+                    pending.extend(self.labels.get(label));
+                    pending.push(ip + 1);
+                }
+                _ => pending.push(ip + 1),
+            }
+        }
+        reached
+    }
+
+    /// Drops the instructions [`Bytecode::reachable`] leaves out, so they don't count towards
+    /// the size of the program. A label pointing at one moves to the next instruction kept.
+    pub fn strip_unreachable(&mut self) -> Optimization {
+        let before = self.instrs.len();
+        let reached = self.reachable();
+        let mut moved = Vec::with_capacity(before + 1);
+        let old = std::mem::take(&mut self.instrs);
+        for (instr, reached) in old.into_iter().zip(reached) {
+            moved.push(self.instrs.len());
+            if reached {
+                self.instrs.push(instr);
+            }
+        }
+        moved.push(self.instrs.len());
+        self.relabel(&moved);
+        Optimization {
+            before,
+            after: self.instrs.len(),
+        }
+    }
+
+    /// Points the labels at where `moved` says their instructions went, by old IP.
+    fn relabel(&mut self, moved: &[IpType]) {
+        for target in self.labels.values_mut() {
+            if let Some(&to) = moved.get(*target) {
+                *target = to;
+            }
+        }
+    }
+
+    /// One pass of the rewrites that drop instructions, whether any applied.
+    fn rewrite(&mut self) -> bool {
+        let targets: BTreeSet<IpType> = self.labels.values().copied().collect();
+        let old = std::mem::take(&mut self.instrs);
+        // Where each old IP ends up, one past the end included.
+        let mut moved = Vec::with_capacity(old.len() + 1);
+        let mut changed = false;
+        let mut ip = 0;
+        while ip < old.len() {
+            // How far the window may reach without a label pointing into it.
+            let free = (ip + 1..old.len().min(ip + 3))
+                .take_while(|next| !targets.contains(next))
+                .count()
+                + 1;
+            let (replaced, with) = match self.peephole(&old[ip..ip + free], ip) {
+                Some(rewrite) => rewrite,
+                None => (1, vec![old[ip].clone()]),
+            };
+            changed |= replaced > 1 || with.len() != 1;
+            moved.extend(std::iter::repeat_n(self.instrs.len(), replaced));
+            self.instrs.extend(with);
+            ip += replaced;
+        }
+        moved.push(self.instrs.len());
+        self.relabel(&moved);
+        changed
+    }
+
+    /// How many of `window`'s instructions to replace and with what, `window` starting at `ip`.
+    fn peephole(&self, window: &[Instruction], ip: IpType) -> Option<(usize, Vec<Instruction>)> {
+        match window {
+            [LoadVal(a), LoadVal(b), op, ..] if foldable(a) && foldable(b) && binary(op) => {
+                let val = evaluate(vec![LoadVal(a.clone()), LoadVal(b.clone()), op.clone()])?;
+                Some((3, vec![LoadVal(val)]))
+            }
+            [LoadVal(a), op @ (Negate | Not | StrLen), ..] if foldable(a) => {
+                let val = evaluate(vec![LoadVal(a.clone()), op.clone()])?;
+                Some((2, vec![LoadVal(val)]))
+            }
+            [LoadVal(_), Pop, ..] => Some((2, vec![])),
+            [Dup, JumpIfNeg(label), ..] => Some((2, vec![JumpIfNegPeek(label.clone())])),
+            [Dup, JumpIfPos(label), ..] => Some((2, vec![JumpIfPosPeek(label.clone())])),
+            [Dup, JumpIfZero(label), ..] => Some((2, vec![JumpIfZeroPeek(label.clone())])),
+            [Dup, JumpIfNotZero(label), ..] => Some((2, vec![JumpIfNotZeroPeek(label.clone())])),
+            [LoadVal(cond), jump @ (JumpIfNeg(label) | JumpIfPos(label) | JumpIfZero(label)
+            | JumpIfNotZero(label)), ..] => {
+                let lands_next = self.labels.get(label) == Some(&(ip + 2));
+                match taken(cond, jump)? {
+                    false => Some((2, vec![])),
+                    true if lands_next => Some((2, vec![])),
+                    true => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Points jumps and calls at a label holding an always taken jump to where that one
+    /// leads, whether any changed. Chains are followed to their end, cycles left alone.
+    fn thread_jumps(&mut self) -> bool {
+        let mut changed = false;
+        for ip in 0..self.instrs.len() {
+            let (JumpIfNeg(label)
+            | JumpIfPos(label)
+            | JumpIfZero(label)
+            | JumpIfNotZero(label)
+            | JumpIfNegPeek(label)
+            | JumpIfPosPeek(label)
+            | JumpIfZeroPeek(label)
+            | JumpIfNotZeroPeek(label)
+            | Call(label)
+            | ArrayNext(label)) = &self.instrs[ip]
+            else {
+                continue;
+            };
+            if let Some(end) = self.chain_end(label) {
+                match &mut self.instrs[ip] {
+                    JumpIfNeg(label)
+                    | JumpIfPos(label)
+                    | JumpIfZero(label)
+                    | JumpIfNotZero(label)
+                    | JumpIfNegPeek(label)
+                    | JumpIfPosPeek(label)
+                    | JumpIfZeroPeek(label)
+                    | JumpIfNotZeroPeek(label)
+                    | Call(label)
+                    | ArrayNext(label) => *label = end,
+                    _ => unreachable!("matched above"),
+                }
+                changed = true;
+            }
+        }
+        changed
+    }
+
+    /// The label an always taken jump at `label` leads to in the end, `None` when there is
+    /// no such jump or the chain is a cycle.
+    fn chain_end(&self, label: &LabelName) -> Option<LabelName> {
+        let mut seen = BTreeSet::new();
+        let mut at = label.clone();
+        loop {
+            let next = self.always_jumps(&at);
+            let Some(next) = next else {
+                return (at != *label).then_some(at);
+            };
+            if !seen.insert(at) {
+                return None;
+            }
+            at = next;
+        }
+    }
+
+    /// The label of the always taken jump `label` points at, if that's what is there.
+    fn always_jumps(&self, label: &LabelName) -> Option<LabelName> {
+        let &ip = self.labels.get(label)?;
+        match self.instrs.get(ip..ip + 2)? {
+            [LoadVal(cond), jump] if taken(cond, jump)? => {
+                let (JumpIfNeg(next) | JumpIfPos(next) | JumpIfZero(next) | JumpIfNotZero(next)) =
+                    jump
+                else {
+                    unreachable!("only jumps are taken");
+                };
+                self.labels.contains_key(next).then(|| next.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Arrays stand for a run's heap, so an operation on one can't be worked out beforehand.
+fn foldable(val: &Value) -> bool {
+    !matches!(val, Value::Array(_))
+}
+
+fn binary(op: &Instruction) -> bool {
+    matches!(
+        op,
+        Add | Multiply
+            | Subtract
+            | Divide
+            | Modulo
+            | And
+            | Or
+            | Xor
+            | Shl
+            | Shr
+            | Eq
+            | Ne
+            | Lt
+            | Le
+            | Gt
+            | Ge
+            | StrEq
+            | StrCmp
+            | StartsWith
+    )
+}
+
+/// What `instrs` leave on the stack, `None` when they fail.
+fn evaluate(mut instrs: Vec<Instruction>) -> Option<Value> {
+    instrs.push(ReturnValue);
+    run(Bytecode {
+        instrs,
+        labels: Labels::new(),
+    })
+    .ok()
+}
+
+/// Whether `jump` is taken with `cond` on the stack, `None` when it fails.
+fn taken(cond: &Value, jump: &Instruction) -> Option<bool> {
+    let label = "taken".to_owned();
+    let jump = match jump {
+        JumpIfNeg(_) => JumpIfNeg(label.clone()),
+        JumpIfPos(_) => JumpIfPos(label.clone()),
+        JumpIfZero(_) => JumpIfZero(label.clone()),
+        JumpIfNotZero(_) => JumpIfNotZero(label.clone()),
+        _ => return None,
+    };
+    if !foldable(cond) {
+        return None;
+    }
+    let instrs = vec![
+        LoadVal(cond.clone()),
+        jump,
+        LoadVal(Value::Int(0)),
+        ReturnValue,
+        LoadVal(Value::Int(1)),
+        ReturnValue,
+    ];
+    let val = run(Bytecode {
+        instrs,
+        labels: Labels::from([(label, 4)]),
+    })
+    .ok()?;
+    Some(val == Value::Int(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::{
+        asm, examples::EXAMPLES, optimize::Optimization, run, InterpretationError, Value,
+    };
+
+    #[test]
+    fn folds_constants_and_drops_dead_jumps() {
+        let mut bytecode = asm::parse(
+            "LoadVal 2\nLoadVal 3\nMultiply\nLoadVal 4\nAdd\nLoadVal 9\nPop\n\
+             LoadVal 1\nJumpIfZero never\nLoadVal 0\nJumpIfZero hop\nhop:\nCall f\nReturnValue\n\
+             never:\nLoadVal 7\nReturnValue\n\
+             f:\nLoadVal 0\nJumpIfZero g\ng:\nLoadVal 1\nNegate\nAdd\nRet",
+        )
+        .unwrap();
+        let optimized = bytecode.optimize();
+        assert_eq!(
+            optimized,
+            Optimization {
+                before: 21,
+                after: 6
+            }
+        );
+        assert_eq!(optimized.to_string(), "21 -> 6 instructions");
+        assert_eq!(bytecode.instrs[0].to_string(), "LoadVal 10");
+        assert_eq!(run(bytecode), Ok(Value::Int(9)));
+
+        let mut bytecode =
+            asm::parse("LoadVal 3\nDup\nJumpIfZero end\nLoadVal 1\nAdd\nend:\nReturnValue")
+                .unwrap();
+        assert_eq!(bytecode.optimize().after, 5);
+        assert_eq!(bytecode.instrs[1].to_string(), "JumpIfZeroPeek end");
+        assert_eq!(run(bytecode), Ok(Value::Int(4)));
+
+        // Division by zero still fails when the program runs.
+        let mut bytecode = asm::parse("LoadVal 0\nLoadVal 1\nDivide\nReturnValue").unwrap();
+        assert_eq!(bytecode.optimize().after, 4);
+        assert_eq!(
+            run(bytecode),
+            Err(InterpretationError::DivisionByZero { ip: 2 })
+        );
+    }
+
+    #[test]
+    fn strips_what_no_run_reaches() {
+        let mut bytecode = asm::parse(
+            "LoadVal 0\nJumpIfZero end\nLoadVal 1\nPop\nend:\nSpawn worker\nLoadVal 2\nReturnValue\n\
+             LoadVal 3\nworker:\nLoadVal 4\nReturnValue\nLoadVal 5",
+        )
+        .unwrap();
+        let reached: String = bytecode
+            .reachable()
+            .iter()
+            .map(|&r| if r { 'x' } else { '.' })
+            .collect();
+        assert_eq!(reached, "xxxxxxx.xx.");
+
+        let stripped = bytecode.strip_unreachable();
+        assert_eq!((stripped.before, stripped.after), (11, 9));
+        assert_eq!(bytecode.labels["worker"], 7);
+        assert_eq!(run(bytecode), Ok(Value::Int(2)));
+    }
+
+    #[test]
+    fn examples_keep_their_results() {
+        for example in EXAMPLES {
+            let mut bytecode = example.bytecode();
+            let optimized = bytecode.optimize();
+            assert!(optimized.after <= optimized.before, "{}", example.name);
+            assert_eq!(run(bytecode), example.run(), "{}", example.name);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::{collections::BTreeMap, fmt};
+
+use super::{
+    run_observed, stats::most_common, Bytecode, InterpretationError, IpType, State, Value, VmConfig,
+};
+
+/// How often each instruction of a run executed, to find the hot loops of a program.
+///
+/// An instruction that failed counts as executed, it was the last one to start.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    pub executed: usize,
+    /// Executions per IP, one entry for every instruction of the program.
+    pub per_ip: Vec<usize>,
+    pub per_opcode: BTreeMap<&'static str, usize>,
+    names: Vec<&'static str>,
+}
+
+impl Profile {
+    pub fn record(
+        bytecode: &Bytecode,
+        config: &VmConfig,
+    ) -> (Result<Value, InterpretationError>, Profile) {
+        let mut per_ip = vec![0; bytecode.instrs.len()];
+        let result = run_observed(bytecode, config, &mut State::default(), |ip| {
+            per_ip[ip] += 1
+        });
+
+        let names: Vec<_> = bytecode.instrs.iter().map(|instr| instr.name()).collect();
+        let mut per_opcode = BTreeMap::new();
+        for (&name, &count) in names.iter().zip(&per_ip) {
+            if count > 0 {
+                *per_opcode.entry(name).or_default() += count;
+            }
+        }
+        let profile = Profile {
+            executed: per_ip.iter().sum(),
+            per_ip,
+            per_opcode,
+            names,
+        };
+        (result, profile)
+    }
+
+    /// The most executed IPs first, with their counts.
+    pub fn hottest(&self) -> Vec<(IpType, usize)> {
+        let counts: BTreeMap<_, _> = self
+            .per_ip
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(ip, &count)| (ip, count))
+            .collect();
+        most_common(&counts)
+    }
+}
+
+/// ```text
+/// 106 instructions executed
+/// hottest:
+///      4 ReadVar            10   9.4%
+/// ...
+/// opcodes:
+///   ReadVar            41  38.7%
+/// ```
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let share = |count: usize| 100.0 * count as f64 / self.executed.max(1) as f64;
+        writeln!(f, "{} instructions executed", self.executed)?;
+        writeln!(f, "hottest:")?;
+        for (ip, count) in self.hottest() {
+            let name = self.names[ip];
+            writeln!(
+                f,
+                "  {:>4} {:<14} {:>6} {:>5.1}%",
+                ip,
+                name,
+                count,
+                share(count)
+            )?;
+        }
+        write!(f, "opcodes:")?;
+        for (name, count) in most_common(&self.per_opcode) {
+            write!(f, "\n  {:<14} {:>6} {:>5.1}%", name, count, share(count))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::{examples::find, profile::Profile, Value, VmConfig};
+
+    #[test]
+    fn counts_executions_per_instruction_and_opcode() {
+        let bytecode = find("sum").unwrap().bytecode();
+        let (result, profile) = Profile::record(&bytecode, &VmConfig::default());
+        assert_eq!(result, Ok(Value::Int(55)));
+        // Four instructions of setup, ten iterations of ten, two to return.
+        assert_eq!(profile.executed, 4 + 10 * 10 + 2);
+        assert_eq!(profile.per_ip.len(), bytecode.instrs.len());
+        assert_eq!(profile.per_ip[0], 1);
+        assert_eq!(profile.hottest()[0], (4, 10));
+        assert_eq!(profile.per_opcode.values().sum::<usize>(), profile.executed);
+
+        let text = profile.to_string();
+        assert!(
+            text.starts_with("106 instructions executed\nhottest:\n     4 "),
+            "{}",
+            text
+        );
+        assert!(text.contains("\nopcodes:\n  ReadVar "), "{}", text);
+    }
+}
@@ -0,0 +1,149 @@
+use std::{sync::Arc, thread};
+
+use super::{run_embedded, Bytecode, InterpretationError, State, Value, VmConfig};
+
+/// A program that can't change any more, so any number of threads can run it at once.
+/// Its `Print` output is dropped and `ReadInput` finds no input, the stdin and stdout of
+/// whoever runs it stay theirs.
+///
+/// Handles from [`Program::share`] point to the same instructions, nothing is copied.
+#[derive(Debug, Clone)]
+pub struct Program {
+    bytecode: Arc<Bytecode>,
+    config: VmConfig,
+}
+
+impl Program {
+    pub fn new(bytecode: Bytecode) -> Self {
+        Program {
+            bytecode: Arc::new(bytecode),
+            config: VmConfig::default(),
+        }
+    }
+
+    pub fn with_config(mut self, config: VmConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Another handle to the same program, cheap enough to make one per thread.
+    pub fn share(&self) -> Program {
+        Program {
+            bytecode: Arc::clone(&self.bytecode),
+            config: self.config,
+        }
+    }
+
+    pub fn bytecode(&self) -> &Bytecode {
+        &self.bytecode
+    }
+
+    pub fn run(&self) -> Result<Value, InterpretationError> {
+        self.run_with(&[])
+    }
+
+    /// Runs with `inputs` already written to their variables.
+    pub fn run_with(&self, inputs: &[(&str, Value)]) -> Result<Value, InterpretationError> {
+        let vars = inputs
+            .iter()
+            .map(|(name, value)| ((*name).to_owned(), value.clone()))
+            .collect();
+        let mut state = State {
+            vars,
+            ..State::default()
+        };
+        run_embedded(&self.bytecode, &self.config, &mut state)
+    }
+
+    /// Runs once per input with it in `var`, for using the program as an expression over
+    /// a column of values.
+    ///
+    /// The stack and variables are reused between runs, variables are cleared each time.
+    pub fn run_map(&self, var: &str, inputs: &[Value]) -> Vec<Result<Value, InterpretationError>> {
+        let mut state = State::default();
+        inputs
+            .iter()
+            .map(|input| {
+                state.vars.clear();
+                state.vars.insert(var.to_owned(), input.clone());
+                run_embedded(&self.bytecode, &self.config, &mut state)
+            })
+            .collect()
+    }
+
+    /// `run_map` over `threads` chunks of the inputs at once, results stay in input order.
+    pub fn run_map_parallel(
+        &self,
+        var: &str,
+        inputs: &[Value],
+        threads: usize,
+    ) -> Vec<Result<Value, InterpretationError>> {
+        let chunk = inputs.len().div_ceil(threads.max(1)).max(1);
+        thread::scope(|scope| {
+            let workers: Vec<_> = inputs
+                .chunks(chunk)
+                .map(|inputs| scope.spawn(move || self.run_map(var, inputs)))
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("the VM doesn't panic"))
+                .collect()
+        })
+    }
+}
+
+impl From<Bytecode> for Program {
+    fn from(bytecode: Bytecode) -> Self {
+        Program::new(bytecode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use crate::task_1_and_2::{asm, program::Program, InterpretationError, Value};
+
+    #[test]
+    fn threads_share_one_program() {
+        let program =
+            Program::new(asm::parse("ReadVar n\nReadVar n\nMultiply\nReturnValue").unwrap());
+        let handles: Vec<_> = (0..4)
+            .map(|n| {
+                let program = program.share();
+                thread::spawn(move || program.run_with(&[("n", Value::Int(n))]))
+            })
+            .collect();
+        let squares: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        assert_eq!(
+            squares,
+            vec![
+                Ok(Value::Int(0)),
+                Ok(Value::Int(1)),
+                Ok(Value::Int(4)),
+                Ok(Value::Int(9))
+            ]
+        );
+        assert!(program.run().is_err());
+        assert!(Arc::ptr_eq(&program.bytecode, &program.share().bytecode));
+    }
+
+    #[test]
+    fn maps_over_inputs_in_order() {
+        let program =
+            Program::new(asm::parse("ReadVar x\nLoadVal 60\nDivide\nReturnValue").unwrap());
+        assert_eq!(
+            program.run_map("x", &[1, 0, 4].map(Value::Int)),
+            vec![
+                Ok(Value::Int(60)),
+                Err(InterpretationError::DivisionByZero { ip: 2 }),
+                Ok(Value::Int(15))
+            ]
+        );
+        let inputs: Vec<_> = (-50..50).map(Value::Int).collect();
+        assert_eq!(
+            program.run_map_parallel("x", &inputs, 3),
+            program.run_map("x", &inputs)
+        );
+    }
+}
@@ -0,0 +1,283 @@
+//! Checkpoints of a [`Vm`] part way through a run, so that a long one survives the process
+//! that started it, see [`Vm::snapshot`] and [`Vm::resume`].
+
+use thiserror::Error;
+
+use super::{
+    binary::{put_len, put_name, put_value, DecodeError, Reader},
+    Bytecode, Context, Frame, IpType, State, Value, Vm, VmConfig, Wait,
+};
+
+/// First bytes of every snapshot.
+pub const MAGIC: &[u8; 4] = b"TVS\0";
+pub const VERSION: u16 = 2;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    #[error("not a VM snapshot")]
+    BadMagic,
+
+    #[error("snapshot version {0} is not supported, expected {VERSION}")]
+    UnsupportedVersion(u16),
+
+    #[error("snapshot was taken running a different program")]
+    OtherProgram,
+
+    #[error("IP {0} is past the end of the program")]
+    IpOutOfRange(IpType),
+
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+}
+
+/// Layout, all integers little-endian and values and names like in
+/// [`Bytecode::to_bytes`]:
+///
+/// ```text
+/// magic "TVS\0", version u16, fingerprint of the program u64,
+/// IP u32, main u8, executed u64, gas u8 then the gas left u64 if it is 1,
+/// the running context's stack and calls, the variables sorted by name,
+/// array count u32, then per array its values,
+/// context count u32, then per context its IP, main, stack, calls and what it waits for
+/// ```
+///
+/// Stacks and arrays are a u32 count followed by the values, calls a u32 count followed by
+/// the u32 IP each returns to and the u32 stack size at it. A context waits for nothing (0), to send a value (1, the channel
+/// name and the value) or to receive (2, the channel name).
+impl Vm<'_> {
+    /// Everything the run needs to go on from the next instruction, `None` once it finished.
+    /// Breakpoints and the tracer aren't part of it.
+    pub fn snapshot(&self) -> Option<Vec<u8>> {
+        if self.is_finished() {
+            return None;
+        }
+        let mut out = MAGIC.to_vec();
+        out.extend(VERSION.to_le_bytes());
+        out.extend(fingerprint(self.bytecode).to_le_bytes());
+        put_len(&mut out, self.ip);
+        out.push(u8::from(self.main));
+        out.extend(self.executed.to_le_bytes());
+        match self.gas_left {
+            Some(left) => {
+                out.push(1);
+                out.extend(left.to_le_bytes());
+            }
+            None => out.push(0),
+        }
+
+        let State {
+            stack,
+            vars,
+            calls,
+            heap,
+            contexts,
+        } = &self.state;
+        put_values(&mut out, stack);
+        put_calls(&mut out, calls);
+        // Sorted, so the same state always gives the same bytes.
+        let mut vars: Vec<_> = vars.iter().collect();
+        vars.sort_by(|a, b| a.0.cmp(b.0));
+        put_len(&mut out, vars.len());
+        for (name, val) in vars {
+            put_name(&mut out, name);
+            put_value(&mut out, val);
+        }
+        put_len(&mut out, heap.len());
+        for array in heap {
+            put_values(&mut out, array);
+        }
+        put_len(&mut out, contexts.len());
+        for context in contexts {
+            put_len(&mut out, context.ip);
+            out.push(u8::from(context.main));
+            put_values(&mut out, &context.stack);
+            put_calls(&mut out, &context.calls);
+            match &context.wait {
+                None => out.push(0),
+                Some(Wait::Send(channel, val)) => {
+                    out.push(1);
+                    put_name(&mut out, channel);
+                    put_value(&mut out, val);
+                }
+                Some(Wait::Recv(channel)) => {
+                    out.push(2);
+                    put_name(&mut out, channel);
+                }
+            }
+        }
+        Some(out)
+    }
+
+    /// Goes on with the run `snapshot` was taken of, which must have been running `bytecode`.
+    ///
+    /// `config` may differ from the one the run started with. The instructions executed so
+    /// far count towards `max_ops`, and the gas left carries over when both meter gas.
+    pub fn resume<'a>(
+        bytecode: &'a Bytecode,
+        config: VmConfig,
+        snapshot: &[u8],
+    ) -> Result<Vm<'a>, SnapshotError> {
+        let mut reader = Reader::new(snapshot);
+        if reader.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = u16::from_le_bytes(reader.array()?);
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        if u64::from_le_bytes(reader.array()?) != fingerprint(bytecode) {
+            return Err(SnapshotError::OtherProgram);
+        }
+
+        let mut vm = Vm::new(bytecode, config);
+        vm.ip = read_ip(&mut reader, bytecode)?;
+        vm.main = read_bool(&mut reader)?;
+        vm.executed = u64::from_le_bytes(reader.array()?);
+        let gas_left = match read_bool(&mut reader)? {
+            true => Some(u64::from_le_bytes(reader.array()?)),
+            false => None,
+        };
+        if vm.gas_left.is_some() {
+            vm.gas_left = gas_left.or(vm.gas_left);
+        }
+
+        let state = &mut vm.state;
+        state.stack = read_values(&mut reader)?;
+        state.calls = read_calls(&mut reader, bytecode)?;
+        for _ in 0..reader.len()? {
+            let name = reader.name()?;
+            state.vars.insert(name, reader.value()?);
+        }
+        for _ in 0..reader.len()? {
+            state.heap.push(read_values(&mut reader)?);
+        }
+        for _ in 0..reader.len()? {
+            let ip = read_ip(&mut reader, bytecode)?;
+            let main = read_bool(&mut reader)?;
+            let stack = read_values(&mut reader)?;
+            let calls = read_calls(&mut reader, bytecode)?;
+            let offset = reader.offset;
+            let wait = match reader.array::<1>()?[0] {
+                0 => None,
+                1 => Some(Wait::Send(reader.name()?, reader.value()?)),
+                2 => Some(Wait::Recv(reader.name()?)),
+                opcode => return Err(DecodeError::UnknownOpcode { opcode, offset }.into()),
+            };
+            state.contexts.push_back(Context {
+                ip,
+                main,
+                stack,
+                calls,
+                wait,
+            });
+        }
+        vm.heap_len = vm.state.heap.iter().map(Vec::len).sum();
+
+        if reader.offset != snapshot.len() {
+            return Err(DecodeError::TrailingBytes(reader.offset).into());
+        }
+        Ok(vm)
+    }
+}
+
+/// FNV-1a of the compiled program, to catch resuming with another one.
+fn fingerprint(bytecode: &Bytecode) -> u64 {
+    bytecode
+        .to_bytes()
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+fn put_values(out: &mut Vec<u8>, values: &[Value]) {
+    put_len(out, values.len());
+    for val in values {
+        put_value(out, val);
+    }
+}
+
+fn put_calls(out: &mut Vec<u8>, calls: &[Frame]) {
+    put_len(out, calls.len());
+    for frame in calls {
+        put_len(out, frame.ret);
+        put_len(out, frame.base);
+    }
+}
+
+/// Like the IPs of labels, one past the last instruction ends the program.
+fn read_ip(reader: &mut Reader<'_>, bytecode: &Bytecode) -> Result<IpType, SnapshotError> {
+    let ip = reader.len()?;
+    if ip > bytecode.instrs.len() {
+        return Err(SnapshotError::IpOutOfRange(ip));
+    }
+    Ok(ip)
+}
+
+fn read_bool(reader: &mut Reader<'_>) -> Result<bool, SnapshotError> {
+    let offset = reader.offset;
+    match reader.array::<1>()?[0] {
+        0 => Ok(false),
+        1 => Ok(true),
+        opcode => Err(DecodeError::UnknownOpcode { opcode, offset }.into()),
+    }
+}
+
+/// Value by value, so that a corrupt count runs out of bytes instead of allocating.
+fn read_values(reader: &mut Reader<'_>) -> Result<Vec<Value>, SnapshotError> {
+    (0..reader.len()?).map(|_| Ok(reader.value()?)).collect()
+}
+
+fn read_calls(reader: &mut Reader<'_>, bytecode: &Bytecode) -> Result<Vec<Frame>, SnapshotError> {
+    (0..reader.len()?)
+        .map(|_| {
+            let ret = read_ip(reader, bytecode)?;
+            Ok(Frame {
+                ret,
+                base: reader.len()?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::{
+        examples::find,
+        snapshot::{SnapshotError, MAGIC},
+        Stop, Value, Vm, VmConfig,
+    };
+
+    #[test]
+    fn resumes_a_run_from_its_snapshot() {
+        let bytecode = find("sum").unwrap().bytecode();
+        let config = VmConfig::default();
+        let mut vm = Vm::new(&bytecode, config);
+        for _ in 0..37 {
+            vm.step().unwrap();
+        }
+        let snapshot = vm.snapshot().unwrap();
+        let mut resumed = Vm::resume(&bytecode, config, &snapshot).unwrap();
+        assert_eq!(resumed.ip(), vm.ip());
+        assert_eq!(resumed.stack(), vm.stack());
+        assert_eq!(resumed.vars(), vm.vars());
+        assert_eq!(resumed.executed(), 37);
+        assert_eq!(resumed.snapshot().unwrap(), snapshot);
+        assert_eq!(resumed.run(), Ok(Stop::Returned(Value::Int(55))));
+        assert_eq!(resumed.snapshot(), None);
+
+        let other = find("factorial").unwrap().bytecode();
+        assert_eq!(
+            Vm::resume(&other, config, &snapshot).err(),
+            Some(SnapshotError::OtherProgram)
+        );
+        assert_eq!(
+            Vm::resume(&bytecode, config, b"TBC\0\x01\x00").err(),
+            Some(SnapshotError::BadMagic)
+        );
+        assert!(snapshot.starts_with(MAGIC));
+        for len in 0..snapshot.len() {
+            assert!(Vm::resume(&bytecode, config, &snapshot[..len]).is_err());
+        }
+    }
+}
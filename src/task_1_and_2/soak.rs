@@ -0,0 +1,225 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+};
+
+use super::{run, Bytecode, Instruction, InterpretationError, Labels, Value, ValueType};
+
+use Instruction::*;
+
+const MAX_LEN: u64 = 32;
+const VARS: &[&str] = &["a", "b", "c"];
+const LABELS: &[&str] = &["l0", "l1", "l2", "l3"];
+const CHANNELS: &[&str] = &["c0", "c1"];
+const OPERATIONS: &[Instruction] = &[
+    Dup, Swap, Pop, Add, Multiply, Subtract, Divide, Modulo, Negate, And, Or, Xor, Not, Shl, Shr,
+    Eq, Ne, Lt, Le, Gt, Ge, Concat, StrLen, StrEq, StrCmp, StartsWith, NewArray, ArrayGet,
+    ArraySet, ArrayLen, ArraySlice,
+];
+/// Values around the edges of the arithmetic, where overflows and divisions go wrong, and
+/// floats and a string to mix in.
+const VALUES: &[Value] = &[
+    Value::Int(0),
+    Value::Int(1),
+    Value::Int(-1),
+    Value::Int(2),
+    Value::Int(10),
+    Value::Int(ValueType::MAX),
+    Value::Int(ValueType::MIN),
+    Value::Float(0.5),
+    Value::Float(-0.0),
+    Value::Float(f64::INFINITY),
+    Value::Float(f64::NAN),
+    Value::Str(String::new()),
+];
+
+/// xorshift64*, random enough for generating programs and reproducible from a seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Zero is the one state xorshift never leaves.
+        Rng(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len() as u64) as usize]
+    }
+}
+
+/// Runs random programs and tallies how they end, remembering the first one that panicked.
+pub struct Soak {
+    rng: Rng,
+    runs: u64,
+    outcomes: BTreeMap<&'static str, u64>,
+    panicked: Option<Bytecode>,
+}
+
+impl Soak {
+    pub fn new(seed: u64) -> Self {
+        Soak {
+            rng: Rng::new(seed),
+            runs: 0,
+            outcomes: BTreeMap::new(),
+            panicked: None,
+        }
+    }
+
+    pub fn batch(&mut self, runs: u64) {
+        for _ in 0..runs {
+            let bytecode = self.program();
+            let result = panic::catch_unwind(AssertUnwindSafe(|| run(bytecode.clone())));
+            let outcome = match &result {
+                Ok(result) => outcome(result),
+                Err(_) => {
+                    self.panicked.get_or_insert(bytecode);
+                    "panic"
+                }
+            };
+            *self.outcomes.entry(outcome).or_default() += 1;
+            self.runs += 1;
+        }
+    }
+
+    /// The listing of the first program that made the VM panic.
+    pub fn panicked(&self) -> Option<String> {
+        self.panicked.as_ref().map(Bytecode::disassemble)
+    }
+
+    fn program(&mut self) -> Bytecode {
+        let len = 1 + self.rng.below(MAX_LEN);
+        // Tracks the straight-line stack depth, so most programs get past their first few
+        // instructions instead of running out of stack.
+        let mut depth = 0;
+        let mut instrs = vec![];
+        for _ in 0..len {
+            let mut instr = self.instruction();
+            let meta = instr.meta();
+            if meta.stack_in > depth && self.rng.below(4) != 0 {
+                instr = LoadVal(self.rng.pick(VALUES).clone());
+                depth += 1;
+            } else {
+                depth = (depth + meta.stack_out).saturating_sub(meta.stack_in);
+            }
+            instrs.push(instr);
+        }
+        let mut labels = Labels::new();
+        for label in LABELS {
+            // Some labels stay undefined, so jumps to them fail.
+            if self.rng.below(4) != 0 {
+                labels.insert(label.to_string(), self.rng.below(len + 1) as usize);
+            }
+        }
+        Bytecode { instrs, labels }
+    }
+
+    fn instruction(&mut self) -> Instruction {
+        let var = |rng: &mut Rng| rng.pick(VARS).to_string();
+        let label = |rng: &mut Rng| rng.pick(LABELS).to_string();
+        let channel = |rng: &mut Rng| rng.pick(CHANNELS).to_string();
+        match self.rng.below(16) {
+            0..=2 => LoadVal(self.rng.pick(VALUES).clone()),
+            3..=4 => LoadVal(Value::Int(self.rng.next() as ValueType)),
+            5..=6 => WriteVar(var(&mut self.rng)),
+            7 => ReadVar(var(&mut self.rng)),
+            8..=11 => self.rng.pick(OPERATIONS).clone(),
+            12 => ReturnValue,
+            _ => match self.rng.below(16) {
+                0 => JumpIfNeg(label(&mut self.rng)),
+                1 => JumpIfPos(label(&mut self.rng)),
+                2 => JumpIfZero(label(&mut self.rng)),
+                3 => JumpIfNotZero(label(&mut self.rng)),
+                4 => Call(label(&mut self.rng)),
+                5 => Spawn(label(&mut self.rng)),
+                6 => SendChannel(channel(&mut self.rng)),
+                7 => RecvChannel(channel(&mut self.rng)),
+                8 => ArrayNext(label(&mut self.rng)),
+                9 => JumpIfNegPeek(label(&mut self.rng)),
+                10 => JumpIfPosPeek(label(&mut self.rng)),
+                11 => JumpIfZeroPeek(label(&mut self.rng)),
+                12 => JumpIfNotZeroPeek(label(&mut self.rng)),
+                13 => PushArg(self.rng.below(3) as usize),
+                14 => PeekFrame(self.rng.below(3) as usize),
+                _ => Ret,
+            },
+        }
+    }
+}
+
+/// `1000 runs: empty stack 420, ok 130, ...`
+impl fmt::Display for Soak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} runs", self.runs)?;
+        for (i, (outcome, count)) in self.outcomes.iter().enumerate() {
+            let sep = if i == 0 { ": " } else { ", " };
+            write!(f, "{}{} {}", sep, outcome, count)?;
+        }
+        Ok(())
+    }
+}
+
+fn outcome(result: &Result<Value, InterpretationError>) -> &'static str {
+    match result {
+        Ok(_) => "ok",
+        Err(InterpretationError::OperationsLimitExceeded) => "operations limit",
+        Err(InterpretationError::StackIsEmpty(_)) => "empty stack",
+        Err(InterpretationError::ReturnDoesntExist) => "no return",
+        Err(InterpretationError::UnknownVariable { .. }) => "unknown variable",
+        Err(InterpretationError::UnknownLabel { .. }) => "unknown label",
+        Err(InterpretationError::DivisionByZero { .. }) => "division by zero",
+        Err(InterpretationError::Overflow { .. }) => "overflow",
+        Err(InterpretationError::StackOverflow { .. }) => "stack overflow",
+        Err(InterpretationError::TooManyVariables(_)) => "too many variables",
+        Err(InterpretationError::OutsideFrame { .. }) => "outside the frame",
+        Err(InterpretationError::CallStackOverflow(_)) => "call stack overflow",
+        Err(InterpretationError::RetWithoutCall(_)) => "ret without call",
+        Err(InterpretationError::NegativeShift { .. }) => "negative shift",
+        Err(InterpretationError::StringTooLong(_)) => "string too long",
+        Err(InterpretationError::TypeMismatch { .. }) => "type mismatch",
+        Err(InterpretationError::InvalidArrayLength { .. }) => "invalid array length",
+        Err(InterpretationError::IndexOutOfBounds { .. }) => "index out of bounds",
+        Err(InterpretationError::SliceOutOfBounds { .. }) => "slice out of bounds",
+        Err(InterpretationError::UnknownArray { .. }) => "unknown array",
+        Err(InterpretationError::HeapExhausted(_)) => "heap exhausted",
+        Err(InterpretationError::TooManyContexts(_)) => "too many contexts",
+        Err(InterpretationError::Deadlock(_)) => "deadlock",
+        Err(InterpretationError::OutOfGas(_)) => "out of gas",
+        Err(InterpretationError::UnknownHost { .. }) => "unknown host",
+        Err(InterpretationError::ImpureHost { .. }) => "impure host",
+        Err(InterpretationError::HostFailed { .. }) => "host failed",
+        Err(InterpretationError::OutputFailed { .. }) => "output failed",
+        Err(InterpretationError::InputEnded(_)) => "input ended",
+        Err(InterpretationError::InputFailed { .. }) => "input failed",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::soak::Soak;
+
+    #[test]
+    fn tallies_every_run_reproducibly() {
+        let mut first = Soak::new(7);
+        first.batch(2000);
+        let mut second = Soak::new(7);
+        second.batch(2000);
+
+        assert_eq!(first.outcomes, second.outcomes);
+        assert_eq!(first.outcomes.values().sum::<u64>(), 2000);
+        assert!(first.outcomes.len() > 4, "{}", first);
+        assert!(first.panicked().is_none());
+        assert!(first.to_string().starts_with("2000 runs: "));
+    }
+}
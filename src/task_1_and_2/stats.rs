@@ -0,0 +1,121 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+};
+
+use super::Bytecode;
+
+/// How many of the most common opcodes and pairs `Display` lists.
+const TOP: usize = 10;
+
+/// Instruction counts over a corpus of programs, for deciding which superinstructions
+/// and optimisations pay off.
+#[derive(Debug, Default)]
+pub struct CorpusStats {
+    pub programs: usize,
+    pub instructions: usize,
+    pub opcodes: BTreeMap<&'static str, usize>,
+    /// Adjacent instructions with no label between them, the idioms a superinstruction
+    /// could replace.
+    pub pairs: BTreeMap<(&'static str, &'static str), usize>,
+}
+
+impl CorpusStats {
+    pub fn add(&mut self, bytecode: &Bytecode) {
+        self.programs += 1;
+        self.instructions += bytecode.instrs.len();
+        for instr in &bytecode.instrs {
+            *self.opcodes.entry(instr.name()).or_default() += 1;
+        }
+        // A jump can land on the second one, so they can't be fused.
+        let targets: HashSet<_> = bytecode.labels.values().collect();
+        for (ip, pair) in bytecode.instrs.windows(2).enumerate() {
+            if !targets.contains(&(ip + 1)) {
+                *self
+                    .pairs
+                    .entry((pair[0].name(), pair[1].name()))
+                    .or_default() += 1;
+            }
+        }
+    }
+
+    pub fn average_len(&self) -> f64 {
+        if self.programs == 0 {
+            return 0.0;
+        }
+        self.instructions as f64 / self.programs as f64
+    }
+}
+
+/// The most frequent first, ties in name order.
+pub(super) fn most_common<K: Copy + Ord>(counts: &BTreeMap<K, usize>) -> Vec<(K, usize)> {
+    let mut sorted: Vec<_> = counts.iter().map(|(&k, &n)| (k, n)).collect();
+    sorted.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    sorted.truncate(TOP);
+    sorted
+}
+
+/// ```text
+/// 3 programs, 51 instructions, 17.0 per program
+/// opcodes:
+///   ReadVar          18  35.3%
+/// ...
+/// pairs:
+///   ReadVar ReadVar   6
+/// ```
+impl fmt::Display for CorpusStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} programs, {} instructions, {:.1} per program",
+            self.programs,
+            self.instructions,
+            self.average_len()
+        )?;
+        writeln!(f, "opcodes:")?;
+        for (name, count) in most_common(&self.opcodes) {
+            let share = 100.0 * count as f64 / self.instructions as f64;
+            writeln!(f, "  {:<14} {:>6} {:>5.1}%", name, count, share)?;
+        }
+        write!(f, "pairs:")?;
+        for ((first, second), count) in most_common(&self.pairs) {
+            let pair = format!("{} {}", first, second);
+            write!(f, "\n  {:<28} {:>6}", pair, count)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::{asm, examples::EXAMPLES, stats::CorpusStats};
+
+    #[test]
+    fn counts_opcodes_and_pairs_outside_jump_targets() {
+        let mut stats = CorpusStats::default();
+        stats
+            .add(&asm::parse("ReadVar a\ntop: ReadVar b\nAdd\nReadVar a\nReadVar b\nAdd").unwrap());
+        assert_eq!(stats.instructions, 6);
+        assert_eq!(stats.opcodes["ReadVar"], 4);
+        assert_eq!(stats.opcodes["Add"], 2);
+        // The first ReadVar pair is cut by the label.
+        assert_eq!(stats.pairs[&("ReadVar", "ReadVar")], 1);
+        assert_eq!(stats.pairs[&("ReadVar", "Add")], 2);
+        assert_eq!(stats.pairs[&("Add", "ReadVar")], 1);
+    }
+
+    #[test]
+    fn summarises_the_examples() {
+        let mut stats = CorpusStats::default();
+        for example in EXAMPLES {
+            stats.add(&example.bytecode());
+        }
+        let text = stats.to_string();
+        assert!(text.starts_with(&format!(
+            "{} programs, {} instructions",
+            EXAMPLES.len(),
+            stats.instructions
+        )));
+        assert!(text.contains("\n  ReadVar "), "{}", text);
+    }
+}
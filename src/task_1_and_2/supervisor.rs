@@ -0,0 +1,313 @@
+//! Running a batch of programs, like a directory of tests, see [`Supervisor`].
+
+use std::{
+    fmt, mem,
+    panic::{self, AssertUnwindSafe},
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    thread,
+};
+
+use super::{gas::Gas, Bytecode, InterpretationError, State, Value, Vm, VmConfig};
+
+/// What a [`Supervisor`] does once a program fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Policy {
+    /// Every program runs whatever the others did.
+    #[default]
+    RunAll,
+    /// Programs that haven't started when one fails are skipped.
+    FailFast,
+}
+
+/// How one program of a batch ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Returned(Value),
+    Failed(InterpretationError),
+    /// The VM panicked, with the message of the panic.
+    Panicked(String),
+    /// Not run, after a failure with [`Policy::FailFast`] or with the total gas spent.
+    Skipped,
+}
+
+impl Outcome {
+    /// Failed or panicked.
+    pub fn is_failure(&self) -> bool {
+        matches!(self, Outcome::Failed(_) | Outcome::Panicked(_))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramReport {
+    pub name: String,
+    pub outcome: Outcome,
+    /// Instructions it executed, the failing one included.
+    pub executed: u64,
+    pub gas_used: u64,
+}
+
+/// Every program of a batch in the order given, and the gas they used together.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Report {
+    pub programs: Vec<ProgramReport>,
+    pub gas_used: u64,
+}
+
+impl Report {
+    pub fn returned(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Returned(_)))
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(Outcome::is_failure)
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.count(|outcome| *outcome == Outcome::Skipped)
+    }
+
+    fn count(&self, matches: impl Fn(&Outcome) -> bool) -> usize {
+        self.programs
+            .iter()
+            .filter(|program| matches(&program.outcome))
+            .count()
+    }
+}
+
+/// `5 returned, 1 failed, 2 skipped, 340 gas`
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} returned, {} failed, {} skipped, {} gas",
+            self.returned(),
+            self.failed(),
+            self.skipped(),
+            self.gas_used
+        )
+    }
+}
+
+/// Runs programs on a pool of threads, each program on a VM of its own that starts without
+/// variables and whose panic only fails that program. Each thread reuses the allocations of
+/// the programs it ran before.
+///
+/// A total gas budget is shared by the whole batch: each program gets at most what is left,
+/// or its own limit from the config when that is less, and hands back what it didn't use when
+/// it ends. Once nothing is left the remaining programs are skipped. With more than one
+/// thread which programs those are depends on timing.
+#[derive(Debug, Clone)]
+pub struct Supervisor {
+    config: VmConfig,
+    total_gas: Option<u64>,
+    policy: Policy,
+    threads: usize,
+}
+
+impl Supervisor {
+    /// Each program runs within the limits of `config`, on as many threads as there are
+    /// cores.
+    pub fn new(config: VmConfig) -> Self {
+        Supervisor {
+            config,
+            total_gas: None,
+            policy: Policy::default(),
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    pub fn total_gas(mut self, gas: u64) -> Self {
+        self.total_gas = Some(gas);
+        self
+    }
+
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// At least 1.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Runs `programs`, each with its name.
+    pub fn run(&self, programs: &[(String, Bytecode)]) -> Report {
+        let next = AtomicUsize::new(0);
+        let stop = AtomicBool::new(false);
+        let gas_left = self.total_gas.map(AtomicU64::new);
+        let mut reports: Vec<(usize, ProgramReport)> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..self.threads.min(programs.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut state = State::default();
+                        let mut reports = vec![];
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some((name, bytecode)) = programs.get(i) else {
+                                break reports;
+                            };
+                            let report = match stop.load(Ordering::Relaxed) {
+                                true => skipped(name),
+                                false => self.run_one(name, bytecode, &mut state, &gas_left),
+                            };
+                            if self.policy == Policy::FailFast && report.outcome.is_failure() {
+                                stop.store(true, Ordering::Relaxed);
+                            }
+                            reports.push((i, report));
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("panics are caught per program"))
+                .collect()
+        });
+        reports.sort_by_key(|&(i, _)| i);
+        let programs: Vec<_> = reports.into_iter().map(|(_, report)| report).collect();
+        Report {
+            gas_used: programs.iter().map(|program| program.gas_used).sum(),
+            programs,
+        }
+    }
+
+    fn run_one(
+        &self,
+        name: &str,
+        bytecode: &Bytecode,
+        state: &mut State,
+        gas_left: &Option<AtomicU64>,
+    ) -> ProgramReport {
+        let mut config = self.config;
+        let mut reserved = None;
+        if let Some(gas_left) = gas_left {
+            let own = config.gas.map(|gas| gas.limit);
+            let share = |left: u64| own.map_or(left, |own| own.min(left));
+            let taken = gas_left.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                (left > 0).then(|| left - share(left))
+            });
+            let Ok(left) = taken else {
+                return skipped(name);
+            };
+            let limit = share(left);
+            let costs = config.gas.map(|gas| gas.costs).unwrap_or_default();
+            config.gas = Some(Gas { limit, costs });
+            reserved = Some(limit);
+        }
+
+        state.vars.clear();
+        let mut vm = Vm::with_state(bytecode, config, mem::take(state));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| loop {
+            match vm.execute(&mut |_| {}) {
+                Ok(None) => continue,
+                Ok(Some(val)) => break Ok(val),
+                Err(err) => break Err(err),
+            }
+        }));
+        let outcome = match result {
+            Ok(Ok(val)) => Outcome::Returned(val),
+            Ok(Err(err)) => Outcome::Failed(err),
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                Outcome::Panicked(message)
+            }
+        };
+        let gas_used = match config.gas {
+            Some(gas) => gas.limit - vm.gas_left.unwrap_or(0),
+            None => 0,
+        };
+        if let (Some(gas_left), Some(reserved)) = (gas_left, reserved) {
+            gas_left.fetch_add(reserved - gas_used, Ordering::Relaxed);
+        }
+        let executed = vm.executed;
+        // After a panic the state may be half updated, better start over.
+        if !matches!(outcome, Outcome::Panicked(_)) {
+            *state = vm.state;
+        }
+        ProgramReport {
+            name: name.to_owned(),
+            outcome,
+            executed,
+            gas_used,
+        }
+    }
+}
+
+fn skipped(name: &str) -> ProgramReport {
+    ProgramReport {
+        name: name.to_owned(),
+        outcome: Outcome::Skipped,
+        executed: 0,
+        gas_used: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::{
+        asm,
+        gas::Gas,
+        supervisor::{Outcome, Policy, Supervisor},
+        InterpretationError, Value, VmConfig,
+    };
+
+    #[test]
+    fn runs_batches_by_policy_and_budget() {
+        let program = |name: &str, text: &str| (name.to_owned(), asm::parse(text).unwrap());
+        let programs = [
+            program("one", "LoadVal 1\nReturnValue"),
+            program("empty", "Pop"),
+            program("two", "LoadVal 1\nLoadVal 1\nAdd\nReturnValue"),
+        ];
+        let outcomes = |supervisor: Supervisor| {
+            let report = supervisor.run(&programs);
+            let outcomes: Vec<_> = report.programs.iter().map(|p| p.outcome.clone()).collect();
+            (outcomes, report.to_string())
+        };
+
+        let supervisor = Supervisor::new(VmConfig::default()).threads(1);
+        let failed = Outcome::Failed(InterpretationError::StackIsEmpty(0));
+        assert_eq!(
+            outcomes(supervisor.clone()),
+            (
+                vec![
+                    Outcome::Returned(Value::Int(1)),
+                    failed.clone(),
+                    Outcome::Returned(Value::Int(2))
+                ],
+                "2 returned, 1 failed, 0 skipped, 0 gas".to_owned()
+            )
+        );
+        assert_eq!(
+            outcomes(supervisor.clone().policy(Policy::FailFast)).0[1..],
+            [failed, Outcome::Skipped]
+        );
+
+        // 2 for the first program, 1 for the failing one, and 1 is not enough for the last.
+        let budget = supervisor.total_gas(4);
+        let report = budget.run(&programs);
+        assert_eq!(
+            report.programs[2].outcome,
+            Outcome::Failed(InterpretationError::OutOfGas(1))
+        );
+        assert_eq!(report.gas_used, 4);
+        let report = budget.total_gas(3).run(&programs);
+        assert_eq!(report.to_string(), "1 returned, 1 failed, 1 skipped, 3 gas");
+
+        let limited = VmConfig {
+            gas: Some(Gas::new(2)),
+            ..VmConfig::default()
+        };
+        let report = Supervisor::new(limited)
+            .total_gas(100)
+            .threads(4)
+            .run(&programs);
+        assert_eq!((report.returned(), report.gas_used), (1, 5));
+    }
+}
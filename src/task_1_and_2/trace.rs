@@ -0,0 +1,108 @@
+use std::{collections::HashMap, fmt::Write as _};
+
+use super::{run_observed, Bytecode, InterpretationError, State, Value, VmConfig};
+use crate::json;
+
+/// A run in the Chrome trace event format, for `about:tracing` or Perfetto.
+///
+/// Timestamps count executed instructions rather than time, so a trace of the same
+/// program always looks the same. There is one slice for the whole run and one for
+/// every loop iteration that jumped back to its label.
+pub struct Trace {
+    events: Vec<Event>,
+}
+
+struct Event {
+    name: String,
+    ts: u64,
+    dur: u64,
+}
+
+impl Trace {
+    pub fn record(
+        name: &str,
+        bytecode: Bytecode,
+        config: &VmConfig,
+    ) -> (Result<Value, InterpretationError>, Trace) {
+        let mut labels: Vec<_> = bytecode
+            .labels
+            .iter()
+            .map(|(label, &ip)| (ip, label.clone()))
+            .collect();
+        // Several labels on one IP, the first by name wins.
+        labels.sort();
+        labels.dedup_by_key(|(ip, _)| *ip);
+        let targets: HashMap<_, _> = labels.into_iter().collect();
+
+        let mut events = vec![];
+        let mut entered = HashMap::new();
+        let mut prev = None;
+        let mut step = 0;
+        let result = run_observed(&bytecode, config, &mut State::default(), |ip| {
+            if let Some(label) = targets.get(&ip) {
+                let jumped_back = prev.is_some_and(|prev| ip <= prev);
+                if let (true, Some(&start)) = (jumped_back, entered.get(&ip)) {
+                    events.push(Event {
+                        name: format!("loop {}", label),
+                        ts: start,
+                        dur: step - start,
+                    });
+                }
+                entered.insert(ip, step);
+            }
+            prev = Some(ip);
+            step += 1;
+        });
+
+        events.insert(
+            0,
+            Event {
+                name: format!("run {}", name),
+                ts: 0,
+                dur: step,
+            },
+        );
+        (result, Trace { events })
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("{\"traceEvents\":[");
+        for (i, event) in self.events.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            let _ = write!(
+                out,
+                "{{\"name\":{},\"cat\":\"vm\",\"ph\":\"X\",\"ts\":{},\"dur\":{},\"pid\":1,\"tid\":1}}",
+                json::string(&event.name),
+                event.ts,
+                event.dur
+            );
+        }
+        out.push_str("]}");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::{examples::find, trace::Trace, Value, VmConfig};
+
+    #[test]
+    fn records_the_run_and_each_loop_iteration() {
+        let (result, trace) =
+            Trace::record("sum", find("sum").unwrap().bytecode(), &VmConfig::default());
+        assert_eq!(result, Ok(Value::Int(55)));
+
+        let names: Vec<_> = trace.events.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names[0], "run sum");
+        // n counts down from 10, the jump back is taken while it is still positive.
+        assert_eq!(names[1..], ["loop loop"; 9]);
+        // Four instructions of setup, ten iterations of ten, two to return.
+        assert_eq!(trace.events[0].dur, 4 + 10 * 10 + 2);
+        assert_eq!((trace.events[1].ts, trace.events[1].dur), (4, 10));
+        assert!(trace.to_json().starts_with(
+            "{\"traceEvents\":[{\"name\":\"run sum\",\"cat\":\"vm\",\"ph\":\"X\",\"ts\":0,"
+        ));
+    }
+}
@@ -0,0 +1,146 @@
+//! The variables of a run, see [`Variables`].
+
+use std::{collections::HashMap, ops::Index};
+
+use super::{Value, VariableName};
+
+/// Where a variable is kept in [`Variables`], see [`Variables::slot`].
+pub type Slot = u32;
+
+/// Variables by name, stored in a `Vec` so that an instruction that knows its [`Slot`]
+/// reaches its variable without hashing the name. The VM looks up the slots of the
+/// variables a program uses once when a run starts.
+///
+/// A name keeps its slot once it has one, also after [`Variables::clear`]. Otherwise this
+/// behaves like a map from names to values.
+#[derive(Debug, Clone, Default)]
+pub struct Variables {
+    slots: HashMap<VariableName, Slot>,
+    /// The name of each slot, for error messages.
+    names: Vec<VariableName>,
+    values: Vec<Option<Value>>,
+    /// Slots holding a value.
+    len: usize,
+}
+
+impl Variables {
+    pub fn new() -> Self {
+        Variables::default()
+    }
+
+    /// The slot of `name`, a new empty one the first time.
+    pub fn slot(&mut self, name: &str) -> Slot {
+        if let Some(&slot) = self.slots.get(name) {
+            return slot;
+        }
+        let slot = Slot::try_from(self.names.len()).expect("fewer than 4G variable names");
+        self.slots.insert(name.to_owned(), slot);
+        self.names.push(name.to_owned());
+        self.values.push(None);
+        slot
+    }
+
+    /// The name `slot` was given out for.
+    pub fn name(&self, slot: Slot) -> &VariableName {
+        &self.names[slot as usize]
+    }
+
+    pub fn get_slot(&self, slot: Slot) -> Option<&Value> {
+        self.values[slot as usize].as_ref()
+    }
+
+    /// Stores `val` in `slot`, returning what was there.
+    pub fn set_slot(&mut self, slot: Slot, val: Value) -> Option<Value> {
+        let old = self.values[slot as usize].replace(val);
+        self.len += usize::from(old.is_none());
+        old
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.get_slot(*self.slots.get(name)?)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn insert(&mut self, name: VariableName, val: Value) -> Option<Value> {
+        let slot = self.slot(&name);
+        self.set_slot(slot, val)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Empties every variable, the slots stay.
+    pub fn clear(&mut self) {
+        self.values.iter_mut().for_each(|val| *val = None);
+        self.len = 0;
+    }
+
+    /// The variables holding a value, in the order their slots were given out.
+    pub fn iter(&self) -> impl Iterator<Item = (&VariableName, &Value)> {
+        self.names
+            .iter()
+            .zip(&self.values)
+            .filter_map(|(name, val)| Some((name, val.as_ref()?)))
+    }
+}
+
+/// The same names with the same values, whatever their slots.
+impl PartialEq for Variables {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().all(|(name, val)| other.get(name) == Some(val))
+    }
+}
+
+impl Index<&str> for Variables {
+    type Output = Value;
+
+    fn index(&self, name: &str) -> &Value {
+        self.get(name).expect("no variable with that name")
+    }
+}
+
+impl FromIterator<(VariableName, Value)> for Variables {
+    fn from_iter<I: IntoIterator<Item = (VariableName, Value)>>(iter: I) -> Self {
+        let mut vars = Variables::new();
+        for (name, val) in iter {
+            vars.insert(name, val);
+        }
+        vars
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::{vars::Variables, Value};
+
+    #[test]
+    fn keeps_slots_across_clears() {
+        let mut vars = Variables::new();
+        let x = vars.slot("x");
+        assert_eq!(vars.slot("y"), x + 1);
+        assert_eq!(vars.slot("x"), x);
+        assert!(vars.is_empty());
+
+        assert_eq!(vars.set_slot(x, Value::Int(1)), None);
+        assert_eq!(vars.insert("x".into(), Value::Int(2)), Some(Value::Int(1)));
+        assert_eq!(
+            (vars.len(), &vars["x"], vars.get("y")),
+            (1, &Value::Int(2), None)
+        );
+        assert_eq!(vars.name(x), "x");
+
+        vars.clear();
+        assert_eq!((vars.len(), vars.slot("x")), (0, x));
+        let other: Variables = [("x".to_owned(), Value::Int(3))].into_iter().collect();
+        vars.insert("x".into(), Value::Int(3));
+        assert_eq!(vars, other);
+    }
+}
@@ -0,0 +1,325 @@
+//! Checks a program before it runs, for mistakes that would otherwise only show up as an
+//! error part way through a run.
+
+use std::{cmp::Ordering, collections::BTreeSet};
+
+use thiserror::Error;
+
+use super::{Bytecode, Instruction, IpType, LabelName, Value, ValueKind};
+
+/// Something wrong with a program, found without running it.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    #[error("unknown label '{label}' (IP={ip})")]
+    UnknownLabel { label: LabelName, ip: IpType },
+
+    #[error("label '{label}' points past the end of the program ({target})")]
+    LabelOutOfRange { label: LabelName, target: IpType },
+
+    #[error("{instr} needs {needed} values but the stack holds {depth} (IP={ip})")]
+    StackUnderflow {
+        instr: &'static str,
+        needed: usize,
+        depth: usize,
+        ip: IpType,
+    },
+
+    #[error("{instr} takes {expected} values, not {found} (IP={ip})")]
+    TypeMismatch {
+        instr: &'static str,
+        expected: ValueKind,
+        found: ValueKind,
+        ip: IpType,
+    },
+
+    #[error("{instr} {offset} reaches outside the frame (IP={ip})")]
+    OutsideFrame {
+        instr: &'static str,
+        offset: usize,
+        ip: IpType,
+    },
+}
+
+/// Every problem found in `bytecode`, empty when it looks fine. Labels pointing past the
+/// end come first, then unknown labels and then what is wrong with the stack.
+///
+/// The stack is only followed along straight-line paths, falling through conditional jumps
+/// and `ArrayNext`, up to the first return or `Call`, after which the callee decides what
+/// is on it. A jump testing a constant it always jumps on ends the path too, `LoadVal 0`
+/// then `JumpIfZero` is how programs jump unconditionally. Paths start empty at the start and at each `Spawn` target, and at each `Call`
+/// target with the arguments below, which the callee may pop but not reach with `PushArg`
+/// once it did. Each path reports its first underflow, a string instruction getting a value
+/// known not to be a string, or a `PushArg` or `PeekFrame` outside the frame.
+pub fn verify(bytecode: &Bytecode) -> Vec<Diagnostic> {
+    let mut found = vec![];
+    let mut labels: Vec<_> = bytecode.labels.iter().collect();
+    labels.sort();
+    for (label, &target) in labels {
+        if target > bytecode.instrs.len() {
+            found.push(Diagnostic::LabelOutOfRange {
+                label: label.clone(),
+                target,
+            });
+        }
+    }
+
+    // Where paths start, and whether in a call.
+    let mut entries = BTreeSet::from([(0, false)]);
+    for (ip, instr) in bytecode.instrs.iter().enumerate() {
+        let Some(label) = instr.label() else {
+            continue;
+        };
+        match bytecode.labels.get(label) {
+            Some(&start) if matches!(instr, Instruction::Spawn(_)) => {
+                entries.insert((start, false));
+            }
+            Some(&start) if matches!(instr, Instruction::Call(_)) => {
+                entries.insert((start, true));
+            }
+            Some(_) => {}
+            None => found.push(Diagnostic::UnknownLabel {
+                label: label.clone(),
+                ip,
+            }),
+        }
+    }
+
+    for (start, called) in entries {
+        // The kind of each value in the frame where it is known, the top last.
+        let mut stack: Vec<Option<ValueKind>> = vec![];
+        // Arguments popped by a call, the frame is empty while there are any.
+        let mut args_popped = 0;
+        // The value the previous instruction pushed, when it is a constant.
+        let mut constant = None;
+        for (ip, instr) in bytecode.instrs.iter().enumerate().skip(start) {
+            let needed = instr.meta().stack_in;
+            let missing = needed.saturating_sub(stack.len());
+            if missing > 0 && !called {
+                found.push(Diagnostic::StackUnderflow {
+                    instr: instr.name(),
+                    needed,
+                    depth: stack.len(),
+                    ip,
+                });
+                break;
+            }
+            let outside = match *instr {
+                Instruction::PushArg(offset) => (!called || offset < args_popped).then_some(offset),
+                Instruction::PeekFrame(offset) => offset
+                    .checked_add(args_popped)
+                    .is_none_or(|end| end >= stack.len())
+                    .then_some(offset),
+                _ => None,
+            };
+            if let Some(offset) = outside {
+                found.push(Diagnostic::OutsideFrame {
+                    instr: instr.name(),
+                    offset,
+                    ip,
+                });
+                break;
+            }
+            args_popped += missing;
+            let mut popped = vec![None; missing];
+            popped.extend(stack.split_off(stack.len() + missing - needed));
+            let takes_strings = matches!(
+                instr,
+                Instruction::StrLen
+                    | Instruction::StrEq
+                    | Instruction::StrCmp
+                    | Instruction::StartsWith
+            );
+            let other = popped
+                .iter()
+                .flatten()
+                .find(|&&kind| kind != ValueKind::Str);
+            if let (true, Some(&other)) = (takes_strings, other) {
+                found.push(Diagnostic::TypeMismatch {
+                    instr: instr.name(),
+                    expected: ValueKind::Str,
+                    found: other,
+                    ip,
+                });
+                break;
+            }
+            stack.extend(pushed_kinds(instr, &popped));
+            if matches!(
+                instr,
+                Instruction::ReturnValue | Instruction::Ret | Instruction::Call(_)
+            ) || constant.is_some_and(|val| always_jumps(instr, val))
+            {
+                break;
+            }
+            constant = match instr {
+                Instruction::LoadVal(val) => Some(val),
+                _ => None,
+            };
+        }
+    }
+    found
+}
+
+/// Whether `instr` is a conditional jump that jumps when it tests `val`, the way the VM
+/// decides it. Strings and arrays make it fail instead.
+fn always_jumps(instr: &Instruction, val: &Value) -> bool {
+    use Instruction::*;
+
+    if matches!(val, Value::Str(_) | Value::Array(_)) {
+        return false;
+    }
+    let sign = val.sign();
+    match instr {
+        JumpIfNeg(_) | JumpIfNegPeek(_) => sign == Some(Ordering::Less),
+        JumpIfPos(_) | JumpIfPosPeek(_) => sign == Some(Ordering::Greater),
+        JumpIfZero(_) | JumpIfZeroPeek(_) => sign == Some(Ordering::Equal),
+        JumpIfNotZero(_) | JumpIfNotZeroPeek(_) => sign != Some(Ordering::Equal),
+        _ => false,
+    }
+}
+
+/// What is known about the values `instr` pushes, given what it popped with the top last.
+fn pushed_kinds(instr: &Instruction, popped: &[Option<ValueKind>]) -> Vec<Option<ValueKind>> {
+    use Instruction::*;
+
+    match instr {
+        LoadVal(val) => vec![Some(val.kind())],
+        Dup => vec![popped[0]; 2],
+        JumpIfNegPeek(_) | JumpIfPosPeek(_) | JumpIfZeroPeek(_) | JumpIfNotZeroPeek(_) => {
+            vec![popped[0]]
+        }
+        Swap => vec![popped[1], popped[0]],
+        Eq | Ne | Lt | Le | Gt | Ge | StrLen | StrEq | StrCmp | StartsWith | ArrayLen => {
+            vec![Some(ValueKind::Int)]
+        }
+        Concat => vec![Some(ValueKind::Str)],
+        NewArray | ArraySlice => vec![Some(ValueKind::Array)],
+        ArrayNext(_) => vec![Some(ValueKind::Int), popped[1], None],
+        _ => vec![None; instr.meta().stack_out],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::task_1_and_2::{
+        asm,
+        builder::BytecodeBuilder,
+        examples::EXAMPLES,
+        verify::{verify, Diagnostic},
+        Instruction, ValueKind,
+    };
+
+    #[test]
+    fn examples_verify() {
+        for example in EXAMPLES {
+            assert_eq!(verify(&example.bytecode()), [], "{}", example.name);
+        }
+    }
+
+    #[test]
+    fn checks_frames_of_calls() {
+        let diagnostics = |text| verify(&asm::parse(text).unwrap());
+        assert_eq!(
+            diagnostics("LoadVal 1\nCall f\nReturnValue\nf:\nPushArg 0\nPeekFrame 0\nAdd\nRet"),
+            []
+        );
+        assert_eq!(
+            diagnostics("LoadVal 1\nCall f\nReturnValue\nf:\nPop\nPushArg 0\nRet"),
+            [Diagnostic::OutsideFrame {
+                instr: "PushArg",
+                offset: 0,
+                ip: 4
+            }]
+        );
+        assert_eq!(
+            diagnostics("LoadVal 1\nPeekFrame 1\nPushArg 0\nReturnValue")[0].to_string(),
+            "PeekFrame 1 reaches outside the frame (IP=1)"
+        );
+        let far = BytecodeBuilder::new()
+            .load_val(1)
+            .peek_frame(usize::MAX)
+            .return_value()
+            .build()
+            .unwrap();
+        assert_eq!(
+            verify(&far),
+            [Diagnostic::OutsideFrame {
+                instr: "PeekFrame",
+                offset: usize::MAX,
+                ip: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_labels_and_underflow() {
+        let mut bytecode = asm::parse(
+            "LoadVal 1\nJumpIfZero worker\nLoadVal 2\nAdd\nReturnValue\n\
+             worker:\nLoadVal 3\nSwap\nReturnValue\nSpawn worker",
+        )
+        .unwrap();
+        // Only programs built in code or decoded from bytes can have these.
+        bytecode.instrs[1] = Instruction::JumpIfZero("nowhere".into());
+        bytecode.labels.insert("far".into(), 99);
+        assert_eq!(
+            verify(&bytecode),
+            [
+                Diagnostic::LabelOutOfRange {
+                    label: "far".into(),
+                    target: 99
+                },
+                Diagnostic::UnknownLabel {
+                    label: "nowhere".into(),
+                    ip: 1
+                },
+                Diagnostic::StackUnderflow {
+                    instr: "Add",
+                    needed: 2,
+                    depth: 1,
+                    ip: 3
+                },
+                Diagnostic::StackUnderflow {
+                    instr: "Swap",
+                    needed: 2,
+                    depth: 1,
+                    ip: 6
+                },
+            ]
+        );
+
+        // Past a jump that is always taken nothing runs, whatever the stack would be there.
+        let jumps = "LoadVal 2\nLoadVal 0\nJumpIfZero end\nAdd\nend:\nLoadVal 3\nAdd\nReturnValue";
+        assert_eq!(verify(&asm::parse(jumps).unwrap()), []);
+        let falls = asm::parse(&jumps.replace("LoadVal 0", "LoadVal 1")).unwrap();
+        assert!(matches!(
+            verify(&falls)[..],
+            [Diagnostic::StackUnderflow { ip: 3, .. }]
+        ));
+
+        // The value a peek jump tests is still there to return.
+        let peek = "LoadVal 1\nJumpIfZeroPeek end\nend:\nReturnValue";
+        assert_eq!(verify(&asm::parse(peek).unwrap()), []);
+        let popped = asm::parse(&peek.replace("Peek", "")).unwrap();
+        assert!(matches!(
+            verify(&popped)[..],
+            [Diagnostic::StackUnderflow { ip: 2, .. }]
+        ));
+
+        let bytecode =
+            asm::parse("LoadVal \"a\"\nReadVar s\nStrEq\nLoadVal 3\nStartsWith\nReturnValue")
+                .unwrap();
+        let found = verify(&bytecode);
+        assert_eq!(
+            found,
+            [Diagnostic::TypeMismatch {
+                instr: "StartsWith",
+                expected: ValueKind::Str,
+                found: ValueKind::Int,
+                ip: 4
+            }]
+        );
+        assert_eq!(
+            found[0].to_string(),
+            "StartsWith takes str values, not int (IP=4)"
+        );
+    }
+}
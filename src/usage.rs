@@ -0,0 +1,117 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    time::Duration,
+};
+
+/// How often each subcommand ran and for how long, kept in the file `stats-file` in the
+/// config names. Nothing is recorded without it and the file never leaves the machine.
+///
+/// The file has `command<TAB>runs<TAB>milliseconds` lines, which add up when a subcommand
+/// has several. Each run appends one.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    commands: BTreeMap<String, Tally>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Tally {
+    runs: u64,
+    total: Duration,
+}
+
+impl Usage {
+    /// A missing file is an empty record, lines that don't parse are dropped.
+    pub fn load(path: &Path) -> io::Result<Usage> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(Usage::parse(&text)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Usage::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Adds one run of `command` to the file at `path`.
+    pub fn record(path: &Path, command: &str, elapsed: Duration) -> io::Result<()> {
+        let line = format!("{}\t1\t{}\n", command, elapsed.as_millis());
+        // One appending write, so runs at the same time can't lose each other's lines.
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(line.as_bytes())
+    }
+
+    fn parse(text: &str) -> Usage {
+        let mut usage = Usage::default();
+        let lines = text.lines().filter_map(|line| {
+            let mut fields = line.split('\t');
+            let command = fields.next()?;
+            let runs: u64 = fields.next()?.parse().ok()?;
+            let total = Duration::from_millis(fields.next()?.parse().ok()?);
+            Some((command, runs, total))
+        });
+        for (command, runs, total) in lines {
+            let tally = usage.commands.entry(command.to_owned()).or_default();
+            tally.runs += runs;
+            tally.total += total;
+        }
+        usage
+    }
+}
+
+/// `run                    12 runs   0.4s total   0.03s on average`, most used first.
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut commands: Vec<_> = self.commands.iter().collect();
+        commands.sort_by(|a, b| b.1.runs.cmp(&a.1.runs).then(a.0.cmp(b.0)));
+        for (i, (command, tally)) in commands.into_iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            let total = tally.total.as_secs_f64();
+            write!(
+                f,
+                "{:<20} {:>6} runs {:>9.1}s total {:>8.2}s on average",
+                command,
+                tally.runs,
+                total,
+                total / tally.runs.max(1) as f64
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::usage::Usage;
+
+    #[test]
+    fn adds_up_the_lines_of_a_command() {
+        let usage = Usage::parse("run\t1\t1000\nbroken line\nsearch\t1\t250\nrun\t2\t1000\n");
+        assert_eq!(usage, Usage::parse("run\t3\t2000\nsearch\t1\t250\n"));
+        assert!(usage
+            .to_string()
+            .starts_with("run                       3 runs"));
+    }
+
+    #[test]
+    fn records_into_a_file() {
+        let dir = std::env::temp_dir().join(format!("testing-usage-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("stats");
+        Usage::record(&path, "map", Duration::from_millis(10)).unwrap();
+        Usage::record(&path, "map", Duration::from_millis(20)).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "map\t1\t10\nmap\t1\t20\n"
+        );
+        assert_eq!(Usage::load(&path).unwrap(), Usage::parse("map\t2\t30\n"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,168 @@
+//! The `testing` binary as users run it.
+#![cfg(feature = "search")]
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+};
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("testing-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn testing(dir: &Path, args: &[&str]) -> (Option<i32>, String) {
+    let output = testing_in("en", dir, args);
+    (
+        output.status.code(),
+        String::from_utf8(output.stdout).unwrap(),
+    )
+}
+
+fn testing_in(lang: &str, dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_testing"))
+        .args(args)
+        .current_dir(dir)
+        .env_remove("TESTING_CONFIG")
+        .env("TESTING_LANG", lang)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn searches_a_directory_named_like_a_subcommand() {
+    let dir = scratch("subcommand");
+    fs::create_dir(dir.join("test")).unwrap();
+    fs::write(dir.join("test/a.rs"), "1\n2\n").unwrap();
+
+    let (code, out) = testing(&dir, &["--format", "json", "search", "test", "rs"]);
+    assert_eq!(code, Some(0));
+    // `test/a.rs`, or `test\a.rs` on Windows.
+    let first = out.lines().next().unwrap_or_default();
+    assert!(first.starts_with(r#"{"path":"test"#), "{}", out);
+    assert!(first.ends_with(r#"a.rs","lines":2}"#), "{}", out);
+    // Without `search` it is the subcommand, which has no directory named rs to test.
+    let (code, _) = testing(&dir, &["test", "rs"]);
+    assert_eq!(code, Some(1));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn programs_in_a_search_leave_its_stdin_and_stdout_alone() {
+    let dir = scratch("print");
+    fs::write(dir.join("a.rs"), "1\n").unwrap();
+    fs::write(dir.join("keep.tasm"), "LoadVal 1\nReturnValue").unwrap();
+    fs::write(
+        dir.join("print.tasm"),
+        "LoadVal \"leak\"\nPrint\nLoadVal 1\nReturnValue",
+    )
+    .unwrap();
+
+    let search = |prog| {
+        testing(
+            &dir,
+            &["--format", "json", "--filter-prog", prog, ".", "rs"],
+        )
+    };
+    let (code, out) = search("print.tasm");
+    assert_eq!(code, Some(0));
+    assert_eq!(out, search("keep.tasm").1);
+
+    // Nothing is read from the search's stdin, the program fails instead.
+    fs::write(dir.join("read.tasm"), "ReadInput\nReturnValue").unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_testing"))
+        .args(["--filter-prog", "read.tasm", ".", "rs"])
+        .current_dir(&dir)
+        .env_remove("TESTING_CONFIG")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    // Fails with a broken pipe when the search ended first, which is fine.
+    let _ = child.stdin.take().unwrap().write_all(b"7\n");
+    assert_eq!(child.wait().unwrap().code(), Some(1));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn reports_bad_choices_in_the_locale() {
+    let dir = scratch("choices");
+    let error = |args: &[&str]| {
+        let output = testing_in("ru", &dir, args);
+        assert_eq!(output.status.code(), Some(1));
+        String::from_utf8(output.stderr).unwrap()
+    };
+    assert!(error(&["--type", "tasm", "."])
+        .contains("неизвестный тип 'tasm', ожидается rust, script, binary или text"));
+    assert!(error(&["--format", "xml", "."])
+        .contains("неизвестный формат 'xml', ожидается human, json, quiet или null"));
+    assert!(error(&["--color", "red", "."])
+        .contains("неизвестный режим цвета 'red', ожидается auto, always или never"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn runs_programs_that_jump_unconditionally() {
+    let dir = scratch("jump");
+    // `LoadVal 0` then `JumpIfZero` always jumps, the `Add` after it never runs.
+    let jumps = "LoadVal 2\nLoadVal 0\nJumpIfZero end\nAdd\nend:\nLoadVal 3\nAdd\nReturnValue\n";
+    fs::write(dir.join("jump.tasm"), jumps).unwrap();
+    assert_eq!(
+        testing(&dir, &["run", "jump.tasm"]),
+        (Some(0), "5\n".to_owned())
+    );
+
+    // A zero the verifier doesn't know about only gets a warning, the run decides.
+    let computed = jumps.replace("LoadVal 0", "LoadVal 0\nLoadVal 0\nAdd");
+    fs::write(dir.join("computed.tasm"), computed).unwrap();
+    let output = testing_in("en", &dir, &["run", "computed.tasm"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.starts_with("warning: computed.tasm: Add needs 2 values"),
+        "{}",
+        stderr
+    );
+    assert_eq!(output.stdout, b"5\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn refuses_to_soak_longer_than_it_can_wait() {
+    let dir = scratch("soak");
+    let output = testing_in("en", &dir, &["soak", "--hours", "1e20"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("--hours 100000000000000000000 is longer than this system can wait"),
+        "{}",
+        stderr
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn saves_bookmarks_of_searches_that_can_run() {
+    let dir = scratch("bookmark");
+    fs::write(dir.join("a.rs"), "1\n").unwrap();
+    for invalid in [
+        &["--save-as", "mine", ".", "rs", "extra"][..],
+        &["--save-as", "mine", "--type", "tasm", "."],
+        &["--save-as", "mine", "--added-lines", ".", "rs"],
+    ] {
+        let (code, _) = testing(&dir, invalid);
+        assert_eq!(code, Some(1), "{:?}", invalid);
+        assert!(!dir.join("testing.conf").exists(), "{:?}", invalid);
+    }
+    let (code, _) = testing(&dir, &["--save-as", "mine", ".", "rs"]);
+    assert_eq!(code, Some(0));
+    assert_eq!(
+        fs::read_to_string(dir.join("testing.conf")).unwrap(),
+        "bookmark.mine = . rs\n"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}